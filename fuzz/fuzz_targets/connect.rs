@@ -0,0 +1,24 @@
+#![no_main]
+
+use std::net::SocketAddr;
+
+use libfuzzer_sys::fuzz_target;
+use mqtt_broker::control_packet::connect;
+use mqtt_broker::models::client::Clients;
+use mqtt_broker::packet::{ self, Packet };
+use mqtt_broker::services::outbound;
+
+// Feeds every CONNECT the decoder accepts to the handler, with no client sessions.
+// The CONNACK of an accepted connection must encode.
+fuzz_target!(|data: &[u8]| {
+    if let Ok((Packet::Connect(connect), _)) = packet::decode(data) {
+        let socket_addr: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 1883));
+        let clients: Clients = Clients::new();
+        let (tx, _rx) = outbound::channel();
+
+        if let Ok(response) = connect::handle(connect, socket_addr, &clients, tx) {
+            assert_eq!(clients.len(), 1);
+            assert!(Packet::Connack(response.connack).to_vec().is_ok());
+        }
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt_broker::packet::{ self, Packet };
+
+// Decodes the bytes a client could send, as the connection handler does with its read buffer.
+// A decoded packet must encode again, and the encoded bytes must decode to the same packet.
+fuzz_target!(|data: &[u8]| {
+    if let Ok((packet, length)) = packet::decode(data) {
+        assert!(length <= data.len());
+
+        let encoded: Vec<u8> = packet.to_vec().expect("A decoded packet can be encoded");
+        let decoded: (Packet, usize) = packet::decode(&encoded).expect("An encoded packet can be decoded");
+
+        assert_eq!(decoded, (packet, encoded.len()));
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt_broker::control_packet::publish;
+use mqtt_broker::packet::{ self, Packet };
+
+// Feeds every PUBLISH the decoder accepts to the handler.
+fuzz_target!(|data: &[u8]| {
+    if let Ok((Packet::Publish(publish), _)) = packet::decode(data) {
+        let qos: u8 = publish.qos;
+
+        if let Ok(response) = publish::handle_publish(publish) {
+            assert_eq!(response.qos_level, qos);
+            assert!(!response.topic_name.contains(['+', '#']));
+        }
+    }
+});
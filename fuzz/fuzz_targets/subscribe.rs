@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt_broker::control_packet::subcribe;
+use mqtt_broker::models::sub_info::SubInfo;
+use mqtt_broker::packet::{ self, Packet, Suback };
+
+// Feeds every SUBSCRIBE the decoder accepts to the handler, the SUBACK has one return code per topic filter.
+fuzz_target!(|data: &[u8]| {
+    if let Ok((Packet::Subscribe(subscribe), _)) = packet::decode(data) {
+        let topic_filters: usize = subscribe.topic_filters.len();
+        let sub_info: SubInfo = subcribe::handle(subscribe, 2);
+
+        assert_eq!(sub_info.topic_qos_pair.len(), topic_filters);
+        assert_eq!(sub_info.return_codes.len(), topic_filters);
+
+        let suback: Packet = Packet::Suback(Suback {
+            packet_id: sub_info.packet_id,
+            return_codes: sub_info.return_codes,
+        });
+        assert!(suback.to_vec().is_ok());
+    }
+});
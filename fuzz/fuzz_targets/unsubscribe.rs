@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mqtt_broker::control_packet::unsubcribe;
+use mqtt_broker::models::sub_info::SubInfo;
+use mqtt_broker::packet::{ self, Packet };
+
+// Feeds every UNSUBSCRIBE the decoder accepts to the handler.
+fuzz_target!(|data: &[u8]| {
+    if let Ok((Packet::Unsubscribe(unsubscribe), _)) = packet::decode(data) {
+        let topic_filters: usize = unsubscribe.topic_filters.len();
+        let sub_info: SubInfo = unsubcribe::handle(unsubscribe);
+
+        assert_eq!(sub_info.topic_qos_pair.len(), topic_filters);
+    }
+});
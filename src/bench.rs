@@ -0,0 +1,303 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::mpsc::{ Receiver, RecvTimeoutError };
+use std::sync::Arc;
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant };
+
+use rand::Rng;
+
+use crate::broker::Broker;
+use crate::mqtt_client::{ ConnectOptions, Message, MqttClient };
+
+pub mod options;
+pub mod stats;
+
+use self::options::BenchOptions;
+use self::stats::{ percentile, ProcessStats };
+
+/// How long to wait for messages still in flight after the publishers have stopped.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The length of the send timestamp at the start of each payload, 16 hex digits.
+pub const TIMESTAMP_LENGTH: usize = 16;
+
+/// The results of a benchmark run.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub published: usize,
+    pub publish_errors: usize,
+    pub expected_deliveries: usize,
+    pub delivered: usize,
+    /// How long the publishers published.
+    pub publish_duration: Duration,
+    /// End-to-end latencies of the delivered messages, in nanoseconds and ascending order.
+    pub latencies: Vec<u64>,
+    /// The peak memory and thread count of the broker process, when it could be read.
+    pub broker: Option<ProcessStats>,
+    /// True if the broker ran in the bench process, so its stats include the simulated clients.
+    pub in_process: bool,
+}
+
+impl Report {
+    /// The latency at a percentile, see [`stats::percentile`].
+    pub fn latency(&self, percentile_value: f64) -> Option<Duration> {
+        percentile(&self.latencies, percentile_value).map(Duration::from_nanos)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds: f64 = self.publish_duration.as_secs_f64().max(f64::EPSILON);
+        let millis = |latency: Option<Duration>| match latency {
+            Some(latency) => format!("{:.3} ms", latency.as_secs_f64() * 1000.0),
+            None => "-".to_string(),
+        };
+
+        writeln!(f, "Published:  {} messages in {:.2} s ({:.0} msg/s), {} errors", self.published, seconds, self.published as f64 / seconds, self.publish_errors)?;
+        writeln!(f, "Delivered:  {} of {} expected ({:.0} msg/s)", self.delivered, self.expected_deliveries, self.delivered as f64 / seconds)?;
+        writeln!(
+            f,
+            "Latency:    p50 {}, p99 {}, p999 {}, max {}",
+            millis(self.latency(50.0)),
+            millis(self.latency(99.0)),
+            millis(self.latency(99.9)),
+            millis(self.latency(100.0))
+        )?;
+
+        match self.broker {
+            Some(broker) => {
+                let note: &str = if self.in_process { " (in-process, includes the bench clients)" } else { "" };
+                write!(f, "Broker:     {:.1} MiB resident, {} threads at peak{}", broker.rss_kib as f64 / 1024.0, broker.threads, note)
+            }
+            None => write!(f, "Broker:     memory and threads unknown, pass --pid"),
+        }
+    }
+}
+
+/// Runs a benchmark.
+///
+/// # Arguments
+///
+/// * `options` - The clients, rates and patterns of the run, see [`BenchOptions`].
+///
+/// # Returns
+///
+/// A Result containing the [`Report`], or an error message if the broker could not be started
+/// or a client could not connect or subscribe.
+///
+/// # Description
+///
+/// Without `options.address` a broker is started in this process, on an ephemeral port.
+/// The subscribers connect and subscribe first, then every publisher publishes at its rate
+/// until the duration has passed, picking the QoS of each message from the QoS mix.
+/// Each payload starts with the time it was sent, so a subscriber can measure the end-to-end latency.
+/// The broker's memory and thread count are sampled during the run.
+///
+/// # Examples
+///
+/// ```
+/// let report: Report = bench::run(&BenchOptions::default()).unwrap();
+/// println!("{}", report);
+/// ```
+pub fn run(options: &BenchOptions) -> Result<Report, String> {
+    // Either the broker at the given address, or a broker in this process
+    let (address, broker_pid, in_process): (SocketAddr, Option<u32>, bool) = match options.address {
+        Some(address) => (address, options.broker_pid, false),
+        None => {
+            let mut broker: Broker = Broker::bind("127.0.0.1:0".parse().unwrap())?;
+            broker.start()?;
+
+            (broker.local_addr, Some(std::process::id()), true)
+        }
+    };
+
+    // The time every send timestamp is relative to
+    let epoch: Instant = Instant::now();
+    let stop: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
+    let delivered: Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+
+    // Client identifiers are unique per bench process, so runs against the same broker don't collide
+    let client_prefix: String = format!("bench-{}", std::process::id());
+
+    // Sample the broker's memory and threads until the run stops
+    let sampler: JoinHandle<Option<ProcessStats>> = {
+        let stop: Arc<AtomicBool> = Arc::clone(&stop);
+
+        thread::spawn(move || {
+            let mut peak: Option<ProcessStats> = None;
+
+            while !stop.load(Ordering::SeqCst) {
+                if let Some(stats) = broker_pid.and_then(ProcessStats::read) {
+                    peak = Some(peak.map_or(stats, |peak: ProcessStats| peak.max(stats)));
+                }
+
+                thread::sleep(Duration::from_millis(200));
+            }
+
+            peak
+        })
+    };
+
+    // Connect and subscribe every subscriber, before anything is published
+    let mut subscribers: Vec<Arc<MqttClient>> = Vec::new();
+    let mut collectors: Vec<JoinHandle<Vec<u64>>> = Vec::new();
+
+    for index in 0..options.subscribers {
+        let (client, messages) = MqttClient::connect(address, &ConnectOptions::new(&format!("{}-sub-{}", client_prefix, index)))?;
+        client.subscribe(&[(options.pattern.topic_filter(index), options.max_qos())])?;
+        subscribers.push(client);
+
+        let stop: Arc<AtomicBool> = Arc::clone(&stop);
+        let delivered: Arc<AtomicUsize> = Arc::clone(&delivered);
+
+        collectors.push(thread::spawn(move || collect_latencies(messages, epoch, &stop, &delivered)));
+    }
+
+    // Publish until the duration has passed
+    let publish_start: Instant = Instant::now();
+    let mut publishers: Vec<JoinHandle<Result<(usize, usize), String>>> = Vec::new();
+
+    for index in 0..options.publishers {
+        let options: BenchOptions = options.clone();
+        let client_id: String = format!("{}-pub-{}", client_prefix, index);
+
+        publishers.push(thread::spawn(move || publish(address, &client_id, index, &options, epoch, publish_start)));
+    }
+
+    let (mut published, mut publish_errors): (usize, usize) = (0, 0);
+
+    for publisher in publishers {
+        let (sent, errors) = publisher.join().map_err(|_| "A publisher panicked".to_string())??;
+        published += sent;
+        publish_errors += errors;
+    }
+
+    let publish_duration: Duration = publish_start.elapsed();
+
+    // Wait for the messages still in flight
+    let expected_deliveries: usize = published * options.pattern.deliveries_per_message(options.subscribers);
+    let drain_start: Instant = Instant::now();
+
+    while delivered.load(Ordering::SeqCst) < expected_deliveries && drain_start.elapsed() < DRAIN_TIMEOUT {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    stop.store(true, Ordering::SeqCst);
+
+    let mut latencies: Vec<u64> = Vec::new();
+
+    for collector in collectors {
+        latencies.extend(collector.join().map_err(|_| "A subscriber panicked".to_string())?);
+    }
+
+    latencies.sort_unstable();
+
+    for subscriber in subscribers {
+        subscriber.disconnect();
+    }
+
+    Ok(Report {
+        published,
+        publish_errors,
+        expected_deliveries,
+        delivered: latencies.len(),
+        publish_duration,
+        latencies,
+        broker: sampler.join().unwrap_or(None),
+        in_process,
+    })
+}
+
+/// Publishes at the rate of the options until the duration has passed.
+///
+/// # Returns
+///
+/// A Result containing the number of published messages and of failed publishes,
+/// or an error message if the publisher could not connect.
+fn publish(
+    address: SocketAddr,
+    client_id: &str,
+    index: usize,
+    options: &BenchOptions,
+    epoch: Instant,
+    publish_start: Instant
+) -> Result<(usize, usize), String> {
+    let (client, _messages) = MqttClient::connect(address, &ConnectOptions::new(client_id))?;
+
+    let topic: String = options.pattern.topic(index);
+    let interval: Duration = Duration::from_secs_f64(1.0 / options.rate);
+    let total_weight: u32 = options.qos_mix.iter().map(|(_, weight)| weight).sum();
+
+    let (mut published, mut errors): (usize, usize) = (0, 0);
+    let mut next_send: Instant = Instant::now();
+
+    while publish_start.elapsed() < options.duration {
+        // Pick the QoS of this message from the weighted mix
+        let mut pick: u32 = rand::thread_rng().gen_range(0..total_weight);
+        let mut qos: u8 = 0;
+
+        for (mix_qos, weight) in &options.qos_mix {
+            if pick < *weight {
+                qos = *mix_qos;
+                break;
+            }
+
+            pick -= weight;
+        }
+
+        // The payload starts with the send time, in nanoseconds since the epoch of the run.
+        // It is written as hex text, the broker passes payloads on as UTF-8
+        let mut payload: Vec<u8> = format!("{:016x}", epoch.elapsed().as_nanos() as u64).into_bytes();
+        payload.resize(options.payload_size, b'.');
+
+        match client.publish(&(Message { topic: topic.clone(), payload, qos, retain: false })) {
+            Ok(()) => {
+                published += 1;
+            }
+            Err(_) => {
+                errors += 1;
+            }
+        }
+
+        // Keep the rate, a publisher that falls behind sends the next message right away
+        next_send += interval;
+
+        if let Some(wait) = next_send.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+
+    client.disconnect();
+
+    Ok((published, errors))
+}
+
+/// Collects the end-to-end latency of each message a subscriber receives, until the run stops.
+fn collect_latencies(messages: Receiver<Message>, epoch: Instant, stop: &AtomicBool, delivered: &AtomicUsize) -> Vec<u64> {
+    let mut latencies: Vec<u64> = Vec::new();
+
+    while !stop.load(Ordering::SeqCst) {
+        let message: Message = match messages.recv_timeout(Duration::from_millis(100)) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => continue,
+            // The connection to the broker is lost
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        let received_at: u64 = epoch.elapsed().as_nanos() as u64;
+
+        let sent_at: Option<u64> = message.payload
+            .get(..TIMESTAMP_LENGTH)
+            .and_then(|timestamp: &[u8]| std::str::from_utf8(timestamp).ok())
+            .and_then(|timestamp: &str| u64::from_str_radix(timestamp, 16).ok());
+
+        if let Some(sent_at) = sent_at {
+            latencies.push(received_at.saturating_sub(sent_at));
+            delivered.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    latencies
+}
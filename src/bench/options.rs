@@ -0,0 +1,198 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The usage text of the `bench` binary.
+pub const USAGE: &str = "\
+Usage: bench [options]
+
+  --address <host:port>   Broker to benchmark, an in-process broker is started when not set
+  --pid <pid>             Process of the broker at --address, for its memory and thread counts
+  --publishers <n>        Publishing clients (default 10)
+  --subscribers <n>       Subscribing clients (default 10, always equal to --publishers for pairs)
+  --rate <n>              Messages per second per publisher (default 100)
+  --payload <bytes>       Payload size, at least 16 bytes for the send timestamp (default 64)
+  --qos <mix>             QoS weights, e.g. 0:50,1:30,2:20 (default 0:100)
+  --pattern <pattern>     pairs, fan-out or fan-in (default pairs)
+  --duration <seconds>    How long the publishers publish (default 10)
+";
+
+/// How publishers and subscribers are connected by topics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// Publisher `i` publishes to `bench/i`, which only subscriber `i` subscribes to.
+    Pairs,
+    /// Every publisher publishes to `bench/shared`, which every subscriber subscribes to.
+    FanOut,
+    /// Publisher `i` publishes to `bench/i`, every subscriber subscribes to `bench/+`.
+    FanIn,
+}
+
+impl Pattern {
+    /// The topic publisher `index` publishes to.
+    pub fn topic(&self, index: usize) -> String {
+        match self {
+            Pattern::FanOut => "bench/shared".to_string(),
+            Pattern::Pairs | Pattern::FanIn => format!("bench/{}", index),
+        }
+    }
+
+    /// The topic filter subscriber `index` subscribes to.
+    pub fn topic_filter(&self, index: usize) -> String {
+        match self {
+            Pattern::Pairs => format!("bench/{}", index),
+            Pattern::FanOut => "bench/shared".to_string(),
+            Pattern::FanIn => "bench/+".to_string(),
+        }
+    }
+
+    /// How many subscribers receive each message.
+    pub fn deliveries_per_message(&self, subscribers: usize) -> usize {
+        match self {
+            Pattern::Pairs => 1,
+            Pattern::FanOut | Pattern::FanIn => subscribers,
+        }
+    }
+}
+
+/// The options of a benchmark run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchOptions {
+    pub address: Option<SocketAddr>,
+    pub broker_pid: Option<u32>,
+    pub publishers: usize,
+    pub subscribers: usize,
+    /// Messages per second, per publisher.
+    pub rate: f64,
+    pub payload_size: usize,
+    /// The QoS levels and their weights, a publisher picks the QoS of each message at random.
+    pub qos_mix: Vec<(u8, u32)>,
+    pub pattern: Pattern,
+    pub duration: Duration,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            address: None,
+            broker_pid: None,
+            publishers: 10,
+            subscribers: 10,
+            rate: 100.0,
+            payload_size: 64,
+            qos_mix: vec![(0, 100)],
+            pattern: Pattern::Pairs,
+            duration: Duration::from_secs(10),
+        }
+    }
+}
+
+impl BenchOptions {
+    /// Parses the command line arguments, without the program name.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the options, or an error message naming the invalid argument.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let args: Vec<String> = vec!["--pattern".to_string(), "fan-out".to_string()];
+    /// let options: BenchOptions = BenchOptions::parse(&args).unwrap();
+    ///
+    /// assert_eq!(options.pattern, Pattern::FanOut);
+    /// ```
+    pub fn parse(args: &[String]) -> Result<BenchOptions, String> {
+        let mut options: BenchOptions = BenchOptions::default();
+        let mut index: usize = 0;
+
+        while index < args.len() {
+            let name: &str = &args[index];
+            let value: &str = args
+                .get(index + 1)
+                .ok_or_else(|| format!("Missing value after {}", name))?;
+
+            match name {
+                "--address" => options.address = Some(parse_value(name, value)?),
+                "--pid" => options.broker_pid = Some(parse_value(name, value)?),
+                "--publishers" => options.publishers = parse_value(name, value)?,
+                "--subscribers" => options.subscribers = parse_value(name, value)?,
+                "--rate" => options.rate = parse_value(name, value)?,
+                "--payload" => options.payload_size = parse_value(name, value)?,
+                "--qos" => options.qos_mix = parse_qos_mix(value)?,
+                "--pattern" => {
+                    options.pattern = match value {
+                        "pairs" => Pattern::Pairs,
+                        "fan-out" => Pattern::FanOut,
+                        "fan-in" => Pattern::FanIn,
+                        _ => {
+                            return Err(format!("Invalid --pattern {}, expected pairs, fan-out or fan-in", value));
+                        }
+                    };
+                }
+                "--duration" => options.duration = Duration::from_secs_f64(parse_value(name, value)?),
+                _ => {
+                    return Err(format!("Unknown option {}", name));
+                }
+            }
+
+            index += 2;
+        }
+
+        if options.pattern == Pattern::Pairs {
+            options.subscribers = options.publishers;
+        }
+
+        if options.publishers == 0 {
+            return Err("--publishers must be at least 1".to_string());
+        }
+
+        if options.rate <= 0.0 {
+            return Err("--rate must be above 0".to_string());
+        }
+
+        if options.payload_size < super::TIMESTAMP_LENGTH {
+            return Err(format!("--payload must be at least {} bytes, for the send timestamp", super::TIMESTAMP_LENGTH));
+        }
+
+        if options.broker_pid.is_some() && options.address.is_none() {
+            return Err("--pid needs --address, the in-process broker is measured without it".to_string());
+        }
+
+        Ok(options)
+    }
+
+    /// The highest QoS in the mix, subscribers subscribe with it so no message is downgraded.
+    pub fn max_qos(&self) -> u8 {
+        self.qos_mix.iter().map(|(qos, _)| *qos).max().unwrap_or(0)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("Invalid {} {}", name, value))
+}
+
+/// Parses a QoS mix like `0:50,1:30,2:20`.
+fn parse_qos_mix(value: &str) -> Result<Vec<(u8, u32)>, String> {
+    let mut qos_mix: Vec<(u8, u32)> = Vec::new();
+
+    for part in value.split(',') {
+        let (qos, weight) = part
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid --qos {}, expected <qos>:<weight>", part))?;
+
+        let qos: u8 = parse_value("--qos", qos)?;
+        let weight: u32 = parse_value("--qos", weight)?;
+
+        if qos > 2 {
+            return Err(format!("Invalid --qos {}, the QoS is 0, 1 or 2", part));
+        }
+
+        qos_mix.push((qos, weight));
+    }
+
+    if qos_mix.iter().all(|(_, weight)| *weight == 0) {
+        return Err("Invalid --qos, at least one weight must be above 0".to_string());
+    }
+
+    Ok(qos_mix)
+}
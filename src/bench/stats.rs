@@ -0,0 +1,69 @@
+use std::fs;
+
+/// Returns the value at a percentile of sorted samples, using the nearest-rank method.
+///
+/// # Arguments
+///
+/// * `sorted` - The samples, in ascending order.
+/// * `percentile` - The percentile, from 0 to 100.
+///
+/// # Returns
+///
+/// The smallest sample that at least `percentile` percent of the samples are less than or equal to,
+/// or None when there are no samples.
+///
+/// # Examples
+///
+/// ```
+/// let samples: Vec<u64> = (1..=1000).collect();
+///
+/// assert_eq!(percentile(&samples, 50.0), Some(500));
+/// assert_eq!(percentile(&samples, 99.9), Some(999));
+/// ```
+pub fn percentile(sorted: &[u64], percentile: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    // Multiply before dividing, 0.99 * 1000 would round up to the next rank
+    let rank: usize = (percentile * sorted.len() as f64 / 100.0).ceil() as usize;
+
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// The memory and thread count of a process, read from `/proc/<pid>/status` (Linux only).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessStats {
+    /// Resident memory, in KiB.
+    pub rss_kib: u64,
+    pub threads: u64,
+}
+
+impl ProcessStats {
+    /// Reads the stats of a process.
+    ///
+    /// # Returns
+    ///
+    /// The stats, or None if the process does not exist or the platform has no `/proc`.
+    pub fn read(pid: u32) -> Option<ProcessStats> {
+        let status: String = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+
+        ProcessStats::parse(&status)
+    }
+
+    /// Parses the `VmRSS` and `Threads` lines of a `/proc/<pid>/status` file.
+    pub fn parse(status: &str) -> Option<ProcessStats> {
+        let field = |name: &str| -> Option<u64> {
+            let line: &str = status.lines().find(|line: &&str| line.starts_with(name))?;
+
+            line[name.len()..].split_whitespace().next()?.parse::<u64>().ok()
+        };
+
+        Some(ProcessStats { rss_kib: field("VmRSS:")?, threads: field("Threads:")? })
+    }
+
+    /// The larger of each field.
+    pub fn max(self, other: ProcessStats) -> ProcessStats {
+        ProcessStats { rss_kib: self.rss_kib.max(other.rss_kib), threads: self.threads.max(other.threads) }
+    }
+}
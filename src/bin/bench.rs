@@ -0,0 +1,40 @@
+use std::process;
+
+use mqtt_broker::bench::{ self, options::{ BenchOptions, USAGE } };
+
+/// Load generator for the broker, see `bench --help`.
+///
+/// Spawns simulated publishers and subscribers against a broker, and reports the throughput,
+/// the end-to-end latency percentiles and the broker's memory and thread count.
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.iter().any(|arg: &String| arg == "--help" || arg == "-h") {
+        print!("{}", USAGE);
+        return;
+    }
+
+    let options: BenchOptions = BenchOptions::parse(&args).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(2);
+    });
+
+    println!(
+        "{} publishers at {} msg/s, {} subscribers, {:?}, {} byte payloads, QoS mix {:?}, {} s",
+        options.publishers,
+        options.rate,
+        options.subscribers,
+        options.pattern,
+        options.payload_size,
+        options.qos_mix,
+        options.duration.as_secs_f64()
+    );
+
+    match bench::run(&options) {
+        Ok(report) => println!("{}", report),
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::net::{ SocketAddr, TcpListener };
+use std::sync::Arc;
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+
+use tracing::{ error, info, warn };
+
+use crate::models::client::Clients;
+use crate::models::publish_queue_item::PublishQueue;
+use crate::models::topic::Topics;
+use crate::services;
+
+/// A broker instance: the MQTT listener and the shared state of its connections.
+///
+/// The client sessions, topics and publish queue are sharded maps, see [`ShardedMap`](crate::common_fn::sharded_map::ShardedMap),
+/// so connections only lock the shards they use.
+///
+/// `main()` runs a single instance, tests and bridges can start more on ephemeral ports.
+pub struct Broker {
+    pub local_addr: SocketAddr,
+    pub clients: Arc<Clients>,
+    pub topics: Arc<Topics>,
+    pub publish_queue: Arc<PublishQueue>,
+    listener: Option<TcpListener>,
+}
+
+impl Broker {
+    /// Binds the MQTT listener, without accepting connections yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address to bind to. Port 0 binds an ephemeral port, see `local_addr`.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the [`Broker`], or an error message if binding failed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut broker: Broker = Broker::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    /// broker.start().unwrap();
+    ///
+    /// let stream: TcpStream = TcpStream::connect(broker.local_addr).unwrap();
+    /// ```
+    pub fn bind(address: SocketAddr) -> Result<Broker, String> {
+        let listener: TcpListener = TcpListener::bind(address).map_err(|err|
+            format!("Failed to bind to {}: {}", address, err)
+        )?;
+
+        let local_addr: SocketAddr = listener.local_addr().map_err(|err| err.to_string())?;
+
+        Ok(Broker {
+            local_addr,
+            clients: Arc::new(Clients::new()),
+            topics: Arc::new(Topics::new()),
+            publish_queue: Arc::new(PublishQueue::new()),
+            listener: Some(listener),
+        })
+    }
+
+    /// Starts accepting connections on a background thread.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the handle of the accept thread, or an error message if the broker
+    /// was already started.
+    ///
+    /// # Description
+    ///
+    /// Each connection is handled on its own thread by `handle_connection`. A connection over the
+    /// configured connection rate of its IP address is closed before anything is read from it.
+    /// The accept thread ends, closing the listener, when a shutdown is requested.
+    pub fn start(&mut self) -> Result<JoinHandle<()>, String> {
+        let listener: TcpListener = self.listener.take().ok_or("Broker is already started")?;
+
+        // Poll the listener, so the loop can notice a requested shutdown
+        listener.set_nonblocking(true).map_err(|err| err.to_string())?;
+
+        let clients: Arc<Clients> = Arc::clone(&self.clients);
+        let topics: Arc<Topics> = Arc::clone(&self.topics);
+        let publish_queue: Arc<PublishQueue> = Arc::clone(&self.publish_queue);
+
+        info!(address = %self.local_addr, "MQTT broker listening");
+
+        Ok(
+            thread::spawn(move || {
+                // For each incoming connection -> Spawn a new thread to handle the client's connection, using the handle_connection function
+                while !services::shutdown::is_requested() {
+                    match listener.accept() {
+                        Ok((stream, peer)) => {
+                            // A connection over the connection rate of its IP address is closed right away
+                            if !services::rate_limit::allow_connection(peer.ip()) {
+                                warn!(peer = %peer, "Connection rate limit exceeded, closing the connection");
+                                continue;
+                            }
+
+                            // The connection itself uses blocking reads, with the keep alive as timeout
+                            _ = stream.set_nonblocking(false);
+
+                            // Clone the shared state for each thread
+                            let clients_clone: Arc<Clients> = Arc::clone(&clients);
+                            let topics_clone: Arc<Topics> = Arc::clone(&topics);
+                            let publish_queue_clone: Arc<PublishQueue> = Arc::clone(&publish_queue);
+
+                            // Spawn a new thread to handle the client connection
+                            thread::spawn(move || {
+                                crate::handle_connection(
+                                    stream,
+                                    clients_clone,
+                                    topics_clone,
+                                    publish_queue_clone
+                                );
+                            });
+                        }
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(50));
+                        }
+                        Err(err) => {
+                            // Print error if accepting a client connection fails
+                            error!(error = %err, "Could not accept client connection");
+                        }
+                    }
+                }
+            })
+        )
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs::File;
+use std::io::{ BufRead, BufReader, Write };
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError };
+use std::thread;
+use std::time::Duration;
+
+use crate::mqtt_client::Message;
+
+pub mod format;
+pub mod options;
+pub mod session;
+
+use self::options::{ Input, PubOptions, SubOptions };
+use self::session::Session;
+
+/// Runs `mqtt-pub`: connects, publishes every payload of the input and disconnects.
+///
+/// # Arguments
+///
+/// * `options` - The topic, QoS, input and connection options.
+/// * `stdin` - The reader of `--stdin`, the process's stdin outside of tests.
+///
+/// # Returns
+///
+/// A Result containing the number of published messages, or an error message if the
+/// connection failed, the input could not be read, or a message was not acknowledged.
+///
+/// # Description
+///
+/// Lines are read on their own thread, so the keep alive pings go out while the input is idle,
+/// and each line is published as soon as it is read. Line breaks are not part of the payload,
+/// and empty lines are skipped.
+///
+/// # Examples
+///
+/// ```
+/// let options: PubOptions = PubOptions::parse(&args).unwrap();
+/// let published: usize = cli::run_pub(&options, BufReader::new(std::io::stdin())).unwrap();
+/// ```
+pub fn run_pub(options: &PubOptions, stdin: impl BufRead + Send + 'static) -> Result<usize, String> {
+    let message = |payload: Vec<u8>| Message { topic: options.topic.clone(), payload, qos: options.qos, retain: options.retain };
+
+    // Open the input before connecting, so a missing file fails without touching the broker
+    let lines: Box<dyn BufRead + Send> = match &options.input {
+        Input::Message(payload) => {
+            let mut session: Session = Session::open(&options.connection)?;
+            session.publish(&message(payload.clone()))?;
+            session.disconnect();
+
+            return Ok(1);
+        }
+        Input::Stdin => Box::new(stdin),
+        Input::File(path) => {
+            let file: File = File::open(path).map_err(|err| format!("Could not open {}: {}", path.display(), err))?;
+
+            Box::new(BufReader::new(file))
+        }
+    };
+
+    let mut session: Session = Session::open(&options.connection)?;
+
+    // Read the lines on their own thread, the channel closes at the end of the input
+    let (line_tx, line_rx) = channel::<std::io::Result<Vec<u8>>>();
+
+    thread::spawn(move || {
+        for line in lines.split(b'\n') {
+            if line_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let published: usize = publish_lines(&mut session, &line_rx, message)?;
+    session.disconnect();
+
+    Ok(published)
+}
+
+/// Publishes each line until the channel closes, pinging the broker while no line arrives.
+fn publish_lines(
+    session: &mut Session,
+    lines: &Receiver<std::io::Result<Vec<u8>>>,
+    message: impl Fn(Vec<u8>) -> Message
+) -> Result<usize, String> {
+    let mut published: usize = 0;
+
+    loop {
+        let line: Vec<u8> = match lines.recv_timeout(session.ping_interval().unwrap_or(Duration::MAX)) {
+            Ok(line) => line.map_err(|err| format!("Could not read the input: {}", err))?,
+            Err(RecvTimeoutError::Timeout) => {
+                session.ping()?;
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Ok(published);
+            }
+        };
+
+        // Drop the line break, a file written on Windows ends its lines with \r\n
+        let payload: &[u8] = line.strip_suffix(b"\r").unwrap_or(&line);
+
+        if payload.is_empty() {
+            continue;
+        }
+
+        session.publish(&message(payload.to_vec()))?;
+        published += 1;
+    }
+}
+
+/// Runs `mqtt-sub`: subscribes and prints each message it receives as a line.
+///
+/// # Arguments
+///
+/// * `options` - The topic filters, QoS, output format and connection options.
+/// * `output` - Where the messages are printed, stdout outside of tests.
+///
+/// # Returns
+///
+/// A Result containing the number of received messages once `--count` is reached,
+/// or an error message if the connection failed, a subscription was refused or the connection was lost.
+pub fn run_sub(options: &SubOptions, output: &mut impl Write) -> Result<usize, String> {
+    let mut session: Session = Session::open(&options.connection)?;
+
+    let topic_filters: Vec<(String, u8)> = options.topic_filters
+        .iter()
+        .map(|topic_filter: &String| (topic_filter.clone(), options.qos))
+        .collect();
+
+    let return_codes: Vec<u8> = session.subscribe(&topic_filters)?;
+
+    for (topic_filter, return_code) in options.topic_filters.iter().zip(&return_codes) {
+        if *return_code == 0x80 {
+            return Err(format!("Subscription to {} refused by the broker", topic_filter));
+        }
+    }
+
+    let mut received: usize = 0;
+
+    while options.count.is_none_or(|count: usize| received < count) {
+        let message: Message = session.next_message()?;
+
+        // Flush every line, so the output can be piped into another tool
+        writeln!(output, "{}", options.format.format(&message))
+            .and_then(|_| output.flush())
+            .map_err(|err| format!("Could not write the output: {}", err))?;
+
+        received += 1;
+    }
+
+    session.disconnect();
+
+    Ok(received)
+}
@@ -0,0 +1,60 @@
+use serde_json::{ json, Value };
+
+use crate::mqtt_client::Message;
+
+/// How `mqtt-sub` prints the messages it receives, one line per message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The topic and the payload as UTF-8, invalid bytes are replaced.
+    Text,
+    /// The topic and the payload in lowercase hex.
+    Hex,
+    /// A JSON object with the topic, QoS and retain flag, and the payload as a string if it is
+    /// valid UTF-8, or else in hex as `payload_hex`.
+    Json,
+}
+
+impl OutputFormat {
+    /// Formats a message as a single line, without the line break.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let message: Message = Message { topic: "a/b".to_string(), payload: b"on".to_vec(), qos: 1, retain: false };
+    ///
+    /// assert_eq!(OutputFormat::Text.format(&message), "a/b on");
+    /// assert_eq!(OutputFormat::Hex.format(&message), "a/b 6f6e");
+    /// assert_eq!(OutputFormat::Json.format(&message), r#"{"payload":"on","qos":1,"retain":false,"topic":"a/b"}"#);
+    /// ```
+    pub fn format(&self, message: &Message) -> String {
+        match self {
+            OutputFormat::Text => format!("{} {}", message.topic, String::from_utf8_lossy(&message.payload)),
+            OutputFormat::Hex => format!("{} {}", message.topic, hex(&message.payload)),
+            OutputFormat::Json => {
+                let mut object: Value = json!({
+                    "topic": message.topic,
+                    "qos": message.qos,
+                    "retain": message.retain,
+                });
+
+                match std::str::from_utf8(&message.payload) {
+                    Ok(payload) => {
+                        object["payload"] = json!(payload);
+                    }
+                    Err(_) => {
+                        object["payload_hex"] = json!(hex(&message.payload));
+                    }
+                }
+
+                object.to_string()
+            }
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte: &u8| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,359 @@
+use std::path::PathBuf;
+
+use crate::common_fn;
+use crate::mqtt_client::{ ConnectOptions, Message };
+
+use super::format::OutputFormat;
+
+/// The connection options shared by `mqtt-pub` and `mqtt-sub`.
+const CONNECTION_USAGE: &str = "\
+Connection:
+  --host <host>            Broker host name or address (default localhost)
+  --port <port>            Broker port (default 1883, 8883 with --tls)
+  --tls                    Connect over TLS, verifying the broker against the bundled root certificates
+  --cafile <path>          PEM file of the certificates to verify the broker against, implies --tls
+  --id <client id>         Client identifier (default mqtt-pub-<pid> or mqtt-sub-<pid>)
+  --username <username>    Username
+  --password <password>    Password, needs --username
+  --keep-alive <seconds>   Keep alive interval, 0 disables it (default 60)
+  --will-topic <topic>     Topic of the will message
+  --will-payload <text>    Payload of the will message (default empty)
+  --will-qos <qos>         QoS of the will message (default 0)
+  --will-retain            Retain the will message
+";
+
+/// The usage text of `mqtt-pub`.
+pub fn pub_usage() -> String {
+    format!(
+        "\
+Usage: mqtt_broker mqtt-pub --topic <topic> (--message <text> | --stdin | --file <path>) [options]
+
+  --topic <topic>          Topic to publish to
+  --message <text>         Publish a single message
+  --stdin                  Publish each line read from stdin as a message, empty lines are skipped
+  --file <path>            Publish each line of a file as a message, empty lines are skipped
+  --qos <qos>              QoS of the messages (default 0)
+  --retain                 Retain the messages
+
+{}",
+        CONNECTION_USAGE
+    )
+}
+
+/// The usage text of `mqtt-sub`.
+pub fn sub_usage() -> String {
+    format!(
+        "\
+Usage: mqtt_broker mqtt-sub --topic <filter> [--topic <filter> ...] [options]
+
+  --topic <filter>         Topic filter to subscribe to, can be repeated
+  --qos <qos>              Requested QoS of the subscriptions (default 0)
+  --format <format>        text, hex or json (default text)
+  --count <n>              Exit after receiving n messages
+
+{}",
+        CONNECTION_USAGE
+    )
+}
+
+/// Where and how to connect.
+#[derive(Debug, Clone)]
+pub struct ConnectionOptions {
+    pub host: String,
+    pub port: u16,
+    /// Connect over TLS when set.
+    pub tls: Option<TlsOptions>,
+    /// The options of the CONNECT packet, reconnecting is never enabled.
+    pub connect: ConnectOptions,
+}
+
+/// How the broker's certificate is verified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    /// A PEM file of trusted certificates, the bundled root certificates are used when None.
+    pub ca_file: Option<PathBuf>,
+}
+
+/// Where `mqtt-pub` reads its payloads from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Message(Vec<u8>),
+    Stdin,
+    File(PathBuf),
+}
+
+/// The options of `mqtt-pub`.
+#[derive(Debug, Clone)]
+pub struct PubOptions {
+    pub connection: ConnectionOptions,
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+    pub input: Input,
+}
+
+/// The options of `mqtt-sub`.
+#[derive(Debug, Clone)]
+pub struct SubOptions {
+    pub connection: ConnectionOptions,
+    pub topic_filters: Vec<String>,
+    pub qos: u8,
+    pub format: OutputFormat,
+    /// Exit after this many messages, run until the connection is lost when None.
+    pub count: Option<usize>,
+}
+
+impl PubOptions {
+    /// Parses the arguments of `mqtt-pub`, without the program and subcommand names.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the options, or an error message naming the invalid argument.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let args: Vec<String> = ["--topic", "lights/kitchen", "--message", "on", "--qos", "1"]
+    ///     .iter()
+    ///     .map(|arg: &&str| arg.to_string())
+    ///     .collect();
+    ///
+    /// let options: PubOptions = PubOptions::parse(&args).unwrap();
+    /// assert_eq!(options.input, Input::Message(b"on".to_vec()));
+    /// ```
+    pub fn parse(args: &[String]) -> Result<PubOptions, String> {
+        let mut connection: ConnectionArguments = ConnectionArguments::new("mqtt-pub");
+        let mut topic: Option<String> = None;
+        let mut qos: u8 = 0;
+        let mut retain: bool = false;
+        let mut inputs: Vec<Input> = Vec::new();
+        let mut arguments: Arguments = Arguments { args, index: 0 };
+
+        while let Some(name) = arguments.next_name() {
+            match name {
+                "--topic" => {
+                    topic = Some(arguments.value(name)?.to_string());
+                }
+                "--qos" => {
+                    qos = parse_qos(name, arguments.value(name)?)?;
+                }
+                "--retain" => {
+                    retain = true;
+                }
+                "--message" => {
+                    inputs.push(Input::Message(arguments.value(name)?.as_bytes().to_vec()));
+                }
+                "--stdin" => {
+                    inputs.push(Input::Stdin);
+                }
+                "--file" => {
+                    inputs.push(Input::File(PathBuf::from(arguments.value(name)?)));
+                }
+                _ => {
+                    connection.parse(name, &mut arguments)?;
+                }
+            }
+        }
+
+        let topic: String = topic.ok_or("--topic is required")?;
+
+        // A PUBLISH topic name must not contain wildcards
+        if topic.is_empty() || topic.contains(['+', '#']) {
+            return Err(format!("Invalid --topic {}, a topic name is not empty and has no wildcards", topic));
+        }
+
+        if inputs.len() != 1 {
+            return Err("Pass exactly one of --message, --stdin or --file".to_string());
+        }
+
+        Ok(PubOptions { connection: connection.finish()?, topic, qos, retain, input: inputs.remove(0) })
+    }
+}
+
+impl SubOptions {
+    /// Parses the arguments of `mqtt-sub`, without the program and subcommand names.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the options, or an error message naming the invalid argument.
+    pub fn parse(args: &[String]) -> Result<SubOptions, String> {
+        let mut connection: ConnectionArguments = ConnectionArguments::new("mqtt-sub");
+        let mut topic_filters: Vec<String> = Vec::new();
+        let mut qos: u8 = 0;
+        let mut format: OutputFormat = OutputFormat::Text;
+        let mut count: Option<usize> = None;
+        let mut arguments: Arguments = Arguments { args, index: 0 };
+
+        while let Some(name) = arguments.next_name() {
+            match name {
+                "--topic" => {
+                    let topic_filter: &str = arguments.value(name)?;
+
+                    if !common_fn::topic_filter::is_valid(topic_filter) {
+                        return Err(format!("Invalid --topic {}", topic_filter));
+                    }
+
+                    topic_filters.push(topic_filter.to_string());
+                }
+                "--qos" => {
+                    qos = parse_qos(name, arguments.value(name)?)?;
+                }
+                "--format" => {
+                    format = match arguments.value(name)? {
+                        "text" => OutputFormat::Text,
+                        "hex" => OutputFormat::Hex,
+                        "json" => OutputFormat::Json,
+                        value => {
+                            return Err(format!("Invalid --format {}, expected text, hex or json", value));
+                        }
+                    };
+                }
+                "--count" => {
+                    count = Some(parse_value(name, arguments.value(name)?)?);
+                }
+                _ => {
+                    connection.parse(name, &mut arguments)?;
+                }
+            }
+        }
+
+        if topic_filters.is_empty() {
+            return Err("--topic is required".to_string());
+        }
+
+        Ok(SubOptions { connection: connection.finish()?, topic_filters, qos, format, count })
+    }
+}
+
+/// Walks the arguments, as option names followed by their values.
+struct Arguments<'a> {
+    args: &'a [String],
+    index: usize,
+}
+
+impl<'a> Arguments<'a> {
+    fn next_name(&mut self) -> Option<&'a str> {
+        let name: &'a str = self.args.get(self.index)?;
+        self.index += 1;
+
+        Some(name)
+    }
+
+    fn value(&mut self, name: &str) -> Result<&'a str, String> {
+        let value: &'a str = self.args.get(self.index).ok_or_else(|| format!("Missing value after {}", name))?;
+        self.index += 1;
+
+        Ok(value)
+    }
+}
+
+/// The connection options as they are parsed, checked once every argument is read.
+struct ConnectionArguments {
+    command: &'static str,
+    host: String,
+    port: Option<u16>,
+    tls: Option<TlsOptions>,
+    client_id: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    keep_alive: u16,
+    will_topic: Option<String>,
+    will_payload: Option<Vec<u8>>,
+    will_qos: Option<u8>,
+    will_retain: bool,
+}
+
+impl ConnectionArguments {
+    fn new(command: &'static str) -> ConnectionArguments {
+        ConnectionArguments {
+            command,
+            host: "localhost".to_string(),
+            port: None,
+            tls: None,
+            client_id: None,
+            username: None,
+            password: None,
+            keep_alive: 60,
+            will_topic: None,
+            will_payload: None,
+            will_qos: None,
+            will_retain: false,
+        }
+    }
+
+    /// Parses a connection option, an unknown option is an error.
+    fn parse(&mut self, name: &str, arguments: &mut Arguments) -> Result<(), String> {
+        match name {
+            "--host" => self.host = arguments.value(name)?.to_string(),
+            "--port" => self.port = Some(parse_value(name, arguments.value(name)?)?),
+            "--tls" => self.tls = Some(self.tls.take().unwrap_or_default()),
+            "--cafile" => self.tls = Some(TlsOptions { ca_file: Some(PathBuf::from(arguments.value(name)?)) }),
+            "--id" => self.client_id = Some(arguments.value(name)?.to_string()),
+            "--username" => self.username = Some(arguments.value(name)?.to_string()),
+            "--password" => self.password = Some(arguments.value(name)?.to_string()),
+            "--keep-alive" => self.keep_alive = parse_value(name, arguments.value(name)?)?,
+            "--will-topic" => self.will_topic = Some(arguments.value(name)?.to_string()),
+            "--will-payload" => self.will_payload = Some(arguments.value(name)?.as_bytes().to_vec()),
+            "--will-qos" => self.will_qos = Some(parse_qos(name, arguments.value(name)?)?),
+            "--will-retain" => self.will_retain = true,
+            _ => {
+                return Err(format!("Unknown option {}", name));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<ConnectionOptions, String> {
+        // A password without a username is not allowed by MQTT 3.1.1 [MQTT-3.1.2-22]
+        if self.password.is_some() && self.username.is_none() {
+            return Err("--password needs --username".to_string());
+        }
+
+        let will: Option<Message> = match self.will_topic {
+            Some(topic) => {
+                if topic.is_empty() || topic.contains(['+', '#']) {
+                    return Err(format!("Invalid --will-topic {}, a topic name is not empty and has no wildcards", topic));
+                }
+
+                Some(Message {
+                    topic,
+                    payload: self.will_payload.unwrap_or_default(),
+                    qos: self.will_qos.unwrap_or(0),
+                    retain: self.will_retain,
+                })
+            }
+            None => {
+                if self.will_payload.is_some() || self.will_qos.is_some() || self.will_retain {
+                    return Err("--will-payload, --will-qos and --will-retain need --will-topic".to_string());
+                }
+
+                None
+            }
+        };
+
+        let mut connect: ConnectOptions = ConnectOptions::new(
+            &self.client_id.unwrap_or_else(|| format!("{}-{}", self.command, std::process::id()))
+        );
+        connect.keep_alive = self.keep_alive;
+        connect.username = self.username;
+        connect.password = self.password;
+        connect.will = will;
+
+        // The default port depends on TLS
+        let port: u16 = self.port.unwrap_or(if self.tls.is_some() { 8883 } else { 1883 });
+
+        Ok(ConnectionOptions { host: self.host, port, tls: self.tls, connect })
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("Invalid {} {}", name, value))
+}
+
+fn parse_qos(name: &str, value: &str) -> Result<u8, String> {
+    match value {
+        "0" | "1" | "2" => Ok(value.parse::<u8>().unwrap()),
+        _ => Err(format!("Invalid {} {}, the QoS is 0, 1 or 2", name, value)),
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{ BufReader, ErrorKind, Read, Write };
+use std::net::{ TcpStream, ToSocketAddrs };
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+use rustls::pki_types::{ pem::PemObject, CertificateDer, ServerName };
+use rustls::{ ClientConfig, ClientConnection, RootCertStore, StreamOwned };
+
+use crate::mqtt_client::codec::{ connect_packet, message_from, publish_packet, read_packet };
+use crate::mqtt_client::Message;
+use crate::packet::{ Packet, Puback, Pubcomp, Pubrec, Pubrel, Subscribe };
+
+use super::options::{ ConnectionOptions, TlsOptions };
+
+/// How long to wait for the connection, the CONNACK and the acknowledgements of the broker.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The connection to the broker, plain or over TLS.
+enum Transport {
+    Tcp(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Transport {
+    /// The underlying socket, to set timeouts on.
+    fn socket(&self) -> &TcpStream {
+        match self {
+            Transport::Tcp(stream) => stream,
+            Transport::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Tcp(stream) => stream.read(buf),
+            Transport::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Tcp(stream) => stream.write(buf),
+            Transport::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Transport::Tcp(stream) => stream.flush(),
+            Transport::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A single-threaded MQTT 3.1.1 session, for the command-line tools.
+///
+/// Unlike [`crate::mqtt_client::MqttClient`] it needs no second handle on the socket,
+/// so it works over TLS. Keep alive pings are sent while waiting for packets, and by
+/// [`Session::ping`] for a caller that is busy elsewhere.
+pub struct Session {
+    transport: Transport,
+    next_packet_id: u16,
+    /// Half the keep alive interval, None if the keep alive is disabled.
+    ping_interval: Option<Duration>,
+    /// Messages received while waiting for an acknowledgement.
+    received: VecDeque<Message>,
+}
+
+impl Session {
+    /// Connects to the broker and waits for the CONNACK.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The host, port, TLS and CONNECT options.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the session, or an error message if the connection or the TLS
+    /// handshake failed, or the broker refused the connection.
+    pub fn open(options: &ConnectionOptions) -> Result<Session, String> {
+        let address: String = format!("{}:{}", options.host, options.port);
+
+        let socket_address = address
+            .to_socket_addrs()
+            .map_err(|err| format!("Could not resolve {}: {}", address, err))?
+            .next()
+            .ok_or_else(|| format!("Could not resolve {}", address))?;
+
+        let socket: TcpStream = TcpStream::connect_timeout(&socket_address, RESPONSE_TIMEOUT).map_err(|err|
+            format!("Could not connect to {}: {}", address, err)
+        )?;
+
+        _ = socket.set_nodelay(true);
+
+        let transport: Transport = match &options.tls {
+            Some(tls) => Transport::Tls(Box::new(tls_stream(socket, &options.host, tls)?)),
+            None => Transport::Tcp(socket),
+        };
+
+        let keep_alive: u16 = options.connect.keep_alive;
+
+        let mut session: Session = Session {
+            transport,
+            next_packet_id: 1,
+            ping_interval: if keep_alive > 0 { Some(Duration::from_secs(keep_alive as u64) / 2) } else { None },
+            received: VecDeque::new(),
+        };
+
+        // The TLS handshake happens on the first write
+        let connect: Vec<u8> = connect_packet(&options.connect).to_vec()?;
+
+        session.transport
+            .write_all(&connect)
+            .and_then(|_| session.transport.flush())
+            .map_err(|err| format!("Could not connect to {}: {}", address, err))?;
+
+        _ = session.transport.socket().set_read_timeout(Some(RESPONSE_TIMEOUT));
+
+        let packet: Packet = read_packet(&mut session.transport).map_err(|err|
+            format!("No CONNACK from {}: {}", address, err)
+        )?;
+
+        let Packet::Connack(connack) = packet else {
+            return Err(format!("Expected a CONNACK from {}", address));
+        };
+
+        if connack.return_code != 0 {
+            return Err(format!("Connection refused by {}, return code {}", address, connack.return_code));
+        }
+
+        Ok(session)
+    }
+
+    /// The interval at which a caller that is not reading should call [`Session::ping`],
+    /// None if the keep alive is disabled.
+    pub fn ping_interval(&self) -> Option<Duration> {
+        self.ping_interval
+    }
+
+    /// Sends a PINGREQ, the PINGRESP is skipped by the next read.
+    pub fn ping(&mut self) -> Result<(), String> {
+        self.send(&Packet::Pingreq)
+    }
+
+    /// Publishes a message, and waits for the PUBACK of QoS 1 or the PUBCOMP of QoS 2.
+    pub fn publish(&mut self, message: &Message) -> Result<(), String> {
+        if message.qos == 0 {
+            return self.send(&publish_packet(message, 0));
+        }
+
+        let packet_id: u16 = self.next_packet_id();
+        self.send(&publish_packet(message, packet_id))?;
+
+        if message.qos == 1 {
+            return self.wait_for(|packet: &Packet| matches!(packet, Packet::Puback(puback) if puback.packet_id == packet_id)).map(|_| ());
+        }
+
+        // QoS 2: PUBREC -> PUBREL -> PUBCOMP
+        self.wait_for(|packet: &Packet| matches!(packet, Packet::Pubrec(pubrec) if pubrec.packet_id == packet_id))?;
+        self.send(&Packet::Pubrel(Pubrel { packet_id }))?;
+        self.wait_for(|packet: &Packet| matches!(packet, Packet::Pubcomp(pubcomp) if pubcomp.packet_id == packet_id)).map(|_| ())
+    }
+
+    /// Subscribes to topic filters, and waits for the SUBACK.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the return codes of the SUBACK, one per topic filter,
+    /// or an error message if no SUBACK arrived.
+    pub fn subscribe(&mut self, topic_filters: &[(String, u8)]) -> Result<Vec<u8>, String> {
+        let packet_id: u16 = self.next_packet_id();
+
+        self.send(&Packet::Subscribe(Subscribe { packet_id, topic_filters: topic_filters.to_vec() }))?;
+
+        match self.wait_for(|packet: &Packet| matches!(packet, Packet::Suback(suback) if suback.packet_id == packet_id))? {
+            Packet::Suback(suback) => Ok(suback.return_codes),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Waits for the next message, sending PINGREQ packets while the connection is idle.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the message, or an error message if the connection was lost.
+    pub fn next_message(&mut self) -> Result<Message, String> {
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Ok(message);
+            }
+
+            let packet: Packet = self.receive(None)?;
+            self.handle(packet)?;
+        }
+    }
+
+    /// Sends a DISCONNECT, so the broker discards the will message, and closes the connection.
+    pub fn disconnect(mut self) {
+        _ = self.send(&Packet::Disconnect);
+
+        if let Transport::Tls(stream) = &mut self.transport {
+            stream.conn.send_close_notify();
+            _ = stream.flush();
+        }
+
+        _ = self.transport.socket().shutdown(std::net::Shutdown::Both);
+    }
+
+    fn next_packet_id(&mut self) -> u16 {
+        let packet_id: u16 = self.next_packet_id;
+
+        // Packet identifiers are non-zero
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+
+        packet_id
+    }
+
+    fn send(&mut self, packet: &Packet) -> Result<(), String> {
+        let bytes: Vec<u8> = packet.to_vec()?;
+
+        self.transport
+            .write_all(&bytes)
+            .and_then(|_| self.transport.flush())
+            .map_err(|err| format!("Could not send packet: {}", err))
+    }
+
+    /// Reads packets until one matches, handling the packets in between.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the matching packet, or an error message if it did not arrive
+    /// within the response timeout or the connection was lost.
+    fn wait_for(&mut self, expected: impl Fn(&Packet) -> bool) -> Result<Packet, String> {
+        let deadline: Instant = Instant::now() + RESPONSE_TIMEOUT;
+
+        loop {
+            let packet: Packet = self.receive(Some(deadline))?;
+
+            if expected(&packet) {
+                return Ok(packet);
+            }
+
+            self.handle(packet)?;
+        }
+    }
+
+    /// Answers the QoS flow of an incoming message and keeps the message, other packets are skipped.
+    fn handle(&mut self, packet: Packet) -> Result<(), String> {
+        match packet {
+            Packet::Publish(publish) => {
+                // PUBACK for QoS 1, PUBREC for QoS 2
+                match (publish.qos, publish.packet_id) {
+                    (1, Some(packet_id)) => self.send(&Packet::Puback(Puback { packet_id }))?,
+                    (2, Some(packet_id)) => self.send(&Packet::Pubrec(Pubrec { packet_id }))?,
+                    _ => {}
+                }
+
+                self.received.push_back(message_from(publish));
+            }
+            Packet::Pubrel(pubrel) => {
+                // PUBREL -> PUBCOMP
+                self.send(&Packet::Pubcomp(Pubcomp { packet_id: pubrel.packet_id }))?;
+            }
+            // PINGRESP and late acknowledgements need no answer
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Reads a single packet, sending a PINGREQ every ping interval while nothing arrives.
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline` - When to give up waiting for the packet, wait as long as the connection lasts when None.
+    fn receive(&mut self, deadline: Option<Instant>) -> Result<Packet, String> {
+        let mut first_byte: [u8; 1] = [0];
+
+        // Only the first byte is read with the ping interval as timeout, a timeout in the middle
+        // of a packet would lose the bytes read so far
+        loop {
+            let timeout: Option<Duration> = match (self.ping_interval, deadline) {
+                (ping_interval, Some(deadline)) => {
+                    let left: Duration = deadline.saturating_duration_since(Instant::now());
+
+                    if left.is_zero() {
+                        return Err("The broker did not answer in time".to_string());
+                    }
+
+                    Some(ping_interval.map_or(left, |ping_interval: Duration| ping_interval.min(left)))
+                }
+                (ping_interval, None) => ping_interval,
+            };
+
+            _ = self.transport.socket().set_read_timeout(timeout);
+
+            match self.transport.read(&mut first_byte) {
+                Ok(0) => {
+                    return Err("Connection closed by the broker".to_string());
+                }
+                Ok(_) => {
+                    break;
+                }
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self.ping_interval.is_some() {
+                        self.ping()?;
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => {
+                    return Err(format!("Connection lost: {}", err));
+                }
+            }
+        }
+
+        _ = self.transport.socket().set_read_timeout(Some(RESPONSE_TIMEOUT));
+
+        read_packet(&mut (&first_byte[..]).chain(&mut self.transport)).map_err(|err| format!("Connection lost: {}", err))
+    }
+}
+
+/// Wraps a connected socket in a TLS client stream, verifying the broker's certificate.
+///
+/// # Arguments
+///
+/// * `socket` - The connected socket.
+/// * `host` - The host name or address the certificate must be valid for.
+/// * `tls` - The certificates to trust, see [`TlsOptions`].
+pub(crate) fn tls_stream(socket: TcpStream, host: &str, tls: &TlsOptions) -> Result<StreamOwned<ClientConnection, TcpStream>, String> {
+    let mut roots: RootCertStore = RootCertStore::empty();
+
+    match &tls.ca_file {
+        Some(path) => {
+            let reader: BufReader<File> = BufReader::new(
+                File::open(path).map_err(|err| format!("Could not open {}: {}", path.display(), err))?
+            );
+
+            for certificate in CertificateDer::pem_reader_iter(reader) {
+                let certificate: CertificateDer<'static> = certificate.map_err(|err|
+                    format!("Invalid certificate in {}: {}", path.display(), err)
+                )?;
+
+                roots.add(certificate).map_err(|err| format!("Invalid certificate in {}: {}", path.display(), err))?;
+            }
+
+            if roots.is_empty() {
+                return Err(format!("No certificates in {}", path.display()));
+            }
+        }
+        None => {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+    }
+
+    let config: ClientConfig = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| err.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let server_name: ServerName<'static> = ServerName::try_from(host.to_string()).map_err(|err|
+        format!("Invalid TLS server name {}: {}", host, err)
+    )?;
+
+    let connection: ClientConnection = ClientConnection::new(Arc::new(config), server_name).map_err(|err| err.to_string())?;
+
+    Ok(StreamOwned::new(connection, socket))
+}
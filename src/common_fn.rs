@@ -1,3 +1,4 @@
 pub mod bit_operations;
-pub mod msb_lsb_reader;
-pub mod msb_lsb_creater;
+pub mod http;
+pub mod sharded_map;
+pub mod topic_filter;
@@ -0,0 +1,398 @@
+use std::io::{ BufRead, BufReader, Read, Write };
+use std::net::TcpStream;
+
+/// A minimal HTTP/1.1 request, as read by [`read_request`].
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the value of a header, matching the name case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the decoded value of a query parameter, if present. A `+` in the query is a space.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query
+            .split('&')
+            .map(|pair: &str| pair.split_once('=').unwrap_or((pair, "")))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| percent_decode(&value.replace('+', " ")))
+    }
+}
+
+/// The largest request body that is read, larger ones are answered with 413.
+pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// The longest request line that is read, longer ones are answered with 414.
+pub const MAX_REQUEST_LINE_BYTES: usize = 8 * 1024;
+
+/// The longest header line that is read, longer ones are answered with 431.
+pub const MAX_HEADER_BYTES: usize = 8 * 1024;
+
+/// The most headers a request may have, requests with more are answered with 431.
+pub const MAX_HEADERS: usize = 100;
+
+/// Why a request could not be read, with the status code to answer it with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestError {
+    pub status: u16,
+    pub message: &'static str,
+}
+
+impl RequestError {
+    fn new(status: u16, message: &'static str) -> RequestError {
+        RequestError { status, message }
+    }
+}
+
+/// Reads a single HTTP request from a stream.
+///
+/// # Arguments
+///
+/// * `stream` - The stream to read the request from.
+///
+/// # Returns
+///
+/// A Result containing the parsed [`Request`], or a [`RequestError`] with the status code to answer
+/// if the request is malformed or too large.
+///
+/// # Description
+///
+/// Only what the broker's own HTTP endpoints need is supported: a request line, headers and
+/// an optional body sized by `Content-Length`. Chunked bodies and keep-alive are not supported,
+/// the connection is expected to be closed after the response has been written.
+///
+/// The request is read before the caller can check who sent it, so its size is bounded: the request
+/// line and every header line by [`MAX_REQUEST_LINE_BYTES`] and [`MAX_HEADER_BYTES`], the number of
+/// headers by [`MAX_HEADERS`] and the body by [`MAX_BODY_BYTES`]. A larger `Content-Length` is
+/// refused before anything is allocated for the body.
+///
+/// # Examples
+///
+/// ```
+/// let request: Request = match common_fn::http::read_request(&mut stream) {
+///     Ok(request) => request,
+///     Err(err) => {
+///         common_fn::http::write_response(&mut stream, err.status, "text/plain", err.message.as_bytes());
+///         return;
+///     }
+/// };
+///
+/// if request.method == "GET" && request.path == "/metrics" {
+///     common_fn::http::write_response(&mut stream, 200, "text/plain", body.as_bytes());
+/// }
+/// ```
+pub fn read_request(stream: &mut TcpStream) -> Result<Request, RequestError> {
+    let mut reader: BufReader<&mut TcpStream> = BufReader::new(stream);
+
+    // Read the request line, e.g. "GET /metrics HTTP/1.1"
+    let request_line: String = match read_line(&mut reader, MAX_REQUEST_LINE_BYTES) {
+        Ok(Some(line)) if !line.is_empty() => line,
+        Ok(None) => {
+            return Err(RequestError::new(414, "Request line too long"));
+        }
+        _ => {
+            return Err(RequestError::new(400, "Could not read request line"));
+        }
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let method: String = parts
+        .next()
+        .ok_or(RequestError::new(400, "Missing method"))?
+        .to_string();
+    let target: &str = parts.next().ok_or(RequestError::new(400, "Missing request target"))?;
+
+    let (path, query): (&str, &str) = target.split_once('?').unwrap_or((target, ""));
+
+    // Read headers until the empty line
+    let mut headers: Vec<(String, String)> = Vec::new();
+    loop {
+        let line: String = match read_line(&mut reader, MAX_HEADER_BYTES) {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                return Err(RequestError::new(431, "Header too long"));
+            }
+            Err(_) => {
+                return Err(RequestError::new(400, "Could not read header"));
+            }
+        };
+
+        let line: &str = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if headers.len() == MAX_HEADERS {
+            return Err(RequestError::new(431, "Too many headers"));
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut request: Request = Request {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+
+    // Read the body, if there is any
+    let content_length: usize = match request.header("Content-Length") {
+        Some(value) => value.parse().map_err(|_| RequestError::new(400, "Invalid Content-Length"))?,
+        None => 0,
+    };
+
+    if content_length > MAX_BODY_BYTES {
+        return Err(RequestError::new(413, "Request body too large"));
+    }
+
+    if content_length > 0 {
+        let mut body: Vec<u8> = vec![0; content_length];
+        if reader.read_exact(&mut body).is_err() {
+            return Err(RequestError::new(400, "Could not read body"));
+        }
+        request.body = body;
+    }
+
+    Ok(request)
+}
+
+/// Reads a line of at most `limit` bytes, including the line break.
+///
+/// # Returns
+///
+/// A Result containing the line, an empty one at the end of the stream, or None if the line is longer than `limit`.
+fn read_line<R: BufRead>(reader: &mut R, limit: usize) -> std::io::Result<Option<String>> {
+    let mut line: Vec<u8> = Vec::new();
+
+    // One byte more than the limit, to tell a line of exactly `limit` bytes from a longer one
+    reader.take((limit as u64) + 1).read_until(b'\n', &mut line)?;
+
+    if line.len() > limit {
+        return Ok(None);
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+/// Writes a complete HTTP response and closes the connection afterwards.
+///
+/// # Arguments
+///
+/// * `stream` - The stream to write the response to.
+/// * `status` - The HTTP status code.
+/// * `content_type` - The value of the `Content-Type` header.
+/// * `body` - The response body.
+pub fn write_response(stream: &mut TcpStream, status: u16, content_type: &str, body: &[u8]) {
+    let reason: &str = match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+
+    let header: String = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    );
+
+    _ = stream.write_all(header.as_bytes());
+    _ = stream.write_all(body);
+    _ = stream.flush();
+}
+
+/// The parts of an `http://` or `https://` URL, as parsed by [`parse_url`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub https: bool,
+    /// The host name or address, without the brackets of an IPv6 address.
+    pub host: String,
+    pub port: u16,
+    /// The path and query, `/` when the URL has none.
+    pub path: String,
+}
+
+/// Parses an `http://` or `https://` URL.
+///
+/// # Returns
+///
+/// A Result containing the [`Url`], or an error message if the scheme is missing or not supported,
+/// or the host or port is invalid. The port defaults to 80 for `http` and 443 for `https`.
+///
+/// # Examples
+///
+/// ```
+/// let url: Url = common_fn::http::parse_url("http://10.0.0.7:8080/mqtt-events").unwrap();
+///
+/// assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("10.0.0.7", 8080, "/mqtt-events"));
+/// ```
+pub fn parse_url(url: &str) -> Result<Url, &'static str> {
+    let (https, rest): (bool, &str) = if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else {
+        return Err("The URL must start with http:// or https://");
+    };
+
+    let (authority, path): (&str, &str) = match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    };
+
+    // An IPv6 address is in brackets, so its colons are not taken for the port
+    let (host, port): (&str, Option<&str>) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']').ok_or("The URL has an unclosed IPv6 address")?;
+            (host, after.strip_prefix(':'))
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+
+    if host.is_empty() {
+        return Err("The URL has no host");
+    }
+
+    let port: u16 = match port {
+        Some(port) => port.parse().map_err(|_| "The URL has an invalid port")?,
+        None if https => 443,
+        None => 80,
+    };
+
+    Ok(Url {
+        https,
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    })
+}
+
+/// Sends a POST request on a connected stream, and reads the status code of the response.
+///
+/// # Arguments
+///
+/// * `stream` - The connection to the server, a TCP or TLS stream.
+/// * `url` - The URL posted to, for the request target and the `Host` header.
+/// * `headers` - Extra headers, e.g. `Authorization`.
+/// * `content_type` - The value of the `Content-Type` header.
+/// * `body` - The request body.
+///
+/// # Returns
+///
+/// A Result containing the status code of the response, or an error message if writing the
+/// request or reading the status line failed.
+///
+/// # Description
+///
+/// The request asks the server to close the connection, so a stream is used for a single request.
+/// Only the status line of the response is read, the rest of it is not needed.
+pub fn post<S: Read + Write>(
+    stream: &mut S,
+    url: &Url,
+    headers: &[(&str, &str)],
+    content_type: &str,
+    body: &[u8]
+) -> Result<u16, String> {
+    let mut request: String = format!(
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        url.path,
+        url.host,
+        url.port,
+        content_type,
+        body.len()
+    );
+
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+
+    request.push_str("\r\n");
+
+    stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .and_then(|_| stream.flush())
+        .map_err(|err| format!("Could not send the request: {}", err))?;
+
+    // The status line, e.g. "HTTP/1.1 204 No Content"
+    let mut status_line: String = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status_line)
+        .map_err(|err| format!("Could not read the response: {}", err))?;
+
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status: &str| status.parse().ok())
+        .ok_or_else(|| format!("Invalid response status line: {:?}", status_line.trim_end()))
+}
+
+/// Decodes `%XX` escapes in a path segment or query value.
+///
+/// # Description
+///
+/// A `+` is kept as it is, it only stands for a space in a query, see [`Request::query_param`].
+/// Client ids and topics can contain it, e.g. `sensors/+` in a path.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(common_fn::http::percent_decode("home%2Fkitchen"), "home/kitchen");
+/// assert_eq!(common_fn::http::percent_decode("device+1"), "device+1");
+/// ```
+pub fn percent_decode(value: &str) -> String {
+    let bytes: &[u8] = value.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut index: usize = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            // Only two hex digits are an escape, `from_str_radix` alone would also take a sign as in `%+1`
+            b'%' if index + 2 < bytes.len() && bytes[index + 1].is_ascii_hexdigit() && bytes[index + 2].is_ascii_hexdigit() => {
+                let hex: &str = std::str::from_utf8(&bytes[index + 1..index + 3]).unwrap_or("");
+
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        index += 3;
+                        continue;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+
+        index += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
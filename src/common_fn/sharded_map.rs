@@ -0,0 +1,161 @@
+use std::borrow::Borrow;
+use std::collections::hash_map::{ Entry, HashMap, RandomState };
+use std::hash::{ BuildHasher, Hash };
+use std::sync::RwLock;
+
+/// The number of shards of a map created with [`ShardedMap::new`].
+const DEFAULT_SHARDS: usize = 16;
+
+/// A hash map split into shards, each behind its own lock.
+///
+/// # Description
+///
+/// Connection threads that work on different keys mostly lock different shards, so they do not
+/// wait on each other. Every method locks a single shard for as long as it runs, the methods that
+/// visit every entry lock one shard at a time.
+///
+/// The closures given to the methods run while their shard is locked, so they must not block:
+/// no I/O, no sleeps and no locking of another map.
+///
+/// # Examples
+///
+/// ```
+/// let clients: ShardedMap<String, u64> = ShardedMap::new();
+/// clients.insert("sensor/1".to_string(), 60);
+///
+/// assert_eq!(clients.with("sensor/1", |keep_alive: &u64| *keep_alive), Some(60));
+/// ```
+#[derive(Debug)]
+pub struct ShardedMap<K, V> {
+    shards: Box<[RwLock<HashMap<K, V>>]>,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    pub fn new() -> ShardedMap<K, V> {
+        ShardedMap::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Creates a map with a given number of shards, at least one.
+    pub fn with_shards(shards: usize) -> ShardedMap<K, V> {
+        ShardedMap {
+            shards: (0..shards.max(1)).map(|_| RwLock::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// The shard a key belongs to. Keys that are equal hash the same through `Borrow`,
+    /// so a `String` key and its `&str` find the same shard.
+    fn shard<Q>(&self, key: &Q) -> &RwLock<HashMap<K, V>> where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        let index: usize = (self.hasher.hash_one(key) as usize) % self.shards.len();
+
+        &self.shards[index]
+    }
+
+    /// Inserts a value, returning the value it replaced.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.shard(&key).write().unwrap().insert(key, value)
+    }
+
+    /// Removes a value, returning it.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V> where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.shard(key).write().unwrap().remove(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool where K: Borrow<Q>, Q: Hash + Eq + ?Sized {
+        self.shard(key).read().unwrap().contains_key(key)
+    }
+
+    /// Returns a copy of a value, so the shard is unlocked before the caller uses it.
+    pub fn get<Q>(&self, key: &Q) -> Option<V> where K: Borrow<Q>, Q: Hash + Eq + ?Sized, V: Clone {
+        self.shard(key).read().unwrap().get(key).cloned()
+    }
+
+    /// Runs a closure on a value, under a read lock of its shard.
+    ///
+    /// # Returns
+    ///
+    /// The result of the closure, or None if the key is not in the map.
+    pub fn with<Q, R>(&self, key: &Q, f: impl FnOnce(&V) -> R) -> Option<R>
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized
+    {
+        self.shard(key).read().unwrap().get(key).map(f)
+    }
+
+    /// Runs a closure on a value, under a write lock of its shard.
+    ///
+    /// # Returns
+    ///
+    /// The result of the closure, or None if the key is not in the map.
+    pub fn with_mut<Q, R>(&self, key: &Q, f: impl FnOnce(&mut V) -> R) -> Option<R>
+        where K: Borrow<Q>, Q: Hash + Eq + ?Sized
+    {
+        self.shard(key).write().unwrap().get_mut(key).map(f)
+    }
+
+    /// Runs a closure on the entry of a key, under a write lock of its shard.
+    ///
+    /// # Description
+    ///
+    /// Checking a key and inserting or updating it happens in a single step,
+    /// so two threads can not both find a key missing and both insert it.
+    pub fn entry<R>(&self, key: K, f: impl FnOnce(Entry<'_, K, V>) -> R) -> R {
+        let mut shard = self.shard(&key).write().unwrap();
+
+        f(shard.entry(key))
+    }
+
+    /// Visits every entry, locking one shard at a time with a read lock.
+    ///
+    /// Entries inserted or removed in a shard that is not locked at the moment may or may not be visited.
+    pub fn for_each(&self, mut f: impl FnMut(&K, &V)) {
+        for shard in self.shards.iter() {
+            for (key, value) in shard.read().unwrap().iter() {
+                f(key, value);
+            }
+        }
+    }
+
+    /// Visits every entry mutably, locking one shard at a time with a write lock.
+    pub fn for_each_mut(&self, mut f: impl FnMut(&K, &mut V)) {
+        for shard in self.shards.iter() {
+            for (key, value) in shard.write().unwrap().iter_mut() {
+                f(key, value);
+            }
+        }
+    }
+
+    /// Keeps the entries the closure returns true for, locking one shard at a time.
+    pub fn retain(&self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for shard in self.shards.iter() {
+            shard.write().unwrap().retain(|key: &K, value: &mut V| f(key, value));
+        }
+    }
+
+    /// Counts the entries that the closure returns true for.
+    pub fn count(&self, mut f: impl FnMut(&K, &V) -> bool) -> usize {
+        let mut count: usize = 0;
+
+        self.for_each(|key: &K, value: &V| {
+            if f(key, value) {
+                count += 1;
+            }
+        });
+
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard: &RwLock<HashMap<K, V>>| shard.read().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard: &RwLock<HashMap<K, V>>| shard.read().unwrap().is_empty())
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ShardedMap<K, V> {
+    fn default() -> ShardedMap<K, V> {
+        ShardedMap::new()
+    }
+}
@@ -0,0 +1,187 @@
+/// Checks if a topic name matches a topic filter.
+///
+/// # Arguments
+///
+/// * `filter` - The topic filter, which may contain `+` and `#` wildcards.
+/// * `topic` - The topic name to match, without wildcards.
+///
+/// # Returns
+///
+/// True if the topic name matches the filter, otherwise false.
+///
+/// # Description
+///
+/// The filter and the topic are compared level by level, split on `/`.
+/// A `+` matches exactly one level, and a `#` matches the parent level and every level below it,
+/// so `sensors/#` matches both `sensors` and `sensors/kitchen/temperature`.
+///
+/// Topics starting with `$` are not matched by filters starting with a wildcard (MQTT-4.7.2-1).
+///
+/// # Examples
+///
+/// ```
+/// assert!(common_fn::topic_filter::matches("sensors/+/temperature", "sensors/kitchen/temperature"));
+/// assert!(!common_fn::topic_filter::matches("sensors/+", "sensors/kitchen/temperature"));
+/// ```
+pub fn matches(filter: &str, topic: &str) -> bool {
+    if topic.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut filter_levels = filter.split('/');
+    let mut topic_levels = topic.split('/');
+
+    loop {
+        match (filter_levels.next(), topic_levels.next()) {
+            // A multi level wildcard matches everything that is left, including the parent level
+            (Some("#"), _) => {
+                return true;
+            }
+            (Some("+"), Some(_)) => {}
+            (Some(filter_level), Some(topic_level)) => {
+                if filter_level != topic_level {
+                    return false;
+                }
+            }
+            (None, None) => {
+                return true;
+            }
+            _ => {
+                return false;
+            }
+        }
+    }
+}
+
+/// Matches a topic name against a topic filter, and returns the levels its wildcards matched.
+///
+/// # Arguments
+///
+/// * `filter` - The topic filter, which may contain `+` and `#` wildcards.
+/// * `topic` - The topic name to match, without wildcards.
+///
+/// # Returns
+///
+/// None if the topic name does not match the filter, see [`matches`]. Otherwise the level matched
+/// by each wildcard, in order: a `+` matches a single level, a `#` the levels below its parent
+/// joined with `/`, which is empty when the topic is the parent level.
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(
+///     common_fn::topic_filter::captures("raw/+/#", "raw/kitchen/temp/celsius"),
+///     Some(vec!["kitchen".to_string(), "temp/celsius".to_string()])
+/// );
+/// ```
+pub fn captures(filter: &str, topic: &str) -> Option<Vec<String>> {
+    if !matches(filter, topic) {
+        return None;
+    }
+
+    let topic_levels: Vec<&str> = topic.split('/').collect();
+    let mut captured: Vec<String> = Vec::new();
+
+    for (index, level) in filter.split('/').enumerate() {
+        match level {
+            "+" => captured.push(topic_levels[index].to_string()),
+            "#" => captured.push(topic_levels.get(index..).map_or(String::new(), |rest: &[&str]| rest.join("/"))),
+            _ => {}
+        }
+    }
+
+    Some(captured)
+}
+
+/// Checks if a topic filter is well formed.
+///
+/// # Arguments
+///
+/// * `filter` - The topic filter to check.
+///
+/// # Returns
+///
+/// True if the filter is not empty, a `+` takes up a whole level (MQTT-4.7.1-3) and a `#`
+/// takes up the last level (MQTT-4.7.1-2), otherwise false.
+///
+/// # Examples
+///
+/// ```
+/// assert!(common_fn::topic_filter::is_valid("sensors/+/temperature"));
+/// assert!(!common_fn::topic_filter::is_valid("sensors/#/temperature"));
+/// ```
+pub fn is_valid(filter: &str) -> bool {
+    if filter.is_empty() {
+        return false;
+    }
+
+    let levels: Vec<&str> = filter.split('/').collect();
+
+    levels.iter().enumerate().all(|(index, level)| {
+        match *level {
+            "+" => true,
+            "#" => index == levels.len() - 1,
+            _ => !level.contains(['+', '#']),
+        }
+    })
+}
+
+/// Checks if a topic filter covers another one, i.e. every topic name the other filter can match is also matched by it.
+///
+/// # Arguments
+///
+/// * `filter` - The covering topic filter, e.g. the topic of an ACL rule.
+/// * `other` - The topic filter to check, e.g. the filter of a SUBSCRIBE request.
+///
+/// # Returns
+///
+/// True if every topic name matched by `other` is matched by `filter`, otherwise false.
+///
+/// # Description
+///
+/// Unlike [`matches`], the wildcards of `other` are not taken as text: a `+` in `other` is only covered by
+/// a `+` or `#` in `filter`, and a `#` in `other` only by a `#`. Both filters are expected to be valid.
+///
+/// # Examples
+///
+/// ```
+/// assert!(common_fn::topic_filter::covers("sensors/#", "sensors/+"));
+/// assert!(!common_fn::topic_filter::covers("sensors/+", "sensors/#"));
+/// assert!(!common_fn::topic_filter::covers("sensors/kitchen", "sensors/+"));
+/// ```
+pub fn covers(filter: &str, other: &str) -> bool {
+    // A filter starting with a wildcard matches no topic starting with `$`, while a filter starting with `$` only does
+    if other.starts_with('$') && (filter.starts_with('+') || filter.starts_with('#')) {
+        return false;
+    }
+
+    let mut filter_levels = filter.split('/');
+    let mut other_levels = other.split('/');
+
+    loop {
+        match (filter_levels.next(), other_levels.next()) {
+            // A multi level wildcard covers everything that is left, including the parent level where `other` ends
+            (Some("#"), _) => {
+                return true;
+            }
+            // A single level wildcard covers any single level, but not the many levels of a `#`
+            (Some("+"), Some(other_level)) => {
+                if other_level == "#" {
+                    return false;
+                }
+            }
+            // A plain level only covers the same plain level
+            (Some(filter_level), Some(other_level)) => {
+                if filter_level != other_level || other_level == "+" || other_level == "#" {
+                    return false;
+                }
+            }
+            (None, None) => {
+                return true;
+            }
+            _ => {
+                return false;
+            }
+        }
+    }
+}
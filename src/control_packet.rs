@@ -1,6 +1,7 @@
 pub mod connect;
-pub mod disconnect;
-pub mod ping;
+pub mod error;
 pub mod publish;
 pub mod subcribe;
 pub mod unsubcribe;
+
+pub use error::ProtocolError;
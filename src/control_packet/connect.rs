@@ -1,307 +1,138 @@
-use std::{ net::SocketAddr, sync::mpsc::Sender };
+use std::collections::hash_map::Entry;
+use std::net::SocketAddr;
+
+use crate::models::{ client::{ Client, Clients }, flags::ConnectFlags };
+use crate::control_packet::ProtocolError;
+use crate::packet::{ Connack, Connect, LastWill };
+use crate::services::{ self, metrics::METRICS, outbound::OutboundSender };
+use crate::services::hooks::{ AuthDecision, BrokerHooks, ConnectInfo };
+use tracing::info;
+
+/// The disconnect reason of a connection whose session was taken over by a new connection with the same client id.
+pub const TAKEN_OVER: &str = "Session taken over";
 
-use crate::{ common_fn, models::{ client::Client, flags::ConnectFlags, text_formatter::Color, text_formatter::Style, text_formatter::Reset } };
 pub struct Response {
-    pub return_packet: [u8; 4],
+    pub connack: Connack,
     pub keep_alive: u64,
+    pub client_id: String,
+    pub username: String,
+    /// The session as it was on the connection that was taken over, if the client id was connected.
+    pub taken_over: Option<Client>,
+    /// True if the client connected with Clean Session 1 and had a session, its subscriptions and QoS flows
+    /// have to be discarded (MQTT-3.1.2-6).
+    pub session_discarded: bool,
 }
 
-/// Handles the MQTT connection by validating the incoming buffer and assembling a response packet.
+/// Handles the MQTT connection by validating the decoded CONNECT packet and assembling a response packet.
 ///
 /// # Arguments
 ///
-/// * `buffer` - The buffer containing the incoming packet data.
-/// * `packet_length` - The length of the packet in the buffer.
+/// * `connect` - The CONNECT packet, decoded with [`packet::decode`](crate::packet::decode).
 /// * `socket_addr` - The socket address of the client.
-/// * `clients` - A mutable reference to the vector of clients.
-/// * `tx` - The sender channel for transmitting data.
+/// * `clients` - The client sessions of the broker.
+/// * `tx` - The outbound queue of the connection.
 ///
 /// # Returns
 ///
 /// A Result containing the assembled response packet and the calculated keep-alive time,
-/// or an error message if the packet is invalid.
+/// or the reason the connection is refused.
 ///
 /// # Description
 ///
-/// This function handles the MQTT connection by validating the incoming buffer data and
-/// assembling a response packet. It first decodes the remaining length of the packet and checks
-/// for the validity of the protocol name and level.
+/// This function handles the MQTT connection by validating the CONNECT packet and
+/// assembling a response packet. The decoder has already checked the structure of the packet
+/// and the connect flags, so this function checks the protocol name and level, and the client id against
+/// the `[session]` settings. A client with an empty client id and a clean session is assigned a unique one.
+/// The credentials are checked by the registered hooks, see [`BrokerHooks::on_connect`], and by the `[auth]`
+/// settings when no hook decides.
 ///
-/// Then, it reads the connect flags and extracts individual flags to determine various parameters like QoS level, clean session, will flag, etc.
-/// Next, it reads the client identifier, will topic, will message, username, and password if
-/// present in the buffer.
-///
-/// Based on the provided data, it creates a new client or updates an existing client in the list of clients.
+/// Based on the provided data, it creates a new client or updates an existing client session. The session is
+/// looked up and updated in a single step, so two connections with the same client id can not both take it.
+/// A session that is still connected is taken over: the old connection is told to close, and the new
+/// connection gets the session (MQTT-3.1.4-2). Whether the will of the old connection is published is
+/// up to the caller, see [`Response::taken_over`].
 ///
 /// Finally, it assembles the response packet (CONNACK) and returns it along with the calculated keep-alive time.
 ///
 /// # Errors
 ///
-/// Returns an error if the packet is invalid or if the CONNACK packet is not accepted.
+/// Returns [`ProtocolError::ProtocolViolation`] if the protocol name is not "MQTT", the connection is
+/// then closed without a CONNACK. Every other error maps to the return code of the refusing CONNACK,
+/// see [`ProtocolError::connack_code`].
 ///
 /// # Examples
 ///
 /// ```
-/// let buffer: [u8; 8192]; // Read from a tcp stream
-/// 
-/// let result = match control_packet::connect::handle(buffer, packet_length, socket_addr, &mut clients, tx.clone())
-/// {
-///     Ok(response) => {
-///         let keep_alive: u64 = response.keep_alive;
+/// let (packet, _) = packet::decode(&buffer)?; // Read from a tcp stream
 ///
-///         // Send response to the client
-///         _ = tx.send(response.return_packet.to_vec());
+/// if let Packet::Connect(connect) = packet {
+///     match control_packet::connect::handle(connect, socket_addr, &clients, tx.clone()) {
+///         Ok(response) => {
+///             // Send response to the client
+///             _ = tx.send(Packet::Connack(response.connack).to_vec());
 ///
-///         // Set keep_alive
-///         let _ = stream.set_read_timeout(Some(Duration::from_secs(keep_alive)));
-///     }
-///     Err(err) => {
-///         println!("An error has occured: {}", err);
-///         break;
+///             // Set keep_alive
+///             let _ = stream.set_read_timeout(Some(Duration::from_secs(response.keep_alive)));
+///         }
+///         Err(err) => {
+///             // Refuse the connection with a CONNACK when the error has a return code
+///             if let Some(return_code) = err.connack_code() {
+///                 _ = tx.send(Packet::Connack(Connack { session_present: false, return_code }).to_vec());
+///             }
+///         }
 ///     }
 /// }
-/// assert_eq!(result, 10);
 /// ```
 pub fn handle(
-    buffer: [u8; 8192],
-    packet_length: usize,
+    connect: Connect,
     socket_addr: SocketAddr,
-    clients: &mut Vec<Client>,
-    tx: Sender<Result<Vec<u8>, String>>
-) -> Result<Response, &'static str> {
-    // Validate packet
-    let mut remaining_length: usize = 0;
-    let mut connect_return_code: u8 = 0; // Used for assembling the connack packet
-
-    match common_fn::bit_operations::decode_remaining_length(&buffer) {
-        Ok(value) => {
-            remaining_length = value;
-        }
-        Err(err) => println!("{1}Error! -> {2}{3}{0}{4}",
-                        err,
-                        Color::BrightRed,
-                        Reset::All,
-                        Style::Italic,
-                        Reset::All
-                    ),
-    }
-
-    let mut has_valid_protocol_length_and_name = true;
-    let mut current_index: usize = packet_length - remaining_length;
-
-    // Control protocol length & name
-    match common_fn::msb_lsb_reader::get_values(&buffer, current_index, true) {
-        Ok(response) => {
-            // Match conditions
-            if response.0 != 4 && response.1 != "MQTT" {
-                has_valid_protocol_length_and_name = false;
-            }
-
-            current_index = response.2;
-        }
-        Err(err) => {
-            println!("{1}Error! -> {2}{3}{0}{4}",
-                err,
-                Color::BrightRed,
-                Reset::All,
-                Style::Italic,
-                Reset::All
-            );
-        }
-    }
+    clients: &Clients,
+    tx: OutboundSender
+) -> Result<Response, ProtocolError> {
+    let mut refusal: Option<ProtocolError> = None; // Used for assembling the connack packet
 
-    if !has_valid_protocol_length_and_name {
-        return Err("Invalid protocol name");
+    if connect.protocol_name != "MQTT" {
+        return Err(ProtocolError::ProtocolViolation("Invalid protocol name"));
     }
 
-    // Control protocol level must be 4 (3.1.1)
-    if (buffer[current_index] as u8) != 4 {
-        connect_return_code = 1;
+    if connect.protocol_level != 4 {
+        // Control protocol level must be 4 (3.1.1)
+        refusal = Some(ProtocolError::UnsupportedProtocolVersion(connect.protocol_level));
+    } else if connect.client_id.is_empty() && !connect.clean_session {
+        // A client without an identifier has no session to resume
+        refusal = Some(ProtocolError::IdentifierRejected("An empty client identifier needs a clean session"));
+    } else if let Err(reason) = services::session::validate_client_id(&connect.client_id) {
+        refusal = Some(ProtocolError::IdentifierRejected(reason));
     }
 
-    current_index += 1; // Move the pointer 1 byte to the right
-
-    // Control the connect flags
-    let byte: u8 = buffer[current_index];
-
-    // Extracting individual flags
-    let flag_0: bool = (byte & 0b00000001) != 0; // Reserved, must be 0
-    let flag_1: bool = (byte & 0b00000010) != 0; // Clean Session
-    let flag_2: bool = (byte & 0b00000100) != 0; // Will Flag
-    let mut flag_3: bool = (byte & 0b00001000) != 0; // QoS 1 (Note: Both can not be true)
-    let mut flag_4: bool = (byte & 0b00010000) != 0; // QoS 2 (Note: Both can not be true)
-    let mut flag_5: bool = (byte & 0b00100000) != 0; // Will Retain
-    let flag_6: bool = (byte & 0b01000000) != 0; // Password Flag
-    let flag_7: bool = (byte & 0b10000000) != 0; // Username Flag
-
-    let mut qos_level: u8 = 0;
-
-    // Reserved flag
-    if flag_0 {
-        return Err("Reserved flag is not 0");
-    }
-
-    // Will flag
-    if !flag_2 {
-        flag_3 = false;
-        flag_4 = false;
-        flag_5 = false;
-        // Disconnect if will message or topic is pressent
-    }
-
-    // QoS 1 flag
-    if flag_3 {
-        qos_level = 1;
-    }
-
-    // QoS 2 flag
-    if flag_4 {
-        qos_level = 2;
-    }
-
-    if flag_3 && flag_4 {
-        return Err("Wrong QoS level specified");
-    }
+    // A client without an identifier gets a unique one, so it does not share a session with other such clients
+    let client_id: String = if connect.client_id.is_empty() && refusal.is_none() {
+        services::session::assign_client_id(clients)
+    } else {
+        connect.client_id
+    };
 
     let connect_flags: ConnectFlags = ConnectFlags::new(
-        flag_1,
-        flag_2,
-        qos_level,
-        flag_5,
-        flag_6,
-        flag_7
+        connect.clean_session,
+        connect.will.is_some(),
+        connect.will.as_ref().map_or(0, |will: &LastWill| will.qos),
+        connect.will.as_ref().is_some_and(|will: &LastWill| will.retain),
+        connect.password.is_some(),
+        connect.username.is_some()
     );
 
-    current_index += 1; // Move the pointer 1 byte to the right
-
-    let mut keep_alive: u64 = 0;
+    let mut keep_alive: u64 = connect.keep_alive as u64;
 
-    // Read the keep alive byte (MSB & LSB)
-    match common_fn::msb_lsb_reader::get_values(&buffer, current_index, false) {
-        Ok(response) => {
-            keep_alive = response.0 as u64;
-
-            current_index = response.2;
-        }
-        Err(err) => {
-            println!("{1}Error! -> {2}{3}{0}{4}",
-                err,
-                Color::BrightRed,
-                Reset::All,
-                Style::Italic,
-                Reset::All
-            );
-        }
-    }
+    // The will message and password are binary data, the client keeps them as strings
+    let (will_topic, will_message): (String, String) = match connect.will {
+        Some(will) => (will.topic, String::from_utf8_lossy(&will.message).into_owned()),
+        None => (String::new(), String::new()),
+    };
 
-    // Field order from here MUST be:
-    // Client Identifier -> Will Topic -> Will Message -> User Name -> Password
-
-    let mut client_id: String = String::new();
-    let mut will_topic: String = String::new();
-    let mut will_message: String = String::new();
-
-    // Read the Client Identifier (MSB & LSB)
-    match common_fn::msb_lsb_reader::get_values(&buffer, current_index, true) {
-        Ok(response) => {
-            client_id = response.1;
-
-            current_index = response.2;
-        }
-        Err(err) => {
-            println!("{1}Error! -> {2}{3}{0}{4}",
-                err,
-                Color::BrightRed,
-                Reset::All,
-                Style::Italic,
-                Reset::All
-            );
-        }
-    }
-
-    // If will flag is true
-    if connect_flags.will_flag {
-        // Read the Will Topic (MSB & LSB)
-        match common_fn::msb_lsb_reader::get_values(&buffer, current_index, true) {
-            Ok(response) => {
-                will_topic = response.1;
-
-                current_index = response.2;
-            }
-            Err(err) => {
-                println!("{1}Error! -> {2}{3}{0}{4}",
-                    err,
-                    Color::BrightRed,
-                    Reset::All,
-                    Style::Italic,
-                    Reset::All
-                );
-            }
-        }
-
-        // Read the Will Message (MSB & LSB)
-        match common_fn::msb_lsb_reader::get_values(&buffer, current_index, true) {
-            Ok(response) => {
-                will_message = response.1;
-
-                current_index = response.2;
-            }
-            Err(err) => {
-                println!("{1}Error! -> {2}{3}{0}{4}",
-                    err,
-                    Color::BrightRed,
-                    Reset::All,
-                    Style::Italic,
-                    Reset::All
-                );
-            }
-        }
-    }
-
-    let mut username: String = String::new();
-    let mut password: String = String::new();
-
-    // If username flag is true
-    if connect_flags.username_flag {
-        // Read the Username (MSB & LSB)
-        match common_fn::msb_lsb_reader::get_values(&buffer, current_index, true) {
-            Ok(response) => {
-                username = response.1;
-
-                current_index = response.2;
-            }
-            Err(err) => {
-                println!("{1}Error! -> {2}{3}{0}{4}",
-                    err,
-                    Color::BrightRed,
-                    Reset::All,
-                    Style::Italic,
-                    Reset::All
-                );
-            }
-        }
-    }
-
-    // If password flag is true
-    if connect_flags.password_flag {
-        // Read the Username (MSB & LSB)
-        match common_fn::msb_lsb_reader::get_values(&buffer, current_index, true) {
-            Ok(response) => {
-                password = response.1;
-
-                current_index = response.2;
-            }
-            Err(err) => {
-                println!("{1}Error! -> {2}{3}{0}{4}",
-                    err,
-                    Color::BrightRed,
-                    Reset::All,
-                    Style::Italic,
-                    Reset::All
-                );
-            }
-        }
-    }
+    let username: String = connect.username.unwrap_or_default();
+    let password: String = connect.password
+        .map(|password: Vec<u8>| String::from_utf8_lossy(&password).into_owned())
+        .unwrap_or_default();
 
     let client: Client = Client::new(
         client_id,
@@ -318,50 +149,102 @@ pub fn handle(
     // Set to 1.5 times the specified amount, AFTER a new Client is created.
     keep_alive = (keep_alive * 3) / 2;
 
-    if packet_length != current_index {
-        return Err("Invalid packet");
-    }
+    // Check the credentials, unless the connection is already rejected.
+    // The registered hooks decide first, the `[auth]` settings decide when no hook does
+    if refusal.is_none() {
+        let username: Option<&str> = if client.connect_flags.username_flag {
+            Some(&client.username)
+        } else {
+            None
+        };
+
+        let decision: AuthDecision = match services::hooks::registered() {
+            Some(hooks) => hooks.on_connect(&ConnectInfo {
+                client_id: &client.id,
+                username,
+                password: if client.connect_flags.password_flag { Some(&client.password) } else { None },
+                address: socket_addr,
+                clean_session: client.connect_flags.clean_session_flag,
+            }),
+            None => AuthDecision::Continue,
+        };
+
+        refusal = match decision {
+            AuthDecision::Allow => None,
+            AuthDecision::BadCredentials => Some(ProtocolError::BadCredentials),
+            AuthDecision::NotAuthorized => Some(ProtocolError::NotAuthorized),
+            AuthDecision::Continue => match services::auth::authenticate(username, &client.password) {
+                0 => None,
+                4 => Some(ProtocolError::BadCredentials),
+                _ => Some(ProtocolError::NotAuthorized),
+            },
+        };
+    }
+
+    // Keep the client id and username for the response, as the client is moved into the list
+    let client_id: String = client.id.clone();
+    let username: String = client.username.clone();
 
     // Assemble return packet
     let mut session_present_byte: u8 = 0;
-
-    if let Some(existing_client) = clients.iter_mut().find(|c: &&mut Client| c.id == client.id) {
-        if existing_client.is_connected {
-            // Reject the connection
-            connect_return_code = 2;
-        } else {
-            // Update the existing client to be connected
-            existing_client.keep_alive = client.keep_alive;
-            existing_client.username = client.username;
-            existing_client.password = client.password;
-            existing_client.connect_flags = client.connect_flags;
-            existing_client.tx = client.tx;
-
-            if existing_client.connect_flags.clean_session_flag {
-                existing_client.will_topic = client.will_topic;
-                existing_client.will_message = client.will_message;
-                existing_client.subscriptions = client.subscriptions;
-                // Store QoS messages, not yet completed
-            } else {
-                if connect_return_code == 0 {
-                    session_present_byte = 1;
+    let mut taken_over: Option<Client> = None;
+    let mut session_discarded: bool = false;
+
+    if refusal.is_none() {
+        // Refused clients are not added to the sessions
+        refusal = clients.entry(client.id.clone(), |entry: Entry<'_, String, Client>| {
+            match entry {
+                Entry::Occupied(mut occupied) => {
+                    let existing_client: &mut Client = occupied.get_mut();
+
+                    if existing_client.is_connected {
+                        // Take the session over, the write thread of the old connection closes its stream.
+                        // Its read loop then finds the session gone from its address and leaves it alone
+                        info!(previous = %existing_client.socket_addr, "Session taken over by a new connection");
+
+                        _ = existing_client.tx.send(Err(TAKEN_OVER.to_string()));
+                        taken_over = Some(existing_client.clone());
+                    }
+
+                    // Update the existing client to be connected
+                    existing_client.keep_alive = client.keep_alive;
+                    existing_client.username = client.username;
+                    existing_client.password = client.password;
+                    existing_client.connect_flags = client.connect_flags;
+                    existing_client.tx = client.tx;
+
+                    if existing_client.connect_flags.clean_session_flag {
+                        existing_client.will_topic = client.will_topic;
+                        existing_client.will_message = client.will_message;
+                        existing_client.subscriptions = client.subscriptions;
+                        session_discarded = true;
+                    } else {
+                        session_present_byte = 1;
+                    }
+
+                    existing_client.socket_addr = socket_addr;
+                    existing_client.is_connected = true;
+                }
+                Entry::Vacant(vacant) => {
+                    // Add the new client to the sessions
+                    vacant.insert(client);
                 }
             }
 
-            existing_client.socket_addr = socket_addr;
-            existing_client.is_connected = true;
-        }
-    } else {
-        // Add the new client to the list
-        clients.push(client);
+            None
+        });
     }
 
-    let connack_packet: [u8; 4] = [32, 2, session_present_byte, connect_return_code];
+    let connect_return_code: u8 = refusal.as_ref().and_then(ProtocolError::connack_code).unwrap_or(0);
+
+    METRICS.connack(connect_return_code);
 
-    if connack_packet != [32, 2, 0, 0] && connack_packet != [32, 2, 1, 0] {
-        return Err("Connack not accepted");
+    if let Some(err) = refusal {
+        return Err(err);
     }
 
+    let connack: Connack = Connack { session_present: session_present_byte == 1, return_code: connect_return_code };
+
     // Return newly assembled return packet
-    return Ok(Response { return_packet: connack_packet, keep_alive });
+    Ok(Response { connack, keep_alive, client_id, username, taken_over, session_discarded })
 }
@@ -0,0 +1,70 @@
+use std::fmt;
+
+use crate::packet::DecodeError;
+
+/// Why the broker refuses a packet from a client.
+///
+/// # Description
+///
+/// MQTT 3.1.1 has two ways of refusing a client: a CONNACK with a non-zero return code in answer to
+/// the CONNECT, or closing the network connection for every other error. [`connack_code`](Self::connack_code)
+/// tells the connection handler which one applies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The bytes received do not form a valid packet.
+    MalformedPacket(DecodeError),
+    /// The packet is well formed, but not allowed at this point or with these values.
+    ProtocolViolation(&'static str),
+    /// The CONNECT asks for a protocol level other than 4 (MQTT 3.1.1).
+    UnsupportedProtocolVersion(u8),
+    /// The client identifier is not allowed, or a client with the same identifier is connected.
+    IdentifierRejected(&'static str),
+    /// The user name or password is wrong.
+    BadCredentials,
+    /// The client is not allowed to connect, or to publish to a topic.
+    NotAuthorized,
+}
+
+impl ProtocolError {
+    /// Maps an error to the CONNACK return code sent before the connection is closed.
+    ///
+    /// # Returns
+    ///
+    /// The return code, or None if the connection is closed without a CONNACK.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(ProtocolError::BadCredentials.connack_code(), Some(4));
+    /// assert_eq!(ProtocolError::ProtocolViolation("Invalid protocol name").connack_code(), None);
+    /// ```
+    pub fn connack_code(&self) -> Option<u8> {
+        match self {
+            ProtocolError::UnsupportedProtocolVersion(_) => Some(1),
+            ProtocolError::IdentifierRejected(_) => Some(2),
+            ProtocolError::BadCredentials => Some(4),
+            ProtocolError::NotAuthorized => Some(5),
+            // A malformed packet or a protocol violation closes the connection without a response
+            ProtocolError::MalformedPacket(_) | ProtocolError::ProtocolViolation(_) => None,
+        }
+    }
+}
+
+impl From<DecodeError> for ProtocolError {
+    fn from(err: DecodeError) -> Self {
+        ProtocolError::MalformedPacket(err)
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::MalformedPacket(err) => write!(f, "{}", err),
+            ProtocolError::ProtocolViolation(reason) => write!(f, "Protocol violation: {}", reason),
+            ProtocolError::UnsupportedProtocolVersion(level) => write!(f, "Unsupported protocol level {}", level),
+            ProtocolError::IdentifierRejected(reason) => write!(f, "Identifier rejected: {}", reason),
+            ProtocolError::BadCredentials => write!(f, "Bad user name or password"),
+            ProtocolError::NotAuthorized => write!(f, "Not authorized"),
+        }
+    }
+}
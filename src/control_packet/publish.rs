@@ -1,13 +1,22 @@
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError, Sender };
+use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
-
-use crate::common_fn;
-use crate::models::publish_queue_item::{ PublishItemDirection, PublishItemState };
-use crate::models::{ client::Client, publish_queue_item::PublishQueueItem, topic::Topic };
-use crate::models::text_formatter:: { Color, Style, Reset };
-use rand::Rng;
+use std::time::{ Duration, Instant };
+
+use crate::control_packet::ProtocolError;
+use crate::models::client::{ Client, Clients };
+use crate::models::publish_queue_item::{ PublishItemDirection, PublishItemState, PublishQueue, PublishQueueItem, PublishQueueKey };
+use crate::models::topic::Topics;
+use crate::packet::{ Packet, Publish, Pubrel };
+use crate::services::{ self, metrics::METRICS, outbound::OutboundSender };
+use crate::services::hooks::{ BrokerHooks, Message };
+use crate::services::storage::state::{ Record, StoredMessage };
+use tracing::{ debug, info_span, warn, Span };
+
+/// How long a QoS flow waits for an acknowledgement before sending its packet again.
+/// Calculated by the max size of a payload (256 MB) downloaded with a 10 Mbps internet connection
+/// (205 seconds), and then a little extra time to handle the packet and get the reply.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(222);
 
 #[derive(Clone)]
 pub struct Response {
@@ -17,240 +26,55 @@ pub struct Response {
     pub packet_id: usize,
     pub topic_name: String,
     pub payload_message: String,
+    pub received_at: Instant,
 }
 
 /// Handles publish packets, according to the MQTT protocol.
 ///
 /// # Arguments
 ///
-/// * `buffer` - A reference to the buffer, passed from the tcpStream Read function.
-/// * `packet_length` - The length of the buffer, to consider part of the packet.
+/// * `publish` - The PUBLISH packet, decoded with [`packet::decode`](crate::packet::decode).
 ///
 /// # Returns
 ///
-/// A Result containing a [`Response`] struct, or an error message.
+/// A Result containing a [`Response`] struct, or the protocol error that closes the connection.
 ///
 /// # Errors
 ///
-/// Returns an error if the publish packet is malformed in any way, or doesn't conform to the MQTT specification.
-pub fn handle_publish(buffer: [u8; 8192], packet_length: usize) -> Result<Response, &'static str> {
-    // Check if each bit is set
-    let flag_3: bool = (&buffer[0] & (1 << 3)) != 0; // DUP Flag
-    let flag_2: bool = (&buffer[0] & (1 << 2)) != 0; // QoS 2 Flag
-    let flag_1: bool = (&buffer[0] & (1 << 1)) != 0; // QoS 1 Flag
-    let flag_0: bool = (&buffer[0] & (1 << 0)) != 0; // Retain Flag
-
-    let mut qos_level: u8 = 0;
-
-    // QoS 1 flag
-    if flag_1 {
-        qos_level = 1;
-    }
-
-    // QoS 2 flag
-    if flag_2 {
-        qos_level = 2;
-    }
-
-    if flag_1 && flag_2 {
-        return Err("Wrong QoS level specified");
-    }
-
-    let mut remaining_length: usize = 0;
-
-    // Get the remaining length from the publish packet
-    match common_fn::bit_operations::decode_remaining_length(&buffer) {
-        Ok(value) => {
-            remaining_length = value;
-        }
-        Err(err) => println!("{1}Error! -> {2}{3}{0}{4}",
-                        err,
-                        Color::BrightRed,
-                        Reset::All,
-                        Style::Italic,
-                        Reset::All
-                    ),
-    }
-
-    // Throws an error if the packet length is lower than remaining length or equel to
-    if packet_length <= remaining_length {
-        return Err("Packet length is lower than remaining length");
-    }
-
-    // Sets the current index in the buffer
-    let mut current_index: usize = packet_length - remaining_length;
-
-    // Gets the topic name
-    let mut topic_name: String = String::new();
-    match common_fn::msb_lsb_reader::get_values(&buffer, current_index, true) {
-        Ok(response) => {
-            // Get the topic name
-            topic_name = response.1;
-
-            // Update current index
-            current_index = response.2;
-        }
-        Err(err) => {
-            println!("{1}Error! -> {2}{3}{0}{4}",
-                err,
-                Color::BrightRed,
-                Reset::All,
-                Style::Italic,
-                Reset::All
-            );
-        }
-    }
-
-    let mut packet_id: usize = 0;
-
-    // If the QoS Level is not 0 then there is a packet id in the packet 
-    if qos_level != 0 {
-        match common_fn::msb_lsb_reader::get_values(&buffer, current_index, false) {
-            Ok(response) => {
-                // Get the packet identifier
-                packet_id = response.0;
-
-                // Update current index
-                current_index = response.2;
-            }
-            Err(err) => {
-                println!("{1}Error! -> {2}{3}{0}{4}",
-                    err,
-                    Color::BrightRed,
-                    Reset::All,
-                    Style::Italic,
-                    Reset::All
-                );
-            }
-        }
+/// Returns [`ProtocolError::ProtocolViolation`] if the topic name is empty or contains a wildcard character, the decoder
+/// has already rejected packets with an invalid QoS or DUP flag.
+pub fn handle_publish(publish: Publish) -> Result<Response, ProtocolError> {
+    // A topic name MUST be at least one character long (MQTT-4.7.3-1)
+    if publish.topic_name.is_empty() {
+        return Err(ProtocolError::ProtocolViolation("Topic name is empty"));
     }
 
-    // Gets the payload of the publish packet
-    let mut payload_message: String = String::new();
-    while current_index < packet_length {
-        payload_message.push(buffer[current_index].clone() as u8 as char);
-        current_index += 1;
+    // The topic name of a PUBLISH packet MUST NOT contain wildcard characters
+    if publish.topic_name.contains(['+', '#']) {
+        return Err(ProtocolError::ProtocolViolation("Topic name contains a wildcard character"));
     }
 
     // Assemble return struct
     let response: Response = Response {
-        dup_flag: flag_3,
-        qos_level,
-        retain_flag: flag_0,
-        packet_id,
-        topic_name,
-        payload_message,
+        dup_flag: publish.dup,
+        qos_level: publish.qos,
+        retain_flag: publish.retain,
+        packet_id: publish.packet_id.unwrap_or(0) as usize,
+        topic_name: publish.topic_name,
+        payload_message: String::from_utf8_lossy(&publish.payload).into_owned(),
+        received_at: Instant::now(),
     };
 
-    return Ok(response);
-}
-
-/// Handles puback packets, according to the MQTT protocol.
-///
-/// # Arguments
-///
-/// * `buffer` - A reference to the buffer, passed from the tcpStream Read function.
-/// * `packet_length` - The length of the buffer, to consider part of the packet.
-///
-/// # Returns the packet identifier as a usize
-///
-/// A Result containing a , or an error message.
-///
-/// # Errors
-///
-/// Returns an error if the packet is malformed in any way, or doesn't conform to the MQTT specification.
-pub fn handle_puback(buffer: [u8; 8192], packet_length: usize) -> Result<usize, &'static str> {
-    validate_qos_packet(buffer, packet_length)
-}
-
-/// Handles pubrec packets, according to the MQTT protocol.
-///
-/// # Arguments
-///
-/// * `buffer` - A reference to the buffer, passed from the tcpStream Read function.
-/// * `packet_length` - The length of the buffer, to consider part of the packet.
-///
-/// # Returns the packet identifier as a usize
-///
-/// A Result containing a , or an error message.
-///
-/// # Errors
-///
-/// Returns an error if the packet is malformed in any way, or doesn't conform to the MQTT specification.
-pub fn handle_pubrec(buffer: [u8; 8192], packet_length: usize) -> Result<usize, &'static str> {
-    validate_qos_packet(buffer, packet_length)
-}
-
-/// Handles pubrel packets, according to the MQTT protocol.
-///
-/// # Arguments
-///
-/// * `buffer` - A reference to the buffer, passed from the tcpStream Read function.
-/// * `packet_length` - The length of the buffer, to consider part of the packet.
-///
-/// # Returns the packet identifier as a usize
-///
-/// A Result containing a , or an error message.
-///
-/// # Errors
-///
-/// Returns an error if the packet is malformed in any way, or doesn't conform to the MQTT specification.
-pub fn handle_pubrel(buffer: [u8; 8192], packet_length: usize) -> Result<usize, &'static str> {
-    validate_qos_packet(buffer, packet_length)
-}
-
-/// Handles pubcomp packets, according to the MQTT protocol.
-///
-/// # Arguments
-///
-/// * `buffer` - A reference to the buffer, passed from the tcpStream Read function.
-/// * `packet_length` - The length of the buffer, to consider part of the packet.
-///
-/// # Returns the packet identifier as a usize
-///
-/// A Result containing a , or an error message.
-///
-/// # Errors
-///
-/// Returns an error if the packet is malformed in any way, or doesn't conform to the MQTT specification.
-pub fn handle_pubcomp(buffer: [u8; 8192], packet_length: usize) -> Result<usize, &'static str> {
-    validate_qos_packet(buffer, packet_length)
-}
-
-/// Validates QoS packets, according to the MQTT protocol.
-///
-/// # Arguments
-///
-/// * `buffer` - A reference to the buffer, passed from the tcpStream Read function.
-/// * `packet_length` - The length of the buffer, to consider part of the packet.
-///
-/// # Returns the packet identifier as a usize
-///
-/// A Result containing a , or an error message.
-///
-/// # Errors
-///
-/// Returns an error if the packet is malformed in any way, or doesn't conform to the MQTT specification.
-fn validate_qos_packet(buffer: [u8; 8192], packet_length: usize) -> Result<usize, &'static str> {
-    if packet_length != 4 {
-        return Err("Invalid packet length");
-    }
-
-    if buffer[1] != 2 {
-        return Err("Invalid remaining length");
-    }
-
-    let packet_id: usize = common_fn::msb_lsb_reader::get_values(&buffer, 2, false)?.0;
-
-    Ok(packet_id)
+    Ok(response)
 }
 
 /// Publishes a message to clients subscribed to the specified topic.
 ///
 /// # Arguments
 ///
-/// * `topics` - A mutable reference to the vector of topics.
-/// * `clients` - A mutable reference to the vector of clients.
+/// * `topics` - The topics of the broker.
+/// * `clients` - The client sessions of the broker.
+/// * `publish_queue` - The QoS flows in flight, shared with the flows this starts.
 /// * `topic_name` - The name of the topic to which the message is published.
 /// * `topic_message` - The message to be published.
 /// * `dup` - A boolean indicating if the message is a duplicate.
@@ -259,310 +83,303 @@ fn validate_qos_packet(buffer: [u8; 8192], packet_length: usize) -> Result<usize
 ///
 /// # Description
 ///
-/// This function publishes a message to clients subscribed to the specified topic. It iterates
-/// over the list of topics to find the matching topic by name. Then, it iterates over the list
-/// of clients to find clients subscribed to the topic. For each subscribed client, it creates
-/// and sends a packet containing the message to be published. The packet is constructed based
-/// on the specified quality of service level, whether the message is a duplicate, and if it
-/// should be retained by the broker.
+/// This function publishes a message to clients subscribed to the specified topic. It collects
+/// the clients subscribed with a matching topic filter from the topics, then looks up each of them
+/// by client id and sends it a packet containing the message. No lock is held while the packets are sent.
+/// A client subscribed with several matching topic filters gets the message once, at the highest granted QoS
+/// of them, and the message is delivered at the lower of that QoS and its own QoS (MQTT-3.8.4-6).
 ///
 /// # Examples
 ///
 /// ```
-/// let mut topics = vec![Topic::new("topic1")];
-/// let mut clients = vec![Client::new("client1", "", "", 0, "", "", SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0), tx.clone(), ConnectFlags::default())];
-/// let topic_name = "topic1";
-/// let topic_message = "message";
-/// let dup = false;
-/// let qos = 0;
-/// let retain = false;
+/// let topics: Topics = Topics::new();
+/// topics.subscribe("client1", ("topic1".to_string(), 0));
 ///
-/// publish(&mut topics, &mut clients, topic_name, topic_message, &dup, &qos, &retain);
+/// publish(&topics, &clients, Arc::clone(&publish_queue), "topic1", "message", &false, &0, &false);
 /// ```
+#[allow(clippy::too_many_arguments)]
 pub fn publish(
-    topics: &mut Vec<Topic>,
-    clients: &mut Vec<Client>,
-    publish_queue: Arc<Mutex<Vec<PublishQueueItem>>>,
+    topics: &Topics,
+    clients: &Clients,
+    publish_queue: Arc<PublishQueue>,
     topic_name: &str,
     topic_message: &str,
     _dup: &bool,
-    _qos: &u8,
+    qos: &u8,
     retain: &bool,
 ) {
-    // Loops through the topic list and if the topic name matches
-    // Then loops all the clients to get the client id
-    // And if that matches with the client id in the topic 
-    for topic in topics.iter() {
-        if topic_name == topic.topic_name {
-            for client in clients.iter() {
-                if let Some(client_index) = topic.client_ids.iter().position(|c: &(String, u8)| &c.0 == &client.id)
-                {
-                    publish_to_client(client, Arc::clone(&publish_queue), topic, topic_message, &topic.client_ids[client_index].1, retain);
-                };
-            }
+    // Collects the clients subscribed with a matching topic filter, a client subscribed
+    // with several matching filters gets the message once, with the highest QoS
+    let subscribers: Vec<(String, u8)> = topics.subscribers(topic_name);
+
+    // Then looks up each subscribed client by its id, and sends the message to it
+    for (client_id, granted_qos) in subscribers.iter() {
+        if let Some(client) = clients.get(client_id) {
+            let delivery_qos: u8 = (*qos).min(*granted_qos);
+
+            publish_to_client(&client, Arc::clone(&publish_queue), topic_name, topic_message, &delivery_qos, retain);
         }
     }
 }
 
 /// Publish a payload to a client.
-/// 
+///
 /// # Arguments
 ///
 /// * `client` - A reference to the [`Client`] struct.
-/// * `publish_queue` - A clone of the publish queue
-/// * `buffer` - A reference to the buffer, passed from the tcpStream Read function.
-/// * `packet_length` - The length of the buffer, to consider part of the packet.
-///
-/// # Returns the packet identifier as a usize
+/// * `publish_queue` - The QoS flows in flight, the flow of a QoS 1 or QoS 2 delivery is added to it.
+/// * `topic_name` - The topic name of the message, not the topic filter the client subscribed with.
+/// * `topic_message` - The payload of the message.
+/// * `qos` - The QoS of the delivery.
+/// * `retain` - If the retain flag should be set.
 ///
-/// A Result containing a , or an error message.
-///
-/// # Errors
+/// # Description
 ///
-/// Returns an error if reciever fails.
-pub fn publish_to_client(client: &Client, publish_queue: Arc<Mutex<Vec<PublishQueueItem>>>, topic: &Topic, topic_message: &str, qos: &u8,retain: &bool) {
-    
-    // Publish packet
-    let mut packet: Vec<u8> = Vec::new();
-
-    // Sets the first half of the first byte (control type) to publish
-    let mut first_byte: u8 = 0b0011_0000;
-
-    if *qos == 2 {
-        first_byte |= 1 << 2;
-    } else if *qos == 1 {
-        first_byte |= 1 << 1;
+/// A QoS 1 or QoS 2 delivery is completed on its own thread, which waits for the acknowledgements
+/// of the client and sends the packet again when they do not arrive within [`RETRY_INTERVAL`].
+/// Nothing is sent when a registered hook skips the delivery, see [`BrokerHooks::on_deliver`].
+pub fn publish_to_client(client: &Client, publish_queue: Arc<PublishQueue>, topic_name: &str, topic_message: &str, qos: &u8,retain: &bool) {
+    // The registered hooks may skip the delivery to this client
+    if let Some(hooks) = services::hooks::registered() {
+        let message: Message = Message {
+            topic: topic_name.to_string(),
+            payload: topic_message.to_string(),
+            qos: *qos,
+            retain: *retain,
+        };
+
+        if !hooks.on_deliver(&client.id, &message) {
+            debug!(client_id = %client.id, topic = %topic_name, "Delivery skipped by a hook");
+            return;
+        }
     }
 
-    // Sets the retain flag, according to the passed parameter [`retain`]
-    if *retain {
-        first_byte |= 1 << 0;
-    }
+    // A QoS 1 or QoS 2 delivery gets the next packet id that is not in flight to the client
+    let packet_id: usize = if *qos > 0 {
+        match publish_queue.next_packet_id(&client.id) {
+            Some(packet_id) => packet_id,
+            None => {
+                warn!(client_id = %client.id, topic = %topic_name, "Every packet id is in flight, dropping the delivery");
+                return;
+            }
+        }
+    } else {
+        0
+    };
 
-    // Gets the topic name bytes 
-    let mut topic_name_bytes = common_fn::msb_lsb_creater::create_packet(&topic.topic_name).unwrap();
+    // If the client have subscribed with QoS 1 or QoS 2 then the publish packet needs to have a packet id
+    let publish: Packet = Packet::Publish(Publish {
+        dup: false,
+        qos: *qos,
+        retain: *retain,
+        topic_name: topic_name.to_string(),
+        packet_id: if *qos > 0 { Some(packet_id as u16) } else { None },
+        payload: topic_message.as_bytes().to_vec(),
+    });
+
+    let packet: Vec<u8> = match publish.to_vec() {
+        Ok(packet) => packet,
+        Err(err) => {
+            warn!(topic = %topic_name, "Could not encode PUBLISH: {}", err);
+            return;
+        }
+    };
 
-    // Generates a random packet id
-    let packet_id: usize = rand::thread_rng().gen_range(1..=65535);
+    // Log the delivery in the context of the subscriber
+    let span: Span = info_span!("delivery", client_id = %client.id, peer = %client.socket_addr);
+    debug!(parent: &span, topic = %topic_name, qos = *qos, packet_id, "Delivering PUBLISH");
 
-    // Gets the topic message bytes, from the passed parameter
-    let mut topic_message_bytes = topic_message.as_bytes().to_vec();
+    deliver(client, publish_queue, packet_id, *qos, packet, true, span);
+}
 
-    // Puts the first in the packet
-    packet.push(first_byte);
+/// Sends an unacknowledged message of a resumed session to the client again.
+///
+/// # Arguments
+///
+/// * `client` - The client that resumed its session.
+/// * `publish_queue` - The QoS flows in flight.
+/// * `message` - The stored message, sent with its own packet id and the DUP flag set.
+///
+/// # Description
+///
+/// The message gets a new QoS 1 or QoS 2 flow, like a delivery of [`publish_to_client`], so the PUBACK
+/// or PUBREC of the client completes it. A flow left for the packet id by the previous connection
+/// is replaced, it would send its packets to the closed connection.
+pub fn resend_to_client(client: &Client, publish_queue: Arc<PublishQueue>, message: StoredMessage) {
+    let StoredMessage { packet_id, qos, mut packet } = message;
 
-    // Sets the remaining length later
-    packet.push(0);
+    // Set dup flag on packet (MQTT-3.3.1-1)
+    packet[0] |= 1 << 3;
 
-    // Append all the topic name bytes to the packet
-    packet.append(&mut topic_name_bytes);
+    let span: Span = info_span!("delivery", client_id = %client.id, peer = %client.socket_addr);
+    debug!(parent: &span, qos, packet_id, "Sending PUBLISH of the resumed session again");
 
-    // If the client have subscribed with QoS 1 or QoS 2 then the publish packet needs to have a packet id
-    if *qos == 1 || *qos == 2 {
-        packet.append(
-            common_fn::msb_lsb_creater::split_into_msb_lsb(packet_id)
-                .to_vec()
-                .as_mut(),
-        );
-    }
+    publish_queue.remove(&PublishQueueKey::new(&client.id, PublishItemDirection::ToSubscriber, packet_id));
 
-    // Append the topic_message_bytes to the publish packet
-    packet.append(&mut topic_message_bytes);
+    deliver(client, publish_queue, packet_id, qos, packet, false, span);
+}
 
-    // Sets the remaining length minus the fixed header
-    packet[1] = u8::try_from(packet.len() - 2).unwrap();
+/// Sends an encoded PUBLISH to a client, and starts its QoS 1 or QoS 2 flow.
+///
+/// # Arguments
+///
+/// * `client` - The client the PUBLISH is sent to.
+/// * `publish_queue` - The QoS flows in flight, the flow is added to it.
+/// * `packet_id` - The packet id of the PUBLISH, unused for QoS 0.
+/// * `qos` - The QoS of the delivery.
+/// * `packet` - The encoded PUBLISH packet.
+/// * `store` - If a QoS 1 or QoS 2 message is stored for the session, false when it is stored already.
+/// * `span` - The span the flow thread logs in.
+///
+/// # Description
+///
+/// Nothing is sent if a flow with the packet id is already in flight to the client,
+/// it would get the acknowledgements of this one.
+fn deliver(client: &Client, publish_queue: Arc<PublishQueue>, packet_id: usize, qos: u8, mut packet: Vec<u8>, store: bool, span: Span) {
+    // The key of the flow in the publish queue
+    let key: PublishQueueKey = PublishQueueKey::new(&client.id, PublishItemDirection::ToSubscriber, packet_id);
+
+    // A QoS 1 or QoS 2 delivery is added to the publish queue before the PUBLISH is sent,
+    // so an acknowledgement that arrives right away finds its flow.
+    // The channel passes the acknowledgements from handle_connection to the flow thread
+    let (tx, rx): (Sender<PublishItemState>, Receiver<PublishItemState>) = channel();
+
+    if qos > 0 {
+        let is_new: bool = publish_queue.push(PublishQueueItem {
+            tx,
+            client_id: client.id.clone(),
+            packet_id,
+            timestamp_sent: Instant::now(),
+            publish_packet: packet.clone(),
+            state: if qos == 1 { PublishItemState::AwaitingPuback } else { PublishItemState::AwaitingPubrec },
+            qos_level: qos,
+            flow_direction: PublishItemDirection::ToSubscriber,
+        });
+
+        if !is_new {
+            warn!(parent: &span, packet_id, "The packet id is already in flight, dropping the delivery");
+            return;
+        }
+
+        // Keep QoS 1 and QoS 2 messages for persistent sessions until they are acknowledged.
+        // Recorded before the PUBLISH is sent, so an acknowledgement can not arrive before the message is queued
+        if store {
+            services::storage::record(Record::Queued {
+                client_id: client.id.clone(),
+                message: StoredMessage { packet_id, qos, packet: packet.clone() },
+            });
+        }
+    }
 
     // Send publish packet to the client
     let _ = client.tx.send(Ok(packet.clone()));
 
     // If the client have subscribe with QoS 1, then make the QoS 1 flow
-    if *qos == 1 {
-        
-        // Create a new channel, for later passing the tx back to another thread (handle_connection), for inter-thread communication.
-        let (tx, rx): (Sender<PublishItemState>, Receiver<PublishItemState>) = channel();
-
-        // Clones the publish queue list
-        let publish_queue_clone: Arc<Mutex<Vec<PublishQueueItem>>> = Arc::clone(&publish_queue);
-
-        // Clone the client
-        let client_clone: Client = client.clone();
+    if qos == 1 {
+        // Clone the client sender, for sending the PUBLISH again
+        let client_tx: OutboundSender = client.tx.clone();
 
         // QoS 1 Session thread
         thread::spawn(move || {
-            {
-                // Unlock the publish_queue, or wait until it is available
-                let mut publish_queue: MutexGuard<'_, Vec<PublishQueueItem>> = publish_queue_clone.lock().unwrap();
-
-                // Adds a new publish queue item to the publish queue 
-                publish_queue.push(PublishQueueItem {
-                    tx,
-                    packet_id,
-                    timestamp_sent: Instant::now(),
-                    publish_packet: packet.clone(),
-                    state: PublishItemState::AwaitingPuback,
-                    qos_level: 1,
-                    flow_direction: PublishItemDirection::ToSubscriber,
-                });
-            }
-            // Wait for 2220 miliseconds, calculated by the max size of a payload (256 mb)
-            // Downloaded with a 10Mbps internet connection (205 seconds), and then a little 
-            // extra time to handle the packet and get the reply.
-            for _i in 0..2220 {
-                match rx.try_recv() {
-                    Ok(state) => {
-                        if state == PublishItemState::PubackRecieved {
-                            // Unlock the publish_queue, or wait until it is available
-                            let mut publish_queue: MutexGuard<'_, Vec<PublishQueueItem>> = publish_queue_clone.lock().unwrap();
-
-                            // Attempt to find the publish item in the queue, to remove it.
-                            if let Some(index) = publish_queue.iter().position(
-                                |t: &PublishQueueItem| t.packet_id == packet_id,
-                            ) {
-                                publish_queue.remove(index);
-                            }
-
-                            return;
-                        }
-                    }
-                    Err(_) => {}
+            let _enter = span.enter();
+
+            match wait_for_state(&rx, PublishItemState::PubackRecieved) {
+                Ok(()) => {
+                    METRICS.qos_1_retries.observe(0.0);
                 }
+                Err(RecvTimeoutError::Timeout) => {
+                    // Send the packet to the client again, once
+                    let _ = client_tx.send(Ok(packet.clone()));
+                    debug!(packet_id, "No PUBACK received, sending PUBLISH again");
 
-                thread::sleep(Duration::from_millis(100));
+                    METRICS.qos_1_retries.observe(1.0);
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    // The flow was removed with the session, or replaced when the session was resumed
+                    return;
+                }
             }
 
-            // Send the packet to the client
-            let _ = client_clone.tx.send(Ok(packet.clone()));
-
-            // Unlock the publish_queue, or wait until it is available
-            let mut publish_queue: MutexGuard<'_, Vec<PublishQueueItem>> = publish_queue_clone.lock().unwrap();
-
-            // Attempt to find the publish item in the queue, to remove it.
-            if let Some(index) = publish_queue
-                .iter()
-                .position(|t: &PublishQueueItem| t.packet_id == packet_id)
-            {
-                publish_queue.remove(index);
-            }
+            publish_queue.remove(&key);
         });
-    } else if *qos == 2 {
-        // Create a new channel, for later passing the tx back to another thread (handle_connection), for inter-thread communication.
-        let (tx, rx): (Sender<PublishItemState>, Receiver<PublishItemState>) = channel();
-
-        // Clones the publish queue list
-        let publish_queue_clone: Arc<Mutex<Vec<PublishQueueItem>>> = Arc::clone(&publish_queue);
-
-        // Clone the client
-        let client_clone: Client = client.clone();
+    } else if qos == 2 {
+        // Clone the client sender, for sending the PUBLISH and PUBREL again
+        let client_tx: OutboundSender = client.tx.clone();
 
         // QoS 2 Session thread
         thread::spawn(move || {
+            let _enter = span.enter();
 
-            // Adds a new publish queue item to the publish queue 
-            {
-                let mut publish_queue: MutexGuard<'_, Vec<PublishQueueItem>> = publish_queue_clone.lock().unwrap();
-
-                // Adds a new publish queue item to the publish queue 
-                publish_queue.push(PublishQueueItem {
-                    tx,
-                    packet_id,
-                    timestamp_sent: Instant::now(),
-                    publish_packet: packet.clone(),
-                    state: PublishItemState::AwaitingPubrec,
-                    qos_level: 2,
-                    flow_direction: PublishItemDirection::ToSubscriber,
-                });
-            }
+            // Counts how many times the PUBLISH or PUBREL had to be sent again
+            let mut retries: u32 = 0;
 
-            let mut has_received_pubrec: bool = false;
-            // Wait for 2220 miliseconds, calculated by the max size of a payload (256 mb)
-            // Downloaded with a 10Mbps internet connection (205 seconds), and then a little 
-            // extra time to handle the packet and get the reply.
-            'pubrec: while !has_received_pubrec {
-                for _i in 0..2220 {
-                    match rx.try_recv() {
-                        Ok(state) => {
-                            if state == PublishItemState::PubrecRecieved {
-
-                                let mut publish_queue: MutexGuard<
-                                    '_,
-                                    Vec<PublishQueueItem>,
-                                > = publish_queue_clone.lock().unwrap();
-                                if let Some(index) = publish_queue.iter().position(
-                                    |t: &PublishQueueItem| t.packet_id == packet_id,
-                                ) {
-                                    publish_queue[index].state = state;
-                                }
-
-                                has_received_pubrec = true;
-                                break 'pubrec;
-                            }
-                        }
-                        Err(err) => {
-                            println!("{1}Subscriber QoS 2 (Pubrec) | {5} Error! -> {2}{3}{0}{4}",
-                                err,
-                                Color::BrightRed,
-                                Reset::All,
-                                Style::Italic,
-                                Reset::All,
-                                client_clone.id
-                            );
-                        }
+            // Waits for the client to send a PUBREC, sending the PUBLISH again with the DUP flag set
+            loop {
+                match wait_for_state(&rx, PublishItemState::PubrecRecieved) {
+                    Ok(()) => {
+                        publish_queue.set_state(&key, PublishItemState::PubrecRecieved);
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        // Set dup flag on packet
+                        packet[0] |= 1 << 3;
+                        debug!(packet_id, "No PUBREC received, sending PUBLISH again");
+                        _ = client_tx.send(Ok(packet.clone()));
+                        retries += 1;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return;
                     }
-
-                    thread::sleep(Duration::from_millis(100));
                 }
-
-                // Set dup flag on packet
-                println!("{:b}", &packet[0]);
-                packet[0] |= 1 << 3;
-                _ = client_clone.tx.send(Ok(packet.clone()))
             }
 
-            // The control packet type and remaining length of the packet
-            let mut pubrel_packet: Vec<u8> = vec![96, 2];
-            
-            // Appends the packet id to the PUBREL packet
-            pubrel_packet.append(common_fn::msb_lsb_creater::split_into_msb_lsb(packet_id).to_vec().as_mut(),);
+            // The PUBREL packet, sent again until the client sends a PUBCOMP
+            let pubrel: Packet = Packet::Pubrel(Pubrel { packet_id: packet_id as u16 });
 
             // Sends pubrel to the client
-            _ = client_clone.tx.send(Ok(pubrel_packet));
+            _ = client_tx.send(pubrel.to_vec().map_err(String::from));
 
-            let mut has_received_pubcomp: bool = false;
             // Waits for the client to send a pubcomp
-            'pubcomp: while !has_received_pubcomp {
-                for _i in 0..2220 {
-                    match rx.try_recv() {
-                        Ok(state) => {
-                            if state == PublishItemState::PubcompRecieved {
-                                let mut publish_queue: MutexGuard<
-                                    '_,
-                                    Vec<PublishQueueItem>,
-                                > = publish_queue_clone.lock().unwrap();
-                                if let Some(index) = publish_queue.iter().position(
-                                    |t: &PublishQueueItem| t.packet_id == packet_id,
-                                ) {
-                                    publish_queue.remove(index);
-                                }
-                                has_received_pubcomp = true;
-                                break 'pubcomp;
-                            }
-                        }
-                        Err(_) => {}
+            loop {
+                match wait_for_state(&rx, PublishItemState::PubcompRecieved) {
+                    Ok(()) => {
+                        publish_queue.remove(&key);
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        // Send the PUBREL packet to the client
+                        _ = client_tx.send(pubrel.to_vec().map_err(String::from));
+                        retries += 1;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return;
                     }
-
-                    thread::sleep(Duration::from_millis(100));
                 }
-                
-                // Set the control packet type and remaining length of the packet
-                let mut pubrel_packet: Vec<u8> = vec![96, 2];
-                
-                // Appends the packet id to the PUBREL packet
-                pubrel_packet.append(common_fn::msb_lsb_creater::split_into_msb_lsb(packet_id).to_vec().as_mut(),);
-                
-                // Send the PUBREL packet to the client
-                _ = client_clone.tx.send(Ok(pubrel_packet));
             }
 
+            METRICS.qos_2_retries.observe(retries as f64);
         });
     }
 }
+
+/// Waits for the thread handling the client to pass on a state of a QoS flow.
+///
+/// # Arguments
+///
+/// * `rx` - The receiver of the flow, its sender is kept in the [`PublishQueueItem`].
+/// * `expected` - The state to wait for, other states are ignored.
+///
+/// # Returns
+///
+/// Ok once the state is received. [`RecvTimeoutError::Timeout`] if it does not arrive within
+/// [`RETRY_INTERVAL`], so the packet should be sent again, or [`RecvTimeoutError::Disconnected`]
+/// if the flow was removed from the publish queue.
+pub fn wait_for_state(rx: &Receiver<PublishItemState>, expected: PublishItemState) -> Result<(), RecvTimeoutError> {
+    let deadline: Instant = Instant::now() + RETRY_INTERVAL;
+
+    loop {
+        if rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))? == expected {
+            return Ok(());
+        }
+    }
+}
@@ -1,217 +1,55 @@
-use crate::{ common_fn, models::sub_info::SubInfo };
-use crate::models::text_formatter:: { Color, Style, Reset };
+use crate::common_fn;
+use crate::{ models::sub_info::SubInfo, packet::Subscribe };
 
 /// Handles the Subscribe packet received from the client.
 ///
 /// # Arguments
 ///
-/// * `buffer` - The buffer containing the packet data.
-/// * `packet_length` - The length of the packet.
+/// * `subscribe` - The SUBSCRIBE packet, decoded with [`packet::decode`](crate::packet::decode).
+/// * `max_qos` - The highest QoS the broker grants, a topic filter asking for more is granted this QoS.
 ///
 /// # Returns
 ///
-/// * `Result<SubInfo, &'static str>` - A result indicating success with subscription information
-///   or an error message if the packet is invalid.
+/// * `SubInfo` - The subscription information, with the granted QoS of every topic filter
+///   and a SUBACK return code per topic filter.
 ///
 /// # Description
 ///
-/// This function handles the Subscribe packet received from the client. It decodes the packet,
-/// validates its structure, retrieves the packet ID, extracts the topic filters and associated
-/// quality of service (QoS) levels, and constructs a SubInfo struct containing the subscription
-/// information. If the packet is invalid, an error message is returned.
+/// This function handles the Subscribe packet received from the client. The decoder has already
+/// checked the structure of the packet, so this function pairs every topic filter with the return
+/// code of the SUBACK: the granted quality of service (QoS), which is the requested QoS capped at `max_qos`,
+/// or the failure code 0x80 when the requested QoS is above 2 or the topic filter is not valid, e.g. `a/#/b` or `a+`
+/// (MQTT-4.7.1-2, MQTT-4.7.1-3). Topic filters with the failure code MUST NOT be subscribed.
 ///
 /// # Examples
 ///
 /// ```
-/// let buffer: [u8; 8192] = [0; 8192];
-/// let packet_length = 10;
+/// let subscribe: Subscribe = Subscribe {
+///     packet_id: 1,
+///     topic_filters: vec![("a".to_string(), 3), ("b".to_string(), 2), ("a/#/b".to_string(), 0)],
+/// };
 ///
-/// match handle(buffer, packet_length) {
-///     Ok(sub_info) => {
-///         println!("Packet ID: {}", sub_info.packet_id);
-///         println!("Subscription Information: {:?}", sub_info.topic_qos_pair);
-///         println!("Suback Packet: {:?}", sub_info.return_packet);
-///     }
-///     Err(err) => println!("Error: {}", err),
-/// }
+/// let sub_info: SubInfo = handle(subscribe, 1);
+/// assert_eq!(sub_info.return_codes, vec![0x80, 1, 0x80]);
 /// ```
-pub fn handle(buffer: &[u8], packet_length: usize) -> Result<SubInfo, &'static str> {
-    let mut remaining_length: usize = 0;
-
-    // Gets the remaining from the packet
-    match common_fn::bit_operations::decode_remaining_length(&buffer) {
-        Ok(value) => {
-            remaining_length = value;
-        }
-        Err(err) => println!("{1}Error! -> {2}{3}{0}{4}",
-                        err,
-                        Color::BrightRed,
-                        Reset::All,
-                        Style::Italic,
-                        Reset::All
-                    ),
-    }
-    
-    if packet_length < 6 {
-        return Err("Subscribe Packet does not have the required lenght");
-    }
-
-    let mut current_index: usize = packet_length - remaining_length;
-
-    // Test if the first byte have bit 1 is on
-    match common_fn::bit_operations::split_byte(&buffer[0], 4) {
-        Ok(value) => {
-            if value[1] != 2 {
-                return Err("The first byte have bit 1 is on");
-            }
-        }
-        Err(err) => println!("{1}Error! -> {2}{3}{0}{4}",
-                        err,
-                        Color::BrightRed,
-                        Reset::All,
-                        Style::Italic,
-                        Reset::All
-                    ),
-    }
-
-    let mut packet_id: u16 = 0;
-
-    // Get Packet ID
-    match common_fn::msb_lsb_reader::get_values(&buffer, current_index, false) {
-        Ok(response) => {
-            // Match conditions
-            packet_id = u16::try_from(response.0).ok().unwrap();
-
-            current_index = response.2;
-
-            //println!("Packet ID: {}", packet_id);
-        }
-        Err(err) => {
-            println!("{1}Error! -> {2}{3}{0}{4}",
-                err,
-                Color::BrightRed,
-                Reset::All,
-                Style::Italic,
-                Reset::All
-            );
-        }
-    }
-
-    // Holds the toptic filters and the associated QoS
-    let mut topic_qos_pair: Vec<(String, u8)> = Vec::new();
+pub fn handle(subscribe: Subscribe, max_qos: u8) -> SubInfo {
+    // The topic filters keep the requested QoS when it is refused, otherwise they get the granted QoS
+    let topic_qos_pair: Vec<(String, u8)> = subscribe.topic_filters
+        .into_iter()
+        .map(|(topic_filter, qos)| if qos >= 3 { (topic_filter, qos) } else { (topic_filter, qos.min(max_qos)) })
+        .collect();
 
     // Only to hold the qos so it can be used to suback packet
-    let mut qos_vec: Vec<u8> = Vec::new();
-
-    // Get all topic filters
-    while current_index <= remaining_length {
-        // Find topic filter
-        match common_fn::msb_lsb_reader::get_values(&buffer, current_index, true) {
-            Ok(response) => {
-                // Puts the current index to after read string
-                current_index = response.2;
-
-                // Gets the QoS to the topic filter
-                match common_fn::bit_operations::split_byte(&buffer[current_index], 6) {
-                    Ok(splited_byte) => {
-
-                        if splited_byte[1] >= 3 {
-                            topic_qos_pair.push((response.1, 0x80));
-                            qos_vec.push(0x80);
-                        }else{
-                        // Inserts both topic filter and QoS into the Vector
-                        topic_qos_pair.push((response.1, splited_byte[1]));
-                        qos_vec.push(splited_byte[1]);
-                        }
-
-                        current_index += 1;
-                    }
-                    Err(err) => println!("{1}Error! -> {2}{3}{0}{4}",
-                                    err,
-                                    Color::BrightRed,
-                                    Reset::All,
-                                    Style::Italic,
-                                    Reset::All
-                                ),
-                }
-            }
-            Err(err) => {
-                println!("{1}Error! -> {2}{3}{0}{4}",
-                    err,
-                    Color::BrightRed,
-                    Reset::All,
-                    Style::Italic,
-                    Reset::All
-                );
-            }
-        }
-    }
-
-    // Makes the suback_packet
-    let suback_packet: Vec<u8> = assemble_suback_packet(
-        qos_vec.as_slice(),
-        u16::to_be_bytes(packet_id)
-    )?;
-
-    return Ok(SubInfo {
-        packet_id,
+    let return_codes: Vec<u8> = topic_qos_pair
+        .iter()
+        .map(|(topic_filter, qos)| {
+            if *qos >= 3 || !common_fn::topic_filter::is_valid(topic_filter) { 0x80 } else { *qos }
+        })
+        .collect();
+
+    SubInfo {
+        packet_id: subscribe.packet_id,
         topic_qos_pair,
-        return_packet: suback_packet,
-    });
-}
-
-/// Assembles the SUBACK packet.
-///
-/// # Arguments
-///
-/// * `qos_arr` - An array slice containing the quality of service (QoS) levels.
-/// * `packet_id` - The packet ID as a 2-byte array.
-///
-/// # Returns
-///
-/// * `Result<Vec<u8>, &'static str>` - A result containing the assembled SUBACK packet if successful,
-///   or an error message if the assembly fails.
-///
-/// # Description
-///
-/// This function assembles the SUBACK packet used in MQTT communication. It takes an array slice of QoS levels
-/// and the packet ID, and constructs the SUBACK packet according to the MQTT protocol. The assembled packet
-/// is returned as a vector of bytes. If there is an error during assembly, an error message is returned.
-///
-/// # Examples
-///
-/// ```
-/// let qos_arr = &[0, 1, 2];
-/// let packet_id: [u8; 2] = [0x12, 0x34];
-///
-/// match assemble_suback_packet(qos_arr, packet_id) {
-///     Ok(suback_packet) => {
-///         println!("Assembled SUBACK packet: {:?}", suback_packet);
-///     }
-///     Err(err) => println!("Error: {}", err),
-/// }
-/// ```
-pub fn assemble_suback_packet(qos_arr: &[u8], packet_id: [u8; 2]) -> Result<Vec<u8>, &'static str> {
-    // The length of the SUBACK Packet
-    let packet_lenght: u8 = 2 + u8::try_from(qos_arr.len()).ok().unwrap();
-
-    // Return packet
-    let mut packet: Vec<u8> = Vec::new();
-
-    // SUBACK control Type: 9
-    packet.push(144);
-
-    // Remaining length
-    packet.push(packet_lenght);
-
-    // Packet ID
-    packet.push(packet_id[0]);
-    packet.push(packet_id[1]);
-
-    // Puts all the QoS levels in the packet
-    for i in qos_arr.iter() {
-        packet.push(*i);
+        return_codes,
     }
-
-    Ok(packet)
 }
@@ -0,0 +1,1038 @@
+//! An MQTT 3.1.1 broker, with the client, codec and services it is built from.
+//!
+//! The `mqtt_broker` binary runs a single [`broker::Broker`], the library lets tests, tools and
+//! fuzz targets use the same modules.
+
+use bytes::BytesMut;
+use std::collections::HashSet;
+use std::io::{ Read, Write };
+use std::net::{ SocketAddr, TcpStream };
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError, Sender };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use crate::models::client::{ Client, Clients };
+use crate::models::config::{ InvalidAction, LimitAction, TakeoverWill };
+use crate::models::publish_queue_item::{ PublishItemDirection, PublishItemState, PublishQueue, PublishQueueItem, PublishQueueKey };
+use crate::models::sub_info::SubInfo;
+use crate::models::topic::Topics;
+use crate::control_packet::ProtocolError;
+use crate::packet::{ Connack, DecodeError, Packet, Puback, Pubcomp, Pubrec, Suback, Unsuback };
+use crate::services::hooks::{ BrokerHooks, HookChain, Message, PublishAction };
+use crate::services::metrics::METRICS;
+use crate::services::outbound::OutboundSender;
+use crate::services::rate_limit::{ ClientLimiter, Decision, Limit };
+use crate::services::rules::RuleSet;
+use crate::services::schemas::SchemaSet;
+use crate::services::storage::state::Record;
+use crate::services::webhooks::Event;
+use tracing::{ debug, info, info_span, trace, warn, Span };
+
+pub mod bench;
+pub mod broker;
+pub mod cli;
+pub mod common_fn;
+pub mod control_packet;
+pub mod models;
+pub mod mqtt_client;
+pub mod packet;
+pub mod services;
+mod tests;
+
+/// Handles the connection with a client, continuously reading data from the client
+/// and processing incoming packets according to the MQTT protocol.
+///
+/// # Arguments
+///
+/// * `stream` - A mutable reference to a TCP stream representing the connection with the client.
+/// * `clients` - The client sessions of the broker, found by client id or socket address.
+/// * `topics` - The topics of the broker, with their subscribers and retained messages.
+/// * `publish_queue` - The QoS 1 and QoS 2 flows in flight.
+///
+/// # Description
+///
+/// This function processes incoming packets from the client according to the MQTT protocol.
+/// It continuously reads data from the client, interprets packet types, and handles them appropriately.
+/// Depending on the packet type, it performs actions such as establishing connections,
+/// publishing messages, subscribing to topics, unsubscribing, responding to PING requests, and disconnecting.
+///
+/// # Notes
+///
+/// This function spawns a separate thread to handle message transmission to the client
+/// and ensures that each client's connection and disconnection are logged.
+/// It also prints information about connected clients for debugging purposes.
+/// Furthermore it creates a bounded outbound queue, which the write thread drains to the client.
+///
+/// The shared state is sharded, each packet only locks the shards it needs for as long as it
+/// looks up or changes them. No lock is held while writing to a client or waiting on one.
+pub fn handle_connection(
+    mut stream: TcpStream,
+    clients: Arc<Clients>,
+    topics: Arc<Topics>,
+    publish_queue: Arc<PublishQueue>
+) {
+    let socket_addr: SocketAddr = stream.peer_addr().unwrap();
+
+    // Every log line of this connection carries the peer address, and the client id once it is known
+    let span: Span = info_span!("connection", peer = %socket_addr, client_id = tracing::field::Empty);
+    let _enter = span.enter();
+
+    // Creates the outbound queue of the connection, returning the sender/receiver halves.
+    // Everything sent on the sender is written to the client by the write thread, in order,
+    // and the queue is bounded by the `[outbound]` limits so a slow client can not grow it forever.
+    let (tx, rx) = services::outbound::channel();
+
+    // Copy the stream
+    let mut stream_clone: TcpStream = stream.try_clone().unwrap();
+
+    // Why the write thread closed the stream, e.g. "Session taken over", for the disconnect event
+    let closed_by: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let write_closed_by: Arc<Mutex<Option<String>>> = Arc::clone(&closed_by);
+
+    // Write thread
+    let write_span: Span = span.clone();
+    thread::spawn(move || {
+        let _enter = write_span.enter();
+
+        while let Ok(message) = rx.recv() {
+            match message {
+                Ok(response) => {
+                    // Sends the message to the client, write_all keeps writing after a partial write
+                    if let Err(err) = stream_clone.write_all(response.as_slice()).and_then(|_| stream_clone.flush()) {
+                        debug!("Closing stream: {}", err);
+
+                        *write_closed_by.lock().unwrap() = Some("Write error".to_string());
+                        _ = stream_clone.shutdown(std::net::Shutdown::Both);
+                        break;
+                    }
+                }
+                Err(err) => {
+                    debug!("Closing stream: {}", err);
+
+                    // Set before the shutdown, so the read loop finds it when its read fails
+                    *write_closed_by.lock().unwrap() = Some(err);
+                    _ = stream_clone.shutdown(std::net::Shutdown::Both);
+                    break;
+                }
+            }
+        }
+    });
+
+    _ = stream.set_read_timeout(Some(Duration::from_secs(0)));
+
+    // Print client connection information
+    info!("Client connected");
+
+    let mut has_first_packet_arrived: bool = false;
+    let mut client_id: String = String::new();
+    let mut username: String = String::new();
+    let mut discard_will_msg: bool = false;
+
+    // Set once the CONNACK accepted the connection, and why the read loop ended
+    let mut is_connected: bool = false;
+    let mut disconnect_reason: &str = "Connection closed by the client";
+
+    // The publish limits of this client, and the QoS 2 packet identifiers of PUBLISH packets
+    // dropped over the limit, which get a PUBCOMP for their PUBREL
+    let mut limiter: ClientLimiter = services::rate_limit::client_limiter();
+    let mut dropped_qos_2: HashSet<u16> = HashSet::new();
+    let mut limit_warned: bool = false;
+
+    // Bytes received from the client that are not decoded into a packet yet,
+    // a read can hold several packets or only a part of one
+    let mut received: BytesMut = BytesMut::new();
+
+    // Infinite loop to continuously read data from the client
+    loop {
+        let (packet, packet_length) = match packet::decode(&received) {
+            Ok(decoded) => decoded,
+            Err(DecodeError::Incomplete) => {
+                // Buffer to store received data from the client
+                let mut buffer: [u8; 8192] = [0; 8192];
+
+                match stream.read(&mut buffer) {
+                    // Check if the client has suddenly disconnected
+                    Ok(0) => {
+                        disconnect_reason = "Connection closed by the client";
+                        break;
+                    }
+                    Ok(length) => {
+                        received.extend_from_slice(&buffer[..length]);
+                        continue;
+                    }
+                    Err(err) => {
+                        // Print error if reading from the client fails
+                        warn!(error = %err, "Could not read from the client, closing the stream");
+
+                        // The read timeout is the keep alive, a read that times out means the client went quiet
+                        disconnect_reason = match err.kind() {
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => "Keep alive timeout",
+                            _ => "Read error",
+                        };
+                        break;
+                    }
+                }
+            }
+            Err(err) => {
+                // A malformed packet closes the connection
+                warn!("{}", ProtocolError::from(err));
+                disconnect_reason = "Malformed packet";
+                break;
+            }
+        };
+
+        // Remove the packet from the received bytes
+        let frame: BytesMut = received.split_to(packet_length);
+        let packet_type: u8 = packet.packet_type();
+
+        debug!(packet_type, packet_length, "Packet received");
+        trace!(packet = %common_fn::bit_operations::to_hex_string(&frame), "Packet dump");
+
+        METRICS.packet_received(packet_type);
+
+        // Match for incoming packets
+        match packet {
+            Packet::Connect(connect) if !has_first_packet_arrived => {
+                // Connect
+                match
+                    control_packet::connect::handle(
+                        connect,
+                        socket_addr,
+                        &clients,
+                        tx.clone()
+                    )
+                {
+                    Ok(response) => {
+                        span.record("client_id", response.client_id.as_str());
+                        client_id = response.client_id;
+                        username = response.username;
+
+                        let keep_alive: u64 = response.keep_alive;
+
+                        // A clean session starts without the subscriptions and QoS flows of the previous session,
+                        // the stored ones are removed with the session record below
+                        if response.session_discarded {
+                            topics.remove_client(&client_id);
+                            publish_queue.remove_client(&client_id);
+                        }
+
+                        // The connection that had the session is closing, its will is published if configured so.
+                        // Its disconnect is reported here, so it comes before anything of the new connection
+                        if let Some(previous) = response.taken_over {
+                            if services::session::takeover_will() == TakeoverWill::Publish {
+                                publish_will(&topics, &clients, Arc::clone(&publish_queue), &previous);
+                            }
+
+                            services::webhooks::emit(Event::ClientDisconnected {
+                                client_id: &client_id,
+                                address: previous.socket_addr,
+                                reason: control_packet::connect::TAKEN_OVER,
+                            });
+
+                            if let Some(hooks) = services::hooks::registered() {
+                                hooks.on_disconnect(&client_id, control_packet::connect::TAKEN_OVER);
+                            }
+                        }
+
+                        // Continue with handling the connection
+                        // Send response to the client
+                        _ = tx.send(Packet::Connack(response.connack).to_vec().map_err(String::from));
+
+                        // Persist the session, or forget a stored one when the client starts a clean session
+                        if let Some(client) = clients.get(&client_id) {
+                            services::storage::record(services::storage::session_record(&client));
+
+                            services::webhooks::emit(Event::ClientConnected {
+                                client_id: &client_id,
+                                username: &username,
+                                address: socket_addr,
+                                clean_session: client.connect_flags.clean_session_flag,
+                            });
+                        }
+
+                        is_connected = true;
+
+                        // A resumed session gets its unacknowledged messages again, with the DUP flag set
+                        // and their own packet ids, each with a new QoS flow
+                        if response.connack.session_present {
+                            if let Some(client) = clients.get(&client_id) {
+                                for message in services::storage::queued_messages(&client_id) {
+                                    control_packet::publish::resend_to_client(&client, Arc::clone(&publish_queue), message);
+                                }
+                            }
+                        }
+
+                        // Set keep_alive
+                        _ = stream.set_read_timeout(
+                            Some(Duration::from_secs(keep_alive))
+                        );
+                    }
+                    Err(err) => {
+                        warn!("{}", err);
+
+                        // Refuse the connection with a CONNACK when the error has a return code.
+                        // Nothing else was sent on this connection, so it is written directly,
+                        // the stream is shut down before the write thread could send it
+                        if let Some(return_code) = err.connack_code() {
+                            let connack: Packet = Packet::Connack(Connack { session_present: false, return_code });
+
+                            if let Ok(bytes) = connack.to_vec() {
+                                _ = stream.write_all(&bytes);
+                            }
+                        }
+
+                        break;
+                    }
+                }
+            }
+            Packet::Publish(publish) if has_first_packet_arrived => {
+                // PUBLISH
+                // Count the PUBLISH against the client's limits first
+                match limiter.check_publish(packet_length, Instant::now()) {
+                    Decision::Allow => {}
+                    Decision::Wait(wait) => {
+                        // Nothing is read from the client while waiting, so TCP pushes back on it
+                        log_over_limit(&mut limit_warned, Limit::Publishes, "throttling");
+                        thread::sleep(wait);
+                    }
+                    Decision::Drop => {
+                        log_over_limit(&mut limit_warned, Limit::Publishes, "dropping the message");
+
+                        // The PUBLISH is acknowledged all the same, so the client does not send it again
+                        acknowledge_dropped(&tx, publish.qos, publish.packet_id, &mut dropped_qos_2);
+
+                        continue;
+                    }
+                    Decision::Disconnect => {
+                        log_over_limit(&mut limit_warned, Limit::Publishes, "closing the connection");
+                        disconnect_reason = "Over the publish limit";
+                        break;
+                    }
+                }
+
+                match control_packet::publish::handle_publish(publish) {
+                    Ok(mut response) => {
+                        // MQTT 3.1.1 has no way to reject a PUBLISH, so a denied publish closes the connection
+                        if !services::auth::can_publish(&username, &response.topic_name) {
+                            warn!(topic = %response.topic_name, "{}", ProtocolError::NotAuthorized);
+                            disconnect_reason = "Not authorized";
+                            break;
+                        }
+
+                        // The registered hooks may change, redirect or drop the message before it is routed,
+                        // then it is checked against the schemas of its topic, and the rule engine may republish, send or drop it
+                        let hooks: Option<Arc<HookChain>> = services::hooks::registered();
+                        let schemas: Option<Arc<SchemaSet>> = services::schemas::active();
+                        let rules: Option<Arc<RuleSet>> = services::rules::active();
+
+                        if hooks.is_some() || schemas.is_some() || rules.is_some() {
+                            let mut message: Message = Message {
+                                topic: std::mem::take(&mut response.topic_name),
+                                payload: std::mem::take(&mut response.payload_message),
+                                qos: response.qos_level,
+                                retain: response.retain_flag,
+                            };
+
+                            let mut action: PublishAction = match hooks {
+                                Some(hooks) => hooks.on_publish(&client_id, &mut message),
+                                None => PublishAction::Continue,
+                            };
+
+                            // A redirected message needs a topic name it can be published on
+                            if action == PublishAction::Continue && (message.topic.is_empty() || message.topic.contains(['+', '#'])) {
+                                warn!(topic = %message.topic, "A hook redirected a PUBLISH to an invalid topic, dropping it");
+                                action = PublishAction::Drop;
+                            }
+
+                            // The topic the message ends up on is checked against the ACL too, a hook can not publish
+                            // for the client where the client may not publish itself
+                            if action == PublishAction::Continue && !services::auth::can_publish(&username, &message.topic) {
+                                warn!(topic = %message.topic, "A hook redirected a PUBLISH to a topic the client may not publish to, dropping it");
+                                action = PublishAction::Drop;
+                            }
+
+                            // An invalid message goes to its dead-letter topic instead of the subscribers
+                            if action == PublishAction::Continue {
+                                if let Some(Err(violation)) = schemas.map(|schemas: Arc<SchemaSet>| schemas.validate(&message)) {
+                                    warn!(topic = %message.topic, error = %violation.error, "PUBLISH payload does not follow the schema of its topic");
+                                    METRICS.invalid_payload();
+
+                                    if violation.dead_letter {
+                                        services::schemas::dead_letter(&topics, &clients, &publish_queue, &client_id, &message, &violation.error);
+                                    }
+
+                                    if violation.action == InvalidAction::Disconnect {
+                                        disconnect_reason = "Invalid payload";
+                                        break;
+                                    }
+
+                                    action = PublishAction::Drop;
+                                }
+                            }
+
+                            if action == PublishAction::Continue {
+                                if let Some(rules) = rules {
+                                    if rules.run(&topics, &clients, &publish_queue, &client_id, &message) {
+                                        action = PublishAction::Drop;
+                                    }
+                                }
+                            }
+
+                            if action == PublishAction::Drop {
+                                debug!(topic = %message.topic, "PUBLISH dropped by a hook, schema or rule");
+
+                                let packet_id: Option<u16> = if response.qos_level > 0 { Some(response.packet_id as u16) } else { None };
+                                acknowledge_dropped(&tx, response.qos_level, packet_id, &mut dropped_qos_2);
+                                continue;
+                            }
+
+                            response.topic_name = message.topic;
+                            response.payload_message = message.payload;
+                            response.retain_flag = message.retain;
+                        }
+
+                        // Check QoS
+                        match response.qos_level {
+                            0 => {
+                                if response.dup_flag {
+                                    disconnect_reason = "Protocol violation";
+                                    break;
+                                }
+
+                                message_accepted(&client_id, &response);
+
+                                // Publish to subscribers
+                                control_packet::publish::publish(
+                                    &topics,
+                                    &clients,
+                                    Arc::clone(&publish_queue),
+                                    &response.topic_name,
+                                    &response.payload_message,
+                                    &false,
+                                    &response.qos_level,
+                                    &false
+                                );
+
+                                METRICS.publish_latency.observe_duration(
+                                    response.received_at.elapsed()
+                                );
+                            }
+                            1 => {
+                                // A QoS 1 message may arrive more than once, every copy is published
+                                message_accepted(&client_id, &response);
+
+                                handle_qos_1_session(
+                                    tx.clone(),
+                                    response.clone(),
+                                    Arc::clone(&clients),
+                                    Arc::clone(&topics),
+                                    Arc::clone(&publish_queue)
+                                );
+                            }
+                            2 => {
+                                handle_qos_2_session(
+                                    tx.clone(),
+                                    client_id.clone(),
+                                    response.clone(),
+                                    Arc::clone(&clients),
+                                    Arc::clone(&topics),
+                                    Arc::clone(&publish_queue)
+                                );
+                            }
+                            _ => {
+                                disconnect_reason = "Protocol violation";
+                                break;
+                            }
+                        }
+
+                        // If response.retain_flag is set, the message replaces the retained message of the topic,
+                        // the topic is created if it does not exist
+                        if response.retain_flag {
+                            services::storage::record(Record::Retained {
+                                topic: response.topic_name.clone(),
+                                payload: response.payload_message.clone(),
+                                qos: response.qos_level,
+                            });
+
+                            topics.set_retained(
+                                &response.topic_name,
+                                (response.payload_message, response.qos_level)
+                            );
+                        }
+                    }
+                    Err(err) => {
+                        warn!("{}", err);
+                        disconnect_reason = "Protocol violation";
+                        break;
+                    }
+                }
+            }
+            Packet::Puback(puback) if has_first_packet_arrived => {
+                // PUBACK
+                let response: usize = puback.packet_id as usize;
+
+                // The message is delivered, so it is no longer queued for the session
+                services::storage::record(Record::Acknowledged {
+                    client_id: client_id.clone(),
+                    packet_id: response,
+                });
+
+                // Passes the PUBACK on to the QoS flow of the delivery with the packet id
+                publish_queue.notify(
+                    &PublishQueueKey::new(&client_id, PublishItemDirection::ToSubscriber, response),
+                    PublishItemState::PubackRecieved
+                );
+            }
+            Packet::Pubrec(pubrec) if has_first_packet_arrived => {
+                // PUBREC
+                let response: usize = pubrec.packet_id as usize;
+
+                // The message is delivered, so it is no longer queued for the session
+                services::storage::record(Record::Acknowledged {
+                    client_id: client_id.clone(),
+                    packet_id: response,
+                });
+
+                // Passes the PUBREC on to the QoS flow of the delivery with the packet id
+                publish_queue.notify(
+                    &PublishQueueKey::new(&client_id, PublishItemDirection::ToSubscriber, response),
+                    PublishItemState::PubrecRecieved
+                );
+            }
+            Packet::Pubrel(pubrel) if has_first_packet_arrived => {
+                // PUBREL
+                // A PUBLISH dropped over the limit has no QoS 2 session, it is completed here
+                if dropped_qos_2.remove(&pubrel.packet_id) {
+                    _ = tx.send(Packet::Pubcomp(Pubcomp { packet_id: pubrel.packet_id }).to_vec().map_err(String::from));
+                    continue;
+                }
+
+                let response: usize = pubrel.packet_id as usize;
+
+                // Passes the PUBREL on to the QoS flow of the PUBLISH with the packet id
+                publish_queue.notify(
+                    &PublishQueueKey::new(&client_id, PublishItemDirection::FromClient, response),
+                    PublishItemState::PubrelRecieved
+                );
+            }
+            Packet::Pubcomp(pubcomp) if has_first_packet_arrived => {
+                // PUBCOMP
+                let response: usize = pubcomp.packet_id as usize;
+
+                // Passes the PUBCOMP on to the QoS flow of the delivery with the packet id
+                publish_queue.notify(
+                    &PublishQueueKey::new(&client_id, PublishItemDirection::ToSubscriber, response),
+                    PublishItemState::PubcompRecieved
+                );
+            }
+            Packet::Subscribe(subscribe) if has_first_packet_arrived => {
+                // SUBSCRIBE
+                let mut sub_packet: SubInfo = control_packet::subcribe::handle(subscribe, services::rate_limit::max_qos());
+
+                // Topic filters denied by the ACL get the failure return code 0x80 in the SUBACK,
+                // and are not subscribed
+                let mut granted_topic_filters: Vec<(String, u8)> = Vec::new();
+
+                // With a subscription limit, the topic filters the client is already subscribed to.
+                // Subscribing to one of them again replaces the subscription, so it is not counted twice
+                let max_subscriptions: Option<usize> = services::rate_limit::max_subscriptions();
+                let mut subscribed: Vec<String> = match max_subscriptions {
+                    Some(_) => topics
+                        .subscriptions(&client_id)
+                        .into_iter()
+                        .map(|(topic_name, _)| topic_name)
+                        .collect(),
+                    None => Vec::new(),
+                };
+                let mut over_subscription_limit: bool = false;
+
+                for (index, topicfilter) in sub_packet.topic_qos_pair.iter().enumerate() {
+                    if sub_packet.return_codes[index] == 0x80 {
+                        // The topic filter or the requested QoS is invalid, the topic filter is refused
+                        warn!(topic_filter = %topicfilter.0, qos = topicfilter.1, "Subscribe with an invalid topic filter or QoS");
+                    } else if !services::auth::can_subscribe(&username, &topicfilter.0) {
+                        warn!(topic_filter = %topicfilter.0, "Subscribe denied by the ACL");
+                        sub_packet.return_codes[index] = 0x80;
+                    } else if
+                        max_subscriptions.is_some_and(|max: usize| {
+                            !subscribed.contains(&topicfilter.0) && subscribed.len() >= max
+                        })
+                    {
+                        // Over the subscription limit, the topic filter is refused
+                        sub_packet.return_codes[index] = 0x80;
+                        over_subscription_limit = true;
+                    } else {
+                        // The registered hooks may lower the QoS, or refuse the topic filter
+                        let granted_qos: Option<u8> = match services::hooks::registered() {
+                            Some(hooks) => hooks
+                                .on_subscribe(&client_id, &topicfilter.0, topicfilter.1)
+                                .map(|qos: u8| qos.min(topicfilter.1)),
+                            None => Some(topicfilter.1),
+                        };
+
+                        let Some(granted_qos) = granted_qos else {
+                            debug!(topic_filter = %topicfilter.0, "Subscribe refused by a hook");
+                            sub_packet.return_codes[index] = 0x80;
+                            continue;
+                        };
+
+                        if !subscribed.contains(&topicfilter.0) {
+                            subscribed.push(topicfilter.0.clone());
+                        }
+
+                        sub_packet.return_codes[index] = granted_qos;
+                        granted_topic_filters.push((topicfilter.0.clone(), granted_qos));
+                    }
+                }
+
+                if over_subscription_limit {
+                    if services::rate_limit::action() == LimitAction::Disconnect {
+                        log_over_limit(&mut limit_warned, Limit::Subscriptions, "closing the connection");
+                        disconnect_reason = "Over the subscription limit";
+                        break;
+                    }
+
+                    log_over_limit(&mut limit_warned, Limit::Subscriptions, "refusing the topic filters");
+                }
+
+                sub_packet.topic_qos_pair = granted_topic_filters;
+
+                // Sends suback to the client
+                let suback: Packet = Packet::Suback(Suback {
+                    packet_id: sub_packet.packet_id,
+                    return_codes: sub_packet.return_codes,
+                });
+                _ = tx.send(suback.to_vec().map_err(String::from));
+
+                // Finds the client that is connected from the socket_addr so we can add the client to the topic list
+                if let Some(client) = clients.get_by_addr(&socket_addr) {
+                    // Adding topic filters to the client
+                    for topicfilter in sub_packet.topic_qos_pair {
+                        // Adds the client to the topic list
+                        topics.subscribe(&client.id, topicfilter.clone());
+                        services::storage::record(Record::Subscribed {
+                            client_id: client.id.clone(),
+                            topic: topicfilter.0.clone(),
+                            qos: topicfilter.1,
+                        });
+                        services::webhooks::emit(Event::Subscribed {
+                            client_id: &client.id,
+                            topic_filter: &topicfilter.0,
+                            qos: topicfilter.1,
+                        });
+
+                        // Send a publish message for every topic the filter matches that has a retained message,
+                        // with its own topic name and at the lower of its QoS and the granted QoS (MQTT-3.3.1-6)
+                        for (topic_name, (message, retained_qos)) in topics.retained_matching(&topicfilter.0) {
+                            control_packet::publish::publish_to_client(
+                                &client,
+                                Arc::clone(&publish_queue),
+                                &topic_name,
+                                &message,
+                                &retained_qos.min(topicfilter.1),
+                                &true
+                            );
+                        }
+                    }
+                }
+            }
+            Packet::Unsubscribe(unsubscribe) if has_first_packet_arrived => {
+                // UNSUBSCRIBE
+                let unsub_packet: SubInfo = control_packet::unsubcribe::handle(unsubscribe);
+
+                // Finds the client that is connected from the socket_addr so we can remove the client from the topic list
+                if let Some(client_id) = clients.id_of(&socket_addr) {
+                    // Removing the client from the topic list
+                    for topic_name in unsub_packet.topic_qos_pair {
+                        services::storage::record(Record::Unsubscribed {
+                            client_id: client_id.clone(),
+                            topic: topic_name.0.clone(),
+                        });
+                        topics.unsubscribe(&client_id, &topic_name.0);
+                        services::webhooks::emit(Event::Unsubscribed {
+                            client_id: &client_id,
+                            topic_filter: &topic_name.0,
+                        });
+                    }
+                }
+
+                // Sends an unsuback
+                let unsuback: Packet = Packet::Unsuback(Unsuback { packet_id: unsub_packet.packet_id });
+                _ = tx.send(unsuback.to_vec().map_err(String::from));
+            }
+            Packet::Pingreq if has_first_packet_arrived => {
+                // PINGREQ
+                // Send response to the client
+                _ = tx.send(Packet::Pingresp.to_vec().map_err(String::from));
+            }
+            Packet::Disconnect if has_first_packet_arrived => {
+                // Disconnect
+                // The decoder has validated that the reserved bits are not set
+                discard_will_msg = true;
+                disconnect_reason = "Client disconnected";
+
+                break;
+            }
+            packet => {
+                // A CONNECT after the first packet, any other packet before it, or a packet only a server sends
+                let err: ProtocolError = if has_first_packet_arrived {
+                    ProtocolError::ProtocolViolation("Unexpected packet")
+                } else {
+                    ProtocolError::ProtocolViolation("The first packet is not a CONNECT")
+                };
+
+                warn!(packet_type = packet.packet_type(), "{}", err);
+                disconnect_reason = "Protocol violation";
+                break;
+            }
+        }
+
+        has_first_packet_arrived = true;
+    }
+
+    disconnect_client_by_socket_addr(
+        &topics,
+        &clients,
+        publish_queue,
+        socket_addr,
+        discard_will_msg
+    );
+
+    info!("Client disconnected");
+
+    // A stream closed by the write thread, e.g. for a session takeover or by the admin API, ends the
+    // read loop with a failed read, so its reason is the one that counts.
+    // The disconnect of a connection whose session was taken over is reported by the new connection
+    let closed_by: Option<String> = closed_by.lock().unwrap().take();
+
+    if is_connected && closed_by.as_deref() != Some(control_packet::connect::TAKEN_OVER) {
+        let reason: &str = closed_by.as_deref().unwrap_or(disconnect_reason);
+
+        services::webhooks::emit(Event::ClientDisconnected {
+            client_id: &client_id,
+            address: socket_addr,
+            reason,
+        });
+
+        if let Some(hooks) = services::hooks::registered() {
+            hooks.on_disconnect(&client_id, reason);
+        }
+    }
+
+    // Sends an error to the Write thread so it can stop the thread and closes the connection
+    _ = tx.send(Err("Close Stream".to_string()));
+
+    _ = stream.shutdown(std::net::Shutdown::Both);
+}
+
+/// Acknowledges a PUBLISH that is dropped, so the client does not send it again.
+///
+/// # Arguments
+///
+/// * `tx` - The sender of the publisher's connection.
+/// * `qos` - The QoS of the PUBLISH.
+/// * `packet_id` - The packet id of the PUBLISH, None for QoS 0.
+/// * `dropped_qos_2` - The dropped QoS 2 packet ids, a QoS 2 PUBLISH is added so its PUBREL gets a PUBCOMP.
+fn acknowledge_dropped(tx: &OutboundSender, qos: u8, packet_id: Option<u16>, dropped_qos_2: &mut HashSet<u16>) {
+    match (qos, packet_id) {
+        (1, Some(packet_id)) => {
+            _ = tx.send(Packet::Puback(Puback { packet_id }).to_vec().map_err(String::from));
+        }
+        (2, Some(packet_id)) => {
+            dropped_qos_2.insert(packet_id);
+            _ = tx.send(Packet::Pubrec(Pubrec { packet_id }).to_vec().map_err(String::from));
+        }
+        _ => {}
+    }
+}
+
+/// Logs a client going over one of its limits, and counts it in the metrics.
+///
+/// # Arguments
+///
+/// * `warned` - True once the client was warned about, later times are logged at debug level
+///   so a client in a tight loop does not flood the log.
+/// * `limit` - The limit that was exceeded.
+/// * `action` - What is done about it, for the log line.
+fn log_over_limit(warned: &mut bool, limit: Limit, action: &str) {
+    METRICS.rate_limited(limit);
+
+    if *warned {
+        debug!(limit = limit.name(), "Limit exceeded, {}", action);
+    } else {
+        warn!(limit = limit.name(), "Limit exceeded, {}", action);
+        *warned = true;
+    }
+}
+
+/// Disconnects a client based on its socket address and performs cleanup tasks.
+///
+/// # Arguments
+///
+/// * `topics` - The topics of the broker.
+/// * `clients` - The client sessions of the broker.
+/// * `publish_queue` - The QoS flows in flight, for publishing the will message.
+/// * `socket_addr` - The socket address of the client to be disconnected.
+/// * `discard_will_msg` - A boolean indicating whether to discard the client's will message.
+///
+/// # Description
+///
+/// This function disconnects a client based on its socket address and performs the following tasks:
+/// - Marks the client as disconnected, its session is kept.
+/// - Optionally discards the client's will message if specified.
+/// - Publishes the will message to clients that have subscribed to the will topic, unless it is discarded.
+/// - Removes a clean session with its subscriptions and QoS flows, it lasts only as long as the connection
+///   (MQTT-3.1.2-6). This also forgets the sessions of clients with an assigned client id.
+///
+/// The will message is published after the session is updated, so no lock is held while publishing.
+///
+/// # Examples
+/// ```
+/// // Obtain the socket address
+/// let socket_addr: SocketAddr = stream.peer_addr().unwrap();
+///
+/// disconnect_client_by_socket_addr(&topics, &clients, Arc::clone(&publish_queue), socket_addr, false);
+/// ```
+fn disconnect_client_by_socket_addr(
+    topics: &Topics,
+    clients: &Clients,
+    publish_queue: Arc<PublishQueue>,
+    socket_addr: SocketAddr,
+    discard_will_msg: bool
+) {
+    // Mark the client as disconnected, a client that was refused at CONNECT has no session to update
+    let Some(client) = clients.disconnect(&socket_addr) else {
+        return;
+    };
+
+    if discard_will_msg {
+        clients.with_mut(&client.id, |client: &mut Client| {
+            client.will_message = String::new();
+        });
+    }
+
+    // Publish the will message to clients that have subscribed on the will topic,
+    // unless the client disconnected gracefully or the will has been suppressed
+    if !discard_will_msg {
+        publish_will(topics, clients, Arc::clone(&publish_queue), &client);
+    }
+
+    if client.connect_flags.clean_session_flag && clients.remove_disconnected(&client.id) {
+        topics.remove_client(&client.id);
+        publish_queue.remove_client(&client.id);
+    }
+}
+
+/// Publishes the will message of a client that lost its connection, if it has one.
+///
+/// # Arguments
+///
+/// * `topics` - The topics of the broker.
+/// * `clients` - The client sessions of the broker.
+/// * `publish_queue` - The QoS flows in flight.
+/// * `client` - The session as it was while the connection was open.
+///
+/// # Description
+///
+/// With Will Retain set, the will also replaces the retained message of its topic (MQTT-3.1.2-17),
+/// like a PUBLISH with the retain flag. The subscribers get it without the retain flag, as they are subscribed already.
+fn publish_will(topics: &Topics, clients: &Clients, publish_queue: Arc<PublishQueue>, client: &Client) {
+    if !client.connect_flags.will_flag {
+        return;
+    }
+
+    control_packet::publish::publish(
+        topics,
+        clients,
+        publish_queue,
+        &client.will_topic,
+        &client.will_message,
+        &false,
+        &client.connect_flags.will_qos_flag,
+        &false
+    );
+
+    if client.connect_flags.will_retain_flag {
+        services::storage::record(Record::Retained {
+            topic: client.will_topic.clone(),
+            payload: client.will_message.clone(),
+            qos: client.connect_flags.will_qos_flag,
+        });
+
+        topics.set_retained(&client.will_topic, (client.will_message.clone(), client.connect_flags.will_qos_flag));
+    }
+}
+
+/// Reports a PUBLISH the broker accepted to the webhooks, and records it in the archives.
+///
+/// # Arguments
+///
+/// * `client_id` - The id of the publisher.
+/// * `response` - The PUBLISH, after the hooks and rules.
+///
+/// # Description
+///
+/// Called once the message is going to be published, so a PUBLISH closed as a protocol violation
+/// or a QoS 2 PUBLISH sent again before its PUBREL is not reported.
+fn message_accepted(client_id: &str, response: &control_packet::publish::Response) {
+    services::webhooks::emit(Event::MessagePublished {
+        client_id,
+        topic: &response.topic_name,
+        qos: response.qos_level,
+        retain: response.retain_flag,
+        payload: &response.payload_message,
+    });
+    services::archive::record(
+        client_id,
+        &response.topic_name,
+        response.qos_level,
+        response.retain_flag,
+        &response.payload_message
+    );
+}
+
+/// Handles a QoS 2 PUBLISH from a client, on its own thread.
+///
+/// # Arguments
+///
+/// * `tx` - The sender of the publisher's connection.
+/// * `client_id` - The id of the publisher.
+/// * `response` - The PUBLISH, handled by [`control_packet::publish::handle_publish`].
+/// * `clients` - The client sessions of the broker.
+/// * `topics` - The topics of the broker.
+/// * `publish_queue` - The QoS flows in flight, the flow of the PUBLISH is added to it.
+///
+/// # Description
+///
+/// The message is published to the subscribers and a PUBREC is sent, then the thread waits for the PUBREL,
+/// sending the PUBREC again every [`control_packet::publish::RETRY_INTERVAL`], and completes the flow with a PUBCOMP.
+/// A PUBLISH with a packet id that is already in flight is a duplicate, it only gets the PUBREC again.
+fn handle_qos_2_session(
+    tx: OutboundSender,
+    client_id: String,
+    response: control_packet::publish::Response,
+    clients: Arc<Clients>,
+    topics: Arc<Topics>,
+    publish_queue: Arc<PublishQueue>
+) {
+    // Creates the channels so the "main client" thread can send the QoS packets to the publisher QoS Session
+    let (tx_qos, rx_qos): (Sender<PublishItemState>, Receiver<PublishItemState>) = channel();
+
+    // QoS Session thread, logging in the context of the publisher's connection
+    let span: Span = Span::current();
+    thread::spawn(move || {
+        let _enter = span.enter();
+
+        // Save packet_id
+        let packet_id: usize = response.packet_id;
+        let key: PublishQueueKey = PublishQueueKey::new(&client_id, PublishItemDirection::FromClient, packet_id);
+
+        // The PUBREC packet, sent again until the client sends a PUBREL
+        let pubrec: Packet = Packet::Pubrec(Pubrec { packet_id: packet_id as u16 });
+
+        // Push new publish queue item to the list, before the PUBREC is sent, so a PUBREL that arrives
+        // right away finds it. If the packet id is already used then this is a duplicate, and the
+        // thread of the first PUBLISH completes the flow
+        let is_new: bool = publish_queue.push(PublishQueueItem {
+            tx: tx_qos,
+            client_id: client_id.clone(),
+            packet_id,
+            timestamp_sent: Instant::now(),
+            publish_packet: vec![],
+            state: PublishItemState::AwaitingPubrel,
+            qos_level: 2,
+            flow_direction: PublishItemDirection::FromClient,
+        });
+
+        if !is_new {
+            // Send pubrec to client (publisher)
+            _ = tx.send(pubrec.to_vec().map_err(String::from));
+            return;
+        }
+
+        message_accepted(&client_id, &response);
+
+        // Publish to subscribers with dup 0
+        control_packet::publish::publish(
+            &topics,
+            &clients,
+            Arc::clone(&publish_queue),
+            &response.topic_name,
+            &response.payload_message,
+            &false,
+            &response.qos_level,
+            &false
+        );
+
+        METRICS.publish_latency.observe_duration(response.received_at.elapsed());
+
+        // Send pubrec to client (publisher)
+        _ = tx.send(pubrec.to_vec().map_err(String::from));
+
+        // Counts how many times the PUBREC had to be sent again
+        let mut retries: u32 = 0;
+
+        // Loops until we have received Pubrel packet
+        loop {
+            match control_packet::publish::wait_for_state(&rx_qos, PublishItemState::PubrelRecieved) {
+                Ok(()) => {
+                    // Send Pubcomp, and remove the publish queue item from the queue
+                    let pubcomp: Packet = Packet::Pubcomp(Pubcomp { packet_id: packet_id as u16 });
+                    _ = tx.send(pubcomp.to_vec().map_err(String::from));
+
+                    publish_queue.remove(&key);
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    // Sends pubrec again if we have not received pubrel from the client
+                    _ = tx.send(pubrec.to_vec().map_err(String::from));
+                    retries += 1;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    // The flow was removed with the session
+                    return;
+                }
+            }
+        }
+
+        METRICS.qos_2_retries.observe(retries as f64);
+    });
+}
+
+/// Handles a QoS 1 PUBLISH from a client, on its own thread: the message is published
+/// to the subscribers, then the PUBACK is sent.
+fn handle_qos_1_session(
+    tx: OutboundSender,
+    response: control_packet::publish::Response,
+    clients: Arc<Clients>,
+    topics: Arc<Topics>,
+    publish_queue: Arc<PublishQueue>
+) {
+    // QoS Session thread, logging in the context of the publisher's connection
+    let span: Span = Span::current();
+    thread::spawn(move || {
+        let _enter = span.enter();
+
+        // Store the packet id
+        let packet_id: usize = response.packet_id;
+
+        // Publish to subscribers with dup 0
+        control_packet::publish::publish(
+            &topics,
+            &clients,
+            publish_queue,
+            &response.topic_name,
+            &response.payload_message,
+            &false,
+            &response.qos_level,
+            &false
+        );
+
+        METRICS.publish_latency.observe_duration(response.received_at.elapsed());
+
+        // Send Puback packet
+        let puback: Packet = Packet::Puback(Puback { packet_id: packet_id as u16 });
+        _ = tx.send(puback.to_vec().map_err(String::from));
+    });
+}
@@ -3,4 +3,4 @@ pub mod flags;
 pub mod topic;
 pub mod sub_info;
 pub mod publish_queue_item;
-pub mod text_formatter;
+pub mod config;
@@ -1,10 +1,12 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashSet;
 use std::hash::{ Hash, Hasher };
 use std::net::SocketAddr;
-use std::sync::mpsc::Sender;
 
 use super::flags::ConnectFlags;
 use super::topic::Topic;
+use crate::common_fn::sharded_map::ShardedMap;
+use crate::services::outbound::OutboundSender;
 
 #[derive(Debug, Clone)]
 pub struct Client {
@@ -18,7 +20,7 @@ pub struct Client {
     pub password: String,
     pub socket_addr: SocketAddr,
     pub connect_flags: ConnectFlags,
-    pub tx: Sender<Result<Vec<u8>, String>>,
+    pub tx: OutboundSender,
 }
 
 // Implement Eq, PartialEq, and Hash for the Client struct
@@ -63,6 +65,7 @@ impl Hash for Client {
 
 impl Client {
     // Constructor for creating a new client session
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         client_id: String,
         will_topic: String,
@@ -71,7 +74,7 @@ impl Client {
         username: String,
         password: String,
         socket_addr: SocketAddr,
-        tx: Sender<Result<Vec<u8>, String>>,
+        tx: OutboundSender,
         connect_flags: ConnectFlags
     ) -> Client {
         Client {
@@ -134,3 +137,169 @@ impl Client {
         self.is_connected = false;
     }
 }
+
+/// The client sessions of a broker, found by client id or by the socket address of their connection.
+///
+/// # Description
+///
+/// Sessions are kept in a [`ShardedMap`] by client id, and the connected ones are indexed
+/// by socket address, so both lookups take constant time and only lock a single shard.
+/// Disconnected sessions stay in the map, without an entry in the address index.
+#[derive(Debug, Default)]
+pub struct Clients {
+    by_id: ShardedMap<String, Client>,
+    by_addr: ShardedMap<SocketAddr, String>,
+}
+
+impl Clients {
+    pub fn new() -> Clients {
+        Clients::default()
+    }
+
+    /// Adds a session, replacing a session with the same client id.
+    pub fn insert(&self, client: Client) {
+        let id: String = client.id.clone();
+        let connected_addr: Option<SocketAddr> = client.is_connected.then_some(client.socket_addr);
+
+        if let Some(replaced) = self.by_id.insert(id.clone(), client) {
+            self.unindex(&replaced);
+        }
+
+        if let Some(socket_addr) = connected_addr {
+            self.by_addr.insert(socket_addr, id);
+        }
+    }
+
+    /// Removes a session, returning it.
+    pub fn remove(&self, client_id: &str) -> Option<Client> {
+        let client: Client = self.by_id.remove(client_id)?;
+        self.unindex(&client);
+
+        Some(client)
+    }
+
+    /// Removes a session, unless a connection has taken it in the meantime.
+    ///
+    /// # Returns
+    ///
+    /// True if the session was removed.
+    pub fn remove_disconnected(&self, client_id: &str) -> bool {
+        self.by_id.entry(client_id.to_string(), |entry: Entry<'_, String, Client>| {
+            match entry {
+                Entry::Occupied(occupied) if !occupied.get().is_connected => {
+                    occupied.remove();
+                    true
+                }
+                _ => false,
+            }
+        })
+    }
+
+    /// Returns a copy of a session.
+    pub fn get(&self, client_id: &str) -> Option<Client> {
+        self.by_id.get(client_id)
+    }
+
+    /// Returns a copy of the session connected from a socket address.
+    pub fn get_by_addr(&self, socket_addr: &SocketAddr) -> Option<Client> {
+        self.get(&self.id_of(socket_addr)?)
+    }
+
+    /// The client id of the session connected from a socket address.
+    pub fn id_of(&self, socket_addr: &SocketAddr) -> Option<String> {
+        self.by_addr.get(socket_addr)
+    }
+
+    /// Runs a closure on a session, see [`ShardedMap::with`].
+    pub fn with<R>(&self, client_id: &str, f: impl FnOnce(&Client) -> R) -> Option<R> {
+        self.by_id.with(client_id, f)
+    }
+
+    /// Runs a closure on a session that may change it, see [`ShardedMap::with_mut`].
+    /// The socket address and connection state are not changed this way, see [`Clients::entry`].
+    pub fn with_mut<R>(&self, client_id: &str, f: impl FnOnce(&mut Client) -> R) -> Option<R> {
+        self.by_id.with_mut(client_id, f)
+    }
+
+    /// Runs a closure on the entry of a client id, so a CONNECT can check for an existing session
+    /// and take it over in a single step. The address index is updated afterwards, a session taken over
+    /// from another connection is no longer found by the address of that connection.
+    pub fn entry<R>(&self, client_id: String, f: impl FnOnce(Entry<'_, String, Client>) -> R) -> R {
+        let connected_addr = |clients: &Clients| -> Option<SocketAddr> {
+            clients.by_id
+                .with(&client_id, |client: &Client| client.is_connected.then_some(client.socket_addr))
+                .flatten()
+        };
+
+        let previous_addr: Option<SocketAddr> = connected_addr(self);
+        let result: R = self.by_id.entry(client_id.clone(), f);
+        let current_addr: Option<SocketAddr> = connected_addr(self);
+
+        if let Some(socket_addr) = previous_addr.filter(|socket_addr: &SocketAddr| Some(*socket_addr) != current_addr) {
+            if self.by_addr.get(&socket_addr).as_deref() == Some(client_id.as_str()) {
+                self.by_addr.remove(&socket_addr);
+            }
+        }
+
+        if let Some(socket_addr) = current_addr {
+            self.by_addr.insert(socket_addr, client_id);
+        }
+
+        result
+    }
+
+    /// Marks the session connected from a socket address as disconnected.
+    ///
+    /// # Returns
+    ///
+    /// A copy of the session as it was before it was disconnected, or None if no session
+    /// is connected from the address. A session that was taken over by another connection
+    /// in the meantime is left alone.
+    pub fn disconnect(&self, socket_addr: &SocketAddr) -> Option<Client> {
+        let client_id: String = self.by_addr.remove(socket_addr)?;
+
+        self.by_id
+            .with_mut(&client_id, |client: &mut Client| {
+                if !client.is_connected || client.socket_addr != *socket_addr {
+                    return None;
+                }
+
+                let connected: Client = client.clone();
+                client.handle_disconnect();
+
+                Some(connected)
+            })
+            .flatten()
+    }
+
+    /// Visits every session, see [`ShardedMap::for_each`].
+    pub fn for_each(&self, mut f: impl FnMut(&Client)) {
+        self.by_id.for_each(|_, client: &Client| f(client));
+    }
+
+    /// Visits every session mutably, see [`ShardedMap::for_each_mut`].
+    pub fn for_each_mut(&self, mut f: impl FnMut(&mut Client)) {
+        self.by_id.for_each_mut(|_, client: &mut Client| f(client));
+    }
+
+    /// The number of sessions, connected or not.
+    pub fn len(&self) -> usize {
+        self.by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_id.is_empty()
+    }
+
+    /// The number of connected sessions.
+    pub fn connected(&self) -> usize {
+        self.by_id.count(|_, client: &Client| client.is_connected)
+    }
+
+    /// Drops the address index entry of a removed session, unless another session uses the address by now.
+    fn unindex(&self, client: &Client) {
+        if client.is_connected && self.by_addr.get(&client.socket_addr).as_deref() == Some(client.id.as_str()) {
+            self.by_addr.remove(&client.socket_addr);
+        }
+    }
+}
@@ -0,0 +1,696 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+
+use serde::Deserialize;
+
+/// The broker configuration, read from a TOML file at startup.
+///
+/// Every section is optional, so an empty file (or no file at all) gives the same
+/// behaviour as before the configuration file was introduced.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BrokerConfig {
+    pub listener: ListenerConfig,
+    pub metrics: MetricsConfig,
+    pub logging: LoggingConfig,
+    pub admin: AdminConfig,
+    pub shutdown: ShutdownConfig,
+    pub auth: AuthConfig,
+    pub acl: Vec<AclRule>,
+    pub storage: StorageConfig,
+    pub limits: LimitsConfig,
+    pub outbound: OutboundConfig,
+    pub session: SessionConfig,
+    #[serde(rename = "bridge")]
+    pub bridges: Vec<BridgeConfig>,
+    #[serde(rename = "webhook")]
+    pub webhooks: Vec<WebhookConfig>,
+    #[serde(rename = "rule")]
+    pub rules: Vec<RuleConfig>,
+    #[serde(rename = "schema")]
+    pub schemas: Vec<SchemaConfig>,
+    #[serde(rename = "archive")]
+    pub archives: Vec<ArchiveConfig>,
+}
+
+/// Where the MQTT listener binds.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ListenerConfig {
+    /// The address to bind to. When not set, the broker binds to the local ip on `port`.
+    pub address: Option<SocketAddr>,
+    pub port: u16,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        ListenerConfig {
+            address: None,
+            port: 1883,
+        }
+    }
+}
+
+/// The optional Prometheus `/metrics` listener.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// The address to serve `/metrics` on. The endpoint is disabled when not set.
+    pub address: Option<SocketAddr>,
+}
+
+/// The optional admin REST API.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// The address to serve the admin API on. The API is disabled when not set.
+    pub address: Option<SocketAddr>,
+    /// The bearer token every admin request must carry. The API refuses to start without one.
+    pub token: String,
+}
+
+/// How long a graceful shutdown may take.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// Seconds to wait for in-flight QoS 2 handshakes to complete, after the listener has stopped.
+    pub drain_timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            drain_timeout_secs: 10,
+        }
+    }
+}
+
+/// Username and password authentication of CONNECT packets.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Accept clients that connect without a username.
+    pub allow_anonymous: bool,
+    pub users: Vec<UserConfig>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            allow_anonymous: true,
+            users: Vec::new(),
+        }
+    }
+}
+
+/// A user that is allowed to connect.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserConfig {
+    pub username: String,
+    pub password: String,
+}
+
+/// A topic access rule. When no rules are configured every topic is open to everyone,
+/// otherwise a client may only use the topics a rule grants it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AclRule {
+    /// The username the rule applies to. The rule applies to every client when not set.
+    pub username: Option<String>,
+    /// A topic filter, `+` and `#` wildcards are allowed.
+    pub topic: String,
+    pub access: AclAccess,
+}
+
+/// What an ACL rule allows on its topics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclAccess {
+    /// Subscribe only.
+    Read,
+    /// Publish only.
+    Write,
+    /// Subscribe and publish.
+    ReadWrite,
+}
+
+/// Persistence of sessions, subscriptions, retained messages and queued QoS messages.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// The directory to store the state in. Persistence is disabled when not set.
+    pub path: Option<String>,
+    pub fsync: FsyncPolicy,
+    /// How often the log is synced with the `interval` policy.
+    pub fsync_interval_ms: u64,
+    /// How often the log is compacted into a snapshot.
+    pub snapshot_interval_secs: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            path: None,
+            fsync: FsyncPolicy::Interval,
+            fsync_interval_ms: 1000,
+            snapshot_interval_secs: 300,
+        }
+    }
+}
+
+impl StorageConfig {
+    /// Checks that the intervals are not zero, the storage thread would wake up without pause.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fsync_interval_ms == 0 {
+            return Err("storage.fsync_interval_ms must be greater than 0".to_string());
+        }
+
+        if self.snapshot_interval_secs == 0 {
+            return Err("storage.snapshot_interval_secs must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// When appended records are synced to disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    /// After every record. Nothing is lost, at the cost of a disk sync per change.
+    Always,
+    /// Every `fsync_interval_ms`. At most that much is lost when the machine crashes.
+    #[default]
+    Interval,
+    /// Left to the operating system.
+    Never,
+}
+
+/// Rate limits and quotas, each disabled when not set.
+///
+/// The rates are token buckets: a bucket holds up to its burst, one second of its rate by default,
+/// and is refilled at its rate.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// New connections per second from a single IP address, checked when the connection is accepted.
+    pub connections_per_second: Option<f64>,
+    pub connection_burst: Option<f64>,
+    /// PUBLISH packets per second from a single client.
+    pub publishes_per_second: Option<f64>,
+    pub publish_burst: Option<f64>,
+    /// PUBLISH bytes per second from a single client, counting the whole packet.
+    pub bytes_per_second: Option<f64>,
+    pub bytes_burst: Option<f64>,
+    /// The most topic filters a single client can be subscribed to.
+    pub max_subscriptions: Option<usize>,
+    /// The highest QoS granted in a SUBACK, a topic filter asking for more is granted this QoS.
+    pub max_qos: Option<u8>,
+    /// What happens to a client over its publish or subscription limit.
+    pub action: LimitAction,
+}
+
+impl LimitsConfig {
+    /// Checks that every rate and burst that is set is a positive number, a bucket that is never
+    /// refilled would make a client wait forever.
+    pub fn validate(&self) -> Result<(), String> {
+        let values: [(&str, Option<f64>); 6] = [
+            ("connections_per_second", self.connections_per_second),
+            ("connection_burst", self.connection_burst),
+            ("publishes_per_second", self.publishes_per_second),
+            ("publish_burst", self.publish_burst),
+            ("bytes_per_second", self.bytes_per_second),
+            ("bytes_burst", self.bytes_burst),
+        ];
+
+        for (name, value) in values {
+            if let Some(value) = value {
+                if !value.is_finite() || value <= 0.0 {
+                    return Err(format!("limits.{} must be a positive number, got {}", name, value));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What happens to a client over its limits. A connection over the connection rate is always closed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitAction {
+    /// Stop reading from the client until its PUBLISH fits the limit, so TCP pushes back on the client.
+    /// A subscription over the limit is refused with return code 0x80.
+    #[default]
+    Throttle,
+    /// Acknowledge a PUBLISH over the limit without delivering it.
+    /// A subscription over the limit is refused with return code 0x80.
+    Drop,
+    /// Close the connection, the will message is published.
+    Disconnect,
+}
+
+/// The queue of packets waiting to be written to a single client, each limit disabled when not set.
+///
+/// Only PUBLISH packets count against the limits and are dropped, the acknowledgements and
+/// responses of a client are always queued.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OutboundConfig {
+    /// The most PUBLISH packets queued for a client.
+    pub max_messages: Option<usize>,
+    /// The most PUBLISH bytes queued for a client, counting the whole packet.
+    pub max_bytes: Option<usize>,
+    /// What happens to a PUBLISH that does not fit the queue.
+    pub overflow: OverflowPolicy,
+}
+
+/// What happens when the outbound queue of a client is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued QoS 0 PUBLISH packets until the new one fits.
+    /// The new PUBLISH is dropped when there are not enough of them.
+    #[default]
+    DropOldest,
+    /// Drop the new PUBLISH.
+    DropNew,
+    /// Close the connection, the will message is published.
+    Disconnect,
+}
+
+/// How client sessions are identified, and handed between connections.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// What happens to the will of a connection that is taken over by a CONNECT with the same client id.
+    pub takeover_will: TakeoverWill,
+    /// The start of the client ids the broker assigns to clients that connect with an empty one.
+    pub assigned_id_prefix: String,
+    /// The longest client id a client may connect with. Any length is accepted when not set.
+    pub max_client_id_length: Option<usize>,
+    /// The characters a client id may have besides `0-9`, `a-z` and `A-Z`, e.g. `"-_:"`.
+    /// Any character is accepted when not set.
+    pub client_id_chars: Option<String>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            takeover_will: TakeoverWill::Suppress,
+            assigned_id_prefix: "auto-".to_string(),
+            max_client_id_length: None,
+            client_id_chars: None,
+        }
+    }
+}
+
+/// The will of a connection that is taken over, see [`SessionConfig::takeover_will`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TakeoverWill {
+    /// Drop the will, the client is still online on its new connection.
+    #[default]
+    Suppress,
+    /// Publish the will, as for any connection the broker closes.
+    Publish,
+}
+
+/// A connection to a remote broker, forwarding topics in one or both directions.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BridgeConfig {
+    /// A name for the log lines of the bridge.
+    pub name: String,
+    /// The address of the remote broker.
+    pub address: SocketAddr,
+    /// The client id used on both brokers. Defaults to `bridge-<name>`.
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(default = "default_bridge_keep_alive")]
+    pub keep_alive: u16,
+    /// The first reconnect delay, doubled after every failed attempt.
+    #[serde(default = "default_reconnect_min_secs")]
+    pub reconnect_min_secs: u64,
+    /// The longest reconnect delay.
+    #[serde(default = "default_reconnect_max_secs")]
+    pub reconnect_max_secs: u64,
+    pub topics: Vec<BridgeTopic>,
+}
+
+fn default_bridge_keep_alive() -> u16 {
+    60
+}
+
+fn default_reconnect_min_secs() -> u64 {
+    1
+}
+
+fn default_reconnect_max_secs() -> u64 {
+    60
+}
+
+/// A topic pattern forwarded by a bridge.
+///
+/// The local topic is `local_prefix` + `pattern`, the remote topic is `remote_prefix` + `pattern`.
+/// A message forwarded in either direction has its prefix swapped.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BridgeTopic {
+    /// A topic filter, `+` and `#` wildcards are allowed.
+    pub pattern: String,
+    pub direction: BridgeDirection,
+    /// The QoS of the subscriptions, and of the forwarded messages.
+    #[serde(default)]
+    pub qos: u8,
+    #[serde(default)]
+    pub local_prefix: String,
+    #[serde(default)]
+    pub remote_prefix: String,
+}
+
+/// Which way a bridge topic is forwarded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BridgeDirection {
+    /// From the remote broker to the local broker.
+    In,
+    /// From the local broker to the remote broker.
+    Out,
+    Both,
+}
+
+/// An HTTP endpoint the broker POSTs its events to, see [`crate::services::webhooks`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    /// The URL to POST to, `http://` or `https://`.
+    pub url: String,
+    /// The events sent to the endpoint. Every event is sent when not set.
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<WebhookEvent>,
+    /// The topic filters of the `message_published` events, `+` and `#` wildcards are allowed.
+    #[serde(default = "default_webhook_topics")]
+    pub topics: Vec<String>,
+    /// The value of the `Authorization` header, e.g. `"Bearer secret"`. No header is sent when not set.
+    pub authorization: Option<String>,
+    /// The most events in a single POST.
+    #[serde(default = "default_webhook_batch_size")]
+    pub batch_size: usize,
+    /// How long the first event of a batch waits for more events.
+    #[serde(default = "default_webhook_batch_interval_ms")]
+    pub batch_interval_ms: u64,
+    /// The most events waiting to be sent, events are dropped when it is full.
+    #[serde(default = "default_webhook_queue_size")]
+    pub queue_size: usize,
+    /// How many times a failed POST is sent again before its events are dropped.
+    #[serde(default = "default_webhook_max_retries")]
+    pub max_retries: u32,
+    /// The delay before the first retry, doubled after every retry.
+    #[serde(default = "default_webhook_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    /// How long connecting, writing the request and reading the response may take.
+    #[serde(default = "default_webhook_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_webhook_events() -> Vec<WebhookEvent> {
+    WebhookEvent::ALL.to_vec()
+}
+
+fn default_webhook_topics() -> Vec<String> {
+    vec!["#".to_string()]
+}
+
+fn default_webhook_batch_size() -> usize {
+    100
+}
+
+fn default_webhook_batch_interval_ms() -> u64 {
+    1000
+}
+
+fn default_webhook_queue_size() -> usize {
+    10_000
+}
+
+fn default_webhook_max_retries() -> u32 {
+    5
+}
+
+fn default_webhook_retry_backoff_ms() -> u64 {
+    500
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+    5000
+}
+
+/// The kinds of events a webhook can be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    ClientConnected,
+    /// Sent with the reason the connection was closed.
+    ClientDisconnected,
+    Subscribed,
+    Unsubscribed,
+    /// A PUBLISH accepted from a client, on a topic matching one of the webhook's topic filters.
+    MessagePublished,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::ClientConnected,
+        WebhookEvent::ClientDisconnected,
+        WebhookEvent::Subscribed,
+        WebhookEvent::Unsubscribed,
+        WebhookEvent::MessagePublished,
+    ];
+}
+
+/// A rule of the rule engine, see [`crate::services::rules`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConfig {
+    /// A name for the log lines of the rule.
+    pub name: String,
+    /// The topic filter of the messages the rule applies to, `+` and `#` wildcards are allowed.
+    pub topic: String,
+    /// Conditions on the fields of a JSON object payload, every one must hold.
+    /// A rule with conditions does not apply to other payloads.
+    #[serde(default)]
+    pub conditions: Vec<RuleCondition>,
+    pub action: RuleAction,
+    /// The topic of a republished message. `{1}`, `{2}`, ... are the levels matched by the wildcards
+    /// of `topic`, `{topic}` is the topic of the message and `{client_id}` its publisher.
+    /// Sent messages keep their topic when not set.
+    pub target: Option<String>,
+    /// The QoS of a republished message. The QoS of the message when not set.
+    pub qos: Option<u8>,
+    /// Retain the republished message.
+    #[serde(default)]
+    pub retain: bool,
+    /// Numeric fields of a JSON object payload to convert, before the message is republished or sent.
+    #[serde(default)]
+    pub convert: Vec<RuleConversion>,
+    /// Fields to set on a JSON object payload, after the conversions. String values are templates, like `target`.
+    #[serde(default)]
+    pub set: BTreeMap<String, serde_json::Value>,
+    /// The endpoint messages are sent to with the `sink` action, posted like a webhook.
+    pub sink: Option<WebhookConfig>,
+}
+
+/// A condition on a field of a JSON payload. The field is a path of object keys separated by dots,
+/// e.g. `reading.unit`. Every check that is set must hold.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleCondition {
+    pub field: String,
+    pub equals: Option<serde_json::Value>,
+    pub not_equals: Option<serde_json::Value>,
+    pub greater_than: Option<f64>,
+    pub less_than: Option<f64>,
+    /// True if the field must be present, false if it must be missing.
+    pub exists: Option<bool>,
+}
+
+/// What a rule does with a matching message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Publish the converted message to `target`, the message itself is delivered as well.
+    Republish,
+    /// Do not deliver the message. The publisher is still acknowledged.
+    Drop,
+    /// Post the converted message to the `sink` endpoint, the message itself is delivered as well.
+    Sink,
+}
+
+/// A linear conversion of a numeric field, `value * multiply + add`,
+/// e.g. Fahrenheit to Celsius with `multiply = 0.5555556` and `add = -17.777778`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleConversion {
+    pub field: String,
+    #[serde(default = "default_multiply")]
+    pub multiply: f64,
+    #[serde(default)]
+    pub add: f64,
+    /// The decimals to round the result to. Not rounded when not set.
+    pub decimals: Option<u32>,
+}
+
+fn default_multiply() -> f64 {
+    1.0
+}
+
+/// A JSON Schema the payloads published on a topic filter must follow, see [`crate::services::schemas`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct SchemaConfig {
+    /// The topic filter of the messages the schema applies to, `+` and `#` wildcards are allowed.
+    pub topic: String,
+    /// The schema, written as a TOML table. One of `schema` and `file` must be set.
+    pub schema: Option<serde_json::Value>,
+    /// The path of a JSON file with the schema.
+    pub file: Option<String>,
+    /// What happens to the publisher of an invalid message.
+    #[serde(default)]
+    pub on_invalid: InvalidAction,
+    /// Publish invalid messages to `$dlq/<topic>`, with the validation error.
+    #[serde(default = "default_dead_letter")]
+    pub dead_letter: bool,
+}
+
+fn default_dead_letter() -> bool {
+    true
+}
+
+/// What happens when a PUBLISH does not follow the schema of its topic.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvalidAction {
+    /// Acknowledge the PUBLISH without delivering it.
+    #[default]
+    Drop,
+    /// Close the connection, the will message is published.
+    Disconnect,
+}
+
+/// A message archive, recording the messages published on its topic filters to files,
+/// see [`crate::services::archive`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArchiveConfig {
+    /// The directory of the files, created if it does not exist.
+    pub directory: String,
+    /// The start of the file names, followed by the time the file was started.
+    #[serde(default = "default_archive_prefix")]
+    pub prefix: String,
+    /// The topic filters of the recorded messages, every topic by default.
+    #[serde(default = "default_archive_topics")]
+    pub topics: Vec<String>,
+    #[serde(default)]
+    pub format: ArchiveFormat,
+    /// Start a new file once the current one is this large. Not rotated by size when not set.
+    pub max_file_bytes: Option<u64>,
+    /// Start a new file once the current one is this old. Not rotated by time when not set.
+    pub rotate_interval_secs: Option<u64>,
+    /// How often the records written so far are flushed to the file.
+    #[serde(default = "default_archive_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    /// The most records waiting to be written, more are dropped.
+    #[serde(default = "default_archive_queue_size")]
+    pub queue_size: usize,
+}
+
+fn default_archive_prefix() -> String {
+    "messages".to_string()
+}
+
+fn default_archive_topics() -> Vec<String> {
+    vec!["#".to_string()]
+}
+
+fn default_archive_flush_interval_ms() -> u64 {
+    1000
+}
+
+fn default_archive_queue_size() -> usize {
+    10000
+}
+
+/// The file format of a message archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum ArchiveFormat {
+    /// One JSON object per line, in a `.jsonl` file.
+    #[default]
+    #[serde(rename = "jsonl")]
+    JsonLines,
+    /// JSON Lines compressed with gzip, in a `.jsonl.gz` file.
+    #[serde(rename = "jsonl_gz")]
+    JsonLinesGzip,
+    /// Parquet, in a `.parquet` file. Needs the `parquet` feature.
+    #[serde(rename = "parquet")]
+    Parquet,
+}
+
+impl BrokerConfig {
+    /// Reads and parses a configuration file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the TOML file to read.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the parsed [`BrokerConfig`], or an error message if the file
+    /// could not be read or is not valid TOML.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let config: BrokerConfig = BrokerConfig::load("mqtt_broker.toml").unwrap();
+    /// ```
+    pub fn load(path: &str) -> Result<BrokerConfig, String> {
+        let content: String = fs
+            ::read_to_string(path)
+            .map_err(|err| format!("Could not read config file {}: {}", path, err))?;
+
+        BrokerConfig::parse(&content)
+    }
+
+    /// Parses a configuration from a TOML string.
+    pub fn parse(content: &str) -> Result<BrokerConfig, String> {
+        let config: BrokerConfig = toml::from_str(content).map_err(|err| format!("Invalid config: {}", err))?;
+
+        // Values TOML accepts but the broker can not run with, checked here so a reload refuses them too
+        config.limits.validate().map_err(|err| format!("Invalid config: {}", err))?;
+        config.storage.validate().map_err(|err| format!("Invalid config: {}", err))?;
+
+        Ok(config)
+    }
+}
+
+/// How log output is filtered and formatted.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// `EnvFilter` directives, e.g. `info` or `info,mqtt_broker::control_packet=trace`.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+/// The output format of the logger.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines, for a terminal.
+    #[default]
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
@@ -1,5 +1,8 @@
+use std::collections::hash_map::Entry;
 use std::{sync::mpsc::Sender, time::Instant};
 
+use crate::common_fn::sharded_map::ShardedMap;
+
 #[derive(PartialEq, Debug)]
 #[allow(dead_code)]
 pub enum PublishItemState {
@@ -13,7 +16,7 @@ pub enum PublishItemState {
     PubcompRecieved,
 }
 
-#[derive( Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(dead_code)]
 pub enum PublishItemDirection {
     ToSubscriber,
@@ -21,7 +24,9 @@ pub enum PublishItemDirection {
 }
 
 #[derive( Debug)]
+#[allow(dead_code)]
 pub struct PublishQueueItem {
+    pub client_id: String,
     pub packet_id: usize,
     pub timestamp_sent: Instant,
     pub publish_packet: Vec<u8>,
@@ -30,3 +35,154 @@ pub struct PublishQueueItem {
     pub flow_direction: PublishItemDirection,
     pub tx: Sender<PublishItemState>
 }
+
+impl PublishQueueItem {
+    /// The key of the item in the [`PublishQueue`].
+    pub fn key(&self) -> PublishQueueKey {
+        PublishQueueKey::new(&self.client_id, self.flow_direction, self.packet_id)
+    }
+}
+
+/// Identifies a QoS flow in the [`PublishQueue`].
+///
+/// Packet identifiers are only unique per client and direction, so a PUBACK of one client
+/// can not complete the flow of another client that happens to use the same packet identifier.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PublishQueueKey {
+    pub client_id: String,
+    pub flow_direction: PublishItemDirection,
+    pub packet_id: usize,
+}
+
+impl PublishQueueKey {
+    pub fn new(client_id: &str, flow_direction: PublishItemDirection, packet_id: usize) -> PublishQueueKey {
+        PublishQueueKey { client_id: client_id.to_string(), flow_direction, packet_id }
+    }
+}
+
+/// The QoS 1 and QoS 2 flows in flight, kept in a [`ShardedMap`] by [`PublishQueueKey`].
+///
+/// # Description
+///
+/// Each flow is run by its own thread, which waits on the receiver of the item's `tx`.
+/// The connection threads pass the acknowledgements on with [`PublishQueue::notify`],
+/// which only locks the shard of the flow.
+#[derive(Debug, Default)]
+pub struct PublishQueue {
+    items: ShardedMap<PublishQueueKey, PublishQueueItem>,
+    /// The packet id last given to a delivery to each client, see [`PublishQueue::next_packet_id`].
+    packet_ids: ShardedMap<String, u16>,
+}
+
+impl PublishQueue {
+    pub fn new() -> PublishQueue {
+        PublishQueue::default()
+    }
+
+    /// Adds a flow to the queue.
+    ///
+    /// # Returns
+    ///
+    /// True if the item was added, false if a flow with the same key is already in flight.
+    pub fn push(&self, item: PublishQueueItem) -> bool {
+        self.items.entry(item.key(), |entry: Entry<'_, PublishQueueKey, PublishQueueItem>| {
+            match entry {
+                Entry::Occupied(_) => false,
+                Entry::Vacant(vacant) => {
+                    vacant.insert(item);
+                    true
+                }
+            }
+        })
+    }
+
+    /// Picks the packet id of a QoS 1 or QoS 2 delivery to a client.
+    ///
+    /// # Returns
+    ///
+    /// The id after the one last given to the client, skipping the ids of its deliveries in flight,
+    /// or None if all 65535 ids are in flight.
+    ///
+    /// # Description
+    ///
+    /// The ids are handed out in turn, so deliveries to the same client from different threads
+    /// get different ids, and an id is only used again after every other one was.
+    pub fn next_packet_id(&self, client_id: &str) -> Option<usize> {
+        self.packet_ids.entry(client_id.to_string(), |entry: Entry<'_, String, u16>| {
+            let last: &mut u16 = entry.or_insert(0);
+
+            for _ in 0..u16::MAX {
+                // Packet ids go from 1 to 65535, 0 is not allowed (MQTT-2.3.1-1)
+                *last = if *last == u16::MAX { 1 } else { *last + 1 };
+
+                let key: PublishQueueKey = PublishQueueKey::new(client_id, PublishItemDirection::ToSubscriber, *last as usize);
+                if !self.items.contains_key(&key) {
+                    return Some(*last as usize);
+                }
+            }
+
+            None
+        })
+    }
+
+    /// Removes a flow from the queue, returning it.
+    pub fn remove(&self, key: &PublishQueueKey) -> Option<PublishQueueItem> {
+        self.items.remove(key)
+    }
+
+    pub fn contains(&self, key: &PublishQueueKey) -> bool {
+        self.items.contains_key(key)
+    }
+
+    /// Passes a new state on to the thread running a flow.
+    ///
+    /// # Returns
+    ///
+    /// True if the flow is in flight.
+    pub fn notify(&self, key: &PublishQueueKey, state: PublishItemState) -> bool {
+        self.items
+            .with(key, |item: &PublishQueueItem| {
+                _ = item.tx.send(state);
+            })
+            .is_some()
+    }
+
+    /// Sets the state of a flow, as shown to the admin API and tests.
+    pub fn set_state(&self, key: &PublishQueueKey, state: PublishItemState) {
+        self.items.with_mut(key, |item: &mut PublishQueueItem| {
+            item.state = state;
+        });
+    }
+
+    /// Removes every flow of a client. The threads running them end without sending anything again.
+    pub fn remove_client(&self, client_id: &str) {
+        self.items.retain(|key: &PublishQueueKey, _| key.client_id != client_id);
+        self.packet_ids.remove(client_id);
+    }
+
+    /// Runs a closure on a flow, see [`ShardedMap::with`].
+    pub fn with<R>(&self, key: &PublishQueueKey, f: impl FnOnce(&PublishQueueItem) -> R) -> Option<R> {
+        self.items.with(key, f)
+    }
+
+    /// The keys of the flows in flight.
+    pub fn keys(&self) -> Vec<PublishQueueKey> {
+        let mut keys: Vec<PublishQueueKey> = Vec::new();
+        self.items.for_each(|key: &PublishQueueKey, _| keys.push(key.clone()));
+
+        keys
+    }
+
+    /// Counts the flows that the closure returns true for.
+    pub fn count(&self, mut f: impl FnMut(&PublishQueueItem) -> bool) -> usize {
+        self.items.count(|_, item: &PublishQueueItem| f(item))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
@@ -1,4 +1,9 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 use std::hash::{ Hash, Hasher };
+
+use crate::common_fn::{ self, sharded_map::ShardedMap };
+
 #[derive(Debug, Clone)]
 pub struct Topic {
     pub topic_name: String,
@@ -29,3 +34,161 @@ impl Hash for Topic {
         self.topic_name.hash(state);
     }
 }
+
+/// The topics of a broker, by topic name or topic filter, with their subscribers and retained message.
+///
+/// # Description
+///
+/// The topics are kept in a [`ShardedMap`], so subscribing and retaining lock a single shard.
+/// Routing a PUBLISH visits every topic filter, as wildcards can match, one shard at a time
+/// and under read locks, so publishers do not wait on each other.
+#[derive(Debug, Default)]
+pub struct Topics {
+    topics: ShardedMap<String, Topic>,
+}
+
+impl Topics {
+    pub fn new() -> Topics {
+        Topics::default()
+    }
+
+    /// Adds a topic, replacing a topic with the same name.
+    pub fn insert(&self, topic: Topic) {
+        self.topics.insert(topic.topic_name.clone(), topic);
+    }
+
+    /// Returns a copy of a topic.
+    pub fn get(&self, topic_name: &str) -> Option<Topic> {
+        self.topics.get(topic_name)
+    }
+
+    /// Subscribes a client to a topic filter, creating the topic if it does not exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `client_id` - The ID of the client to be added.
+    /// * `topic` - A tuple containing the topic filter and quality of service (QoS) level.
+    ///
+    /// # Description
+    ///
+    /// A client that is already subscribed to the topic filter is subscribed again with the new QoS.
+    pub fn subscribe(&self, client_id: &str, topic: (String, u8)) {
+        let (topic_name, qos): (String, u8) = topic;
+
+        self.topics.entry(topic_name.clone(), |entry: Entry<'_, String, Topic>| {
+            let topic: &mut Topic = entry.or_insert_with(|| Topic::new(topic_name));
+
+            topic.client_ids.retain(|(id, _)| id != client_id);
+            topic.client_ids.push((client_id.to_string(), qos));
+        });
+    }
+
+    /// Removes the subscription of a client to a topic filter.
+    ///
+    /// # Returns
+    ///
+    /// True if the client was subscribed to the topic filter.
+    pub fn unsubscribe(&self, client_id: &str, topic_name: &str) -> bool {
+        self.topics
+            .with_mut(topic_name, |topic: &mut Topic| {
+                let length: usize = topic.client_ids.len();
+                topic.client_ids.retain(|(id, _)| id != client_id);
+
+                topic.client_ids.len() != length
+            })
+            .unwrap_or(false)
+    }
+
+    /// Removes every subscription of a client.
+    pub fn remove_client(&self, client_id: &str) {
+        self.topics.for_each_mut(|_, topic: &mut Topic| {
+            topic.client_ids.retain(|(id, _)| id != client_id);
+        });
+    }
+
+    /// The clients subscribed with a topic filter that matches a topic name.
+    ///
+    /// # Returns
+    ///
+    /// The client ids with their QoS. A client subscribed with several matching topic filters
+    /// is listed once, with the highest QoS.
+    pub fn subscribers(&self, topic_name: &str) -> Vec<(String, u8)> {
+        let mut subscribers: HashMap<String, u8> = HashMap::new();
+
+        self.topics.for_each(|_, topic: &Topic| {
+            if common_fn::topic_filter::matches(&topic.topic_name, topic_name) {
+                for (client_id, qos) in topic.client_ids.iter() {
+                    let subscriber: &mut u8 = subscribers.entry(client_id.clone()).or_insert(*qos);
+                    *subscriber = (*subscriber).max(*qos);
+                }
+            }
+        });
+
+        subscribers.into_iter().collect()
+    }
+
+    /// The topic filters a client is subscribed to, with their QoS.
+    pub fn subscriptions(&self, client_id: &str) -> Vec<(String, u8)> {
+        let mut subscriptions: Vec<(String, u8)> = Vec::new();
+
+        self.topics.for_each(|_, topic: &Topic| {
+            if let Some((_, qos)) = topic.client_ids.iter().find(|(id, _)| id == client_id) {
+                subscriptions.push((topic.topic_name.clone(), *qos));
+            }
+        });
+
+        subscriptions
+    }
+
+    /// Sets the retained message of a topic, creating the topic if it does not exist.
+    /// An empty payload removes the retained message.
+    pub fn set_retained(&self, topic_name: &str, retained_msg: (String, u8)) {
+        self.topics.entry(topic_name.to_string(), |entry: Entry<'_, String, Topic>| {
+            entry.or_insert_with(|| Topic::new(topic_name.to_string())).retained_msg = retained_msg;
+        });
+    }
+
+    /// The retained message of a topic, None if it has none.
+    pub fn retained(&self, topic_name: &str) -> Option<(String, u8)> {
+        self.topics
+            .with(topic_name, |topic: &Topic| topic.retained_msg.clone())
+            .filter(|(payload, _)| !payload.is_empty())
+    }
+
+    /// The retained messages of the topics a topic filter matches.
+    ///
+    /// # Returns
+    ///
+    /// The topic names with their retained message and QoS, a topic without a retained message is not listed.
+    pub fn retained_matching(&self, topic_filter: &str) -> Vec<(String, (String, u8))> {
+        let mut retained: Vec<(String, (String, u8))> = Vec::new();
+
+        self.topics.for_each(|_, topic: &Topic| {
+            if !topic.retained_msg.0.is_empty() && common_fn::topic_filter::matches(topic_filter, &topic.topic_name) {
+                retained.push((topic.topic_name.clone(), topic.retained_msg.clone()));
+            }
+        });
+
+        retained
+    }
+
+    /// Removes the retained message of a topic, returning it.
+    pub fn take_retained(&self, topic_name: &str) -> Option<(String, u8)> {
+        self.topics
+            .with_mut(topic_name, |topic: &mut Topic| std::mem::take(&mut topic.retained_msg))
+            .filter(|(payload, _)| !payload.is_empty())
+    }
+
+    /// Visits every topic, see [`ShardedMap::for_each`].
+    pub fn for_each(&self, mut f: impl FnMut(&Topic)) {
+        self.topics.for_each(|_, topic: &Topic| f(topic));
+    }
+
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+}
@@ -0,0 +1,573 @@
+use std::collections::{ HashMap, HashSet };
+use std::io::Write;
+use std::net::{ Shutdown, SocketAddr, TcpStream };
+use std::sync::atomic::{ AtomicBool, AtomicU16, Ordering };
+use std::sync::mpsc::{ channel, Receiver, Sender };
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::Duration;
+
+use tracing::{ debug, info, trace, warn };
+
+use crate::common_fn;
+use crate::packet::{ Packet, Puback, Pubcomp, Pubrec, Pubrel, Subscribe, Unsuback, Unsubscribe };
+
+pub mod codec;
+
+use self::codec::{ connect_packet, message_from, publish_packet, read_packet };
+
+/// How long to wait for the broker to answer a CONNECT, SUBSCRIBE, UNSUBSCRIBE or QoS 1/2 PUBLISH.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A function called with the messages of a subscription, see [`MqttClient::subscribe_with`].
+type Callback = Arc<dyn Fn(&Message) + Send + Sync>;
+
+/// The options of the CONNECT packet, and of the connection.
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub client_id: String,
+    pub clean_session: bool,
+    /// Seconds between PINGREQ packets, 0 disables the keep alive.
+    pub keep_alive: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The will message, published by the broker if the connection is lost.
+    pub will: Option<Message>,
+    /// Reconnect automatically when the connection is lost, disabled when None.
+    pub reconnect: Option<Reconnect>,
+}
+
+impl ConnectOptions {
+    /// Creates options for a clean session with a 60 second keep alive, without reconnecting.
+    pub fn new(client_id: &str) -> ConnectOptions {
+        ConnectOptions {
+            client_id: client_id.to_string(),
+            clean_session: true,
+            keep_alive: 60,
+            username: None,
+            password: None,
+            will: None,
+            reconnect: None,
+        }
+    }
+}
+
+/// The delays between reconnect attempts. The delay starts at `min_delay`, and is doubled
+/// after every failed attempt up to `max_delay`.
+#[derive(Debug, Clone, Copy)]
+pub struct Reconnect {
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for Reconnect {
+    fn default() -> Reconnect {
+        Reconnect { min_delay: Duration::from_secs(1), max_delay: Duration::from_secs(60) }
+    }
+}
+
+/// An application message, sent or received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// An MQTT 3.1.1 client, connected to a single broker.
+///
+/// Received messages are passed to the callback of a matching [`MqttClient::subscribe_with`]
+/// subscription, or else delivered on the channel returned by [`MqttClient::connect`].
+/// The channel closes when the client is closed, or when the connection is lost and
+/// reconnecting is disabled.
+pub struct MqttClient {
+    address: SocketAddr,
+    options: ConnectOptions,
+    /// The writing half of the current connection, replaced on reconnect.
+    stream: Mutex<TcpStream>,
+    next_packet_id: AtomicU16,
+    /// The senders waiting for an acknowledgement (PUBACK, PUBCOMP, SUBACK or UNSUBACK), by packet identifier.
+    pending: Mutex<HashMap<u16, Sender<Vec<u8>>>>,
+    /// The granted subscriptions, subscribed again after a reconnect.
+    subscriptions: Mutex<Vec<(String, u8)>>,
+    /// The packet identifiers of the QoS 2 messages received and not released by a PUBREL yet,
+    /// a PUBLISH the broker sends again with one of them is not delivered twice.
+    received_qos_2: Mutex<HashSet<u16>>,
+    callbacks: Mutex<Vec<(String, Callback)>>,
+    connected: AtomicBool,
+    closed: AtomicBool,
+}
+
+impl MqttClient {
+    /// Connects to a broker and waits for the CONNACK.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the broker.
+    /// * `options` - The options of the CONNECT packet.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the client and the receiver of incoming messages, or an error message
+    /// if the connection failed or the broker refused it.
+    ///
+    /// # Description
+    ///
+    /// A reader thread answers the QoS flows of incoming messages, and a ping thread sends PINGREQ
+    /// packets at half the keep alive interval, so the broker never times the connection out.
+    /// With `options.reconnect` set, the reader thread reconnects when the connection is lost
+    /// and subscribes to the granted subscriptions again. Only the first connection attempt
+    /// returns an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (client, messages) = MqttClient::connect(address, &ConnectOptions::new("tool")).unwrap();
+    /// client.subscribe(&[("sensors/#".to_string(), 1)]).unwrap();
+    ///
+    /// for message in messages {
+    ///     println!("{}: {:?}", message.topic, message.payload);
+    /// }
+    /// ```
+    pub fn connect(
+        address: SocketAddr,
+        options: &ConnectOptions
+    ) -> Result<(Arc<MqttClient>, Receiver<Message>), String> {
+        let stream: TcpStream = open(address, options)?;
+
+        let (message_tx, message_rx) = channel::<Message>();
+
+        let client: Arc<MqttClient> = Arc::new(MqttClient {
+            address,
+            options: options.clone(),
+            stream: Mutex::new(stream.try_clone().map_err(|err| err.to_string())?),
+            next_packet_id: AtomicU16::new(1),
+            pending: Mutex::new(HashMap::new()),
+            subscriptions: Mutex::new(Vec::new()),
+            received_qos_2: Mutex::new(HashSet::new()),
+            callbacks: Mutex::new(Vec::new()),
+            connected: AtomicBool::new(true),
+            closed: AtomicBool::new(false),
+        });
+
+        // Reader thread
+        let reader_client: Arc<MqttClient> = Arc::clone(&client);
+        thread::spawn(move || {
+            reader_client.read_loop(stream, message_tx);
+        });
+
+        // Ping thread, ends when the client is closed
+        if options.keep_alive > 0 {
+            let ping_client: Arc<MqttClient> = Arc::clone(&client);
+            let interval: Duration = Duration::from_secs(options.keep_alive as u64) / 2;
+
+            thread::spawn(move || {
+                while !ping_client.closed.load(Ordering::SeqCst) {
+                    thread::sleep(interval);
+
+                    if ping_client.is_connected() {
+                        _ = ping_client.send(&Packet::Pingreq);
+                    }
+                }
+            });
+        }
+
+        Ok((client, message_rx))
+    }
+
+    /// Publishes a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The message to publish, at QoS 0, 1 or 2.
+    ///
+    /// # Returns
+    ///
+    /// A Result that is an error message if the packet could not be written, or if a QoS 1
+    /// or QoS 2 message was not acknowledged.
+    ///
+    /// # Description
+    ///
+    /// A QoS 0 message returns once it is written. A QoS 1 message waits for the PUBACK,
+    /// a QoS 2 message for the PUBCOMP, the PUBREL in between is sent by the reader thread.
+    /// A message is not resent when the connection is lost before it was acknowledged.
+    pub fn publish(&self, message: &Message) -> Result<(), String> {
+        if message.qos > 2 {
+            return Err(format!("Invalid QoS {}", message.qos));
+        }
+
+        if message.qos == 0 {
+            return self.send(&publish_packet(message, 0));
+        }
+
+        let packet_id: u16 = self.next_packet_id();
+
+        self.request(&publish_packet(message, packet_id), packet_id, "PUBLISH").map(|_| ())
+    }
+
+    /// Subscribes to topic filters, and waits for the SUBACK.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic_filters` - The topic filters and their requested QoS.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the return codes of the SUBACK, one per topic filter,
+    /// or an error message if no SUBACK arrived.
+    pub fn subscribe(&self, topic_filters: &[(String, u8)]) -> Result<Vec<u8>, String> {
+        let packet_id: u16 = self.next_packet_id();
+
+        let subscribe: Packet = Packet::Subscribe(Subscribe { packet_id, topic_filters: topic_filters.to_vec() });
+
+        let return_codes: Vec<u8> = self.request(&subscribe, packet_id, "SUBSCRIBE")?;
+
+        // Remember the granted subscriptions, to subscribe again after a reconnect
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+
+        for ((topic_filter, qos), return_code) in topic_filters.iter().zip(&return_codes) {
+            if *return_code != 0x80 {
+                subscriptions.retain(|(subscribed, _)| subscribed != topic_filter);
+                subscriptions.push((topic_filter.clone(), *qos));
+            }
+        }
+
+        Ok(return_codes)
+    }
+
+    /// Subscribes to a topic filter, passing its messages to a callback instead of the channel.
+    ///
+    /// # Arguments
+    ///
+    /// * `topic_filter` - The topic filter, `+` and `#` wildcards are allowed.
+    /// * `qos` - The requested QoS.
+    /// * `callback` - Called on the reader thread for every message matching the topic filter.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the return code of the SUBACK, or an error message if no SUBACK arrived.
+    /// The callback is only kept if the subscription was granted.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// client.subscribe_with("buttons/+", 1, |message: &Message| {
+    ///     println!("{} pressed", message.topic);
+    /// }).unwrap();
+    /// ```
+    pub fn subscribe_with(
+        &self,
+        topic_filter: &str,
+        qos: u8,
+        callback: impl Fn(&Message) + Send + Sync + 'static
+    ) -> Result<u8, String> {
+        // Add the callback first, so a retained message sent right after the SUBACK reaches it
+        self.callbacks.lock().unwrap().push((topic_filter.to_string(), Arc::new(callback)));
+
+        let return_code: Result<u8, String> = self
+            .subscribe(&[(topic_filter.to_string(), qos)])
+            .map(|return_codes: Vec<u8>| return_codes.first().copied().unwrap_or(0x80));
+
+        if !matches!(return_code, Ok(0..=2)) {
+            let mut callbacks = self.callbacks.lock().unwrap();
+
+            if let Some(index) = callbacks.iter().rposition(|(filter, _)| filter == topic_filter) {
+                callbacks.remove(index);
+            }
+        }
+
+        return_code
+    }
+
+    /// Unsubscribes from topic filters, and waits for the UNSUBACK.
+    ///
+    /// # Returns
+    ///
+    /// A Result that is an error message if no UNSUBACK arrived.
+    /// The callbacks of the topic filters are removed as well.
+    pub fn unsubscribe(&self, topic_filters: &[String]) -> Result<(), String> {
+        let packet_id: u16 = self.next_packet_id();
+
+        let unsubscribe: Packet = Packet::Unsubscribe(Unsubscribe { packet_id, topic_filters: topic_filters.to_vec() });
+
+        self.request(&unsubscribe, packet_id, "UNSUBSCRIBE")?;
+
+        self.subscriptions.lock().unwrap().retain(|(subscribed, _)| !topic_filters.contains(subscribed));
+        self.callbacks.lock().unwrap().retain(|(filter, _)| !topic_filters.contains(filter));
+
+        Ok(())
+    }
+
+    /// Returns true while the client has a connection to the broker.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Sends a DISCONNECT and closes the connection. The will message is discarded by the broker.
+    pub fn disconnect(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        _ = self.send(&Packet::Disconnect);
+        self.close();
+    }
+
+    /// Closes the connection without a DISCONNECT, as if the network failed, and does not reconnect.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        _ = self.stream.lock().unwrap().shutdown(Shutdown::Both);
+    }
+
+    fn next_packet_id(&self) -> u16 {
+        // Packet identifiers are non-zero
+        loop {
+            let packet_id: u16 = self.next_packet_id.fetch_add(1, Ordering::Relaxed);
+
+            if packet_id != 0 {
+                return packet_id;
+            }
+        }
+    }
+
+    fn send(&self, packet: &Packet) -> Result<(), String> {
+        let bytes: Vec<u8> = packet.to_vec()?;
+
+        self.stream
+            .lock()
+            .unwrap()
+            .write_all(&bytes)
+            .map_err(|err| format!("Could not send packet: {}", err))
+    }
+
+    /// Sends a packet and waits for the acknowledgement with the same packet identifier.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the return codes of a SUBACK, empty for the other acknowledgements,
+    /// or an error message if none arrived in time or the connection was lost.
+    fn request(&self, packet: &Packet, packet_id: u16, packet_name: &str) -> Result<Vec<u8>, String> {
+        let (tx, rx) = channel::<Vec<u8>>();
+        self.pending.lock().unwrap().insert(packet_id, tx);
+
+        let result: Result<Vec<u8>, String> = self
+            .send(packet)
+            .and_then(|_|
+                rx
+                    .recv_timeout(RESPONSE_TIMEOUT)
+                    .map_err(|_| format!("{} {} was not acknowledged", packet_name, packet_id))
+            );
+
+        self.pending.lock().unwrap().remove(&packet_id);
+
+        result
+    }
+
+    /// Reads packets until the client is closed, reconnecting if enabled.
+    fn read_loop(&self, mut stream: TcpStream, message_tx: Sender<Message>) {
+        loop {
+            self.read_packets(&mut stream, &message_tx);
+
+            self.connected.store(false, Ordering::SeqCst);
+            _ = stream.shutdown(Shutdown::Both);
+
+            // Wake every waiting request, their packets will not be acknowledged on this connection
+            self.pending.lock().unwrap().clear();
+
+            if self.closed.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let Some(reconnect) = self.options.reconnect else {
+                break;
+            };
+
+            warn!(client_id = %self.options.client_id, address = %self.address, "Connection to the broker lost");
+
+            match self.reconnect(reconnect) {
+                Some(new_stream) => {
+                    stream = new_stream;
+                }
+                None => {
+                    break;
+                }
+            }
+        }
+
+        // Nothing is received anymore, which also closes the message channel
+        self.closed.store(true, Ordering::SeqCst);
+    }
+
+    /// Connects again with exponential backoff, until connected or the client is closed.
+    ///
+    /// # Returns
+    ///
+    /// The reading half of the new connection, or None if the client was closed.
+    fn reconnect(&self, reconnect: Reconnect) -> Option<TcpStream> {
+        let mut delay: Duration = reconnect.min_delay;
+
+        loop {
+            thread::sleep(delay);
+
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+
+            let stream: TcpStream = match open(self.address, &self.options) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!(client_id = %self.options.client_id, error = %err, "Reconnect failed");
+                    delay = (delay * 2).min(reconnect.max_delay.max(reconnect.min_delay));
+                    continue;
+                }
+            };
+
+            let Ok(write_stream) = stream.try_clone() else {
+                continue;
+            };
+
+            *self.stream.lock().unwrap() = write_stream;
+
+            // Closed while connecting
+            if self.closed.load(Ordering::SeqCst) {
+                _ = stream.shutdown(Shutdown::Both);
+                return None;
+            }
+
+            // A clean session starts without the QoS 2 messages of the previous one
+            if self.options.clean_session {
+                self.received_qos_2.lock().unwrap().clear();
+            }
+
+            self.connected.store(true, Ordering::SeqCst);
+            info!(client_id = %self.options.client_id, address = %self.address, "Reconnected to the broker");
+
+            // Subscribe again, the SUBACK is read once the read loop resumes
+            let subscriptions: Vec<(String, u8)> = self.subscriptions.lock().unwrap().clone();
+
+            if !subscriptions.is_empty() {
+                let packet_id: u16 = self.next_packet_id();
+
+                _ = self.send(&Packet::Subscribe(Subscribe { packet_id, topic_filters: subscriptions }));
+            }
+
+            return Some(stream);
+        }
+    }
+
+    /// Reads packets until the connection is lost, answering the QoS flows of the broker.
+    fn read_packets(&self, stream: &mut TcpStream, message_tx: &Sender<Message>) {
+        while let Ok(packet) = read_packet(stream) {
+            trace!(packet_type = packet.packet_type(), "Client received packet");
+
+            match packet {
+                Packet::Publish(publish) => {
+                    // PUBACK for QoS 1, PUBREC for QoS 2
+                    let is_duplicate: bool = match (publish.qos, publish.packet_id) {
+                        (1, Some(packet_id)) => {
+                            _ = self.send(&Packet::Puback(Puback { packet_id }));
+                            false
+                        }
+                        (2, Some(packet_id)) => {
+                            // Answered again, as the PUBREC may have been lost, but delivered only once until the PUBREL
+                            _ = self.send(&Packet::Pubrec(Pubrec { packet_id }));
+                            !self.received_qos_2.lock().unwrap().insert(packet_id)
+                        }
+                        _ => false,
+                    };
+
+                    if is_duplicate {
+                        debug!(packet_id = publish.packet_id, "Dropping a QoS 2 PUBLISH that was already delivered");
+                        continue;
+                    }
+
+                    self.deliver(message_from(publish), message_tx);
+                }
+                Packet::Pubrec(pubrec) => {
+                    // PUBREC -> PUBREL
+                    _ = self.send(&Packet::Pubrel(Pubrel { packet_id: pubrec.packet_id }));
+                }
+                Packet::Pubrel(pubrel) => {
+                    // PUBREL -> PUBCOMP, a PUBLISH with the packet identifier is a new message from now on
+                    self.received_qos_2.lock().unwrap().remove(&pubrel.packet_id);
+                    _ = self.send(&Packet::Pubcomp(Pubcomp { packet_id: pubrel.packet_id }));
+                }
+                Packet::Puback(Puback { packet_id }) |
+                Packet::Pubcomp(Pubcomp { packet_id }) |
+                Packet::Unsuback(Unsuback { packet_id }) => {
+                    // Wake the waiting request
+                    self.acknowledge(packet_id, Vec::new());
+                }
+                Packet::Suback(suback) => {
+                    self.acknowledge(suback.packet_id, suback.return_codes);
+                }
+                // PINGRESP needs no answer
+                _ => {}
+            }
+        }
+    }
+
+    /// Wakes the request waiting for the acknowledgement with this packet identifier.
+    fn acknowledge(&self, packet_id: u16, return_codes: Vec<u8>) {
+        if let Some(tx) = self.pending.lock().unwrap().remove(&packet_id) {
+            _ = tx.send(return_codes);
+        }
+    }
+
+    /// Passes a message to the callbacks of the matching subscriptions, or to the channel if none match.
+    fn deliver(&self, message: Message, message_tx: &Sender<Message>) {
+        // Call the callbacks without holding the lock, so a callback can subscribe or unsubscribe
+        let callbacks: Vec<Callback> = self.callbacks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(topic_filter, _)| common_fn::topic_filter::matches(topic_filter, &message.topic))
+            .map(|(_, callback)| Arc::clone(callback))
+            .collect();
+
+        if callbacks.is_empty() {
+            // The receiver may have been dropped by a client that only uses callbacks
+            _ = message_tx.send(message);
+            return;
+        }
+
+        for callback in callbacks {
+            callback(&message);
+        }
+    }
+}
+
+/// Opens a connection, sends the CONNECT and waits for the CONNACK.
+///
+/// # Returns
+///
+/// A Result containing the connected stream, or an error message if the connection failed
+/// or the broker refused it.
+fn open(address: SocketAddr, options: &ConnectOptions) -> Result<TcpStream, String> {
+    let mut stream: TcpStream = TcpStream::connect_timeout(&address, RESPONSE_TIMEOUT).map_err(|err|
+        format!("Could not connect to {}: {}", address, err)
+    )?;
+
+    _ = stream.set_nodelay(true);
+
+    stream.write_all(&connect_packet(options).to_vec()?).map_err(|err| err.to_string())?;
+
+    // Wait for the CONNACK
+    _ = stream.set_read_timeout(Some(RESPONSE_TIMEOUT));
+
+    let packet: Packet = read_packet(&mut stream).map_err(|err|
+        format!("No CONNACK from {}: {}", address, err)
+    )?;
+
+    let Packet::Connack(connack) = packet else {
+        return Err(format!("Expected a CONNACK from {}", address));
+    };
+
+    if connack.return_code != 0 {
+        return Err(format!("Connection refused by {}, return code {}", address, connack.return_code));
+    }
+
+    _ = stream.set_read_timeout(None);
+
+    Ok(stream)
+}
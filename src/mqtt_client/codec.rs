@@ -0,0 +1,99 @@
+use std::io::{ Error, ErrorKind, Read };
+
+use crate::packet::{ self, Connect, LastWill, Packet, Publish };
+
+use super::{ ConnectOptions, Message };
+
+/// Reads a single control packet.
+///
+/// # Arguments
+///
+/// * `stream` - The connection to read from, blocks until a full packet has arrived.
+///
+/// # Returns
+///
+/// A Result containing the decoded packet, or an error if the connection was closed
+/// or the packet is malformed.
+pub fn read_packet(stream: &mut impl Read) -> std::io::Result<Packet> {
+    let mut frame: Vec<u8> = vec![0];
+    stream.read_exact(&mut frame[..1])?;
+
+    // Read the Remaining Length, one byte at a time until the continuation bit is clear
+    let mut remaining_length: usize = 0;
+
+    for index in 0..4 {
+        let mut byte: [u8; 1] = [0];
+        stream.read_exact(&mut byte)?;
+        frame.push(byte[0]);
+
+        remaining_length += ((byte[0] & 127) as usize) << (7 * index);
+
+        if byte[0] & 128 == 0 {
+            break;
+        }
+    }
+
+    // Read the rest of the packet, a malformed Remaining Length is left to the decoder
+    let header_length: usize = frame.len();
+    frame.resize(header_length + remaining_length, 0);
+    stream.read_exact(&mut frame[header_length..])?;
+
+    packet::decode(&frame)
+        .map(|(packet, _)| packet)
+        .map_err(|err: packet::DecodeError| Error::new(ErrorKind::InvalidData, err.to_string()))
+}
+
+/// Assembles a CONNECT packet for protocol level 4 (MQTT 3.1.1).
+///
+/// # Examples
+///
+/// ```
+/// let packet: Packet = connect_packet(&ConnectOptions::new("button"));
+/// assert_eq!(packet.to_vec().unwrap()[0], 0x10);
+/// ```
+pub fn connect_packet(options: &ConnectOptions) -> Packet {
+    Packet::Connect(Connect {
+        protocol_name: "MQTT".to_string(),
+        protocol_level: 4,
+        clean_session: options.clean_session,
+        keep_alive: options.keep_alive,
+        client_id: options.client_id.clone(),
+        will: options.will.as_ref().map(|will: &Message| LastWill {
+            topic: will.topic.clone(),
+            message: will.payload.clone(),
+            qos: will.qos,
+            retain: will.retain,
+        }),
+        username: options.username.clone(),
+        password: options.password.as_ref().map(|password: &String| password.as_bytes().to_vec()),
+    })
+}
+
+/// Assembles a PUBLISH packet. The packet identifier is only used for QoS 1 and QoS 2.
+///
+/// # Examples
+///
+/// ```
+/// let message: Message = Message { topic: "a".to_string(), payload: b"on".to_vec(), qos: 1, retain: false };
+/// assert_eq!(publish_packet(&message, 7).to_vec().unwrap(), vec![0x32, 7, 0, 1, b'a', 0, 7, b'o', b'n']);
+/// ```
+pub fn publish_packet(message: &Message, packet_id: u16) -> Packet {
+    Packet::Publish(Publish {
+        dup: false,
+        qos: message.qos,
+        retain: message.retain,
+        topic_name: message.topic.clone(),
+        packet_id: if message.qos > 0 { Some(packet_id) } else { None },
+        payload: message.payload.clone(),
+    })
+}
+
+/// Takes the message out of a received PUBLISH packet.
+pub fn message_from(publish: Publish) -> Message {
+    Message {
+        topic: publish.topic_name,
+        payload: publish.payload,
+        qos: publish.qos,
+        retain: publish.retain,
+    }
+}
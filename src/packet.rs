@@ -0,0 +1,188 @@
+use std::fmt;
+
+use bytes::BytesMut;
+
+pub mod decode;
+pub mod encode;
+
+pub use self::decode::decode;
+pub use self::encode::encode;
+
+/// An MQTT 3.1.1 control packet.
+///
+/// Every packet the broker and the client receive is decoded into a [`Packet`] with [`decode`],
+/// and every packet they send is assembled as a [`Packet`] and written with [`encode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Connect(Connect),
+    Connack(Connack),
+    Publish(Publish),
+    Puback(Puback),
+    Pubrec(Pubrec),
+    Pubrel(Pubrel),
+    Pubcomp(Pubcomp),
+    Subscribe(Subscribe),
+    Suback(Suback),
+    Unsubscribe(Unsubscribe),
+    Unsuback(Unsuback),
+    Pingreq,
+    Pingresp,
+    Disconnect,
+}
+
+impl Packet {
+    /// The control packet type, the upper four bits of the first byte.
+    pub fn packet_type(&self) -> u8 {
+        match self {
+            Packet::Connect(_) => 1,
+            Packet::Connack(_) => 2,
+            Packet::Publish(_) => 3,
+            Packet::Puback(_) => 4,
+            Packet::Pubrec(_) => 5,
+            Packet::Pubrel(_) => 6,
+            Packet::Pubcomp(_) => 7,
+            Packet::Subscribe(_) => 8,
+            Packet::Suback(_) => 9,
+            Packet::Unsubscribe(_) => 10,
+            Packet::Unsuback(_) => 11,
+            Packet::Pingreq => 12,
+            Packet::Pingresp => 13,
+            Packet::Disconnect => 14,
+        }
+    }
+
+    /// Encodes the packet into a new buffer.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the encoded packet, or an error message if a field does not fit
+    /// its length prefix, see [`encode`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Packet::Pingresp.to_vec().unwrap(), vec![0xd0, 0]);
+    /// ```
+    pub fn to_vec(&self) -> Result<Vec<u8>, &'static str> {
+        let mut buffer: BytesMut = BytesMut::new();
+        encode(self, &mut buffer)?;
+
+        Ok(buffer.to_vec())
+    }
+}
+
+/// A CONNECT packet, the first packet of a client.
+///
+/// The protocol name and level are kept as received, so the broker can answer an unsupported
+/// level with CONNACK return code 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connect {
+    pub protocol_name: String,
+    pub protocol_level: u8,
+    pub clean_session: bool,
+    pub keep_alive: u16,
+    pub client_id: String,
+    pub will: Option<LastWill>,
+    pub username: Option<String>,
+    pub password: Option<Vec<u8>>,
+}
+
+/// The will message of a CONNECT packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastWill {
+    pub topic: String,
+    pub message: Vec<u8>,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connack {
+    pub session_present: bool,
+    pub return_code: u8,
+}
+
+/// A PUBLISH packet. The packet identifier is only present for QoS 1 and QoS 2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publish {
+    pub dup: bool,
+    pub qos: u8,
+    pub retain: bool,
+    pub topic_name: String,
+    pub packet_id: Option<u16>,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Puback {
+    pub packet_id: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pubrec {
+    pub packet_id: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pubrel {
+    pub packet_id: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pubcomp {
+    pub packet_id: u16,
+}
+
+/// A SUBSCRIBE packet, with the topic filters and their requested QoS.
+///
+/// The requested QoS is kept as received, a value above 2 is refused in the SUBACK.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscribe {
+    pub packet_id: u16,
+    pub topic_filters: Vec<(String, u8)>,
+}
+
+/// A SUBACK packet, with one return code per topic filter: the granted QoS, or 0x80 for a failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suback {
+    pub packet_id: u16,
+    pub return_codes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unsubscribe {
+    pub packet_id: u16,
+    pub topic_filters: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsuback {
+    pub packet_id: u16,
+}
+
+/// Why a packet could not be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The buffer does not hold a complete packet yet, read more bytes and decode again.
+    Incomplete,
+    /// The Remaining Length is longer than four bytes.
+    MalformedRemainingLength,
+    /// The control packet type is reserved (0 or 15).
+    InvalidPacketType(u8),
+    /// The flags in the lower four bits of the first byte are not valid for the packet type.
+    InvalidFlags(u8),
+    /// The fields of the packet do not match its Remaining Length, or a field has an invalid value.
+    MalformedPacket(&'static str),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Incomplete => write!(f, "Incomplete packet"),
+            DecodeError::MalformedRemainingLength => write!(f, "Malformed Remaining Length"),
+            DecodeError::InvalidPacketType(packet_type) => write!(f, "Invalid packet type {}", packet_type),
+            DecodeError::InvalidFlags(first_byte) => write!(f, "Invalid fixed header flags {:#010b}", first_byte),
+            DecodeError::MalformedPacket(reason) => write!(f, "Malformed packet: {}", reason),
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use super::{
+    Connack,
+    Connect,
+    DecodeError,
+    LastWill,
+    Packet,
+    Puback,
+    Pubcomp,
+    Publish,
+    Pubrec,
+    Pubrel,
+    Suback,
+    Subscribe,
+    Unsuback,
+    Unsubscribe,
+};
+
+/// Decodes the first packet in a buffer.
+///
+/// # Arguments
+///
+/// * `buffer` - The bytes received so far. It may hold a partial packet, or more than one packet.
+///
+/// # Returns
+///
+/// A Result containing the packet and the number of bytes it took, so the caller can remove them
+/// from the buffer and decode the next one. [`DecodeError::Incomplete`] means the buffer does not
+/// hold a complete packet yet, every other error means the stream is malformed and must be closed.
+///
+/// # Description
+///
+/// The fixed header is validated first: the packet type, the flags of the type, and the Remaining
+/// Length of at most four bytes. The variable header and payload must fill the Remaining Length
+/// exactly, and strings must be valid UTF-8 without null characters.
+///
+/// # Examples
+///
+/// ```
+/// // A PINGREQ followed by the first byte of another packet
+/// let (packet, length) = packet::decode(&[0xc0, 0x00, 0x30]).unwrap();
+///
+/// assert_eq!(packet, Packet::Pingreq);
+/// assert_eq!(length, 2);
+/// ```
+pub fn decode(buffer: &[u8]) -> Result<(Packet, usize), DecodeError> {
+    let Some(&first_byte) = buffer.first() else {
+        return Err(DecodeError::Incomplete);
+    };
+
+    let packet_type: u8 = first_byte >> 4;
+    let flags: u8 = first_byte & 0b0000_1111;
+
+    if packet_type == 0 || packet_type == 15 {
+        return Err(DecodeError::InvalidPacketType(packet_type));
+    }
+
+    // PUBLISH carries DUP, QoS and RETAIN in the flags, PUBREL, SUBSCRIBE and UNSUBSCRIBE have 0b0010,
+    // every other packet 0b0000
+    let expected_flags: Option<u8> = match packet_type {
+        3 => None,
+        6 | 8 | 10 => Some(0b0010),
+        _ => Some(0b0000),
+    };
+
+    if expected_flags.is_some_and(|expected: u8| expected != flags) {
+        return Err(DecodeError::InvalidFlags(first_byte));
+    }
+
+    let (remaining_length, header_length) = decode_remaining_length(&buffer[1..])?;
+    let packet_length: usize = 1 + header_length + remaining_length;
+
+    if buffer.len() < packet_length {
+        return Err(DecodeError::Incomplete);
+    }
+
+    let mut reader: Reader<'_> = Reader { body: &buffer[1 + header_length..packet_length], index: 0 };
+
+    let packet: Packet = match packet_type {
+        1 => Packet::Connect(decode_connect(&mut reader)?),
+        2 => {
+            let acknowledge_flags: u8 = reader.u8()?;
+
+            if acknowledge_flags & 0b1111_1110 != 0 {
+                return Err(DecodeError::MalformedPacket("Reserved CONNACK flags are set"));
+            }
+
+            Packet::Connack(Connack { session_present: acknowledge_flags == 1, return_code: reader.u8()? })
+        }
+        3 => Packet::Publish(decode_publish(flags, &mut reader)?),
+        4 => Packet::Puback(Puback { packet_id: reader.packet_id()? }),
+        5 => Packet::Pubrec(Pubrec { packet_id: reader.packet_id()? }),
+        6 => Packet::Pubrel(Pubrel { packet_id: reader.packet_id()? }),
+        7 => Packet::Pubcomp(Pubcomp { packet_id: reader.packet_id()? }),
+        8 => {
+            let packet_id: u16 = reader.packet_id()?;
+            let mut topic_filters: Vec<(String, u8)> = Vec::new();
+
+            while !reader.is_empty() {
+                topic_filters.push((reader.string()?, reader.u8()?));
+            }
+
+            if topic_filters.is_empty() {
+                return Err(DecodeError::MalformedPacket("SUBSCRIBE without topic filters"));
+            }
+
+            Packet::Subscribe(Subscribe { packet_id, topic_filters })
+        }
+        9 => Packet::Suback(Suback { packet_id: reader.packet_id()?, return_codes: reader.rest().to_vec() }),
+        10 => {
+            let packet_id: u16 = reader.packet_id()?;
+            let mut topic_filters: Vec<String> = Vec::new();
+
+            while !reader.is_empty() {
+                topic_filters.push(reader.string()?);
+            }
+
+            if topic_filters.is_empty() {
+                return Err(DecodeError::MalformedPacket("UNSUBSCRIBE without topic filters"));
+            }
+
+            Packet::Unsubscribe(Unsubscribe { packet_id, topic_filters })
+        }
+        11 => Packet::Unsuback(Unsuback { packet_id: reader.packet_id()? }),
+        12 => Packet::Pingreq,
+        13 => Packet::Pingresp,
+        _ => Packet::Disconnect,
+    };
+
+    if !reader.is_empty() {
+        return Err(DecodeError::MalformedPacket("Unexpected bytes after the last field"));
+    }
+
+    Ok((packet, packet_length))
+}
+
+/// Decodes the Remaining Length that follows the first byte.
+///
+/// # Returns
+///
+/// A Result containing the Remaining Length and the number of bytes it took.
+fn decode_remaining_length(bytes: &[u8]) -> Result<(usize, usize), DecodeError> {
+    let mut value: usize = 0;
+
+    for index in 0..4 {
+        let Some(&encoded_byte) = bytes.get(index) else {
+            return Err(DecodeError::Incomplete);
+        };
+
+        value += ((encoded_byte & 127) as usize) << (7 * index);
+
+        if encoded_byte & 128 == 0 {
+            return Ok((value, index + 1));
+        }
+    }
+
+    Err(DecodeError::MalformedRemainingLength)
+}
+
+fn decode_connect(reader: &mut Reader<'_>) -> Result<Connect, DecodeError> {
+    let protocol_name: String = reader.string()?;
+    let protocol_level: u8 = reader.u8()?;
+    let connect_flags: u8 = reader.u8()?;
+    let keep_alive: u16 = reader.u16()?;
+
+    let clean_session: bool = connect_flags & 0b0000_0010 != 0;
+    let will_flag: bool = connect_flags & 0b0000_0100 != 0;
+    let will_qos: u8 = (connect_flags >> 3) & 0b11;
+    let will_retain: bool = connect_flags & 0b0010_0000 != 0;
+    let password_flag: bool = connect_flags & 0b0100_0000 != 0;
+    let username_flag: bool = connect_flags & 0b1000_0000 != 0;
+
+    if connect_flags & 0b0000_0001 != 0 {
+        return Err(DecodeError::MalformedPacket("Reserved connect flag is set"));
+    }
+
+    if will_qos == 3 {
+        return Err(DecodeError::MalformedPacket("Invalid will QoS"));
+    }
+
+    if !will_flag && (will_qos != 0 || will_retain) {
+        return Err(DecodeError::MalformedPacket("Will QoS or retain set without a will"));
+    }
+
+    if password_flag && !username_flag {
+        return Err(DecodeError::MalformedPacket("Password without a user name"));
+    }
+
+    // Field order MUST be:
+    // Client Identifier -> Will Topic -> Will Message -> User Name -> Password
+    let client_id: String = reader.string()?;
+
+    let will: Option<LastWill> = if will_flag {
+        Some(LastWill {
+            topic: reader.string()?,
+            message: reader.binary()?,
+            qos: will_qos,
+            retain: will_retain,
+        })
+    } else {
+        None
+    };
+
+    let username: Option<String> = if username_flag { Some(reader.string()?) } else { None };
+    let password: Option<Vec<u8>> = if password_flag { Some(reader.binary()?) } else { None };
+
+    Ok(Connect {
+        protocol_name,
+        protocol_level,
+        clean_session,
+        keep_alive,
+        client_id,
+        will,
+        username,
+        password,
+    })
+}
+
+fn decode_publish(flags: u8, reader: &mut Reader<'_>) -> Result<Publish, DecodeError> {
+    let dup: bool = flags & 0b1000 != 0;
+    let qos: u8 = (flags >> 1) & 0b11;
+    let retain: bool = flags & 0b0001 != 0;
+
+    if qos == 3 {
+        return Err(DecodeError::InvalidFlags(0x30 | flags));
+    }
+
+    // The DUP flag MUST be 0 for QoS 0 messages
+    if qos == 0 && dup {
+        return Err(DecodeError::InvalidFlags(0x30 | flags));
+    }
+
+    let topic_name: String = reader.string()?;
+    let packet_id: Option<u16> = if qos > 0 { Some(reader.packet_id()?) } else { None };
+
+    Ok(Publish {
+        dup,
+        qos,
+        retain,
+        topic_name,
+        packet_id,
+        payload: reader.rest().to_vec(),
+    })
+}
+
+/// Reads the fields of a packet body, never past its end.
+struct Reader<'a> {
+    body: &'a [u8],
+    index: usize,
+}
+
+impl Reader<'_> {
+    fn is_empty(&self) -> bool {
+        self.index >= self.body.len()
+    }
+
+    fn take(&mut self, length: usize) -> Result<&[u8], DecodeError> {
+        let end: usize = self.index + length;
+
+        if end > self.body.len() {
+            return Err(DecodeError::MalformedPacket("A field is longer than the Remaining Length"));
+        }
+
+        let bytes: &[u8] = &self.body[self.index..end];
+        self.index = end;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        let bytes: &[u8] = self.take(2)?;
+
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Reads a packet identifier, which MUST be non-zero.
+    fn packet_id(&mut self) -> Result<u16, DecodeError> {
+        match self.u16()? {
+            0 => Err(DecodeError::MalformedPacket("Packet identifier is 0")),
+            packet_id => Ok(packet_id),
+        }
+    }
+
+    /// Reads binary data with a two byte length prefix.
+    fn binary(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let length: usize = self.u16()? as usize;
+
+        Ok(self.take(length)?.to_vec())
+    }
+
+    /// Reads a UTF-8 encoded string with a two byte length prefix.
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let bytes: Vec<u8> = self.binary()?;
+
+        let string: String = String::from_utf8(bytes).map_err(|_|
+            DecodeError::MalformedPacket("String is not valid UTF-8")
+        )?;
+
+        if string.contains('\0') {
+            return Err(DecodeError::MalformedPacket("String contains a null character"));
+        }
+
+        Ok(string)
+    }
+
+    fn rest(&mut self) -> &[u8] {
+        let bytes: &[u8] = &self.body[self.index..];
+        self.index = self.body.len();
+
+        bytes
+    }
+}
@@ -0,0 +1,161 @@
+use bytes::{ BufMut, BytesMut };
+
+use crate::common_fn;
+
+use super::Packet;
+
+/// Encodes a packet and appends it to a buffer.
+///
+/// # Arguments
+///
+/// * `packet` - The packet to encode.
+/// * `buffer` - The buffer the packet is appended to, it may already hold other packets.
+///
+/// # Returns
+///
+/// A Result that is empty on success, or an error message if a string or binary field is longer
+/// than 65,535 bytes, the packet is longer than the maximum Remaining Length, or a QoS is above 2.
+/// On error the buffer is left unchanged.
+///
+/// # Description
+///
+/// The inverse of [`decode`](super::decode): the variable header and payload are written first,
+/// so the Remaining Length is known when the fixed header is written in front of them.
+///
+/// # Examples
+///
+/// ```
+/// let mut buffer: BytesMut = BytesMut::new();
+/// packet::encode(&Packet::Puback(Puback { packet_id: 258 }), &mut buffer).unwrap();
+///
+/// assert_eq!(&buffer[..], &[0x40, 2, 1, 2]);
+/// ```
+pub fn encode(packet: &Packet, buffer: &mut BytesMut) -> Result<(), &'static str> {
+    let mut body: BytesMut = BytesMut::new();
+    let mut flags: u8 = 0;
+
+    match packet {
+        Packet::Connect(connect) => {
+            let mut connect_flags: u8 = 0;
+
+            if connect.clean_session {
+                connect_flags |= 0b0000_0010;
+            }
+
+            if let Some(will) = &connect.will {
+                if will.qos > 2 {
+                    return Err("Invalid will QoS");
+                }
+
+                connect_flags |= 0b0000_0100 | (will.qos << 3);
+
+                if will.retain {
+                    connect_flags |= 0b0010_0000;
+                }
+            }
+
+            if connect.password.is_some() {
+                connect_flags |= 0b0100_0000;
+            }
+
+            if connect.username.is_some() {
+                connect_flags |= 0b1000_0000;
+            }
+
+            put_string(&mut body, &connect.protocol_name)?;
+            body.put_u8(connect.protocol_level);
+            body.put_u8(connect_flags);
+            body.put_u16(connect.keep_alive);
+
+            // Client Identifier -> Will Topic -> Will Message -> User Name -> Password
+            put_string(&mut body, &connect.client_id)?;
+
+            if let Some(will) = &connect.will {
+                put_string(&mut body, &will.topic)?;
+                put_binary(&mut body, &will.message)?;
+            }
+
+            if let Some(username) = &connect.username {
+                put_string(&mut body, username)?;
+            }
+
+            if let Some(password) = &connect.password {
+                put_binary(&mut body, password)?;
+            }
+        }
+        Packet::Connack(connack) => {
+            body.put_u8(connack.session_present as u8);
+            body.put_u8(connack.return_code);
+        }
+        Packet::Publish(publish) => {
+            if publish.qos > 2 {
+                return Err("Invalid QoS");
+            }
+
+            flags = ((publish.dup as u8) << 3) | (publish.qos << 1) | (publish.retain as u8);
+
+            put_string(&mut body, &publish.topic_name)?;
+
+            // The packet identifier is only present for QoS 1 and QoS 2
+            if publish.qos > 0 {
+                body.put_u16(publish.packet_id.ok_or("Missing packet identifier")?);
+            }
+
+            body.put_slice(&publish.payload);
+        }
+        Packet::Puback(puback) => body.put_u16(puback.packet_id),
+        Packet::Pubrec(pubrec) => body.put_u16(pubrec.packet_id),
+        Packet::Pubrel(pubrel) => {
+            flags = 0b0010;
+            body.put_u16(pubrel.packet_id);
+        }
+        Packet::Pubcomp(pubcomp) => body.put_u16(pubcomp.packet_id),
+        Packet::Subscribe(subscribe) => {
+            flags = 0b0010;
+            body.put_u16(subscribe.packet_id);
+
+            for (topic_filter, qos) in &subscribe.topic_filters {
+                put_string(&mut body, topic_filter)?;
+                body.put_u8(*qos);
+            }
+        }
+        Packet::Suback(suback) => {
+            body.put_u16(suback.packet_id);
+            body.put_slice(&suback.return_codes);
+        }
+        Packet::Unsubscribe(unsubscribe) => {
+            flags = 0b0010;
+            body.put_u16(unsubscribe.packet_id);
+
+            for topic_filter in &unsubscribe.topic_filters {
+                put_string(&mut body, topic_filter)?;
+            }
+        }
+        Packet::Unsuback(unsuback) => body.put_u16(unsuback.packet_id),
+        Packet::Pingreq | Packet::Pingresp | Packet::Disconnect => {}
+    }
+
+    let remaining_length: Vec<u8> = common_fn::bit_operations::encode_remaining_length(body.len())?;
+
+    buffer.reserve(1 + remaining_length.len() + body.len());
+    buffer.put_u8((packet.packet_type() << 4) | flags);
+    buffer.put_slice(&remaining_length);
+    buffer.put_slice(&body);
+
+    Ok(())
+}
+
+/// Writes binary data with a two byte length prefix.
+fn put_binary(body: &mut BytesMut, bytes: &[u8]) -> Result<(), &'static str> {
+    let length: u16 = u16::try_from(bytes.len()).map_err(|_| "Field is longer than 65535 bytes")?;
+
+    body.put_u16(length);
+    body.put_slice(bytes);
+
+    Ok(())
+}
+
+/// Writes a UTF-8 encoded string with a two byte length prefix.
+fn put_string(body: &mut BytesMut, string: &str) -> Result<(), &'static str> {
+    put_binary(body, string.as_bytes())
+}
@@ -0,0 +1,16 @@
+pub mod metrics;
+pub mod logging;
+pub mod admin_api;
+pub mod auth;
+pub mod rate_limit;
+pub mod outbound;
+pub mod session;
+pub mod shutdown;
+pub mod signals;
+pub mod storage;
+pub mod bridge;
+pub mod webhooks;
+pub mod hooks;
+pub mod rules;
+pub mod schemas;
+pub mod archive;
@@ -0,0 +1,311 @@
+use std::net::{ SocketAddr, TcpListener, TcpStream };
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::{ info, warn };
+
+use crate::common_fn;
+use crate::models::client::{ Client, Clients };
+use crate::models::publish_queue_item::{ PublishQueue, PublishQueueItem };
+use crate::models::topic::{ Topic, Topics };
+use crate::services;
+use crate::services::outbound::QueueDepth;
+use crate::services::storage::state::Record;
+
+/// A subscription of a client, as listed by the admin API.
+#[derive(Debug, Serialize)]
+pub struct SubscriptionInfo {
+    pub topic: String,
+    pub qos: u8,
+}
+
+/// A client session, as listed by the admin API.
+#[derive(Debug, Serialize)]
+pub struct ClientInfo {
+    pub id: String,
+    pub address: String,
+    pub connected: bool,
+    pub keep_alive: u64,
+    pub clean_session: bool,
+    pub username: String,
+    pub will_topic: String,
+    pub subscriptions: Vec<SubscriptionInfo>,
+    pub in_flight: usize,
+    /// The PUBLISH packets waiting to be written to the client, and their bytes.
+    pub outbound_messages: usize,
+    pub outbound_bytes: usize,
+}
+
+/// A retained message, as listed by the admin API.
+#[derive(Debug, Serialize)]
+pub struct RetainedInfo {
+    pub topic: String,
+    pub payload: String,
+    pub qos: u8,
+}
+
+/// The shared broker state the admin API operates on.
+#[derive(Clone)]
+pub struct AdminState {
+    pub clients: Arc<Clients>,
+    pub topics: Arc<Topics>,
+    pub publish_queue: Arc<PublishQueue>,
+}
+
+/// Starts the admin HTTP API.
+///
+/// # Arguments
+///
+/// * `address` - The address to bind the HTTP listener to.
+/// * `token` - The bearer token every request must carry in its `Authorization` header.
+/// * `state` - The shared broker state.
+///
+/// # Returns
+///
+/// A Result containing the address actually bound, or an error message if the token is empty
+/// or binding failed.
+///
+/// # Description
+///
+/// The API serves the following routes, all answering with JSON:
+///
+/// * `GET /clients` - Lists every client session.
+/// * `GET /clients/{id}` - Shows a single client session.
+/// * `POST /clients/{id}/disconnect` - Force-disconnects a client. With `?suppress_will=true`
+///   the will message is not published.
+/// * `DELETE /sessions/{id}` - Deletes a session, disconnecting the client first (without its will).
+/// * `GET /retained` - Lists every retained message.
+/// * `DELETE /retained/{topic}` - Clears the retained message of a topic.
+///
+/// Client ids and topics in the path are percent-decoded, so `home%2Fkitchen` is the topic `home/kitchen`.
+pub fn start(address: SocketAddr, token: String, state: AdminState) -> Result<SocketAddr, String> {
+    if token.is_empty() {
+        return Err("The admin API requires a token".to_string());
+    }
+
+    let listener: TcpListener = TcpListener::bind(address).map_err(|err|
+        format!("Failed to bind admin API to {}: {}", address, err)
+    )?;
+
+    let local_addr: SocketAddr = listener.local_addr().map_err(|err| err.to_string())?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    serve(&mut stream, &token, &state);
+                }
+                Err(err) => {
+                    warn!(error = %err, "Could not accept admin API connection");
+                }
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Answers a single admin request.
+fn serve(stream: &mut TcpStream, token: &str, state: &AdminState) {
+    _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+
+    let request: common_fn::http::Request = match common_fn::http::read_request(stream) {
+        Ok(request) => request,
+        Err(err) => {
+            write_json(stream, err.status, &error_body(err.message));
+            return;
+        }
+    };
+
+    // Every route requires the bearer token
+    let is_authorized: bool = request
+        .header("Authorization")
+        .and_then(|value: &str| value.strip_prefix("Bearer "))
+        .is_some_and(|value: &str| constant_time_eq(value.as_bytes(), token.as_bytes()));
+
+    if !is_authorized {
+        write_json(stream, 401, &error_body("Missing or invalid token"));
+        return;
+    }
+
+    let (status, body): (u16, String) = route(&request, state);
+
+    write_json(stream, status, &body);
+}
+
+/// Dispatches a request to its handler, returning the status code and JSON body.
+pub fn route(request: &common_fn::http::Request, state: &AdminState) -> (u16, String) {
+    let segments: Vec<&str> = request.path.trim_start_matches('/').splitn(2, '/').collect();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["clients"]) => {
+            // Copy the sessions first, so no shard of the clients is locked while the topics are visited
+            let mut clients: Vec<Client> = Vec::new();
+            state.clients.for_each(|client: &Client| clients.push(client.clone()));
+            clients.sort_by(|a: &Client, b: &Client| a.id.cmp(&b.id));
+
+            let list: Vec<ClientInfo> = clients
+                .iter()
+                .map(|client: &Client| client_info(client, &state.topics, &state.publish_queue))
+                .collect();
+
+            (200, serde_json::to_string(&list).unwrap())
+        }
+        ("GET", ["clients", rest]) => {
+            let client_id: String = common_fn::http::percent_decode(rest);
+
+            match state.clients.get(&client_id) {
+                Some(client) => {
+                    (200, serde_json::to_string(&client_info(&client, &state.topics, &state.publish_queue)).unwrap())
+                }
+                None => (404, error_body("Unknown client")),
+            }
+        }
+        ("POST", ["clients", rest]) if rest.ends_with("/disconnect") => {
+            let client_id: String = common_fn::http::percent_decode(
+                rest.trim_end_matches("/disconnect")
+            );
+            let suppress_will: bool = request.query_param("suppress_will").as_deref() == Some("true");
+
+            if disconnect_client(&state.clients, &client_id, suppress_will) {
+                info!(client_id = %client_id, suppress_will, "Client disconnected by admin");
+                (200, "{\"disconnected\":true}".to_string())
+            } else {
+                (404, error_body("Client is not connected"))
+            }
+        }
+        ("DELETE", ["sessions", rest]) => {
+            let client_id: String = common_fn::http::percent_decode(rest);
+
+            // Close the connection first, the session is removed either way
+            disconnect_client(&state.clients, &client_id, true);
+
+            match state.clients.remove(&client_id) {
+                Some(_) => {
+                    // The subscriptions and the QoS flows in flight go with the session
+                    state.topics.remove_client(&client_id);
+                    state.publish_queue.remove_client(&client_id);
+
+                    services::storage::record(Record::SessionRemoved { client_id: client_id.clone() });
+
+                    info!(client_id = %client_id, "Session deleted by admin");
+                    (200, "{\"deleted\":true}".to_string())
+                }
+                None => (404, error_body("Unknown session")),
+            }
+        }
+        ("GET", ["retained"]) => {
+            let mut list: Vec<RetainedInfo> = Vec::new();
+
+            state.topics.for_each(|topic: &Topic| {
+                if !topic.retained_msg.0.is_empty() {
+                    list.push(RetainedInfo {
+                        topic: topic.topic_name.clone(),
+                        payload: topic.retained_msg.0.clone(),
+                        qos: topic.retained_msg.1,
+                    });
+                }
+            });
+            list.sort_by(|a: &RetainedInfo, b: &RetainedInfo| a.topic.cmp(&b.topic));
+
+            (200, serde_json::to_string(&list).unwrap())
+        }
+        ("DELETE", ["retained", rest]) => {
+            let topic_name: String = common_fn::http::percent_decode(rest);
+
+            match state.topics.take_retained(&topic_name) {
+                Some(_) => {
+                    services::storage::record(Record::Retained {
+                        topic: topic_name.clone(),
+                        payload: String::new(),
+                        qos: 0,
+                    });
+
+                    info!(topic = %topic_name, "Retained message deleted by admin");
+                    (200, "{\"deleted\":true}".to_string())
+                }
+                None => (404, error_body("No retained message on topic")),
+            }
+        }
+        (_, ["clients" | "sessions" | "retained", ..]) => (405, error_body("Method not allowed")),
+        _ => (404, error_body("Not found")),
+    }
+}
+
+/// Builds the admin view of a client, collecting its subscriptions from the topics.
+fn client_info(client: &Client, topics: &Topics, publish_queue: &PublishQueue) -> ClientInfo {
+    let mut subscriptions: Vec<SubscriptionInfo> = topics
+        .subscriptions(&client.id)
+        .into_iter()
+        .map(|(topic, qos)| SubscriptionInfo { topic, qos })
+        .collect();
+    subscriptions.sort_by(|a: &SubscriptionInfo, b: &SubscriptionInfo| a.topic.cmp(&b.topic));
+
+    let depth: QueueDepth = client.tx.depth();
+
+    ClientInfo {
+        id: client.id.clone(),
+        address: client.socket_addr.to_string(),
+        connected: client.is_connected,
+        keep_alive: client.keep_alive,
+        clean_session: client.connect_flags.clean_session_flag,
+        username: client.username.clone(),
+        will_topic: client.will_topic.clone(),
+        subscriptions,
+        in_flight: publish_queue.count(|item: &PublishQueueItem| item.client_id == client.id),
+        outbound_messages: depth.messages,
+        outbound_bytes: depth.bytes,
+    }
+}
+
+/// Closes the connection of a connected client.
+///
+/// # Arguments
+///
+/// * `clients` - The client sessions of the broker.
+/// * `client_id` - The id of the client to disconnect.
+/// * `suppress_will` - If true, the will message is not published when the connection closes.
+///
+/// # Returns
+///
+/// True if the client was connected, otherwise false.
+///
+/// # Description
+///
+/// The write thread of the connection is told to shut down the stream, which makes the
+/// read loop in `handle_connection` end and run the normal disconnect handling.
+pub fn disconnect_client(clients: &Clients, client_id: &str, suppress_will: bool) -> bool {
+    clients
+        .with_mut(client_id, |client: &mut Client| {
+            if !client.is_connected {
+                return false;
+            }
+
+            if suppress_will {
+                client.connect_flags.will_flag = false;
+            }
+
+            _ = client.tx.send(Err("Disconnected by admin".to_string()));
+            true
+        })
+        .unwrap_or(false)
+}
+
+/// Compares two byte strings without leaking where they differ through timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0u8, |acc: u8, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn error_body(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+fn write_json(stream: &mut TcpStream, status: u16, body: &str) {
+    common_fn::http::write_response(stream, status, "application/json", body.as_bytes());
+}
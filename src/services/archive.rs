@@ -0,0 +1,508 @@
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, BufWriter, ErrorKind, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::mpsc::{ sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError };
+use std::sync::RwLock;
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{ Deserialize, Serialize };
+use tracing::{ debug, info_span, warn, Span };
+
+use crate::common_fn;
+use crate::models::config::{ ArchiveConfig, ArchiveFormat };
+use crate::services::metrics::METRICS;
+
+/// The active archives, replaced as a whole when the config is reloaded.
+static ARCHIVES: RwLock<Vec<Archive>> = RwLock::new(Vec::new());
+
+/// A message as it is recorded, one line of a JSON Lines file or one row of a Parquet file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveRecord {
+    /// When the broker received the message, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub client_id: String,
+    pub topic: String,
+    pub qos: u8,
+    pub retain: bool,
+    pub payload: String,
+}
+
+/// What happened to archive records, counted in the `mqtt_archive_records_total` metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Written to a file.
+    Written = 0,
+    /// Not queued, because the queue of the archive was full.
+    Dropped = 1,
+    /// Lost because the file could not be opened or written.
+    Failed = 2,
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 3] = [Outcome::Written, Outcome::Dropped, Outcome::Failed];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Outcome::Written => "written",
+            Outcome::Dropped => "dropped",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+/// A configured archive, with the thread that writes its files.
+///
+/// # Description
+///
+/// Records are queued without waiting, a full queue drops the record, so a slow disk never holds up
+/// a connection. The writer thread appends them to the current file, flushes it every `flush_interval_ms`
+/// and starts a new file once the current one reaches `max_file_bytes` or is `rotate_interval_secs` old.
+/// A file is only started when there is a record to write to it.
+///
+/// The writer thread stops once the archive is stopped or dropped and its queued records are written,
+/// and finishes the current file, which is when a gzip or Parquet file becomes readable.
+#[derive(Debug)]
+pub struct Archive {
+    topics: Vec<String>,
+    tx: SyncSender<ArchiveRecord>,
+    thread: JoinHandle<()>,
+}
+
+impl Archive {
+    /// Creates the directory of an archive, and starts its writer thread.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the [`Archive`], or an error message if the config is invalid or the directory can not be created.
+    pub fn start(config: &ArchiveConfig) -> Result<Archive, String> {
+        if let Some(topic) = config.topics.iter().find(|topic: &&String| !common_fn::topic_filter::is_valid(topic)) {
+            return Err(format!("Invalid archive topic filter: {}", topic));
+        }
+
+        if config.format == ArchiveFormat::Parquet && !cfg!(feature = "parquet") {
+            return Err("Parquet archives need the broker to be built with the parquet feature".to_string());
+        }
+
+        fs::create_dir_all(&config.directory).map_err(|err| format!("Could not create archive directory {}: {}", config.directory, err))?;
+
+        let (tx, rx): (SyncSender<ArchiveRecord>, Receiver<ArchiveRecord>) = sync_channel(config.queue_size.max(1));
+
+        let thread_config: ArchiveConfig = config.clone();
+        let span: Span = info_span!("archive", directory = %config.directory, prefix = %config.prefix);
+
+        let thread: JoinHandle<()> = thread::spawn(move || {
+            let _enter = span.enter();
+            write_records(&thread_config, rx);
+        });
+
+        Ok(Archive { topics: config.topics.clone(), tx, thread })
+    }
+
+    /// Checks if the archive records the messages published on a topic.
+    pub fn wants(&self, topic: &str) -> bool {
+        self.topics.iter().any(|filter: &String| common_fn::topic_filter::matches(filter, topic))
+    }
+
+    /// Queues a record without waiting, dropping it when the queue is full.
+    pub fn record(&self, record: ArchiveRecord) {
+        match self.tx.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                METRICS.archive_records(Outcome::Dropped, 1);
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                METRICS.archive_records(Outcome::Failed, 1);
+            }
+        }
+    }
+
+    /// Stops the writer thread, and waits until it has written the queued records and finished its file.
+    pub fn stop(self) {
+        let Archive { tx, thread, .. } = self;
+
+        drop(tx);
+        _ = thread.join();
+    }
+}
+
+/// Starts new archives, replacing the running ones.
+///
+/// # Returns
+///
+/// A Result that is an error message if an archive is invalid, the running archives are kept in that case.
+///
+/// # Description
+///
+/// The replaced archives write the records they have queued, and finish their files.
+pub fn configure(configs: &[ArchiveConfig]) -> Result<(), String> {
+    install(start(configs)?);
+
+    Ok(())
+}
+
+/// Starts new archives without installing them, see [`install`].
+///
+/// # Returns
+///
+/// A Result containing the archives, or an error message if an archive is invalid.
+/// The archives started before an invalid one stop right away, without starting a file.
+pub fn start(configs: &[ArchiveConfig]) -> Result<Vec<Archive>, String> {
+    configs.iter().map(Archive::start).collect()
+}
+
+/// Installs started archives, replacing the running ones, which write the records they have queued and finish their files.
+pub fn install(archives: Vec<Archive>) {
+    *ARCHIVES.write().unwrap() = archives;
+}
+
+/// The number of running archives.
+pub fn count() -> usize {
+    ARCHIVES.read().unwrap().len()
+}
+
+/// Queues a message published by a client for every archive that records its topic.
+///
+/// # Description
+///
+/// Never waits on a file, so it is called from the connection threads directly.
+/// The record is only built when an archive wants it.
+///
+/// # Examples
+///
+/// ```
+/// services::archive::record(&client_id, "sensors/kitchen/temperature", 1, false, "21.5");
+/// ```
+pub fn record(client_id: &str, topic: &str, qos: u8, retain: bool, payload: &str) {
+    let archives = ARCHIVES.read().unwrap();
+    let mut record: Option<ArchiveRecord> = None;
+
+    for archive in archives.iter().filter(|archive: &&Archive| archive.wants(topic)) {
+        let record: &ArchiveRecord = record.get_or_insert_with(|| ArchiveRecord {
+            timestamp: now_millis(),
+            client_id: client_id.to_string(),
+            topic: topic.to_string(),
+            qos,
+            retain,
+            payload: payload.to_string(),
+        });
+
+        archive.record(record.clone());
+    }
+}
+
+/// Stops every archive, waiting until their files are finished. Called when the broker shuts down.
+pub fn close() {
+    let archives: Vec<Archive> = std::mem::take(&mut *ARCHIVES.write().unwrap());
+
+    for archive in archives {
+        archive.stop();
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed: Duration| elapsed.as_millis() as u64)
+}
+
+/// Writes the queued records of an archive to its files, until its queue is dropped and empty.
+fn write_records(config: &ArchiveConfig, rx: Receiver<ArchiveRecord>) {
+    let flush_interval: Duration = Duration::from_millis(config.flush_interval_ms.max(1));
+    let rotate_interval: Option<Duration> = config.rotate_interval_secs.map(Duration::from_secs);
+
+    let mut file: Option<ArchiveFile> = None;
+    let mut last_flush: Instant = Instant::now();
+
+    loop {
+        match rx.recv_timeout(flush_interval) {
+            Ok(record) => {
+                if file.is_none() {
+                    match ArchiveFile::create(config) {
+                        Ok(created) => {
+                            debug!(path = %created.path.display(), "Archive file started");
+                            file = Some(created);
+                        }
+                        Err(err) => {
+                            warn!(error = %err, "Could not start an archive file, dropping the record");
+                            METRICS.archive_records(Outcome::Failed, 1);
+                            continue;
+                        }
+                    }
+                }
+
+                if let Some(current) = file.as_mut() {
+                    match current.write(&record) {
+                        Ok(()) => METRICS.archive_records(Outcome::Written, 1),
+                        Err(err) => {
+                            // The file may be left broken, the next record starts a new one
+                            warn!(path = %current.path.display(), error = %err, "Could not write to the archive file");
+                            METRICS.archive_records(Outcome::Failed, 1);
+                            finish(file.take());
+                        }
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                break;
+            }
+        }
+
+        let Some(current) = file.as_mut() else {
+            continue;
+        };
+
+        let is_full: bool = config.max_file_bytes.is_some_and(|max: u64| current.bytes() >= max);
+        let is_old: bool = rotate_interval.is_some_and(|interval: Duration| current.started.elapsed() >= interval);
+
+        if is_full || is_old {
+            finish(file.take());
+        } else if last_flush.elapsed() >= flush_interval {
+            if let Err(err) = current.flush() {
+                warn!(path = %current.path.display(), error = %err, "Could not flush the archive file");
+            }
+
+            last_flush = Instant::now();
+        }
+    }
+
+    finish(file);
+    debug!("Archive stopped");
+}
+
+/// Finishes an archive file, if there is one.
+fn finish(file: Option<ArchiveFile>) {
+    if let Some(file) = file {
+        let path: PathBuf = file.path.clone();
+
+        match file.finish() {
+            Ok(()) => debug!(path = %path.display(), "Archive file finished"),
+            Err(err) => warn!(path = %path.display(), error = %err, "Could not finish the archive file"),
+        }
+    }
+}
+
+/// A writer that counts the bytes written through it, so a compressed file knows its size on disk.
+struct Counted<W: Write> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written: usize = self.inner.write(buf)?;
+        self.bytes += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The file an archive is writing to.
+struct ArchiveFile {
+    path: PathBuf,
+    started: Instant,
+    writer: FileWriter,
+}
+
+enum FileWriter {
+    JsonLines(Counted<BufWriter<File>>),
+    JsonLinesGzip(GzEncoder<Counted<BufWriter<File>>>),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet_output::ParquetWriter),
+}
+
+impl ArchiveFile {
+    /// Creates a new file, named after the archive's prefix and the current time, e.g. `messages-1760780000000.jsonl`.
+    fn create(config: &ArchiveConfig) -> io::Result<ArchiveFile> {
+        let extension: &str = match config.format {
+            ArchiveFormat::JsonLines => "jsonl",
+            ArchiveFormat::JsonLinesGzip => "jsonl.gz",
+            ArchiveFormat::Parquet => "parquet",
+        };
+
+        let (path, file) = create_new(Path::new(&config.directory), &config.prefix, extension)?;
+        let output: Counted<BufWriter<File>> = Counted { inner: BufWriter::new(file), bytes: 0 };
+
+        let writer: FileWriter = match config.format {
+            ArchiveFormat::JsonLines => FileWriter::JsonLines(output),
+            ArchiveFormat::JsonLinesGzip => FileWriter::JsonLinesGzip(GzEncoder::new(output, Compression::default())),
+            #[cfg(feature = "parquet")]
+            ArchiveFormat::Parquet => FileWriter::Parquet(parquet_output::ParquetWriter::new(output)?),
+            #[cfg(not(feature = "parquet"))]
+            ArchiveFormat::Parquet => {
+                return Err(io::Error::new(ErrorKind::Unsupported, "built without the parquet feature"));
+            }
+        };
+
+        Ok(ArchiveFile { path, started: Instant::now(), writer })
+    }
+
+    fn write(&mut self, record: &ArchiveRecord) -> io::Result<()> {
+        match &mut self.writer {
+            FileWriter::JsonLines(writer) => write_line(writer, record),
+            FileWriter::JsonLinesGzip(writer) => write_line(writer, record),
+            #[cfg(feature = "parquet")]
+            FileWriter::Parquet(writer) => writer.write(record),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.writer {
+            FileWriter::JsonLines(writer) => writer.flush(),
+            // Flushing a gzip stream ends its current block, so it is only flushed to the file as it fills up
+            FileWriter::JsonLinesGzip(writer) => writer.get_mut().flush(),
+            #[cfg(feature = "parquet")]
+            FileWriter::Parquet(writer) => writer.flush(),
+        }
+    }
+
+    /// The size of the file, counting what is still buffered for a Parquet file.
+    fn bytes(&self) -> u64 {
+        match &self.writer {
+            FileWriter::JsonLines(writer) => writer.bytes,
+            FileWriter::JsonLinesGzip(writer) => writer.get_ref().bytes,
+            #[cfg(feature = "parquet")]
+            FileWriter::Parquet(writer) => writer.bytes(),
+        }
+    }
+
+    /// Writes what is buffered and the end of the file, a gzip trailer or a Parquet footer.
+    fn finish(self) -> io::Result<()> {
+        match self.writer {
+            FileWriter::JsonLines(mut writer) => writer.flush(),
+            FileWriter::JsonLinesGzip(writer) => writer.finish()?.flush(),
+            #[cfg(feature = "parquet")]
+            FileWriter::Parquet(writer) => writer.finish(),
+        }
+    }
+}
+
+fn write_line<W: Write>(writer: &mut W, record: &ArchiveRecord) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writer.write_all(b"\n")
+}
+
+/// Creates a file that does not exist yet, named `<prefix>-<milliseconds>.<extension>`.
+/// When a file with that name exists, e.g. after a rotation in the same millisecond, a counter is added to the name.
+fn create_new(directory: &Path, prefix: &str, extension: &str) -> io::Result<(PathBuf, File)> {
+    let timestamp: u64 = now_millis();
+
+    for attempt in 0u32.. {
+        let name: String = match attempt {
+            0 => format!("{}-{}.{}", prefix, timestamp, extension),
+            _ => format!("{}-{}-{}.{}", prefix, timestamp, attempt, extension),
+        };
+        let path: PathBuf = directory.join(name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => {
+                return Ok((path, file));
+            }
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+
+    unreachable!()
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_output {
+    use std::fs::File;
+    use std::io::{ self, BufWriter, Write };
+    use std::sync::{ Arc, LazyLock };
+
+    use arrow_array::{ ArrayRef, BooleanArray, RecordBatch, StringArray, TimestampMillisecondArray, UInt8Array };
+    use arrow_schema::{ DataType, Field, Schema, SchemaRef, TimeUnit };
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+
+    use super::{ ArchiveRecord, Counted };
+
+    /// The rows collected before they are handed to the Parquet writer as one batch.
+    const BATCH_ROWS: usize = 1024;
+
+    static SCHEMA: LazyLock<SchemaRef> = LazyLock::new(|| {
+        Arc::new(
+            Schema::new(vec![
+                Field::new("timestamp", DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())), false),
+                Field::new("client_id", DataType::Utf8, false),
+                Field::new("topic", DataType::Utf8, false),
+                Field::new("qos", DataType::UInt8, false),
+                Field::new("retain", DataType::Boolean, false),
+                Field::new("payload", DataType::Utf8, false),
+            ])
+        )
+    });
+
+    /// Writes records to a Snappy compressed Parquet file.
+    pub(super) struct ParquetWriter {
+        writer: ArrowWriter<Counted<BufWriter<File>>>,
+        rows: Vec<ArchiveRecord>,
+    }
+
+    impl ParquetWriter {
+        pub(super) fn new(output: Counted<BufWriter<File>>) -> io::Result<ParquetWriter> {
+            let properties: WriterProperties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+            let writer: ArrowWriter<Counted<BufWriter<File>>> = ArrowWriter::try_new(output, Arc::clone(&SCHEMA), Some(properties)).map_err(
+                io::Error::other
+            )?;
+
+            Ok(ParquetWriter { writer, rows: Vec::with_capacity(BATCH_ROWS) })
+        }
+
+        pub(super) fn write(&mut self, record: &ArchiveRecord) -> io::Result<()> {
+            self.rows.push(record.clone());
+
+            if self.rows.len() >= BATCH_ROWS { self.write_batch() } else { Ok(()) }
+        }
+
+        /// Hands the collected rows to the Parquet writer. A row group is only written to the file once it is
+        /// full or the file is finished, the footer is what makes the file readable.
+        pub(super) fn flush(&mut self) -> io::Result<()> {
+            self.write_batch()
+        }
+
+        pub(super) fn bytes(&self) -> u64 {
+            (self.writer.bytes_written() + self.writer.in_progress_size()) as u64
+        }
+
+        pub(super) fn finish(mut self) -> io::Result<()> {
+            self.write_batch()?;
+
+            let mut output: Counted<BufWriter<File>> = self.writer.into_inner().map_err(io::Error::other)?;
+            output.flush()
+        }
+
+        fn write_batch(&mut self) -> io::Result<()> {
+            if self.rows.is_empty() {
+                return Ok(());
+            }
+
+            let rows: Vec<ArchiveRecord> = std::mem::take(&mut self.rows);
+
+            let columns: Vec<ArrayRef> = vec![
+                Arc::new(TimestampMillisecondArray::from_iter_values(rows.iter().map(|row: &ArchiveRecord| row.timestamp as i64)).with_timezone("UTC")),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|row: &ArchiveRecord| &row.client_id))),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|row: &ArchiveRecord| &row.topic))),
+                Arc::new(UInt8Array::from_iter_values(rows.iter().map(|row: &ArchiveRecord| row.qos))),
+                Arc::new(BooleanArray::from(rows.iter().map(|row: &ArchiveRecord| row.retain).collect::<Vec<bool>>())),
+                Arc::new(StringArray::from_iter_values(rows.iter().map(|row: &ArchiveRecord| &row.payload))),
+            ];
+
+            let batch: RecordBatch = RecordBatch::try_new(Arc::clone(&SCHEMA), columns).map_err(io::Error::other)?;
+
+            self.writer.write(&batch).map_err(io::Error::other)
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::RwLock;
+
+use crate::common_fn;
+use crate::models::config::{ AclAccess, AclRule, AuthConfig, UserConfig };
+
+/// The active authentication and ACL settings. Replaced as a whole when the config is reloaded,
+/// so connected clients keep their connection and the new rules apply from their next packet on.
+static ACCESS_CONTROL: RwLock<AccessControl> = RwLock::new(AccessControl {
+    allow_anonymous: true,
+    users: Vec::new(),
+    acl: Vec::new(),
+});
+
+/// Authentication and ACL settings, built from the `[auth]` and `[[acl]]` config sections.
+pub struct AccessControl {
+    allow_anonymous: bool,
+    users: Vec<UserConfig>,
+    acl: Vec<AclRule>,
+}
+
+impl AccessControl {
+    pub fn new(auth: &AuthConfig, acl: &[AclRule]) -> AccessControl {
+        AccessControl {
+            allow_anonymous: auth.allow_anonymous,
+            users: auth.users.clone(),
+            acl: acl.to_vec(),
+        }
+    }
+
+    /// Checks the credentials of a CONNECT packet.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username of the CONNECT packet, if the username flag is set.
+    /// * `password` - The password of the CONNECT packet.
+    ///
+    /// # Returns
+    ///
+    /// The CONNACK return code: 0 when the client is accepted, 4 when the username or password is wrong
+    /// and 5 when an anonymous client is not authorized to connect.
+    pub fn authenticate(&self, username: Option<&str>, password: &str) -> u8 {
+        match username {
+            None if self.allow_anonymous => 0,
+            None => 5,
+            Some(username) => {
+                // Without any configured users, a username is accepted as is
+                if self.users.is_empty() {
+                    return 0;
+                }
+
+                let is_known_user: bool = self.users
+                    .iter()
+                    .any(|user: &UserConfig| user.username == username && user.password == password);
+
+                if is_known_user { 0 } else { 4 }
+            }
+        }
+    }
+
+    /// Checks the ACL rules for a topic name.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username of the client, empty for anonymous clients.
+    /// * `topic` - The topic name, e.g. of a PUBLISH packet.
+    /// * `access` - The access that is needed, either `Read` or `Write`.
+    ///
+    /// # Returns
+    ///
+    /// True if there are no ACL rules, or if a rule for the client grants the access on the topic.
+    pub fn is_allowed(&self, username: &str, topic: &str, access: AclAccess) -> bool {
+        self.grants(username, access, |rule_topic: &str| common_fn::topic_filter::matches(rule_topic, topic))
+    }
+
+    /// Checks the ACL rules for a topic filter.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username of the client, empty for anonymous clients.
+    /// * `filter` - The topic filter, e.g. of a SUBSCRIBE packet.
+    /// * `access` - The access that is needed, either `Read` or `Write`.
+    ///
+    /// # Returns
+    ///
+    /// True if there are no ACL rules, or if a rule for the client grants the access on every topic
+    /// the filter can match. The wildcards of the filter are not matched as text, so a `sensors/+` rule
+    /// does not allow a subscription to `sensors/#`.
+    pub fn is_allowed_filter(&self, username: &str, filter: &str, access: AclAccess) -> bool {
+        self.grants(username, access, |rule_topic: &str| common_fn::topic_filter::covers(rule_topic, filter))
+    }
+
+    /// Checks if a rule for the client grants the access on a topic, as decided by `applies_to_topic`.
+    fn grants<F: Fn(&str) -> bool>(&self, username: &str, access: AclAccess, applies_to_topic: F) -> bool {
+        if self.acl.is_empty() {
+            return true;
+        }
+
+        self.acl.iter().any(|rule: &AclRule| {
+            let applies_to_client: bool = match &rule.username {
+                Some(rule_username) => rule_username == username,
+                None => true,
+            };
+
+            let grants_access: bool = rule.access == access || rule.access == AclAccess::ReadWrite;
+
+            applies_to_client && grants_access && applies_to_topic(&rule.topic)
+        })
+    }
+}
+
+/// Installs new authentication and ACL settings, used for every following check.
+///
+/// # Arguments
+///
+/// * `auth` - The auth section of the broker config.
+/// * `acl` - The ACL rules of the broker config.
+pub fn configure(auth: &AuthConfig, acl: &[AclRule]) {
+    *ACCESS_CONTROL.write().unwrap() = AccessControl::new(auth, acl);
+}
+
+/// Checks the credentials of a CONNECT packet against the active settings.
+/// See [`AccessControl::authenticate`].
+pub fn authenticate(username: Option<&str>, password: &str) -> u8 {
+    ACCESS_CONTROL.read().unwrap().authenticate(username, password)
+}
+
+/// Checks if a client may publish to a topic.
+pub fn can_publish(username: &str, topic: &str) -> bool {
+    ACCESS_CONTROL.read().unwrap().is_allowed(username, topic, AclAccess::Write)
+}
+
+/// Checks if a client may subscribe to a topic filter, see [`AccessControl::is_allowed_filter`].
+pub fn can_subscribe(username: &str, topic_filter: &str) -> bool {
+    ACCESS_CONTROL.read().unwrap().is_allowed_filter(username, topic_filter, AclAccess::Read)
+}
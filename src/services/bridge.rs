@@ -0,0 +1,300 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::{ channel, Receiver, Sender };
+use std::sync::{ Arc, Mutex };
+use std::thread::{ self, JoinHandle };
+use std::time::{ Duration, Instant };
+
+use tracing::{ debug, info, info_span, warn, Span };
+
+use crate::common_fn;
+use crate::models::config::{ BridgeConfig, BridgeDirection, BridgeTopic };
+use crate::mqtt_client::{ ConnectOptions, Message, MqttClient };
+use crate::services;
+
+/// How long a forwarded message is remembered, to recognise it when it comes back.
+const ECHO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One side of a bridge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Local,
+    Remote,
+}
+
+/// Starts every configured bridge, each on its own thread.
+///
+/// # Arguments
+///
+/// * `bridges` - The bridge sections of the broker config.
+/// * `local_addr` - The address of the local MQTT listener, the bridges connect to it as clients.
+pub fn start(bridges: &[BridgeConfig], local_addr: SocketAddr) {
+    for config in bridges {
+        let config: BridgeConfig = config.clone();
+
+        thread::spawn(move || {
+            run(&config, local_addr);
+        });
+    }
+}
+
+/// Keeps a bridge connected until the broker shuts down.
+///
+/// # Description
+///
+/// When the connection to either broker fails or is lost, both connections are closed
+/// and the bridge connects again after a delay. The delay starts at `reconnect_min_secs`,
+/// is doubled after every failed attempt up to `reconnect_max_secs`, and is reset once a
+/// connection succeeds.
+fn run(config: &BridgeConfig, local_addr: SocketAddr) {
+    let span: Span = info_span!("bridge", name = %config.name, remote = %config.address);
+    let _enter = span.enter();
+
+    let min_delay: Duration = Duration::from_secs(config.reconnect_min_secs);
+    let max_delay: Duration = Duration::from_secs(config.reconnect_max_secs).max(min_delay);
+    let mut delay: Duration = min_delay;
+
+    while !services::shutdown::is_requested() {
+        match connect_and_forward(config, local_addr) {
+            Ok(()) => {
+                info!("Bridge connection lost");
+                delay = min_delay;
+            }
+            Err(err) => {
+                warn!(error = %err, retry_in_secs = delay.as_secs(), "Bridge could not connect");
+            }
+        }
+
+        thread::sleep(delay);
+        delay = (delay * 2).min(max_delay);
+    }
+}
+
+/// Connects to both brokers, subscribes the bridged topics and forwards messages until
+/// either connection is lost.
+///
+/// # Returns
+///
+/// Ok when the bridge was connected and the connection was lost later,
+/// or an error message if connecting or subscribing failed.
+fn connect_and_forward(config: &BridgeConfig, local_addr: SocketAddr) -> Result<(), String> {
+    let client_id: String = config.client_id.clone().unwrap_or(format!("bridge-{}", config.name));
+
+    let mut remote_options: ConnectOptions = ConnectOptions::new(&client_id);
+    remote_options.keep_alive = config.keep_alive;
+    remote_options.username = config.username.clone();
+    remote_options.password = config.password.clone();
+
+    let mut local_options: ConnectOptions = ConnectOptions::new(&client_id);
+    local_options.keep_alive = config.keep_alive;
+
+    let (remote, remote_messages) = MqttClient::connect(config.address, &remote_options)?;
+
+    let (local, local_messages) = match MqttClient::connect(local_addr, &local_options) {
+        Ok(connection) => connection,
+        Err(err) => {
+            remote.disconnect();
+            return Err(err);
+        }
+    };
+
+    let subscribed: Result<(), String> = subscribe(&local, &config.topics, Side::Local).and_then(|_|
+        subscribe(&remote, &config.topics, Side::Remote)
+    );
+
+    if let Err(err) = subscribed {
+        local.disconnect();
+        remote.disconnect();
+        return Err(err);
+    }
+
+    info!("Bridge connected");
+
+    let echo_filter: Arc<Mutex<EchoFilter>> = Arc::new(Mutex::new(EchoFilter::default()));
+    let (done_tx, done_rx) = channel::<()>();
+
+    let outgoing: JoinHandle<()> = forward(
+        local_messages,
+        Arc::clone(&remote),
+        config.topics.clone(),
+        Side::Local,
+        Arc::clone(&echo_filter),
+        done_tx.clone()
+    );
+
+    let incoming: JoinHandle<()> = forward(
+        remote_messages,
+        Arc::clone(&local),
+        config.topics.clone(),
+        Side::Remote,
+        echo_filter,
+        done_tx
+    );
+
+    // Wait until one side is lost, then close both, which ends the other forwarder as well
+    _ = done_rx.recv();
+
+    local.disconnect();
+    remote.disconnect();
+
+    _ = outgoing.join();
+    _ = incoming.join();
+
+    Ok(())
+}
+
+/// Subscribes to the bridged topics that are forwarded away from one side.
+fn subscribe(client: &MqttClient, topics: &[BridgeTopic], side: Side) -> Result<(), String> {
+    let topic_filters: Vec<(String, u8)> = topics
+        .iter()
+        .filter(|topic: &&BridgeTopic| forwards_from(topic, side))
+        .map(|topic: &BridgeTopic| (format!("{}{}", prefix(topic, side), topic.pattern), topic.qos))
+        .collect();
+
+    if topic_filters.is_empty() {
+        return Ok(());
+    }
+
+    let return_codes: Vec<u8> = client.subscribe(&topic_filters)?;
+
+    for ((topic_filter, _), return_code) in topic_filters.iter().zip(return_codes) {
+        if return_code == 0x80 {
+            warn!(side = ?side, topic_filter = %topic_filter, "Bridge subscription refused");
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts a thread forwarding the messages received from one side to the other side.
+fn forward(
+    messages: Receiver<Message>,
+    destination: Arc<MqttClient>,
+    topics: Vec<BridgeTopic>,
+    source: Side,
+    echo_filter: Arc<Mutex<EchoFilter>>,
+    done_tx: Sender<()>
+) -> JoinHandle<()> {
+    let span: Span = Span::current();
+
+    thread::spawn(move || {
+        let _enter = span.enter();
+
+        for message in messages {
+            // A message this bridge forwarded to this side is coming back, don't send it again
+            if echo_filter.lock().unwrap().take(source, &message) {
+                debug!(topic = %message.topic, "Dropping a forwarded message that came back");
+                continue;
+            }
+
+            let Some(forwarded) = map_message(&topics, &message, source) else {
+                continue;
+            };
+
+            let destination_side: Side = if source == Side::Local { Side::Remote } else { Side::Local };
+            echo_filter.lock().unwrap().remember(destination_side, &forwarded);
+
+            debug!(from = %message.topic, to = %forwarded.topic, "Forwarding message");
+
+            if let Err(err) = destination.publish(&forwarded) {
+                warn!("{}", err);
+                break;
+            }
+        }
+
+        _ = done_tx.send(());
+    })
+}
+
+/// Maps a message received on one side to the message published on the other side.
+///
+/// # Arguments
+///
+/// * `topics` - The bridged topics.
+/// * `message` - The received message.
+/// * `source` - The side the message was received from.
+///
+/// # Returns
+///
+/// The message with its topic prefix swapped and the QoS of the first matching bridged topic,
+/// or None if no bridged topic forwards it in this direction.
+///
+/// # Examples
+///
+/// ```
+/// // pattern = "sensors/#", direction = "out", local_prefix = "", remote_prefix = "site1/"
+/// let forwarded: Message = map_message(&topics, &message_on("sensors/kitchen"), Side::Local).unwrap();
+/// assert_eq!(forwarded.topic, "site1/sensors/kitchen");
+/// ```
+pub fn map_message(topics: &[BridgeTopic], message: &Message, source: Side) -> Option<Message> {
+    let destination: Side = if source == Side::Local { Side::Remote } else { Side::Local };
+
+    topics
+        .iter()
+        .filter(|topic: &&BridgeTopic| forwards_from(topic, source))
+        .find_map(|topic: &BridgeTopic| {
+            let source_prefix: &str = prefix(topic, source);
+            let topic_filter: String = format!("{}{}", source_prefix, topic.pattern);
+
+            if !common_fn::topic_filter::matches(&topic_filter, &message.topic) {
+                return None;
+            }
+
+            let unprefixed_topic: &str = message.topic.strip_prefix(source_prefix)?;
+
+            Some(Message {
+                topic: format!("{}{}", prefix(topic, destination), unprefixed_topic),
+                payload: message.payload.clone(),
+                qos: topic.qos.min(2),
+                retain: message.retain,
+            })
+        })
+}
+
+fn forwards_from(topic: &BridgeTopic, side: Side) -> bool {
+    matches!(
+        (topic.direction, side),
+        (BridgeDirection::Both, _) | (BridgeDirection::Out, Side::Local) | (BridgeDirection::In, Side::Remote)
+    )
+}
+
+fn prefix(topic: &BridgeTopic, side: Side) -> &str {
+    match side {
+        Side::Local => &topic.local_prefix,
+        Side::Remote => &topic.remote_prefix,
+    }
+}
+
+/// Remembers the messages a bridge forwarded, so they are not forwarded back when
+/// the destination broker delivers them to the bridge's own subscription (MQTT 3.1.1 has no "no local").
+#[derive(Default)]
+pub struct EchoFilter {
+    forwarded: Vec<(Side, String, Vec<u8>, Instant)>,
+}
+
+impl EchoFilter {
+    /// Remembers a message forwarded to a side.
+    pub fn remember(&mut self, side: Side, message: &Message) {
+        let now: Instant = Instant::now();
+
+        self.forwarded.retain(|(_, _, _, forwarded_at)| now.duration_since(*forwarded_at) < ECHO_TIMEOUT);
+        self.forwarded.push((side, message.topic.clone(), message.payload.clone(), now));
+    }
+
+    /// Checks if a message received from a side was forwarded to that side by the bridge,
+    /// and forgets it if so.
+    pub fn take(&mut self, side: Side, message: &Message) -> bool {
+        match
+            self.forwarded
+                .iter()
+                .position(|(forwarded_side, topic, payload, _)| {
+                    *forwarded_side == side && *topic == message.topic && *payload == message.payload
+                })
+        {
+            Some(index) => {
+                self.forwarded.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::net::SocketAddr;
+use std::sync::{ Arc, LazyLock, RwLock };
+
+/// The registered hooks, in the order they were registered.
+///
+/// Replaced as a whole when a hook is registered, so a caller clones the `Arc` and runs the
+/// hooks without holding the lock.
+static HOOKS: LazyLock<RwLock<Arc<HookChain>>> = LazyLock::new(|| RwLock::new(Arc::new(HookChain::new())));
+
+/// A CONNECT, as passed to [`BrokerHooks::on_connect`].
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectInfo<'a> {
+    /// The client id, already assigned by the broker if the client connected with an empty one.
+    pub client_id: &'a str,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub address: SocketAddr,
+    pub clean_session: bool,
+}
+
+/// What a hook decides about a CONNECT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthDecision {
+    /// No decision, the next hook decides, or the `[auth]` settings when no hook does.
+    Continue,
+    /// Accept the connection, without checking the `[auth]` settings.
+    Allow,
+    /// Refuse the connection with CONNACK return code 4.
+    BadCredentials,
+    /// Refuse the connection with CONNACK return code 5.
+    NotAuthorized,
+}
+
+/// A message, as passed to [`BrokerHooks::on_publish`] and [`BrokerHooks::on_deliver`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub qos: u8,
+    pub retain: bool,
+}
+
+/// What a hook decides about a PUBLISH.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishAction {
+    /// Route the message, as the hook left it.
+    Continue,
+    /// Drop the message. The publisher still gets its acknowledgement.
+    Drop,
+}
+
+/// Callbacks for embedding custom logic in the broker, e.g. authentication, payload validation or enrichment.
+///
+/// # Description
+///
+/// Every callback has a default that changes nothing, so a hook only implements the ones it needs.
+/// The callbacks run on the connection and delivery threads, so they should return quickly;
+/// a hook that has to wait on something else should hand the work to its own thread.
+///
+/// Hooks are registered with [`register`], and run in the order they were registered,
+/// see [`HookChain`] for how their results are combined.
+///
+/// # Examples
+///
+/// ```
+/// struct Tenants;
+///
+/// impl BrokerHooks for Tenants {
+///     fn on_publish(&self, client_id: &str, message: &mut Message) -> PublishAction {
+///         // Every client publishes below its own prefix
+///         message.topic = format!("tenants/{}/{}", client_id, message.topic);
+///         PublishAction::Continue
+///     }
+/// }
+///
+/// services::hooks::register(Arc::new(Tenants));
+/// ```
+pub trait BrokerHooks: Send + Sync {
+    /// Called for a CONNECT that passed the protocol and client id checks, before the `[auth]` settings are checked.
+    fn on_connect(&self, _connect: &ConnectInfo) -> AuthDecision {
+        AuthDecision::Continue
+    }
+
+    /// Called for a topic filter the ACL and the subscription limit allow.
+    ///
+    /// # Returns
+    ///
+    /// The QoS to grant, a higher QoS than the one given is lowered to it, or None to refuse
+    /// the topic filter with return code 0x80.
+    fn on_subscribe(&self, _client_id: &str, _topic_filter: &str, qos: u8) -> Option<u8> {
+        Some(qos)
+    }
+
+    /// Called for a PUBLISH from a client that the ACL allows, before it is routed.
+    ///
+    /// The hook may change the topic (redirecting the message), the payload and the retain flag.
+    /// A message redirected to a topic the ACL does not let the client publish to is dropped.
+    /// The QoS decides the acknowledgement flow with the publisher, so a changed QoS is ignored.
+    fn on_publish(&self, _client_id: &str, _message: &mut Message) -> PublishAction {
+        PublishAction::Continue
+    }
+
+    /// Called before a message is sent to a subscriber, with the QoS it is delivered at.
+    ///
+    /// # Returns
+    ///
+    /// False to skip the delivery to this client.
+    fn on_deliver(&self, _client_id: &str, _message: &Message) -> bool {
+        true
+    }
+
+    /// Called once the connection of a client that was accepted is closed.
+    ///
+    /// The connection of a session that is taken over by a new connection with the same client id is reported
+    /// with the reason [`TAKEN_OVER`](crate::control_packet::connect::TAKEN_OVER), by the new connection
+    /// after its [`on_connect`](BrokerHooks::on_connect) accepted it and before its CONNACK is sent.
+    fn on_disconnect(&self, _client_id: &str, _reason: &str) {}
+}
+
+/// Hooks that run one after the other, itself a [`BrokerHooks`].
+///
+/// # Description
+///
+/// * `on_connect` - The first hook that does not return [`AuthDecision::Continue`] decides.
+/// * `on_subscribe` - Each hook gets the QoS granted by the hook before it, the first one that
+///   refuses the topic filter decides.
+/// * `on_publish` - Each hook gets the message as the hook before it left it, the first one that
+///   drops the message decides.
+/// * `on_deliver` - The first hook that skips the delivery decides.
+/// * `on_disconnect` - Every hook is called.
+#[derive(Default)]
+pub struct HookChain {
+    hooks: Vec<Arc<dyn BrokerHooks>>,
+}
+
+impl HookChain {
+    pub fn new() -> HookChain {
+        HookChain { hooks: Vec::new() }
+    }
+
+    /// Adds a hook to the end of the chain.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain: HookChain = HookChain::new().with(Arc::new(Authenticator)).with(Arc::new(Validator));
+    /// ```
+    pub fn with(mut self, hooks: Arc<dyn BrokerHooks>) -> HookChain {
+        self.hooks.push(hooks);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+}
+
+impl BrokerHooks for HookChain {
+    fn on_connect(&self, connect: &ConnectInfo) -> AuthDecision {
+        self.hooks
+            .iter()
+            .map(|hooks: &Arc<dyn BrokerHooks>| hooks.on_connect(connect))
+            .find(|decision: &AuthDecision| *decision != AuthDecision::Continue)
+            .unwrap_or(AuthDecision::Continue)
+    }
+
+    fn on_subscribe(&self, client_id: &str, topic_filter: &str, qos: u8) -> Option<u8> {
+        self.hooks.iter().try_fold(qos, |granted: u8, hooks: &Arc<dyn BrokerHooks>| {
+            hooks.on_subscribe(client_id, topic_filter, granted).map(|qos: u8| qos.min(granted))
+        })
+    }
+
+    fn on_publish(&self, client_id: &str, message: &mut Message) -> PublishAction {
+        let qos: u8 = message.qos;
+
+        for hooks in self.hooks.iter() {
+            let action: PublishAction = hooks.on_publish(client_id, message);
+            message.qos = qos;
+
+            if action == PublishAction::Drop {
+                return PublishAction::Drop;
+            }
+        }
+
+        PublishAction::Continue
+    }
+
+    fn on_deliver(&self, client_id: &str, message: &Message) -> bool {
+        self.hooks.iter().all(|hooks: &Arc<dyn BrokerHooks>| hooks.on_deliver(client_id, message))
+    }
+
+    fn on_disconnect(&self, client_id: &str, reason: &str) {
+        for hooks in self.hooks.iter() {
+            hooks.on_disconnect(client_id, reason);
+        }
+    }
+}
+
+/// Adds a hook to the end of the registered hooks, it applies to the packets handled afterwards.
+pub fn register(hooks: Arc<dyn BrokerHooks>) {
+    let mut registered = HOOKS.write().unwrap();
+
+    let mut chain: HookChain = HookChain { hooks: registered.hooks.clone() };
+    chain.hooks.push(hooks);
+
+    *registered = Arc::new(chain);
+}
+
+/// The registered hooks, None when there are none so callers can skip building their arguments.
+///
+/// # Examples
+///
+/// ```
+/// if let Some(hooks) = services::hooks::registered() {
+///     hooks.on_disconnect(&client_id, "Keep alive timeout");
+/// }
+/// ```
+pub fn registered() -> Option<Arc<HookChain>> {
+    let chain: Arc<HookChain> = Arc::clone(&HOOKS.read().unwrap());
+
+    if chain.is_empty() { None } else { Some(chain) }
+}
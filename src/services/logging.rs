@@ -0,0 +1,93 @@
+use std::sync::OnceLock;
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{ fmt, reload, EnvFilter, Registry };
+
+use crate::models::config::{ LogFormat, LoggingConfig };
+
+/// The handle used to swap the level filter when the config is reloaded.
+static FILTER_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Installs the global `tracing` subscriber.
+///
+/// # Arguments
+///
+/// * `config` - The logging section of the broker config.
+///
+/// # Returns
+///
+/// A Result that is an error message if the level directives could not be parsed.
+///
+/// # Description
+///
+/// The level is an `EnvFilter` directive list, so levels can be set per module, e.g.
+/// `info,mqtt_broker::control_packet=debug`. When the `RUST_LOG` environment variable is set,
+/// it takes precedence over the configured level.
+///
+/// The text format is meant for a terminal, the json format writes one JSON object per line
+/// (including the fields of the current spans), for log shippers.
+///
+/// The level can be changed later with [`reload`], the format is fixed once installed.
+///
+/// # Examples
+///
+/// ```
+/// services::logging::init(&config.logging).unwrap();
+/// ```
+pub fn init(config: &LoggingConfig) -> Result<(), String> {
+    let (filter_layer, handle) = reload::Layer::new(filter(config)?);
+
+    // Only one of the two format layers is set, the other one is a no-op
+    let (text_layer, json_layer) = match config.format {
+        LogFormat::Text => (Some(fmt::layer()), None),
+        LogFormat::Json => (None, Some(fmt::layer().json().with_current_span(true).with_span_list(true))),
+    };
+
+    tracing_subscriber
+        ::registry()
+        .with(filter_layer)
+        .with(text_layer)
+        .with(json_layer)
+        .try_init()
+        .map_err(|err| format!("Could not install logger: {}", err))?;
+
+    _ = FILTER_HANDLE.set(handle);
+
+    Ok(())
+}
+
+/// Replaces the level filter of the installed subscriber, keeping the log format.
+///
+/// # Arguments
+///
+/// * `config` - The logging section of the reloaded broker config.
+///
+/// # Returns
+///
+/// A Result that is an error message if the level directives could not be parsed,
+/// or if no logger has been installed with [`init`].
+pub fn reload(config: &LoggingConfig) -> Result<(), String> {
+    install(filter(config)?)
+}
+
+/// Replaces the level filter of the installed subscriber with a built one, see [`filter`].
+///
+/// # Returns
+///
+/// A Result that is an error message if no logger has been installed with [`init`].
+pub fn install(filter: EnvFilter) -> Result<(), String> {
+    let handle: &reload::Handle<EnvFilter, Registry> = FILTER_HANDLE.get().ok_or(
+        "No logger installed"
+    )?;
+
+    handle.reload(filter).map_err(|err| format!("Could not reload logger: {}", err))
+}
+
+/// Builds the level filter, preferring `RUST_LOG` over the configured level.
+pub fn filter(config: &LoggingConfig) -> Result<EnvFilter, String> {
+    match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(directives),
+        _ => EnvFilter::try_new(&config.level),
+    }.map_err(|err| format!("Invalid log level: {}", err))
+}
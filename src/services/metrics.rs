@@ -0,0 +1,443 @@
+use std::fmt::Write as _;
+use std::net::{ SocketAddr, TcpListener, TcpStream };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::common_fn;
+use crate::models::client::{ Client, Clients };
+use crate::models::publish_queue_item::PublishQueue;
+use crate::models::topic::Topics;
+use crate::services::archive::Outcome;
+use crate::services::outbound::QueueDepth;
+use crate::services::rate_limit::Limit;
+use crate::services::webhooks::Delivery;
+use tracing::warn;
+
+/// The broker wide metrics, updated from the connection and publish threads.
+pub static METRICS: Metrics = Metrics::new();
+
+/// The control packet names, indexed by packet type.
+const PACKET_TYPE_NAMES: [&str; 16] = [
+    "RESERVED",
+    "CONNECT",
+    "CONNACK",
+    "PUBLISH",
+    "PUBACK",
+    "PUBREC",
+    "PUBREL",
+    "PUBCOMP",
+    "SUBSCRIBE",
+    "SUBACK",
+    "UNSUBSCRIBE",
+    "UNSUBACK",
+    "PINGREQ",
+    "PINGRESP",
+    "DISCONNECT",
+    "RESERVED",
+];
+
+/// Latency buckets in seconds, from half a millisecond up to 10 seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 10.0,
+];
+
+/// Retry buckets, counting how many times a QoS packet had to be sent again.
+const RETRY_BUCKETS: [f64; 6] = [0.0, 1.0, 2.0, 3.0, 5.0, 10.0];
+
+/// A Prometheus histogram with fixed bucket bounds.
+pub struct Histogram<const N: usize> {
+    bounds: [f64; N],
+    buckets: [AtomicU64; N],
+    count: AtomicU64,
+    sum: AtomicU64, // f64 bits
+}
+
+impl<const N: usize> Histogram<N> {
+    pub const fn new(bounds: [f64; N]) -> Histogram<N> {
+        Histogram {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; N],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a single observation.
+    pub fn observe(&self, value: f64) {
+        for (index, bound) in self.bounds.iter().enumerate() {
+            if value <= *bound {
+                self.buckets[index].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        _ = self.sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits: u64| {
+            Some((f64::from_bits(bits) + value).to_bits())
+        });
+    }
+
+    /// Records a duration, in seconds.
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Writes the histogram in the Prometheus text format. `labels` is either empty,
+    /// or a comma separated list of labels to put in front of the `le` label.
+    fn render(&self, output: &mut String, name: &str, labels: &str) {
+        let separator: &str = if labels.is_empty() { "" } else { "," };
+
+        for (index, bound) in self.bounds.iter().enumerate() {
+            _ = writeln!(
+                output,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name,
+                labels,
+                separator,
+                bound,
+                self.buckets[index].load(Ordering::Relaxed)
+            );
+        }
+
+        let labels_block: String = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+
+        _ = writeln!(output, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, self.count());
+        _ = writeln!(output, "{}_sum{} {}", name, labels_block, f64::from_bits(self.sum.load(Ordering::Relaxed)));
+        _ = writeln!(output, "{}_count{} {}", name, labels_block, self.count());
+    }
+}
+
+/// Counters and histograms exported on the `/metrics` endpoint.
+pub struct Metrics {
+    packets_received: [AtomicU64; 16],
+    connections_accepted: AtomicU64,
+    connections_rejected: [AtomicU64; 6],
+    rate_limited: [AtomicU64; 3],
+    outbound_dropped: AtomicU64,
+    outbound_overflow_disconnects: AtomicU64,
+    webhook_events: [AtomicU64; 3],
+    invalid_payloads: AtomicU64,
+    archive_records: [AtomicU64; 3],
+    pub publish_latency: Histogram<12>,
+    pub qos_1_retries: Histogram<6>,
+    pub qos_2_retries: Histogram<6>,
+}
+
+/// The sizes of the shared broker state, sampled when the metrics are scraped.
+pub struct Gauges {
+    pub clients: usize,
+    pub clients_connected: usize,
+    pub topics: usize,
+    pub publish_queue: usize,
+    /// The PUBLISH packets waiting in the outbound queues of the connected clients, and their bytes.
+    pub outbound_messages: usize,
+    pub outbound_bytes: usize,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub const fn new() -> Metrics {
+        Metrics {
+            packets_received: [const { AtomicU64::new(0) }; 16],
+            connections_accepted: AtomicU64::new(0),
+            connections_rejected: [const { AtomicU64::new(0) }; 6],
+            rate_limited: [const { AtomicU64::new(0) }; 3],
+            outbound_dropped: AtomicU64::new(0),
+            outbound_overflow_disconnects: AtomicU64::new(0),
+            webhook_events: [const { AtomicU64::new(0) }; 3],
+            invalid_payloads: AtomicU64::new(0),
+            archive_records: [const { AtomicU64::new(0) }; 3],
+            publish_latency: Histogram::new(LATENCY_BUCKETS),
+            qos_1_retries: Histogram::new(RETRY_BUCKETS),
+            qos_2_retries: Histogram::new(RETRY_BUCKETS),
+        }
+    }
+
+    /// Counts a control packet handled in `handle_connection`.
+    pub fn packet_received(&self, packet_type: u8) {
+        self.packets_received[(packet_type & 0x0f) as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a CONNACK by its return code. Return code 0 is an accepted connection,
+    /// every other code is a rejection.
+    pub fn connack(&self, return_code: u8) {
+        if return_code == 0 {
+            self.connections_accepted.fetch_add(1, Ordering::Relaxed);
+        } else if let Some(counter) = self.connections_rejected.get(return_code as usize) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a connection, PUBLISH or SUBSCRIBE that went over its limit.
+    pub fn rate_limited(&self, limit: Limit) {
+        self.rate_limited[limit as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a PUBLISH dropped from, or not let into, a full outbound queue.
+    pub fn outbound_dropped(&self) {
+        self.outbound_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connection closed because its outbound queue was full.
+    pub fn outbound_overflow_disconnect(&self) {
+        self.outbound_overflow_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts webhook events by what happened to them.
+    pub fn webhook_events(&self, delivery: Delivery, count: u64) {
+        self.webhook_events[delivery as usize].fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Counts a PUBLISH that did not follow the schema of its topic.
+    pub fn invalid_payload(&self) {
+        self.invalid_payloads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts archive records by what happened to them.
+    pub fn archive_records(&self, outcome: Outcome, count: u64) {
+        self.archive_records[outcome as usize].fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    ///
+    /// # Arguments
+    ///
+    /// * `gauges` - The current sizes of the shared broker state.
+    ///
+    /// # Returns
+    ///
+    /// The metrics as a String, ready to be served on `/metrics`.
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let mut output: String = String::new();
+
+        _ = writeln!(output, "# HELP mqtt_packets_received_total Control packets handled, by packet type.");
+        _ = writeln!(output, "# TYPE mqtt_packets_received_total counter");
+        for (packet_type, name) in PACKET_TYPE_NAMES.iter().enumerate() {
+            // Both reserved types share a name, so only the first one is exported
+            if packet_type == 15 {
+                continue;
+            }
+
+            _ = writeln!(
+                output,
+                "mqtt_packets_received_total{{type=\"{}\"}} {}",
+                name,
+                self.packets_received[packet_type].load(Ordering::Relaxed)
+            );
+        }
+
+        _ = writeln!(output, "# HELP mqtt_connections_accepted_total Connections accepted with CONNACK return code 0.");
+        _ = writeln!(output, "# TYPE mqtt_connections_accepted_total counter");
+        _ = writeln!(
+            output,
+            "mqtt_connections_accepted_total {}",
+            self.connections_accepted.load(Ordering::Relaxed)
+        );
+
+        _ = writeln!(output, "# HELP mqtt_connections_rejected_total Connections rejected, by CONNACK return code.");
+        _ = writeln!(output, "# TYPE mqtt_connections_rejected_total counter");
+        for return_code in 1..self.connections_rejected.len() {
+            _ = writeln!(
+                output,
+                "mqtt_connections_rejected_total{{return_code=\"{}\"}} {}",
+                return_code,
+                self.connections_rejected[return_code].load(Ordering::Relaxed)
+            );
+        }
+
+        _ = writeln!(output, "# HELP mqtt_rate_limited_total Connections, PUBLISH and SUBSCRIBE packets over their limit, by limit.");
+        _ = writeln!(output, "# TYPE mqtt_rate_limited_total counter");
+        for limit in Limit::ALL {
+            _ = writeln!(
+                output,
+                "mqtt_rate_limited_total{{limit=\"{}\"}} {}",
+                limit.name(),
+                self.rate_limited[limit as usize].load(Ordering::Relaxed)
+            );
+        }
+
+        _ = writeln!(output, "# HELP mqtt_outbound_dropped_total PUBLISH packets dropped because the outbound queue of a client was full.");
+        _ = writeln!(output, "# TYPE mqtt_outbound_dropped_total counter");
+        _ = writeln!(output, "mqtt_outbound_dropped_total {}", self.outbound_dropped.load(Ordering::Relaxed));
+
+        _ = writeln!(output, "# HELP mqtt_outbound_overflow_disconnects_total Connections closed because their outbound queue was full.");
+        _ = writeln!(output, "# TYPE mqtt_outbound_overflow_disconnects_total counter");
+        _ = writeln!(
+            output,
+            "mqtt_outbound_overflow_disconnects_total {}",
+            self.outbound_overflow_disconnects.load(Ordering::Relaxed)
+        );
+
+        _ = writeln!(output, "# HELP mqtt_webhook_events_total Webhook events, by whether they were delivered, dropped from a full queue or failed.");
+        _ = writeln!(output, "# TYPE mqtt_webhook_events_total counter");
+        for delivery in Delivery::ALL {
+            _ = writeln!(
+                output,
+                "mqtt_webhook_events_total{{result=\"{}\"}} {}",
+                delivery.name(),
+                self.webhook_events[delivery as usize].load(Ordering::Relaxed)
+            );
+        }
+
+        _ = writeln!(output, "# HELP mqtt_invalid_payloads_total PUBLISH packets rejected because their payload did not follow the schema of their topic.");
+        _ = writeln!(output, "# TYPE mqtt_invalid_payloads_total counter");
+        _ = writeln!(output, "mqtt_invalid_payloads_total {}", self.invalid_payloads.load(Ordering::Relaxed));
+
+        _ = writeln!(output, "# HELP mqtt_archive_records_total Archived messages, by whether they were written, dropped from a full queue or failed.");
+        _ = writeln!(output, "# TYPE mqtt_archive_records_total counter");
+        for outcome in Outcome::ALL {
+            _ = writeln!(
+                output,
+                "mqtt_archive_records_total{{result=\"{}\"}} {}",
+                outcome.name(),
+                self.archive_records[outcome as usize].load(Ordering::Relaxed)
+            );
+        }
+
+        let gauge_list: [(&str, &str, usize); 6] = [
+            ("mqtt_clients", "Client sessions known to the broker.", gauges.clients),
+            ("mqtt_clients_connected", "Clients currently connected.", gauges.clients_connected),
+            ("mqtt_topics", "Topics known to the broker.", gauges.topics),
+            ("mqtt_publish_queue", "QoS 1 and QoS 2 flows in flight.", gauges.publish_queue),
+            ("mqtt_outbound_queue_messages", "PUBLISH packets waiting to be written to the clients.", gauges.outbound_messages),
+            ("mqtt_outbound_queue_bytes", "Bytes of the PUBLISH packets waiting to be written to the clients.", gauges.outbound_bytes),
+        ];
+
+        for (name, help, value) in gauge_list {
+            _ = writeln!(output, "# HELP {} {}", name, help);
+            _ = writeln!(output, "# TYPE {} gauge", name);
+            _ = writeln!(output, "{} {}", name, value);
+        }
+
+        _ = writeln!(
+            output,
+            "# HELP mqtt_publish_deliver_latency_seconds Time from receiving a PUBLISH until it is handed to the subscribers."
+        );
+        _ = writeln!(output, "# TYPE mqtt_publish_deliver_latency_seconds histogram");
+        self.publish_latency.render(&mut output, "mqtt_publish_deliver_latency_seconds", "");
+
+        _ = writeln!(output, "# HELP mqtt_qos_retries Times a QoS packet was sent again before the flow completed.");
+        _ = writeln!(output, "# TYPE mqtt_qos_retries histogram");
+        self.qos_1_retries.render(&mut output, "mqtt_qos_retries", "qos=\"1\"");
+        self.qos_2_retries.render(&mut output, "mqtt_qos_retries", "qos=\"2\"");
+
+        output
+    }
+}
+
+/// Starts the HTTP listener serving `/metrics`.
+///
+/// # Arguments
+///
+/// * `address` - The address to bind the HTTP listener to.
+/// * `clients` - The shared client sessions, sampled for the client gauges.
+/// * `topics` - The shared topics, sampled for the topic gauge.
+/// * `publish_queue` - The shared publish queue, sampled for the publish queue gauge.
+///
+/// # Returns
+///
+/// A Result containing the address actually bound, or an error message if binding failed.
+///
+/// # Description
+///
+/// The listener runs on its own thread, and serves one request per connection.
+/// Every other path answers with 404.
+pub fn start(
+    address: SocketAddr,
+    clients: Arc<Clients>,
+    topics: Arc<Topics>,
+    publish_queue: Arc<PublishQueue>
+) -> Result<SocketAddr, String> {
+    let listener: TcpListener = TcpListener::bind(address).map_err(|err|
+        format!("Failed to bind metrics listener to {}: {}", address, err)
+    )?;
+
+    let local_addr: SocketAddr = listener.local_addr().map_err(|err| err.to_string())?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    serve(&mut stream, &clients, &topics, &publish_queue);
+                }
+                Err(err) => {
+                    warn!(error = %err, "Could not accept metrics connection");
+                }
+            }
+        }
+    });
+
+    Ok(local_addr)
+}
+
+/// Answers a single scrape request.
+fn serve(
+    stream: &mut TcpStream,
+    clients: &Clients,
+    topics: &Topics,
+    publish_queue: &PublishQueue
+) {
+    _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+
+    let request: common_fn::http::Request = match common_fn::http::read_request(stream) {
+        Ok(request) => request,
+        Err(err) => {
+            common_fn::http::write_response(stream, err.status, "text/plain", err.message.as_bytes());
+            return;
+        }
+    };
+
+    if request.path != "/metrics" {
+        common_fn::http::write_response(stream, 404, "text/plain", b"Not Found");
+        return;
+    }
+
+    if request.method != "GET" {
+        common_fn::http::write_response(stream, 405, "text/plain", b"Method Not Allowed");
+        return;
+    }
+
+    // Sample the gauges, one shard at a time
+    let mut outbound: QueueDepth = QueueDepth::default();
+
+    clients.for_each(|client: &Client| {
+        if client.is_connected {
+            let depth: QueueDepth = client.tx.depth();
+            outbound.messages += depth.messages;
+            outbound.bytes += depth.bytes;
+        }
+    });
+
+    let gauges: Gauges = Gauges {
+        clients: clients.len(),
+        clients_connected: clients.connected(),
+        topics: topics.len(),
+        publish_queue: publish_queue.len(),
+        outbound_messages: outbound.messages,
+        outbound_bytes: outbound.bytes,
+    };
+
+    let body: String = METRICS.render(&gauges);
+
+    common_fn::http::write_response(
+        stream,
+        200,
+        "text/plain; version=0.0.4",
+        body.as_bytes()
+    );
+}
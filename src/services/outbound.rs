@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{ RecvError, RecvTimeoutError, TryRecvError };
+use std::sync::{ Arc, Condvar, Mutex, MutexGuard, RwLock };
+use std::time::{ Duration, Instant };
+
+use crate::models::config::{ OutboundConfig, OverflowPolicy };
+use crate::services::metrics::METRICS;
+
+/// The active queue limits, replaced as a whole when the config is reloaded.
+/// Connected clients keep the limits they connected with.
+static OUTBOUND: RwLock<OutboundConfig> = RwLock::new(OutboundConfig {
+    max_messages: None,
+    max_bytes: None,
+    overflow: OverflowPolicy::DropOldest,
+});
+
+/// A packet for the write thread, or an error message telling it to close the stream.
+pub type Outgoing = Result<Vec<u8>, String>;
+
+/// Why a packet was not queued, see [`OutboundSender::send`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The connection is closed, or closing.
+    Closed,
+    /// The PUBLISH did not fit the queue and was dropped.
+    Dropped,
+    /// The PUBLISH did not fit the queue and the connection is being closed.
+    Overflow,
+}
+
+/// How much is waiting in an outbound queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueDepth {
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+/// A queued packet.
+#[derive(Debug)]
+struct Item {
+    packet: Outgoing,
+    /// A PUBLISH, counted against the limits.
+    limited: bool,
+    /// A QoS 0 PUBLISH, which the `drop_oldest` policy may drop.
+    qos_0: bool,
+}
+
+#[derive(Debug)]
+struct Queue {
+    items: VecDeque<Item>,
+    /// The PUBLISH packets in `items`, and their bytes.
+    depth: QueueDepth,
+    senders: usize,
+    /// Set when the receiver is dropped, or the queue overflowed with the `disconnect` policy.
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+    config: OutboundConfig,
+}
+
+/// The sending half of the outbound queue of a client, cloned for every thread that writes to it.
+#[derive(Debug)]
+pub struct OutboundSender {
+    shared: Arc<Shared>,
+}
+
+/// The receiving half of the outbound queue of a client, drained by its write thread.
+#[derive(Debug)]
+pub struct OutboundReceiver {
+    shared: Arc<Shared>,
+}
+
+/// Creates an outbound queue with the configured limits.
+pub fn channel() -> (OutboundSender, OutboundReceiver) {
+    bounded(&OUTBOUND.read().unwrap())
+}
+
+/// Creates an outbound queue with the given limits.
+///
+/// # Arguments
+///
+/// * `config` - The limits and overflow policy of the queue, unbounded without limits.
+///
+/// # Returns
+///
+/// The sending and receiving halves, like `std::sync::mpsc::channel`.
+///
+/// # Examples
+///
+/// ```
+/// let (tx, rx) = outbound::bounded(&OutboundConfig { max_messages: Some(100), ..Default::default() });
+/// _ = tx.send(Ok(vec![0xd0, 0x00]));
+///
+/// assert_eq!(rx.recv(), Ok(Ok(vec![0xd0, 0x00])));
+/// ```
+pub fn bounded(config: &OutboundConfig) -> (OutboundSender, OutboundReceiver) {
+    let shared: Arc<Shared> = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            items: VecDeque::new(),
+            depth: QueueDepth::default(),
+            senders: 1,
+            closed: false,
+        }),
+        ready: Condvar::new(),
+        config: config.clone(),
+    });
+
+    (OutboundSender { shared: Arc::clone(&shared) }, OutboundReceiver { shared })
+}
+
+/// Installs new limits, for the clients that connect afterwards.
+pub fn configure(config: &OutboundConfig) {
+    *OUTBOUND.write().unwrap() = config.clone();
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap()
+    }
+
+    /// True if a PUBLISH of `bytes` fits next to what is queued.
+    fn fits(&self, depth: &QueueDepth, bytes: usize) -> bool {
+        self.config.max_messages.is_none_or(|max: usize| depth.messages < max) &&
+            self.config.max_bytes.is_none_or(|max: usize| depth.bytes + bytes <= max)
+    }
+}
+
+impl Queue {
+    fn pop(&mut self) -> Option<Outgoing> {
+        let item: Item = self.items.pop_front()?;
+
+        if item.limited {
+            self.forget(&item);
+        }
+
+        Some(item.packet)
+    }
+
+    fn forget(&mut self, item: &Item) {
+        self.depth.messages -= 1;
+        self.depth.bytes -= item.packet.as_ref().map_or(0, Vec::len);
+    }
+}
+
+impl OutboundSender {
+    /// Queues a packet for the write thread.
+    ///
+    /// # Arguments
+    ///
+    /// * `packet` - The encoded packet, or an error message that makes the write thread close the stream.
+    ///
+    /// # Returns
+    ///
+    /// Ok if the packet was queued. A PUBLISH that does not fit is handled by the overflow policy:
+    /// `drop_oldest` drops queued QoS 0 PUBLISH packets to make room, and drops the new one if that
+    /// is not enough, `drop_new` drops the new one and `disconnect` empties the queue and closes the
+    /// connection. Dropped QoS 1 and QoS 2 PUBLISH packets are sent again when their flow retries.
+    pub fn send(&self, packet: Outgoing) -> Result<(), SendError> {
+        let mut queue: MutexGuard<'_, Queue> = self.shared.lock();
+
+        if queue.closed {
+            return Err(SendError::Closed);
+        }
+
+        let (limited, qos_0): (bool, bool) = match &packet {
+            // The fixed header of a PUBLISH is 0x3_, with the QoS in bits 1 and 2
+            Ok(bytes) => {
+                let publish: bool = bytes.first().is_some_and(|byte: &u8| byte >> 4 == 3);
+                (publish, publish && bytes[0] & 0x06 == 0)
+            }
+            Err(_) => (false, false),
+        };
+        let length: usize = packet.as_ref().map_or(0, Vec::len);
+
+        if limited && !self.shared.fits(&queue.depth, length) {
+            match self.shared.config.overflow {
+                OverflowPolicy::DropOldest => {
+                    // Drop the oldest QoS 0 PUBLISH packets, front to back, until the new one fits
+                    while !self.shared.fits(&queue.depth, length) {
+                        let Some(index) = queue.items.iter().position(|item: &Item| item.qos_0) else {
+                            break;
+                        };
+
+                        let item: Item = queue.items.remove(index).unwrap();
+                        queue.forget(&item);
+                        METRICS.outbound_dropped();
+                    }
+
+                    if !self.shared.fits(&queue.depth, length) {
+                        METRICS.outbound_dropped();
+                        return Err(SendError::Dropped);
+                    }
+                }
+                OverflowPolicy::DropNew => {
+                    METRICS.outbound_dropped();
+                    return Err(SendError::Dropped);
+                }
+                OverflowPolicy::Disconnect => {
+                    // Nothing queued is written any more, the write thread only closes the stream
+                    queue.items.clear();
+                    queue.depth = QueueDepth::default();
+                    queue.items.push_back(Item {
+                        packet: Err("Outbound queue full".to_string()),
+                        limited: false,
+                        qos_0: false,
+                    });
+                    queue.closed = true;
+                    self.shared.ready.notify_one();
+
+                    METRICS.outbound_overflow_disconnect();
+                    return Err(SendError::Overflow);
+                }
+            }
+        }
+
+        if limited {
+            queue.depth.messages += 1;
+            queue.depth.bytes += length;
+        }
+
+        queue.items.push_back(Item { packet, limited, qos_0 });
+        self.shared.ready.notify_one();
+
+        Ok(())
+    }
+
+    /// The PUBLISH packets waiting to be written, and their bytes.
+    pub fn depth(&self) -> QueueDepth {
+        self.shared.lock().depth
+    }
+}
+
+impl Clone for OutboundSender {
+    fn clone(&self) -> OutboundSender {
+        self.shared.lock().senders += 1;
+
+        OutboundSender { shared: Arc::clone(&self.shared) }
+    }
+}
+
+impl Drop for OutboundSender {
+    fn drop(&mut self) {
+        let mut queue: MutexGuard<'_, Queue> = self.shared.lock();
+        queue.senders -= 1;
+
+        // Wake the receiver, so it sees there is nobody left to send
+        if queue.senders == 0 {
+            self.shared.ready.notify_all();
+        }
+    }
+}
+
+impl OutboundReceiver {
+    /// Waits for the next packet, like `std::sync::mpsc::Receiver::recv`.
+    ///
+    /// # Returns
+    ///
+    /// The next packet, or an error once the queue is empty and every sender is dropped.
+    pub fn recv(&self) -> Result<Outgoing, RecvError> {
+        let mut queue: MutexGuard<'_, Queue> = self.shared.lock();
+
+        loop {
+            if let Some(packet) = queue.pop() {
+                return Ok(packet);
+            }
+
+            if queue.senders == 0 {
+                return Err(RecvError);
+            }
+
+            queue = self.shared.ready.wait(queue).unwrap();
+        }
+    }
+
+    /// Waits up to `timeout` for the next packet, like `std::sync::mpsc::Receiver::recv_timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Outgoing, RecvTimeoutError> {
+        let deadline: Instant = Instant::now() + timeout;
+        let mut queue: MutexGuard<'_, Queue> = self.shared.lock();
+
+        loop {
+            if let Some(packet) = queue.pop() {
+                return Ok(packet);
+            }
+
+            if queue.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            let remaining: Duration = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            queue = self.shared.ready.wait_timeout(queue, remaining).unwrap().0;
+        }
+    }
+
+    /// Takes the next packet without waiting, like `std::sync::mpsc::Receiver::try_recv`.
+    pub fn try_recv(&self) -> Result<Outgoing, TryRecvError> {
+        let mut queue: MutexGuard<'_, Queue> = self.shared.lock();
+
+        match queue.pop() {
+            Some(packet) => Ok(packet),
+            None if queue.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl Drop for OutboundReceiver {
+    fn drop(&mut self) {
+        // Nothing is written any more, so the senders stop queueing
+        let mut queue: MutexGuard<'_, Queue> = self.shared.lock();
+        queue.closed = true;
+        queue.items.clear();
+        queue.depth = QueueDepth::default();
+    }
+}
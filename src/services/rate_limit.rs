@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{ Mutex, RwLock };
+use std::time::{ Duration, Instant };
+
+use crate::models::config::{ LimitAction, LimitsConfig };
+use crate::services::metrics::METRICS;
+
+/// The active limits, replaced as a whole when the config is reloaded.
+/// Connected clients keep the limits they connected with.
+static LIMITS: RwLock<LimitsConfig> = RwLock::new(LimitsConfig {
+    connections_per_second: None,
+    connection_burst: None,
+    publishes_per_second: None,
+    publish_burst: None,
+    bytes_per_second: None,
+    bytes_burst: None,
+    max_subscriptions: None,
+    max_qos: None,
+    action: LimitAction::Throttle,
+});
+
+/// The connection buckets of the IP addresses that connected recently.
+static CONNECTIONS: Mutex<Option<ConnectionLimiter>> = Mutex::new(None);
+
+/// The limits that are counted in the `mqtt_rate_limited_total` metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Connections = 0,
+    Publishes = 1,
+    Subscriptions = 2,
+}
+
+impl Limit {
+    pub const ALL: [Limit; 3] = [Limit::Connections, Limit::Publishes, Limit::Subscriptions];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Limit::Connections => "connections",
+            Limit::Publishes => "publishes",
+            Limit::Subscriptions => "subscriptions",
+        }
+    }
+}
+
+/// A token bucket, holding up to `burst` tokens and refilled at `rate` tokens per second.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    /// Negative when a caller took more than there was, see [`TokenBucket::take`].
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    ///
+    /// # Arguments
+    ///
+    /// * `rate` - Tokens added per second.
+    /// * `burst` - The most tokens the bucket holds, one second of the rate when None.
+    pub fn new(rate: f64, burst: Option<f64>, now: Instant) -> TokenBucket {
+        let burst: f64 = burst.unwrap_or(rate).max(1.0);
+
+        TokenBucket { rate, burst, tokens: burst, updated_at: now }
+    }
+
+    /// Takes tokens if the bucket has them.
+    ///
+    /// # Returns
+    ///
+    /// True if the tokens were taken. An amount larger than the burst is taken from a full bucket,
+    /// so a single large message is not refused forever.
+    pub fn try_take(&mut self, amount: f64, now: Instant) -> bool {
+        self.refill(now);
+
+        if self.tokens < amount.min(self.burst) {
+            return false;
+        }
+
+        self.tokens -= amount;
+        true
+    }
+
+    /// Takes tokens even if the bucket does not have them, leaving it in debt.
+    ///
+    /// # Returns
+    ///
+    /// How long until the debt is paid off, zero if the bucket had the tokens.
+    /// [`Duration::MAX`] if the bucket is never refilled, the config refuses such a rate.
+    pub fn take(&mut self, amount: f64, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= amount;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::try_from_secs_f64(-self.tokens / self.rate).unwrap_or(Duration::MAX)
+        }
+    }
+
+    /// True if the bucket is full, so forgetting it changes nothing.
+    pub fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.burst
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed: f64 = now.saturating_duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated_at = now;
+    }
+}
+
+/// Limits the new connections per IP address.
+pub struct ConnectionLimiter {
+    rate: f64,
+    burst: Option<f64>,
+    buckets: HashMap<IpAddr, TokenBucket>,
+}
+
+impl ConnectionLimiter {
+    /// The most IP addresses kept before the full buckets are forgotten.
+    const MAX_TRACKED: usize = 1024;
+
+    pub fn new(rate: f64, burst: Option<f64>) -> ConnectionLimiter {
+        ConnectionLimiter { rate, burst, buckets: HashMap::new() }
+    }
+
+    /// Checks if an IP address may open another connection, and counts it if so.
+    pub fn allow(&mut self, ip: IpAddr, now: Instant) -> bool {
+        // A full bucket behaves like a missing one, so they are forgotten before the map grows large
+        if self.buckets.len() >= ConnectionLimiter::MAX_TRACKED && !self.buckets.contains_key(&ip) {
+            self.buckets.retain(|_, bucket: &mut TokenBucket| !bucket.is_full(now));
+        }
+
+        self.buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(self.rate, self.burst, now))
+            .try_take(1.0, now)
+    }
+}
+
+/// What to do with a PUBLISH, see [`ClientLimiter::check_publish`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// Handle the PUBLISH now.
+    Allow,
+    /// Wait this long, then handle the PUBLISH.
+    Wait(Duration),
+    /// Acknowledge the PUBLISH without delivering it.
+    Drop,
+    /// Close the connection.
+    Disconnect,
+}
+
+/// The publish limits of a single client, created when it connects.
+#[derive(Debug, Clone)]
+pub struct ClientLimiter {
+    publishes: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+    action: LimitAction,
+}
+
+impl ClientLimiter {
+    pub fn new(limits: &LimitsConfig, now: Instant) -> ClientLimiter {
+        ClientLimiter {
+            publishes: limits.publishes_per_second.map(|rate: f64| TokenBucket::new(rate, limits.publish_burst, now)),
+            bytes: limits.bytes_per_second.map(|rate: f64| TokenBucket::new(rate, limits.bytes_burst, now)),
+            action: limits.action,
+        }
+    }
+
+    /// Counts a PUBLISH against the limits.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The length of the whole PUBLISH packet.
+    /// * `now` - The time the PUBLISH was read.
+    ///
+    /// # Returns
+    ///
+    /// [`Decision::Allow`] within the limits. Over a limit, how long to wait with the `throttle` action,
+    /// [`Decision::Drop`] with `drop` and [`Decision::Disconnect`] with `disconnect`.
+    /// A dropped or refused PUBLISH is not counted.
+    pub fn check_publish(&mut self, bytes: usize, now: Instant) -> Decision {
+        let bytes: f64 = bytes as f64;
+
+        if self.action == LimitAction::Throttle {
+            // Both buckets are charged, the wait is the longer of the two
+            let publish_wait: Duration = self.publishes.as_mut().map_or(Duration::ZERO, |bucket: &mut TokenBucket| bucket.take(1.0, now));
+            let bytes_wait: Duration = self.bytes.as_mut().map_or(Duration::ZERO, |bucket: &mut TokenBucket| bucket.take(bytes, now));
+            let wait: Duration = publish_wait.max(bytes_wait);
+
+            return if wait.is_zero() { Decision::Allow } else { Decision::Wait(wait) };
+        }
+
+        // Only charge the buckets when both have room, a refused PUBLISH costs nothing
+        let has_room = |bucket: &Option<TokenBucket>, amount: f64| -> bool {
+            bucket.clone().is_none_or(|mut bucket: TokenBucket| bucket.try_take(amount, now))
+        };
+
+        if has_room(&self.publishes, 1.0) && has_room(&self.bytes, bytes) {
+            if let Some(bucket) = self.publishes.as_mut() {
+                bucket.try_take(1.0, now);
+            }
+
+            if let Some(bucket) = self.bytes.as_mut() {
+                bucket.try_take(bytes, now);
+            }
+
+            return Decision::Allow;
+        }
+
+        match self.action {
+            LimitAction::Drop => Decision::Drop,
+            _ => Decision::Disconnect,
+        }
+    }
+}
+
+/// Installs new limits. The connection buckets start over, clients that are connected keep their limits.
+pub fn configure(limits: &LimitsConfig) {
+    *LIMITS.write().unwrap() = limits.clone();
+
+    *CONNECTIONS.lock().unwrap() = limits.connections_per_second.map(|rate: f64|
+        ConnectionLimiter::new(rate, limits.connection_burst)
+    );
+}
+
+/// Checks if an IP address may open another connection, see [`ConnectionLimiter::allow`].
+/// Always true when no connection rate is configured.
+pub fn allow_connection(ip: IpAddr) -> bool {
+    let allowed: bool = match CONNECTIONS.lock().unwrap().as_mut() {
+        Some(limiter) => limiter.allow(ip, Instant::now()),
+        None => true,
+    };
+
+    if !allowed {
+        METRICS.rate_limited(Limit::Connections);
+    }
+
+    allowed
+}
+
+/// The publish limits for a client that has just connected.
+pub fn client_limiter() -> ClientLimiter {
+    ClientLimiter::new(&LIMITS.read().unwrap(), Instant::now())
+}
+
+/// The most topic filters a client can be subscribed to, None without a limit.
+pub fn max_subscriptions() -> Option<usize> {
+    LIMITS.read().unwrap().max_subscriptions
+}
+
+/// The highest QoS granted to a subscription, 2 without a limit.
+pub fn max_qos() -> u8 {
+    LIMITS.read().unwrap().max_qos.unwrap_or(2)
+}
+
+/// What happens to a client over its limits.
+pub fn action() -> LimitAction {
+    LIMITS.read().unwrap().action
+}
@@ -0,0 +1,397 @@
+use std::sync::{ Arc, LazyLock, RwLock };
+
+use serde_json::{ Map, Value };
+use tracing::{ debug, warn };
+
+use crate::common_fn;
+use crate::control_packet;
+use crate::models::client::Clients;
+use crate::models::config::{ RuleAction, RuleCondition, RuleConfig };
+use crate::models::publish_queue_item::PublishQueue;
+use crate::models::topic::Topics;
+use crate::services;
+use crate::services::hooks::Message;
+use crate::services::storage::state::Record;
+use crate::services::webhooks::{ Event, Webhook };
+
+/// The active rules, replaced as a whole when the config is reloaded.
+static RULES: LazyLock<RwLock<Arc<RuleSet>>> = LazyLock::new(|| RwLock::new(Arc::new(RuleSet { rules: Vec::new() })));
+
+/// A rule, checked and with its sink started.
+#[derive(Debug)]
+struct Rule {
+    config: RuleConfig,
+    sink: Option<Webhook>,
+}
+
+/// The rules of the rule engine, run in the publish path on every PUBLISH from a client.
+///
+/// # Description
+///
+/// Every rule whose topic filter and conditions match a message is applied, in the order of the
+/// config file. A rule sees the message as the client published it, not as another rule converted it:
+///
+/// * `republish` - Publishes the converted message to the `target` topic. Republished messages
+///   are not run through the rules again, so rules can not loop.
+/// * `drop` - The message is not delivered, retained or sent to the webhooks.
+/// * `sink` - Posts the converted message to the `sink` endpoint as a `message_published` event.
+///
+/// # Examples
+///
+/// ```toml
+/// [[rule]]
+/// name = "fahrenheit"
+/// topic = "raw/+/temp"
+/// conditions = [{ field = "unit", equals = "F" }]
+/// action = "republish"
+/// target = "normalized/{1}/temperature"
+/// convert = [{ field = "value", multiply = 0.5555556, add = -17.777778, decimals = 1 }]
+/// set = { unit = "C", device = "{1}" }
+/// ```
+#[derive(Debug)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    /// Checks the rules, and starts the sinks of the `sink` rules.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the [`RuleSet`], or an error message naming the first invalid rule.
+    pub fn new(configs: &[RuleConfig]) -> Result<RuleSet, String> {
+        let mut rules: Vec<Rule> = Vec::new();
+
+        for config in configs {
+            let invalid = |reason: &str| -> String { format!("Invalid rule {}: {}", config.name, reason) };
+
+            if !common_fn::topic_filter::is_valid(&config.topic) {
+                return Err(invalid("the topic filter is not valid"));
+            }
+
+            if config.qos.is_some_and(|qos: u8| qos > 2) {
+                return Err(invalid("the QoS must be 0, 1 or 2"));
+            }
+
+            if config.action == RuleAction::Republish && config.target.is_none() {
+                return Err(invalid("a republish rule needs a target"));
+            }
+
+            let sink: Option<Webhook> = match (config.action, &config.sink) {
+                (RuleAction::Sink, Some(sink)) => Some(Webhook::start(sink).map_err(|err| invalid(&err))?),
+                (RuleAction::Sink, None) => {
+                    return Err(invalid("a sink rule needs a sink"));
+                }
+                _ => None,
+            };
+
+            rules.push(Rule { config: config.clone(), sink });
+        }
+
+        Ok(RuleSet { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies the matching rules to a message, as they would be in the publish path, without publishing anything.
+    ///
+    /// # Returns
+    ///
+    /// The action and converted message of every matching rule, in order.
+    pub fn evaluate(&self, client_id: &str, message: &Message) -> Vec<(RuleAction, Message)> {
+        self.rules
+            .iter()
+            .filter_map(|rule: &Rule| Some((rule.config.action, rule.apply(client_id, message)?)))
+            .collect()
+    }
+
+    /// Runs the rules on a PUBLISH from a client.
+    ///
+    /// # Arguments
+    ///
+    /// * `topics` - The topics of the broker, for republished messages.
+    /// * `clients` - The client sessions of the broker, for republished messages.
+    /// * `publish_queue` - The QoS flows in flight, for republished messages.
+    /// * `client_id` - The publisher.
+    /// * `message` - The message, as the client published it and the hooks left it.
+    ///
+    /// # Returns
+    ///
+    /// True if a rule dropped the message.
+    pub fn run(
+        &self,
+        topics: &Topics,
+        clients: &Clients,
+        publish_queue: &Arc<PublishQueue>,
+        client_id: &str,
+        message: &Message
+    ) -> bool {
+        let mut dropped: bool = false;
+
+        for rule in self.rules.iter() {
+            let Some(converted) = rule.apply(client_id, message) else {
+                continue;
+            };
+
+            debug!(rule = %rule.config.name, topic = %message.topic, "Rule matched");
+
+            match rule.config.action {
+                RuleAction::Republish => {
+                    // A target built from the topic levels can still end up with a wildcard or empty
+                    if converted.topic.is_empty() || converted.topic.contains(['+', '#']) {
+                        warn!(rule = %rule.config.name, target = %converted.topic, "Rule target is not a valid topic name");
+                        continue;
+                    }
+
+                    republish(topics, clients, publish_queue, &converted);
+                }
+                RuleAction::Drop => {
+                    dropped = true;
+                }
+                RuleAction::Sink => {
+                    if let Some(sink) = &rule.sink {
+                        sink.send(&Event::MessagePublished {
+                            client_id,
+                            topic: &converted.topic,
+                            qos: converted.qos,
+                            retain: converted.retain,
+                            payload: &converted.payload,
+                        });
+                    }
+                }
+            }
+        }
+
+        dropped
+    }
+}
+
+impl Rule {
+    /// Matches a message against the topic filter and conditions of the rule, and converts it.
+    ///
+    /// # Returns
+    ///
+    /// The message as the rule republishes or sends it, or None if the rule does not apply.
+    fn apply(&self, client_id: &str, message: &Message) -> Option<Message> {
+        let captured: Vec<String> = common_fn::topic_filter::captures(&self.config.topic, &message.topic)?;
+
+        let uses_payload: bool =
+            !self.config.conditions.is_empty() || !self.config.convert.is_empty() || !self.config.set.is_empty();
+
+        // Conditions and conversions only apply to JSON object payloads
+        let mut payload: Option<Value> = None;
+
+        if uses_payload {
+            let Ok(Value::Object(object)) = serde_json::from_str::<Value>(&message.payload) else {
+                return None;
+            };
+
+            let object: Value = Value::Object(object);
+
+            if !self.config.conditions.iter().all(|condition: &RuleCondition| holds(condition, &object)) {
+                return None;
+            }
+
+            payload = Some(object);
+        }
+
+        let fill = |template: &str| -> String { render(template, &captured, client_id, &message.topic) };
+
+        if let Some(object) = payload.as_mut() {
+            for conversion in self.config.convert.iter() {
+                let Some(field) = object.pointer_mut(&pointer(&conversion.field)) else {
+                    continue;
+                };
+
+                let Some(value) = field.as_f64() else {
+                    continue;
+                };
+
+                let mut converted: f64 = value * conversion.multiply + conversion.add;
+
+                if let Some(decimals) = conversion.decimals {
+                    let factor: f64 = (10f64).powi(decimals as i32);
+                    converted = (converted * factor).round() / factor;
+                }
+
+                if let Some(number) = serde_json::Number::from_f64(converted) {
+                    *field = Value::Number(number);
+                }
+            }
+
+            for (field, value) in self.config.set.iter() {
+                let value: Value = match value {
+                    Value::String(template) => Value::String(fill(template)),
+                    other => other.clone(),
+                };
+
+                set_field(object, field, value);
+            }
+        }
+
+        Some(Message {
+            topic: self.config.target.as_deref().map_or_else(|| message.topic.clone(), fill),
+            payload: payload.map_or_else(|| message.payload.clone(), |object: Value| object.to_string()),
+            qos: self.config.qos.unwrap_or(message.qos),
+            retain: self.config.retain,
+        })
+    }
+}
+
+/// Publishes a message to the subscribers of its topic, and retains it if it has the retain flag.
+fn republish(topics: &Topics, clients: &Clients, publish_queue: &Arc<PublishQueue>, message: &Message) {
+    control_packet::publish::publish(
+        topics,
+        clients,
+        Arc::clone(publish_queue),
+        &message.topic,
+        &message.payload,
+        &false,
+        &message.qos,
+        &false
+    );
+
+    if message.retain {
+        services::storage::record(Record::Retained {
+            topic: message.topic.clone(),
+            payload: message.payload.clone(),
+            qos: message.qos,
+        });
+
+        topics.set_retained(&message.topic, (message.payload.clone(), message.qos));
+    }
+}
+
+/// Checks a condition on a JSON object.
+fn holds(condition: &RuleCondition, object: &Value) -> bool {
+    let field: Option<&Value> = object.pointer(&pointer(&condition.field));
+
+    if let Some(exists) = condition.exists {
+        if field.is_some() != exists {
+            return false;
+        }
+    }
+
+    if let Some(expected) = &condition.equals {
+        if !field.is_some_and(|value: &Value| same(value, expected)) {
+            return false;
+        }
+    }
+
+    if let Some(unexpected) = &condition.not_equals {
+        if field.is_some_and(|value: &Value| same(value, unexpected)) {
+            return false;
+        }
+    }
+
+    let number: Option<f64> = field.and_then(Value::as_f64);
+
+    if condition.greater_than.is_some_and(|bound: f64| !number.is_some_and(|number: f64| number > bound)) {
+        return false;
+    }
+
+    if condition.less_than.is_some_and(|bound: f64| !number.is_some_and(|number: f64| number < bound)) {
+        return false;
+    }
+
+    true
+}
+
+/// Compares two JSON values, numbers by their value so `5` in the config equals `5.0` in a payload.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Turns a field path like `reading.unit` into a JSON pointer like `/reading/unit`.
+fn pointer(field: &str) -> String {
+    format!("/{}", field.replace('~', "~0").replace('/', "~1").replace('.', "/"))
+}
+
+/// Sets a field on a JSON object, creating the objects on its path that are missing.
+fn set_field(object: &mut Value, field: &str, value: Value) {
+    let mut current: &mut Value = object;
+    let mut keys = field.split('.').peekable();
+
+    while let Some(key) = keys.next() {
+        if !current.is_object() {
+            *current = Value::Object(Map::new());
+        }
+
+        let map: &mut Map<String, Value> = current.as_object_mut().unwrap();
+
+        if keys.peek().is_none() {
+            map.insert(key.to_string(), value);
+            return;
+        }
+
+        current = map.entry(key.to_string()).or_insert_with(|| Value::Object(Map::new()));
+    }
+}
+
+/// Fills in a template: `{1}`, `{2}`, ... with the levels matched by the wildcards, `{topic}` with the
+/// topic of the message and `{client_id}` with its publisher. Anything else in braces is kept as it is.
+///
+/// # Examples
+///
+/// ```
+/// let captured: Vec<String> = vec!["kitchen".to_string()];
+///
+/// assert_eq!(render("normalized/{1}/temperature", &captured, "sensor/1", "raw/kitchen/temp"), "normalized/kitchen/temperature");
+/// ```
+fn render(template: &str, captured: &[String], client_id: &str, topic: &str) -> String {
+    let mut rendered: String = String::with_capacity(template.len());
+    let mut rest: &str = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+
+        let name: &str = &rest[1..end];
+
+        match name {
+            "topic" => rendered.push_str(topic),
+            "client_id" => rendered.push_str(client_id),
+            _ => match name.parse::<usize>().ok().and_then(|index: usize| captured.get(index.checked_sub(1)?)) {
+                Some(level) => rendered.push_str(level),
+                None => rendered.push_str(&rest[..=end]),
+            },
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Installs new rules, replacing the running ones.
+///
+/// # Returns
+///
+/// A Result that is an error message if a rule is invalid, the running rules are kept in that case.
+pub fn configure(configs: &[RuleConfig]) -> Result<(), String> {
+    install(RuleSet::new(configs)?);
+
+    Ok(())
+}
+
+/// Installs built rules, replacing the running ones.
+pub fn install(rules: RuleSet) {
+    *RULES.write().unwrap() = Arc::new(rules);
+}
+
+/// The active rules, None when there are none so the publish path can skip them.
+pub fn active() -> Option<Arc<RuleSet>> {
+    let rules: Arc<RuleSet> = Arc::clone(&RULES.read().unwrap());
+
+    if rules.is_empty() { None } else { Some(rules) }
+}
@@ -0,0 +1,216 @@
+use std::fs;
+use std::sync::{ Arc, LazyLock, RwLock };
+
+use jsonschema::{ ValidationError, Validator };
+use serde_json::{ json, Value };
+use tracing::debug;
+
+use crate::common_fn;
+use crate::control_packet;
+use crate::models::client::Clients;
+use crate::models::config::{ InvalidAction, SchemaConfig };
+use crate::models::publish_queue_item::PublishQueue;
+use crate::models::topic::Topics;
+use crate::services::hooks::Message;
+
+/// The active schemas, replaced as a whole when the config is reloaded.
+static SCHEMAS: LazyLock<RwLock<Arc<SchemaSet>>> = LazyLock::new(|| RwLock::new(Arc::new(SchemaSet { schemas: Vec::new() })));
+
+/// The start of the topics invalid messages are published to, followed by their own topic.
+pub const DEAD_LETTER_PREFIX: &str = "$dlq/";
+
+/// A schema, compiled.
+struct TopicSchema {
+    config: SchemaConfig,
+    validator: Validator,
+}
+
+/// A message that does not follow the schema of its topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// What happens to the publisher, from the first schema the message failed.
+    pub action: InvalidAction,
+    /// True if the message is published to the dead-letter topic.
+    pub dead_letter: bool,
+    /// The validation error, with the location of the invalid field.
+    pub error: String,
+}
+
+/// The JSON Schemas the payloads published on their topic filters must follow, checked in the publish path
+/// on every PUBLISH from a client, before it is routed.
+///
+/// # Description
+///
+/// A message on a topic that matches the topic filter of a schema must be a JSON document that
+/// follows the schema, and when several topic filters match it has to follow every one of their schemas.
+/// Messages on other topics are not checked.
+///
+/// # Examples
+///
+/// ```toml
+/// [[schema]]
+/// topic = "sensors/+/telemetry"
+/// on_invalid = "drop"
+///
+/// [schema.schema]
+/// type = "object"
+/// required = ["value", "unit"]
+/// properties = { value = { type = "number" }, unit = { enum = ["C", "F"] } }
+/// ```
+pub struct SchemaSet {
+    schemas: Vec<TopicSchema>,
+}
+
+impl SchemaSet {
+    /// Loads and compiles the schemas.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the [`SchemaSet`], or an error message naming the topic filter of the first invalid schema.
+    pub fn new(configs: &[SchemaConfig]) -> Result<SchemaSet, String> {
+        let mut schemas: Vec<TopicSchema> = Vec::new();
+
+        for config in configs {
+            let invalid = |reason: &str| -> String { format!("Invalid schema for {}: {}", config.topic, reason) };
+
+            if !common_fn::topic_filter::is_valid(&config.topic) {
+                return Err(invalid("the topic filter is not valid"));
+            }
+
+            let schema: Value = match (&config.schema, &config.file) {
+                (Some(schema), None) => schema.clone(),
+                (None, Some(file)) => {
+                    let content: String = fs
+                        ::read_to_string(file)
+                        .map_err(|err| invalid(&format!("could not read {}: {}", file, err)))?;
+
+                    serde_json::from_str(&content).map_err(|err| invalid(&format!("{} is not valid JSON: {}", file, err)))?
+                }
+                _ => {
+                    return Err(invalid("one of schema and file must be set"));
+                }
+            };
+
+            let validator: Validator = jsonschema::validator_for(&schema).map_err(|err| invalid(&err.to_string()))?;
+
+            schemas.push(TopicSchema { config: config.clone(), validator });
+        }
+
+        Ok(SchemaSet { schemas })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Validates a message against the schemas of its topic.
+    ///
+    /// # Returns
+    ///
+    /// A Result that is a [`Violation`] of the first schema the message does not follow.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if let Err(violation) = schemas.validate(&message) {
+    ///     warn!(error = %violation.error, "Invalid payload");
+    /// }
+    /// ```
+    pub fn validate(&self, message: &Message) -> Result<(), Violation> {
+        // The payload is parsed once, and only if a schema applies
+        let mut payload: Option<Result<Value, String>> = None;
+
+        for schema in self.schemas.iter() {
+            if !common_fn::topic_filter::matches(&schema.config.topic, &message.topic) {
+                continue;
+            }
+
+            let parsed: &Result<Value, String> = payload.get_or_insert_with(|| {
+                serde_json::from_str::<Value>(&message.payload).map_err(|err| format!("The payload is not valid JSON: {}", err))
+            });
+
+            let error: Option<String> = match parsed {
+                Ok(document) => schema.validator.validate(document).err().map(|err: ValidationError| describe(&err)),
+                Err(err) => Some(err.clone()),
+            };
+
+            if let Some(error) = error {
+                return Err(Violation {
+                    action: schema.config.on_invalid,
+                    dead_letter: schema.config.dead_letter,
+                    error,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Describes a validation error, prefixed with the JSON pointer of the invalid field unless it is the whole document.
+fn describe(err: &ValidationError) -> String {
+    match err.instance_path.as_str() {
+        "" => err.to_string(),
+        path => format!("{}: {}", path, err),
+    }
+}
+
+/// Publishes an invalid message to its dead-letter topic, `$dlq/` followed by its topic.
+///
+/// # Description
+///
+/// The payload is a JSON object with the `topic`, `client_id`, `error` and original `payload` of the message,
+/// published at the QoS of the message, without the retain flag. Topics starting with `$` are not matched by
+/// topic filters starting with a wildcard, so only clients subscribed to `$dlq/...` get them.
+///
+/// # Arguments
+///
+/// * `topics` - The topics of the broker.
+/// * `clients` - The client sessions of the broker.
+/// * `publish_queue` - The QoS flows in flight.
+/// * `client_id` - The publisher of the message.
+/// * `message` - The invalid message.
+/// * `error` - Why the message is invalid.
+pub fn dead_letter(
+    topics: &Topics,
+    clients: &Clients,
+    publish_queue: &Arc<PublishQueue>,
+    client_id: &str,
+    message: &Message,
+    error: &str
+) {
+    let topic: String = format!("{}{}", DEAD_LETTER_PREFIX, message.topic);
+    let payload: String = json!({
+        "topic": message.topic,
+        "client_id": client_id,
+        "error": error,
+        "payload": message.payload,
+    }).to_string();
+
+    debug!(topic = %topic, "Publishing an invalid message to its dead-letter topic");
+
+    control_packet::publish::publish(topics, clients, Arc::clone(publish_queue), &topic, &payload, &false, &message.qos, &false);
+}
+
+/// Installs new schemas, replacing the active ones.
+///
+/// # Returns
+///
+/// A Result that is an error message if a schema is invalid or can not be read, the active schemas are kept in that case.
+pub fn configure(configs: &[SchemaConfig]) -> Result<(), String> {
+    install(SchemaSet::new(configs)?);
+
+    Ok(())
+}
+
+/// Installs compiled schemas, replacing the active ones.
+pub fn install(schemas: SchemaSet) {
+    *SCHEMAS.write().unwrap() = Arc::new(schemas);
+}
+
+/// The active schemas, None when there are none so the publish path can skip them.
+pub fn active() -> Option<Arc<SchemaSet>> {
+    let schemas: Arc<SchemaSet> = Arc::clone(&SCHEMAS.read().unwrap());
+
+    if schemas.is_empty() { None } else { Some(schemas) }
+}
@@ -0,0 +1,60 @@
+use std::sync::{ LazyLock, RwLock };
+
+use crate::models::client::Clients;
+use crate::models::config::{ SessionConfig, TakeoverWill };
+
+/// The active session settings, replaced as a whole when the config is reloaded.
+static SESSION: LazyLock<RwLock<SessionConfig>> = LazyLock::new(|| RwLock::new(SessionConfig::default()));
+
+/// Installs new session settings, for the CONNECT packets handled afterwards.
+pub fn configure(config: &SessionConfig) {
+    *SESSION.write().unwrap() = config.clone();
+}
+
+/// What happens to the will of a connection that is taken over.
+pub fn takeover_will() -> TakeoverWill {
+    SESSION.read().unwrap().takeover_will
+}
+
+/// Checks a client id against the configured length and characters.
+///
+/// # Returns
+///
+/// The reason the client id is rejected, if it is.
+pub fn validate_client_id(client_id: &str) -> Result<(), &'static str> {
+    check_client_id(&SESSION.read().unwrap(), client_id)
+}
+
+/// Checks a client id against the length and characters of a session config.
+pub fn check_client_id(config: &SessionConfig, client_id: &str) -> Result<(), &'static str> {
+    if config.max_client_id_length.is_some_and(|max: usize| client_id.chars().count() > max) {
+        return Err("The client identifier is too long");
+    }
+
+    if let Some(allowed) = &config.client_id_chars {
+        let is_allowed = |character: char| -> bool { character.is_ascii_alphanumeric() || allowed.contains(character) };
+
+        if !client_id.chars().all(is_allowed) {
+            return Err("The client identifier has a character that is not allowed");
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates a client id for a client that connected with an empty one (MQTT-3.1.3-6).
+///
+/// # Returns
+///
+/// The configured prefix followed by 16 random hex digits, which no session uses yet.
+pub fn assign_client_id(clients: &Clients) -> String {
+    let prefix: String = SESSION.read().unwrap().assigned_id_prefix.clone();
+
+    loop {
+        let client_id: String = format!("{}{:016x}", prefix, rand::random::<u64>());
+
+        if clients.with(&client_id, |_| ()).is_none() {
+            return client_id;
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use tracing::{ info, warn };
+
+use crate::models::client::{ Client, Clients };
+use crate::models::publish_queue_item::{ PublishQueue, PublishQueueItem };
+
+/// Set when the broker has been asked to stop, checked by the accept loop in `main()`.
+static SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// How often the drain and close steps check if they are done.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait for the connection threads to run their disconnect handling.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Asks the broker to stop accepting connections and shut down.
+pub fn request() {
+    SHUTDOWN_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Checks if a shutdown has been requested.
+pub fn is_requested() -> bool {
+    SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Waits for the in-flight QoS 2 handshakes of the connected clients to complete.
+///
+/// # Arguments
+///
+/// * `clients` - The shared client sessions, only the handshakes of connected clients are waited for.
+/// * `publish_queue` - The shared publish queue, holding the QoS flows in flight.
+/// * `timeout` - How long to wait before giving up on the remaining handshakes.
+///
+/// # Returns
+///
+/// The number of QoS 2 handshakes that were still in flight when the timeout was reached.
+///
+/// # Description
+///
+/// Connections stay open while draining, so the clients can send the PUBREL and PUBCOMP packets
+/// that complete their handshakes. The flows of offline persistent sessions can not complete
+/// before the client reconnects, so they are not waited for. The clients and the publish queue
+/// are locked one after the other, one shard at a time.
+pub fn drain(clients: &Clients, publish_queue: &PublishQueue, timeout: Duration) -> usize {
+    let deadline: Instant = Instant::now() + timeout;
+
+    loop {
+        let mut connected: HashSet<String> = HashSet::new();
+        clients.for_each(|client: &Client| {
+            if client.is_connected {
+                connected.insert(client.id.clone());
+            }
+        });
+
+        let in_flight: usize = publish_queue.count(|item: &PublishQueueItem|
+            item.qos_level == 2 && connected.contains(&item.client_id)
+        );
+
+        if in_flight == 0 {
+            return 0;
+        }
+
+        if Instant::now() >= deadline {
+            warn!(in_flight, "Shutdown deadline reached with QoS 2 handshakes in flight");
+            return in_flight;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Closes the connection of every connected client.
+///
+/// # Arguments
+///
+/// * `clients` - The shared client sessions.
+///
+/// # Description
+///
+/// The will messages are suppressed, as every other client is disconnected as well.
+/// Each write thread is told to shut down its stream, which makes the read loop in
+/// `handle_connection` run the normal disconnect handling. This waits until every client
+/// is marked as disconnected, or a short timeout is reached.
+pub fn close_clients(clients: &Clients) {
+    let mut closed: usize = 0;
+
+    clients.for_each_mut(|client: &mut Client| {
+        if client.is_connected {
+            client.connect_flags.will_flag = false;
+
+            _ = client.tx.send(Err("Broker shutting down".to_string()));
+            closed += 1;
+        }
+    });
+
+    info!(clients = closed, "Closing client connections");
+
+    let deadline: Instant = Instant::now() + CLOSE_TIMEOUT;
+
+    while Instant::now() < deadline {
+        if clients.connected() == 0 {
+            return;
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    warn!("Some connections did not close in time");
+}
@@ -0,0 +1,102 @@
+use std::thread;
+
+use signal_hook::consts::{ SIGHUP, SIGINT, SIGTERM };
+use signal_hook::iterator::Signals;
+use tracing::{ error, info, warn };
+use tracing_subscriber::EnvFilter;
+
+use crate::models::config::BrokerConfig;
+use crate::services;
+use crate::services::archive::Archive;
+use crate::services::rules::RuleSet;
+use crate::services::schemas::SchemaSet;
+use crate::services::webhooks::Webhook;
+
+/// Starts a thread handling the process signals.
+///
+/// # Arguments
+///
+/// * `config_path` - The path of the config file, if the broker was started with `--config`.
+///
+/// # Returns
+///
+/// A Result that is an error message if the signal handlers could not be registered.
+///
+/// # Description
+///
+/// * `SIGTERM` and `SIGINT` request a graceful shutdown, see [`services::shutdown`].
+/// * `SIGHUP` reloads the config file, see [`reload_config`].
+pub fn listen(config_path: Option<String>) -> Result<(), String> {
+    let mut signals: Signals = Signals::new([SIGTERM, SIGINT, SIGHUP]).map_err(|err|
+        format!("Could not register signal handlers: {}", err)
+    )?;
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGHUP => {
+                    match &config_path {
+                        Some(path) => {
+                            match reload_config(path) {
+                                Ok(()) => info!(path = %path, "Config reloaded"),
+                                Err(err) => error!("{}", err),
+                            }
+                        }
+                        None => warn!("Received SIGHUP, but the broker was started without --config"),
+                    }
+                }
+                _ => {
+                    info!(signal, "Shutdown requested");
+                    services::shutdown::request();
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Reads the config file again, and applies the parts that can change while running.
+///
+/// # Arguments
+///
+/// * `path` - The path of the config file.
+///
+/// # Returns
+///
+/// A Result that is an error message if the file could not be read or parsed, or a section is invalid.
+/// Nothing is applied in that case.
+///
+/// # Description
+///
+/// The `[auth]`, `[[acl]]`, `[session]` and `[[schema]]` sections and the log level are replaced without touching the
+/// open connections, so they apply to the next packet of every client.
+/// The `[[webhook]]`, `[[rule]]` and `[[archive]]` sections are restarted, the replaced webhooks and sinks post the events
+/// they have queued and the replaced archives finish their files.
+/// The `[limits]` and `[outbound]` sections apply to the clients that connect after the reload.
+/// Changes to the listener, metrics and admin addresses need a restart.
+pub fn reload_config(path: &str) -> Result<(), String> {
+    let config: BrokerConfig = BrokerConfig::load(path)?;
+
+    // Every section that can be invalid is built before anything is installed, so an invalid schema,
+    // webhook URL, rule, archive or log level leaves the running config as it was.
+    // What was started for the sections before the invalid one is dropped and stops right away
+    let schemas: SchemaSet = SchemaSet::new(&config.schemas)?;
+    let webhooks: Vec<Webhook> = services::webhooks::start(&config.webhooks)?;
+    let rules: RuleSet = RuleSet::new(&config.rules)?;
+    let archives: Vec<Archive> = services::archive::start(&config.archives)?;
+    let log_filter: EnvFilter = services::logging::filter(&config.logging)?;
+
+    // Only fails when no logger was installed, which is checked before the other sections are installed
+    services::logging::install(log_filter)?;
+    services::schemas::install(schemas);
+    services::webhooks::install(webhooks);
+    services::rules::install(rules);
+    services::archive::install(archives);
+    services::auth::configure(&config.auth, &config.acl);
+    services::rate_limit::configure(&config.limits);
+    services::outbound::configure(&config.outbound);
+    services::session::configure(&config.session);
+
+    Ok(())
+}
@@ -0,0 +1,332 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc::{ channel, Receiver, Sender };
+use std::sync::{ Mutex, OnceLock };
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+
+use tracing::{ info, warn };
+
+use crate::models::client::{ Client, Clients };
+use crate::models::config::{ FsyncPolicy, StorageConfig };
+use crate::models::flags::ConnectFlags;
+use crate::models::topic::Topics;
+use crate::services;
+
+pub mod file_store;
+pub mod state;
+
+use self::file_store::FileStore;
+use self::state::{ Record, State, StoredMessage, StoredSession };
+
+/// The storage of the running broker, set once at startup when `[storage]` is configured.
+static STORAGE: OnceLock<Storage> = OnceLock::new();
+
+/// A backend that persists records, e.g. [`FileStore`].
+pub trait Store: Send + Sync {
+    /// Reads every stored record, in the order they should be applied.
+    fn load(&self) -> Result<Vec<Record>, String>;
+
+    /// Appends a single record.
+    fn append(&self, record: &Record) -> Result<(), String>;
+
+    /// Makes sure every appended record is on disk.
+    fn sync(&self) -> Result<(), String>;
+
+    /// Replaces everything stored with the given records, which describe the full state.
+    fn write_snapshot(&self, records: &[Record]) -> Result<(), String>;
+}
+
+/// A write to the [`Store`], done by the writer thread of the [`Storage`] in the order they were sent.
+enum Write {
+    Append(Record),
+    /// The records of the full state, and where to send the result.
+    Snapshot(Vec<Record>, Sender<Result<(), String>>),
+    Sync(Sender<Result<(), String>>),
+}
+
+/// The persistent state, kept in memory and written through to a [`Store`].
+///
+/// # Description
+///
+/// Changes are applied to the state under its lock, and then written to the store by a writer thread,
+/// so a connection thread recording a change never waits for the file I/O or fsync of another one.
+/// The writes are sent while the lock is held, so the store gets them in the order they were applied.
+pub struct Storage {
+    state: Mutex<State>,
+    writer: Option<Sender<Write>>,
+    writer_thread: Option<JoinHandle<()>>,
+}
+
+impl Storage {
+    /// Loads the state from a store, and compacts the store into a fresh snapshot.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The backend to load from and write to, moved to the writer thread.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the [`Storage`], or an error message if the store could not be read or compacted.
+    pub fn open(store: Box<dyn Store>) -> Result<Storage, String> {
+        let mut state: State = State::default();
+
+        for record in store.load()? {
+            state.apply(&record);
+        }
+
+        // Start from a clean snapshot, which also drops a record torn by a crash
+        store.write_snapshot(&state.to_records())?;
+
+        let (writer, rx): (Sender<Write>, Receiver<Write>) = channel();
+        let writer_thread: JoinHandle<()> = thread::spawn(move || write_to_store(store, rx));
+
+        Ok(Storage {
+            state: Mutex::new(state),
+            writer: Some(writer),
+            writer_thread: Some(writer_thread),
+        })
+    }
+
+    /// Applies a record, and has it appended to the store if it changed the state.
+    pub fn record(&self, record: Record) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.apply(&record) {
+            self.send(Write::Append(record));
+        }
+    }
+
+    /// Writes a snapshot of the current state, which truncates the log.
+    /// Returns once the snapshot is written, after every record applied before it.
+    pub fn snapshot(&self) -> Result<(), String> {
+        let (tx, rx) = channel::<Result<(), String>>();
+
+        {
+            let state = self.state.lock().unwrap();
+            self.send(Write::Snapshot(state.to_records(), tx));
+        }
+
+        rx.recv().unwrap_or_else(|_| Err("The storage writer has stopped".to_string()))
+    }
+
+    /// Syncs the appended records to disk.
+    /// Returns once every record applied before the call is synced.
+    pub fn sync(&self) -> Result<(), String> {
+        let (tx, rx) = channel::<Result<(), String>>();
+        self.send(Write::Sync(tx));
+
+        rx.recv().unwrap_or_else(|_| Err("The storage writer has stopped".to_string()))
+    }
+
+    /// Returns a copy of the current state.
+    pub fn state(&self) -> State {
+        self.state.lock().unwrap().clone()
+    }
+
+    fn send(&self, write: Write) {
+        if let Some(writer) = &self.writer {
+            _ = writer.send(write);
+        }
+    }
+}
+
+impl Drop for Storage {
+    /// Waits for the writer thread to write everything that was recorded.
+    fn drop(&mut self) {
+        // Closing the channel ends the writer thread once it has done the writes before
+        self.writer.take();
+
+        if let Some(writer_thread) = self.writer_thread.take() {
+            _ = writer_thread.join();
+        }
+    }
+}
+
+/// Runs the writes of a [`Storage`] on its store, until the storage is dropped.
+fn write_to_store(store: Box<dyn Store>, rx: Receiver<Write>) {
+    for write in rx {
+        match write {
+            Write::Append(record) => {
+                if let Err(err) = store.append(&record) {
+                    warn!("{}", err);
+                }
+            }
+            Write::Snapshot(records, done) => {
+                _ = done.send(store.write_snapshot(&records));
+            }
+            Write::Sync(done) => {
+                _ = done.send(store.sync());
+            }
+        }
+    }
+}
+
+/// Opens the configured storage, and starts the sync and snapshot threads.
+///
+/// # Arguments
+///
+/// * `config` - The storage section of the broker config.
+///
+/// # Returns
+///
+/// A Result containing the restored [`State`], or an error message if the storage could not be opened.
+/// Without a configured path, persistence is disabled and the state is empty.
+///
+/// # Description
+///
+/// With the `interval` fsync policy the log is synced every `fsync_interval_ms`,
+/// and a snapshot is written every `snapshot_interval_secs` to keep the log short.
+pub fn open(config: &StorageConfig) -> Result<State, String> {
+    let Some(path) = &config.path else {
+        return Ok(State::default());
+    };
+
+    let store: FileStore = FileStore::open(Path::new(path), config.fsync)?;
+    let storage: Storage = Storage::open(Box::new(store))?;
+    let state: State = storage.state();
+
+    if STORAGE.set(storage).is_err() {
+        return Err("Storage is already open".to_string());
+    }
+
+    info!(
+        path = %path,
+        sessions = state.sessions.len(),
+        retained = state.retained.len(),
+        "Storage opened"
+    );
+
+    if config.fsync == FsyncPolicy::Interval {
+        let interval: Duration = Duration::from_millis(config.fsync_interval_ms);
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(interval);
+
+                if let Err(err) = sync() {
+                    warn!("{}", err);
+                }
+            }
+        });
+    }
+
+    let snapshot_interval: Duration = Duration::from_secs(config.snapshot_interval_secs);
+
+    thread::spawn(move || {
+        loop {
+            thread::sleep(snapshot_interval);
+
+            if let Err(err) = snapshot() {
+                warn!("{}", err);
+            }
+        }
+    });
+
+    Ok(state)
+}
+
+/// Records a change to the persistent state. Does nothing when persistence is disabled.
+pub fn record(record: Record) {
+    if let Some(storage) = STORAGE.get() {
+        storage.record(record);
+    }
+}
+
+/// Syncs the log to disk. Does nothing when persistence is disabled.
+pub fn sync() -> Result<(), String> {
+    match STORAGE.get() {
+        Some(storage) => storage.sync(),
+        None => Ok(()),
+    }
+}
+
+/// Writes a snapshot of the persistent state. Does nothing when persistence is disabled.
+pub fn snapshot() -> Result<(), String> {
+    match STORAGE.get() {
+        Some(storage) => storage.snapshot(),
+        None => Ok(()),
+    }
+}
+
+/// Returns the unacknowledged messages of a persistent session.
+pub fn queued_messages(client_id: &str) -> Vec<StoredMessage> {
+    match STORAGE.get() {
+        Some(storage) => storage.state.lock().unwrap().queued.get(client_id).cloned().unwrap_or_default(),
+        None => Vec::new(),
+    }
+}
+
+/// Builds the record for a client that has just connected.
+///
+/// # Returns
+///
+/// A [`Record::Session`] for a persistent session (clean session 0), otherwise a
+/// [`Record::SessionRemoved`], as a clean session discards any stored session of the same client id.
+pub fn session_record(client: &Client) -> Record {
+    if client.connect_flags.clean_session_flag {
+        return Record::SessionRemoved { client_id: client.id.clone() };
+    }
+
+    Record::Session(StoredSession {
+        client_id: client.id.clone(),
+        username: client.username.clone(),
+        keep_alive: client.keep_alive,
+        will_flag: client.connect_flags.will_flag,
+        will_topic: client.will_topic.clone(),
+        will_message: client.will_message.clone(),
+        will_qos: client.connect_flags.will_qos_flag,
+        will_retain: client.connect_flags.will_retain_flag,
+    })
+}
+
+/// Puts the restored state into the broker sessions and topics.
+///
+/// # Arguments
+///
+/// * `state` - The state returned by [`open`].
+/// * `clients` - The client sessions of the broker.
+/// * `topics` - The topics of the broker.
+///
+/// # Description
+///
+/// Every stored session becomes a disconnected client, so a reconnect with clean session 0
+/// resumes it (session present 1). Subscriptions and retained messages are added to the topic list.
+pub fn restore(state: &State, clients: &Clients, topics: &Topics) {
+    for session in state.sessions.values() {
+        // Restored clients have no connection yet, the sender is replaced when they reconnect
+        let (tx, _rx) = services::outbound::channel();
+
+        let mut client: Client = Client::new(
+            session.client_id.clone(),
+            session.will_topic.clone(),
+            session.will_message.clone(),
+            session.keep_alive,
+            session.username.clone(),
+            String::new(),
+            SocketAddr::from(([0, 0, 0, 0], 0)),
+            tx,
+            ConnectFlags::new(
+                false,
+                session.will_flag,
+                session.will_qos,
+                session.will_retain,
+                false,
+                !session.username.is_empty()
+            )
+        );
+
+        client.handle_disconnect();
+        clients.insert(client);
+    }
+
+    for (client_id, subscriptions) in &state.subscriptions {
+        for (topic_name, qos) in subscriptions {
+            topics.subscribe(client_id, (topic_name.clone(), *qos));
+        }
+    }
+
+    for (topic_name, retained_msg) in &state.retained {
+        topics.set_retained(topic_name, retained_msg.clone());
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ BufRead, BufReader, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::Mutex;
+
+use tracing::warn;
+
+use super::state::Record;
+use super::Store;
+use crate::models::config::FsyncPolicy;
+
+const SNAPSHOT_FILE: &str = "snapshot.jsonl";
+const LOG_FILE: &str = "log.jsonl";
+
+/// Stores the records as JSON lines in a directory: a snapshot of the full state,
+/// and an append-only log of the changes since that snapshot.
+pub struct FileStore {
+    directory: PathBuf,
+    fsync: FsyncPolicy,
+    log: Mutex<File>,
+}
+
+impl FileStore {
+    /// Opens the store, creating the directory and log file if needed.
+    ///
+    /// # Arguments
+    ///
+    /// * `directory` - The directory holding the snapshot and log files.
+    /// * `fsync` - When appended records are synced to disk.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the [`FileStore`], or an error message if the directory or log
+    /// could not be opened.
+    pub fn open(directory: &Path, fsync: FsyncPolicy) -> Result<FileStore, String> {
+        fs::create_dir_all(directory).map_err(|err|
+            format!("Could not create storage directory {}: {}", directory.display(), err)
+        )?;
+
+        let log: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(directory.join(LOG_FILE))
+            .map_err(|err| format!("Could not open storage log: {}", err))?;
+
+        Ok(FileStore {
+            directory: directory.to_path_buf(),
+            fsync,
+            log: Mutex::new(log),
+        })
+    }
+}
+
+impl Store for FileStore {
+    fn load(&self) -> Result<Vec<Record>, String> {
+        let mut records: Vec<Record> = read_records(&self.directory.join(SNAPSHOT_FILE))?;
+        records.append(&mut read_records(&self.directory.join(LOG_FILE))?);
+
+        Ok(records)
+    }
+
+    fn append(&self, record: &Record) -> Result<(), String> {
+        let mut line: String = serde_json::to_string(record).map_err(|err| err.to_string())?;
+        line.push('\n');
+
+        let mut log = self.log.lock().unwrap();
+
+        // One write per record, so a crash can at most tear the last line
+        log.write_all(line.as_bytes()).map_err(|err| format!("Could not append to storage log: {}", err))?;
+
+        if self.fsync == FsyncPolicy::Always {
+            log.sync_data().map_err(|err| format!("Could not sync storage log: {}", err))?;
+        }
+
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), String> {
+        self.log
+            .lock()
+            .unwrap()
+            .sync_data()
+            .map_err(|err| format!("Could not sync storage log: {}", err))
+    }
+
+    fn write_snapshot(&self, records: &[Record]) -> Result<(), String> {
+        let temporary_path: PathBuf = self.directory.join(format!("{}.tmp", SNAPSHOT_FILE));
+
+        // Hold the log lock, so no record is appended between writing the snapshot and truncating the log
+        let log = self.log.lock().unwrap();
+
+        {
+            let mut file: File = File::create(&temporary_path).map_err(|err|
+                format!("Could not create snapshot: {}", err)
+            )?;
+
+            let mut content: String = String::new();
+            for record in records {
+                content.push_str(&serde_json::to_string(record).map_err(|err| err.to_string())?);
+                content.push('\n');
+            }
+
+            file.write_all(content.as_bytes()).map_err(|err| format!("Could not write snapshot: {}", err))?;
+            file.sync_all().map_err(|err| format!("Could not sync snapshot: {}", err))?;
+        }
+
+        // Replace the old snapshot in one step, then start a new log.
+        // If the broker stops in between, the old log is replayed on top of the new snapshot, which is harmless.
+        fs::rename(&temporary_path, self.directory.join(SNAPSHOT_FILE)).map_err(|err|
+            format!("Could not replace snapshot: {}", err)
+        )?;
+
+        log.set_len(0).map_err(|err| format!("Could not truncate storage log: {}", err))?;
+        log.sync_all().map_err(|err| format!("Could not sync storage log: {}", err))
+    }
+}
+
+/// Reads the records of a JSON lines file. A missing file has no records.
+///
+/// Reading stops at the first line that can not be parsed, which is a record torn by a crash.
+fn read_records(path: &Path) -> Result<Vec<Record>, String> {
+    let file: File = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Vec::new());
+        }
+        Err(err) => {
+            return Err(format!("Could not open {}: {}", path.display(), err));
+        }
+    };
+
+    let mut records: Vec<Record> = Vec::new();
+
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line: String = line.map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+
+        match serde_json::from_str::<Record>(&line) {
+            Ok(record) => records.push(record),
+            Err(err) => {
+                warn!(path = %path.display(), line = index + 1, error = %err, "Ignoring the rest of a damaged storage file");
+                break;
+            }
+        }
+    }
+
+    Ok(records)
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use serde::{ Deserialize, Serialize };
+
+/// A persistent session (clean session 0), as stored on disk. The password is never stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredSession {
+    pub client_id: String,
+    pub username: String,
+    pub keep_alive: u64,
+    pub will_flag: bool,
+    pub will_topic: String,
+    pub will_message: String,
+    pub will_qos: u8,
+    pub will_retain: bool,
+}
+
+/// A QoS 1 or QoS 2 PUBLISH to a persistent session that has not been acknowledged yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredMessage {
+    pub packet_id: usize,
+    pub qos: u8,
+    /// The complete PUBLISH packet, as it was sent to the client.
+    pub packet: Vec<u8>,
+}
+
+/// A single change to the persistent state, one line in the append-only log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Session(StoredSession),
+    SessionRemoved {
+        client_id: String,
+    },
+    Subscribed {
+        client_id: String,
+        topic: String,
+        qos: u8,
+    },
+    Unsubscribed {
+        client_id: String,
+        topic: String,
+    },
+    /// A retained message. An empty payload clears the retained message of the topic.
+    Retained {
+        topic: String,
+        payload: String,
+        qos: u8,
+    },
+    Queued {
+        client_id: String,
+        message: StoredMessage,
+    },
+    /// A queued message was acknowledged, with a PUBACK for QoS 1 or a PUBREC for QoS 2.
+    Acknowledged {
+        client_id: String,
+        packet_id: usize,
+    },
+}
+
+/// The persistent broker state, rebuilt by applying the records of the snapshot and the log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    pub sessions: BTreeMap<String, StoredSession>,
+    /// Topic filters and their QoS, by client id.
+    pub subscriptions: BTreeMap<String, Vec<(String, u8)>>,
+    /// Payload and QoS, by topic name.
+    pub retained: BTreeMap<String, (String, u8)>,
+    /// Unacknowledged messages, by client id, in the order they were sent.
+    pub queued: BTreeMap<String, Vec<StoredMessage>>,
+}
+
+impl State {
+    /// Applies a record to the state.
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - The record to apply.
+    ///
+    /// # Returns
+    ///
+    /// True if the state changed, and the record should be written to the log.
+    ///
+    /// # Description
+    ///
+    /// Subscriptions and queued messages are only kept for clients with a persistent session,
+    /// everything else about clean sessions is ignored. Applying a record twice gives the same state,
+    /// so replaying a log on top of a snapshot that already contains some of its records is safe.
+    pub fn apply(&mut self, record: &Record) -> bool {
+        match record {
+            Record::Session(session) => {
+                if self.sessions.get(&session.client_id) == Some(session) {
+                    return false;
+                }
+
+                self.sessions.insert(session.client_id.clone(), session.clone());
+                true
+            }
+            Record::SessionRemoved { client_id } => {
+                self.subscriptions.remove(client_id);
+                self.queued.remove(client_id);
+                self.sessions.remove(client_id).is_some()
+            }
+            Record::Subscribed { client_id, topic, qos } => {
+                if !self.sessions.contains_key(client_id) {
+                    return false;
+                }
+
+                let subscriptions: &mut Vec<(String, u8)> = self.subscriptions
+                    .entry(client_id.clone())
+                    .or_default();
+
+                // A new subscription on the same topic filter replaces the old one
+                subscriptions.retain(|(existing_topic, _)| existing_topic != topic);
+                subscriptions.push((topic.clone(), *qos));
+                true
+            }
+            Record::Unsubscribed { client_id, topic } => {
+                match self.subscriptions.get_mut(client_id) {
+                    Some(subscriptions) => {
+                        let length: usize = subscriptions.len();
+                        subscriptions.retain(|(existing_topic, _)| existing_topic != topic);
+                        subscriptions.len() != length
+                    }
+                    None => false,
+                }
+            }
+            Record::Retained { topic, payload, qos } => {
+                if payload.is_empty() {
+                    return self.retained.remove(topic).is_some();
+                }
+
+                self.retained.insert(topic.clone(), (payload.clone(), *qos)) !=
+                    Some((payload.clone(), *qos))
+            }
+            Record::Queued { client_id, message } => {
+                if !self.sessions.contains_key(client_id) {
+                    return false;
+                }
+
+                let queued: &mut Vec<StoredMessage> = self.queued.entry(client_id.clone()).or_default();
+
+                if queued.contains(message) {
+                    return false;
+                }
+
+                queued.retain(|m: &StoredMessage| m.packet_id != message.packet_id);
+                queued.push(message.clone());
+                true
+            }
+            Record::Acknowledged { client_id, packet_id } => {
+                match self.queued.get_mut(client_id) {
+                    Some(queued) => {
+                        let length: usize = queued.len();
+                        queued.retain(|m: &StoredMessage| m.packet_id != *packet_id);
+                        queued.len() != length
+                    }
+                    None => false,
+                }
+            }
+        }
+    }
+
+    /// Lists the records that rebuild this state, used to write a snapshot.
+    pub fn to_records(&self) -> Vec<Record> {
+        let mut records: Vec<Record> = Vec::new();
+
+        for session in self.sessions.values() {
+            records.push(Record::Session(session.clone()));
+        }
+
+        for (client_id, subscriptions) in &self.subscriptions {
+            for (topic, qos) in subscriptions {
+                records.push(Record::Subscribed {
+                    client_id: client_id.clone(),
+                    topic: topic.clone(),
+                    qos: *qos,
+                });
+            }
+        }
+
+        for (topic, (payload, qos)) in &self.retained {
+            records.push(Record::Retained {
+                topic: topic.clone(),
+                payload: payload.clone(),
+                qos: *qos,
+            });
+        }
+
+        for (client_id, queued) in &self.queued {
+            for message in queued {
+                records.push(Record::Queued {
+                    client_id: client_id.clone(),
+                    message: message.clone(),
+                });
+            }
+        }
+
+        records
+    }
+}
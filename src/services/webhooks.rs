@@ -0,0 +1,368 @@
+use std::net::{ SocketAddr, TcpStream, ToSocketAddrs };
+use std::sync::mpsc::{ sync_channel, Receiver, SyncSender, TrySendError };
+use std::sync::RwLock;
+use std::thread;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+use serde::Serialize;
+use tracing::{ debug, info_span, warn, Span };
+
+use crate::cli::options::TlsOptions;
+use crate::common_fn;
+use crate::common_fn::http::Url;
+use crate::models::config::{ WebhookConfig, WebhookEvent };
+use crate::services::metrics::METRICS;
+
+/// The longest delay between two attempts of a POST.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The active webhooks, replaced as a whole when the config is reloaded.
+static WEBHOOKS: RwLock<Vec<Webhook>> = RwLock::new(Vec::new());
+
+/// An event of the broker, sent to the webhooks as a JSON object with an `event` field naming it.
+///
+/// The fields borrow from the caller, an event is only serialized when a webhook wants it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    ClientConnected {
+        client_id: &'a str,
+        username: &'a str,
+        address: SocketAddr,
+        clean_session: bool,
+    },
+    ClientDisconnected {
+        client_id: &'a str,
+        address: SocketAddr,
+        /// Why the connection was closed, e.g. `Client disconnected` or `Keep alive timeout`.
+        reason: &'a str,
+    },
+    Subscribed {
+        client_id: &'a str,
+        topic_filter: &'a str,
+        /// The granted QoS.
+        qos: u8,
+    },
+    Unsubscribed {
+        client_id: &'a str,
+        topic_filter: &'a str,
+    },
+    MessagePublished {
+        client_id: &'a str,
+        topic: &'a str,
+        qos: u8,
+        retain: bool,
+        payload: &'a str,
+    },
+}
+
+/// An event as it is posted, with the time it happened in milliseconds since the Unix epoch.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    timestamp: u64,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+/// What happened to webhook events, counted in the `mqtt_webhook_events_total` metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// Posted, and answered with a 2xx status.
+    Delivered = 0,
+    /// Not queued, because the queue of the webhook was full.
+    Dropped = 1,
+    /// Dropped after the POST failed and every retry failed as well.
+    Failed = 2,
+}
+
+impl Delivery {
+    pub const ALL: [Delivery; 3] = [Delivery::Delivered, Delivery::Dropped, Delivery::Failed];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Delivery::Delivered => "delivered",
+            Delivery::Dropped => "dropped",
+            Delivery::Failed => "failed",
+        }
+    }
+}
+
+impl Event<'_> {
+    pub fn kind(&self) -> WebhookEvent {
+        match self {
+            Event::ClientConnected { .. } => WebhookEvent::ClientConnected,
+            Event::ClientDisconnected { .. } => WebhookEvent::ClientDisconnected,
+            Event::Subscribed { .. } => WebhookEvent::Subscribed,
+            Event::Unsubscribed { .. } => WebhookEvent::Unsubscribed,
+            Event::MessagePublished { .. } => WebhookEvent::MessagePublished,
+        }
+    }
+
+    /// Serializes the event as a JSON object, stamped with the current time.
+    fn to_json(&self) -> String {
+        let timestamp: u64 = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed: Duration| elapsed.as_millis() as u64);
+
+        serde_json::to_string(&(Envelope { timestamp, event: self })).unwrap_or_default()
+    }
+}
+
+/// A configured endpoint, with the thread that posts its events.
+///
+/// # Description
+///
+/// Events are queued without waiting, a full queue drops the event, so a slow or unreachable
+/// endpoint never holds up a connection. The delivery thread collects up to `batch_size` events,
+/// waiting at most `batch_interval_ms` after the first one, and posts them as a JSON array.
+/// A failed POST is sent again after `retry_backoff_ms`, doubled for every retry, until
+/// `max_retries` retries have failed and the batch is dropped.
+///
+/// The delivery thread stops once the webhook is dropped and its queued events are posted.
+#[derive(Debug)]
+pub struct Webhook {
+    events: Vec<WebhookEvent>,
+    topics: Vec<String>,
+    tx: SyncSender<String>,
+}
+
+impl Webhook {
+    /// Starts the delivery thread of a webhook.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The `[[webhook]]` section.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing the [`Webhook`], or an error message if the URL is invalid.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let webhook: Webhook = Webhook::start(&config)?;
+    ///
+    /// webhook.emit(&Event::Unsubscribed { client_id: "sensor/1", topic_filter: "sensors/#" });
+    /// ```
+    pub fn start(config: &WebhookConfig) -> Result<Webhook, String> {
+        let url: Url = common_fn::http
+            ::parse_url(&config.url)
+            .map_err(|err| format!("Invalid webhook URL {}: {}", config.url, err))?;
+
+        let (tx, rx): (SyncSender<String>, Receiver<String>) = sync_channel(config.queue_size.max(1));
+
+        let delivery_config: WebhookConfig = config.clone();
+        let span: Span = info_span!("webhook", url = %config.url);
+        thread::spawn(move || {
+            let _enter = span.enter();
+
+            deliver(&delivery_config, &url, rx);
+        });
+
+        Ok(Webhook {
+            events: config.events.clone(),
+            topics: config.topics.clone(),
+            tx,
+        })
+    }
+
+    /// True if the webhook is configured for the event, and for its topic if it is a published message.
+    pub fn wants(&self, event: &Event) -> bool {
+        if !self.events.contains(&event.kind()) {
+            return false;
+        }
+
+        match event {
+            Event::MessagePublished { topic, .. } => {
+                self.topics.iter().any(|filter: &String| common_fn::topic_filter::matches(filter, topic))
+            }
+            _ => true,
+        }
+    }
+
+    /// Queues an event for the delivery thread, if the webhook wants it.
+    pub fn emit(&self, event: &Event) {
+        if self.wants(event) {
+            self.send(event);
+        }
+    }
+
+    /// Queues an event for the delivery thread, whatever the webhook is configured for.
+    pub fn send(&self, event: &Event) {
+        self.queue(event.to_json());
+    }
+
+    /// Queues a serialized event, dropping it if the queue is full.
+    fn queue(&self, json: String) {
+        match self.tx.try_send(json) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                METRICS.webhook_events(Delivery::Dropped, 1);
+                debug!("Webhook queue full, dropping the event");
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+/// Starts the configured webhooks, replacing the running ones.
+///
+/// # Arguments
+///
+/// * `configs` - The `[[webhook]]` sections of the broker config.
+///
+/// # Returns
+///
+/// A Result that is an error message if a URL is invalid, the running webhooks are kept in that case.
+///
+/// # Description
+///
+/// The replaced webhooks post the events they have queued, and stop.
+pub fn configure(configs: &[WebhookConfig]) -> Result<(), String> {
+    install(start(configs)?);
+
+    Ok(())
+}
+
+/// Starts the configured webhooks without installing them, see [`install`].
+///
+/// # Returns
+///
+/// A Result containing the webhooks, or an error message if a URL is invalid.
+/// The webhooks started before an invalid one stop right away, their queues are empty.
+pub fn start(configs: &[WebhookConfig]) -> Result<Vec<Webhook>, String> {
+    configs.iter().map(Webhook::start).collect()
+}
+
+/// Installs started webhooks, replacing the running ones, which post the events they have queued and stop.
+pub fn install(webhooks: Vec<Webhook>) {
+    *WEBHOOKS.write().unwrap() = webhooks;
+}
+
+/// The number of running webhooks.
+pub fn count() -> usize {
+    WEBHOOKS.read().unwrap().len()
+}
+
+/// Queues an event for every webhook that wants it.
+///
+/// # Description
+///
+/// Never waits on an endpoint, so it is called from the connection threads directly.
+/// The event is serialized once, and only when a webhook wants it.
+///
+/// # Examples
+///
+/// ```
+/// services::webhooks::emit(Event::Subscribed { client_id: &client_id, topic_filter: "sensors/#", qos: 1 });
+/// ```
+pub fn emit(event: Event) {
+    let webhooks = WEBHOOKS.read().unwrap();
+    let mut json: Option<String> = None;
+
+    for webhook in webhooks.iter().filter(|webhook: &&Webhook| webhook.wants(&event)) {
+        let json: &String = json.get_or_insert_with(|| event.to_json());
+
+        webhook.queue(json.clone());
+    }
+}
+
+/// Posts the queued events of a webhook in batches, until its queue is dropped and empty.
+fn deliver(config: &WebhookConfig, url: &Url, rx: Receiver<String>) {
+    let batch_size: usize = config.batch_size.max(1);
+    let batch_interval: Duration = Duration::from_millis(config.batch_interval_ms);
+
+    while let Ok(first) = rx.recv() {
+        // Wait for more events, up to the batch interval after the first one
+        let deadline: Instant = Instant::now() + batch_interval;
+        let mut batch: Vec<String> = vec![first];
+
+        while batch.len() < batch_size {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) => batch.push(event),
+                Err(_) => break,
+            }
+        }
+
+        let body: String = format!("[{}]", batch.join(","));
+
+        match post_with_retries(config, url, body.as_bytes()) {
+            Ok(()) => {
+                debug!(events = batch.len(), "Webhook events delivered");
+                METRICS.webhook_events(Delivery::Delivered, batch.len() as u64);
+            }
+            Err(err) => {
+                warn!(events = batch.len(), error = %err, "Could not deliver webhook events, dropping them");
+                METRICS.webhook_events(Delivery::Failed, batch.len() as u64);
+            }
+        }
+    }
+
+    debug!("Webhook stopped");
+}
+
+/// Posts a batch, retrying with a doubling delay.
+///
+/// # Returns
+///
+/// Ok once the endpoint answered with a 2xx status, or the error of the last attempt.
+/// A 4xx status other than 408 and 429 is not retried, sending the same batch again would not help.
+fn post_with_retries(config: &WebhookConfig, url: &Url, body: &[u8]) -> Result<(), String> {
+    let mut backoff: Duration = Duration::from_millis(config.retry_backoff_ms);
+    let mut retries: u32 = 0;
+
+    loop {
+        let err: String = match post(config, url, body) {
+            Ok(200..=299) => return Ok(()),
+            Ok(status @ (400..=499)) if status != 408 && status != 429 => {
+                return Err(format!("The endpoint answered with status {}", status));
+            }
+            Ok(status) => format!("The endpoint answered with status {}", status),
+            Err(err) => err,
+        };
+
+        if retries >= config.max_retries {
+            return Err(err);
+        }
+
+        debug!(retry_in_ms = backoff.as_millis() as u64, error = %err, "Webhook POST failed");
+
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        retries += 1;
+    }
+}
+
+/// Posts a batch once, on a new connection.
+///
+/// # Returns
+///
+/// The status code of the response, or an error message if connecting or posting failed.
+fn post(config: &WebhookConfig, url: &Url, body: &[u8]) -> Result<u16, String> {
+    let timeout: Duration = Duration::from_millis(config.timeout_ms.max(1));
+
+    let address: SocketAddr = (url.host.as_str(), url.port)
+        .to_socket_addrs()
+        .map_err(|err| format!("Could not resolve {}: {}", url.host, err))?
+        .next()
+        .ok_or_else(|| format!("Could not resolve {}", url.host))?;
+
+    let mut socket: TcpStream = TcpStream::connect_timeout(&address, timeout).map_err(|err|
+        format!("Could not connect to {}: {}", address, err)
+    )?;
+    _ = socket.set_read_timeout(Some(timeout));
+    _ = socket.set_write_timeout(Some(timeout));
+
+    let headers: Vec<(&str, &str)> = config.authorization
+        .iter()
+        .map(|authorization: &String| ("Authorization", authorization.as_str()))
+        .collect();
+
+    if url.https {
+        let mut stream = crate::cli::session::tls_stream(socket, &url.host, &TlsOptions::default())?;
+
+        common_fn::http::post(&mut stream, url, &headers, "application/json", body)
+    } else {
+        common_fn::http::post(&mut socket, url, &headers, "application/json", body)
+    }
+}
@@ -1,9 +1,32 @@
 pub mod ping_test;
 pub mod connect_test;
-pub mod common_fn_test;
 mod disconnect_test;
 mod subscribe_test;
 mod unsubscribe_test;
 mod publish_subscriber_test;
 mod publish_publisher_test;
-mod topic_list_test;
\ No newline at end of file
+mod topic_list_test;
+mod metrics_test;
+mod logging_test;
+mod admin_api_test;
+mod auth_test;
+mod shutdown_test;
+mod storage_test;
+mod bridge_test;
+mod mqtt_client_test;
+mod packet_test;
+mod fuzz_regression_test;
+mod scripted_client;
+mod end_to_end_test;
+mod conformance_test;
+mod bench_test;
+mod cli_test;
+mod rate_limit_test;
+mod sharded_map_test;
+mod outbound_test;
+mod webhook_test;
+mod hooks_test;
+mod rules_test;
+mod schema_test;
+mod archive_test;
+mod signals_test;
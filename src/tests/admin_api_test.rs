@@ -0,0 +1,229 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{ Read, Write };
+    use std::net::{ SocketAddr, TcpStream };
+    use std::sync::Arc;
+
+    use crate::common_fn::http::{ self, Request };
+    use crate::models::client::{ Client, Clients };
+    use crate::models::config::BrokerConfig;
+    use crate::models::flags::ConnectFlags;
+    use crate::models::publish_queue_item::PublishQueue;
+    use crate::models::topic::{ Topic, Topics };
+    use crate::services::admin_api::{ self, AdminState };
+    use crate::services::outbound::{ self, OutboundReceiver };
+
+    fn state_with_client() -> (AdminState, OutboundReceiver) {
+        let connect_flags: ConnectFlags = ConnectFlags {
+            username_flag: true,
+            password_flag: true,
+            will_retain_flag: false,
+            will_qos_flag: 0,
+            will_flag: true,
+            clean_session_flag: false,
+        };
+
+        let (tx, rx) = outbound::channel();
+        let client: Client = Client::new(
+            "sensor/1".to_string(),
+            "will_topic".to_string(),
+            "will_message".to_string(),
+            60,
+            "username".to_string(),
+            "secret_password".to_string(),
+            SocketAddr::from(([127, 0, 0, 1], 50000)),
+            tx,
+            connect_flags
+        );
+
+        let state: AdminState = AdminState {
+            clients: Arc::new(Clients::new()),
+            topics: Arc::new(Topics::new()),
+            publish_queue: Arc::new(PublishQueue::new()),
+        };
+
+        state.clients.insert(client);
+        state.topics.insert(Topic {
+            topic_name: "home/kitchen".to_string(),
+            retained_msg: ("21.5".to_string(), 1),
+            client_ids: vec![("sensor/1".to_string(), 1)],
+        });
+
+        (state, rx)
+    }
+
+    fn request(method: &str, path: &str, query: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_list_and_show_clients() {
+        let (state, _rx) = state_with_client();
+
+        let (status, body): (u16, String) = admin_api::route(&request("GET", "/clients", ""), &state);
+
+        assert_eq!(status, 200);
+        assert!(body.contains("\"id\":\"sensor/1\""));
+        assert!(body.contains("\"topic\":\"home/kitchen\""));
+        assert!(body.contains("\"in_flight\":0"));
+        assert!(body.contains("\"outbound_messages\":0,\"outbound_bytes\":0"));
+        assert!(!body.contains("secret_password"));
+
+        let (status, _): (u16, String) = admin_api::route(&request("GET", "/clients/sensor%2F1", ""), &state);
+        assert_eq!(status, 200);
+
+        let (status, _): (u16, String) = admin_api::route(&request("GET", "/clients/unknown", ""), &state);
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn test_disconnect_suppresses_will() {
+        let (state, rx) = state_with_client();
+
+        let (status, _): (u16, String) = admin_api::route(
+            &request("POST", "/clients/sensor%2F1/disconnect", "suppress_will=true"),
+            &state
+        );
+
+        assert_eq!(status, 200);
+        assert!(rx.try_recv().unwrap().is_err());
+        assert!(!state.clients.get("sensor/1").unwrap().connect_flags.will_flag);
+    }
+
+    #[test]
+    fn test_delete_session_and_retained() {
+        let (state, _rx) = state_with_client();
+
+        let (status, _): (u16, String) = admin_api::route(&request("DELETE", "/sessions/sensor%2F1", ""), &state);
+
+        assert_eq!(status, 200);
+        assert!(state.clients.is_empty());
+        assert!(state.topics.get("home/kitchen").unwrap().client_ids.is_empty());
+
+        let (_, body): (u16, String) = admin_api::route(&request("GET", "/retained", ""), &state);
+        assert!(body.contains("\"payload\":\"21.5\""));
+
+        let (status, _): (u16, String) = admin_api::route(
+            &request("DELETE", "/retained/home%2Fkitchen", ""),
+            &state
+        );
+        assert_eq!(status, 200);
+        assert!(state.topics.get("home/kitchen").unwrap().retained_msg.0.is_empty());
+
+        // Nothing retained anymore
+        let (status, _): (u16, String) = admin_api::route(
+            &request("DELETE", "/retained/home%2Fkitchen", ""),
+            &state
+        );
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn test_token_is_required() {
+        let (state, _rx) = state_with_client();
+
+        // Without a token the API does not start at all
+        assert!(admin_api::start("127.0.0.1:0".parse().unwrap(), String::new(), state.clone()).is_err());
+
+        let bound_addr: SocketAddr = admin_api
+            ::start("127.0.0.1:0".parse().unwrap(), "s3cret".to_string(), state)
+            .unwrap();
+
+        let mut stream: TcpStream = TcpStream::connect(bound_addr).unwrap();
+        stream.write_all(b"GET /clients HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n").unwrap();
+
+        let mut response: String = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 401"));
+
+        let mut stream: TcpStream = TcpStream::connect(bound_addr).unwrap();
+        stream.write_all(b"GET /clients HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").unwrap();
+
+        let mut response: String = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("sensor/1"));
+    }
+
+    #[test]
+    fn test_oversized_requests_are_refused() {
+        let (state, _rx) = state_with_client();
+
+        let bound_addr: SocketAddr = admin_api
+            ::start("127.0.0.1:0".parse().unwrap(), "s3cret".to_string(), state)
+            .unwrap();
+
+        let response_to = |request: &[u8]| -> String {
+            let mut stream: TcpStream = TcpStream::connect(bound_addr).unwrap();
+            stream.write_all(request).unwrap();
+
+            // The broker closes the connection without reading the rest of a refused request,
+            // which can reset it after the response arrived, so a read error is not a failure
+            let mut response: Vec<u8> = Vec::new();
+            _ = stream.read_to_end(&mut response);
+            String::from_utf8_lossy(&response).into_owned()
+        };
+
+        // The body is refused from its Content-Length, before the token is checked or the body is sent
+        let response: String = response_to(b"POST /clients HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 413"));
+        assert!(response.contains("Request body too large"));
+
+        let response: String = response_to(
+            format!("POST /clients HTTP/1.1\r\nContent-Length: {}\r\n\r\n", http::MAX_BODY_BYTES + 1).as_bytes()
+        );
+        assert!(response.starts_with("HTTP/1.1 413"));
+
+        let response: String = response_to(format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(http::MAX_REQUEST_LINE_BYTES)).as_bytes());
+        assert!(response.starts_with("HTTP/1.1 414"));
+
+        let response: String = response_to(format!("GET /clients HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(http::MAX_HEADER_BYTES)).as_bytes());
+        assert!(response.starts_with("HTTP/1.1 431"));
+
+        let response: String = response_to(format!("GET /clients HTTP/1.1\r\n{}\r\n", "X-Padding: a\r\n".repeat(http::MAX_HEADERS + 1)).as_bytes());
+        assert!(response.starts_with("HTTP/1.1 431"));
+
+        // A body within the limit is still read
+        let response: String = response_to(
+            b"GET /clients HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: 2\r\n\r\n{}"
+        );
+        assert!(response.starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(http::percent_decode("home%2Fkitchen"), "home/kitchen");
+
+        // A `+` is only a space in a query, ids and topics in a path keep it
+        assert_eq!(http::percent_decode("device+1"), "device+1");
+        assert_eq!(http::percent_decode("sensors%2F%2B"), "sensors/+");
+
+        // Only a `%` followed by two hex digits is an escape
+        assert_eq!(http::percent_decode("%+1"), "%+1");
+        assert_eq!(http::percent_decode("%-f%zz%4"), "%-f%zz%4");
+
+        let mut request: Request = request("POST", "/clients/device+1/disconnect", "reason=admin+request&topic=a%2Bb");
+        assert_eq!(request.query_param("reason"), Some("admin request".to_string()));
+        assert_eq!(request.query_param("topic"), Some("a+b".to_string()));
+
+        request.query = String::new();
+        assert_eq!(request.query_param("reason"), None);
+    }
+
+    #[test]
+    fn test_config_admin_section() {
+        let config: BrokerConfig = BrokerConfig::parse(
+            "[admin]\naddress = \"127.0.0.1:8081\"\ntoken = \"s3cret\"\n"
+        ).unwrap();
+
+        assert_eq!(config.admin.address, Some("127.0.0.1:8081".parse().unwrap()));
+        assert_eq!(config.admin.token, "s3cret");
+        assert!(BrokerConfig::parse("").unwrap().admin.address.is_none());
+    }
+}
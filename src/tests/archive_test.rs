@@ -0,0 +1,269 @@
+#[cfg(test)]
+mod tests {
+    use std::fs::{ self, File };
+    use std::io::{ BufRead, BufReader, Read };
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Duration;
+
+    use flate2::read::GzDecoder;
+
+    use crate::models::config::{ ArchiveConfig, ArchiveFormat, BrokerConfig };
+    use crate::services::archive::{ Archive, ArchiveRecord };
+
+    /// An empty directory for the files of one test.
+    fn directory(name: &str) -> PathBuf {
+        let directory: PathBuf = std::env::temp_dir().join(format!("mqtt-archive-test-{}-{}", name, std::process::id()));
+        _ = fs::remove_dir_all(&directory);
+
+        directory
+    }
+
+    fn archive_config(directory: &PathBuf) -> ArchiveConfig {
+        let mut config: ArchiveConfig = BrokerConfig::parse(&format!("[[archive]]\ndirectory = {:?}", directory))
+            .unwrap()
+            .archives.remove(0);
+
+        config.flush_interval_ms = 20;
+        config
+    }
+
+    fn record(index: usize) -> ArchiveRecord {
+        ArchiveRecord {
+            timestamp: 1760780000000 + (index as u64),
+            client_id: "sensor/1".to_string(),
+            topic: format!("sensors/{}/temperature", index),
+            qos: (index % 3) as u8,
+            retain: index.is_multiple_of(2),
+            payload: format!("{{\"value\":{}}}", index),
+        }
+    }
+
+    /// The files of an archive, oldest first.
+    fn files(directory: &PathBuf) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = fs
+            ::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+
+        // By the milliseconds in the name, then the counter added to names with the same milliseconds
+        files.sort_by_key(|path: &PathBuf| -> Vec<u64> {
+            let name: &str = path.file_name().unwrap().to_str().unwrap();
+
+            name.split('.').next().unwrap().split('-').skip(1).map(|number: &str| number.parse::<u64>().unwrap()).collect()
+        });
+        files
+    }
+
+    fn read_lines<R: Read>(reader: R) -> Vec<ArchiveRecord> {
+        BufReader::new(reader)
+            .lines()
+            .map(|line| serde_json::from_str::<ArchiveRecord>(&line.unwrap()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_archive_config() {
+        let config: BrokerConfig = BrokerConfig::parse(
+            "[[archive]]\ndirectory = \"/var/lib/mqtt/archive\"\nprefix = \"audit\"\ntopics = [\"sensors/#\"]\nformat = \"jsonl_gz\"\nmax_file_bytes = 1048576\nrotate_interval_secs = 3600"
+        ).unwrap();
+
+        assert_eq!(config.archives[0].prefix, "audit");
+        assert_eq!(config.archives[0].topics, vec!["sensors/#".to_string()]);
+        assert_eq!(config.archives[0].format, ArchiveFormat::JsonLinesGzip);
+        assert_eq!(config.archives[0].max_file_bytes, Some(1048576));
+        assert_eq!(config.archives[0].rotate_interval_secs, Some(3600));
+
+        // Every topic, to JSON Lines files that are never rotated, by default
+        let config: BrokerConfig = BrokerConfig::parse("[[archive]]\ndirectory = \"archive\"").unwrap();
+        assert_eq!(config.archives[0].prefix, "messages");
+        assert_eq!(config.archives[0].topics, vec!["#".to_string()]);
+        assert_eq!(config.archives[0].format, ArchiveFormat::JsonLines);
+        assert_eq!(config.archives[0].max_file_bytes, None);
+        assert_eq!(config.archives[0].rotate_interval_secs, None);
+
+        assert!(BrokerConfig::parse("[[archive]]\ndirectory = \"archive\"\nformat = \"csv\"").is_err());
+    }
+
+    #[test]
+    fn test_archive_writes_json_lines() {
+        let directory: PathBuf = directory("jsonl");
+
+        let mut config: ArchiveConfig = archive_config(&directory);
+        config.topics = vec!["sensors/+/temperature".to_string()];
+
+        let archive: Archive = Archive::start(&config).unwrap();
+        assert!(archive.wants("sensors/1/temperature"));
+        assert!(!archive.wants("lights/kitchen"));
+
+        // No file is started before there is something to write
+        thread::sleep(Duration::from_millis(50));
+        assert!(files(&directory).is_empty());
+
+        for index in 0..3 {
+            archive.record(record(index));
+        }
+
+        // The records are flushed to the file while it is still open
+        thread::sleep(Duration::from_millis(200));
+
+        let files: Vec<PathBuf> = files(&directory);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].file_name().unwrap().to_str().unwrap().starts_with("messages-"));
+        assert!(files[0].to_str().unwrap().ends_with(".jsonl"));
+        assert_eq!(read_lines(File::open(&files[0]).unwrap()), (0..3).map(record).collect::<Vec<ArchiveRecord>>());
+
+        archive.stop();
+        _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_archive_rotates_by_size() {
+        let directory: PathBuf = directory("size");
+
+        let mut config: ArchiveConfig = archive_config(&directory);
+        config.max_file_bytes = Some(300);
+
+        let archive: Archive = Archive::start(&config).unwrap();
+
+        for index in 0..10 {
+            archive.record(record(index));
+        }
+
+        archive.stop();
+
+        // A file is rotated once it reaches the limit, so every file but the last one holds 300 bytes or more
+        let files: Vec<PathBuf> = files(&directory);
+        assert!(files.len() > 1);
+
+        for file in files[..files.len() - 1].iter() {
+            assert!(fs::metadata(file).unwrap().len() >= 300);
+        }
+
+        let records: Vec<ArchiveRecord> = files
+            .iter()
+            .flat_map(|file: &PathBuf| read_lines(File::open(file).unwrap()))
+            .collect();
+        assert_eq!(records, (0..10).map(record).collect::<Vec<ArchiveRecord>>());
+
+        _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_archive_rotates_by_time() {
+        let directory: PathBuf = directory("time");
+
+        let mut config: ArchiveConfig = archive_config(&directory);
+        config.rotate_interval_secs = Some(1);
+
+        let archive: Archive = Archive::start(&config).unwrap();
+        archive.record(record(0));
+
+        // The file is finished when it is old enough, without waiting for another record
+        thread::sleep(Duration::from_millis(1200));
+        archive.record(record(1));
+        archive.stop();
+
+        let files: Vec<PathBuf> = files(&directory);
+        assert_eq!(files.len(), 2);
+        assert_eq!(read_lines(File::open(&files[0]).unwrap()), vec![record(0)]);
+        assert_eq!(read_lines(File::open(&files[1]).unwrap()), vec![record(1)]);
+
+        _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_archive_writes_gzip() {
+        let directory: PathBuf = directory("gzip");
+
+        let mut config: ArchiveConfig = archive_config(&directory);
+        config.format = ArchiveFormat::JsonLinesGzip;
+
+        let archive: Archive = Archive::start(&config).unwrap();
+
+        for index in 0..100 {
+            archive.record(record(index));
+        }
+
+        archive.stop();
+
+        let files: Vec<PathBuf> = files(&directory);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_str().unwrap().ends_with(".jsonl.gz"));
+        assert_eq!(read_lines(GzDecoder::new(File::open(&files[0]).unwrap())), (0..100).map(record).collect::<Vec<ArchiveRecord>>());
+
+        _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_invalid_archive() {
+        let directory: PathBuf = directory("invalid");
+
+        let mut config: ArchiveConfig = archive_config(&directory);
+        config.topics = vec!["sensors/#/temperature".to_string()];
+        assert_eq!(Archive::start(&config).err().unwrap(), "Invalid archive topic filter: sensors/#/temperature");
+
+        // A file where the directory should be
+        fs::write(&directory, b"").unwrap();
+
+        let config: ArchiveConfig = archive_config(&directory);
+        assert!(Archive::start(&config).err().unwrap().starts_with("Could not create archive directory"));
+
+        _ = fs::remove_file(&directory);
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    #[test]
+    fn test_parquet_needs_feature() {
+        let directory: PathBuf = directory("no-parquet");
+
+        let mut config: ArchiveConfig = archive_config(&directory);
+        config.format = ArchiveFormat::Parquet;
+
+        assert_eq!(Archive::start(&config).err().unwrap(), "Parquet archives need the broker to be built with the parquet feature");
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_archive_writes_parquet() {
+        use arrow_array::{ Array, RecordBatch, StringArray, UInt8Array };
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let directory: PathBuf = directory("parquet");
+
+        let mut config: ArchiveConfig = archive_config(&directory);
+        config.format = ArchiveFormat::Parquet;
+
+        let archive: Archive = Archive::start(&config).unwrap();
+
+        for index in 0..2000 {
+            archive.record(record(index));
+        }
+
+        archive.stop();
+
+        let files: Vec<PathBuf> = files(&directory);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].to_str().unwrap().ends_with(".parquet"));
+
+        let batches: Vec<RecordBatch> = ParquetRecordBatchReaderBuilder::try_new(File::open(&files[0]).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap())
+            .collect();
+
+        assert_eq!(batches.iter().map(|batch: &RecordBatch| batch.num_rows()).sum::<usize>(), 2000);
+
+        let first: &RecordBatch = &batches[0];
+        let topics: &StringArray = first.column_by_name("topic").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+        let qos: &UInt8Array = first.column_by_name("qos").unwrap().as_any().downcast_ref::<UInt8Array>().unwrap();
+
+        assert_eq!(topics.value(7), "sensors/7/temperature");
+        assert_eq!(qos.value(7), 1);
+        assert_eq!(first.schema().fields().len(), 6);
+
+        _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,110 @@
+#[cfg(test)]
+mod tests {
+    use crate::common_fn::topic_filter;
+    use crate::models::config::{ AclAccess, BrokerConfig };
+    use crate::services::auth::AccessControl;
+
+    const CONFIG: &str = r#"
+[auth]
+allow_anonymous = false
+
+[[auth.users]]
+username = "sensor"
+password = "s3cret"
+
+[[acl]]
+username = "sensor"
+topic = "sensors/+/temperature"
+access = "write"
+
+[[acl]]
+topic = "commands/#"
+access = "readwrite"
+"#;
+
+    #[test]
+    fn test_topic_filter_matches() {
+        assert!(topic_filter::matches("sensors/kitchen", "sensors/kitchen"));
+        assert!(topic_filter::matches("sensors/+/temperature", "sensors/kitchen/temperature"));
+        assert!(topic_filter::matches("sensors/#", "sensors"));
+        assert!(topic_filter::matches("sensors/#", "sensors/kitchen/temperature"));
+        assert!(topic_filter::matches("#", "sensors/kitchen"));
+
+        assert!(!topic_filter::matches("sensors/+", "sensors/kitchen/temperature"));
+        assert!(!topic_filter::matches("sensors/kitchen", "sensors"));
+        assert!(!topic_filter::matches("#", "$SYS/uptime"));
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let config: BrokerConfig = BrokerConfig::parse(CONFIG).unwrap();
+        let access_control: AccessControl = AccessControl::new(&config.auth, &config.acl);
+
+        assert_eq!(access_control.authenticate(Some("sensor"), "s3cret"), 0);
+        assert_eq!(access_control.authenticate(Some("sensor"), "wrong"), 4);
+        assert_eq!(access_control.authenticate(None, ""), 5);
+
+        // The default config accepts everyone
+        let config: BrokerConfig = BrokerConfig::parse("").unwrap();
+        let access_control: AccessControl = AccessControl::new(&config.auth, &config.acl);
+
+        assert_eq!(access_control.authenticate(None, ""), 0);
+        assert_eq!(access_control.authenticate(Some("anyone"), "anything"), 0);
+    }
+
+    #[test]
+    fn test_acl() {
+        let config: BrokerConfig = BrokerConfig::parse(CONFIG).unwrap();
+        let access_control: AccessControl = AccessControl::new(&config.auth, &config.acl);
+
+        assert!(access_control.is_allowed("sensor", "sensors/kitchen/temperature", AclAccess::Write));
+        assert!(!access_control.is_allowed("sensor", "sensors/kitchen/temperature", AclAccess::Read));
+        assert!(!access_control.is_allowed("other", "sensors/kitchen/temperature", AclAccess::Write));
+
+        // Rules without a username apply to everyone
+        assert!(access_control.is_allowed("other", "commands/kitchen", AclAccess::Read));
+        assert!(access_control.is_allowed("", "commands/#", AclAccess::Write));
+
+        // A subscription can not widen a rule
+        assert!(!access_control.is_allowed_filter("other", "#", AclAccess::Read));
+    }
+
+    #[test]
+    fn test_topic_filter_covers() {
+        assert!(topic_filter::covers("sensors/+", "sensors/kitchen"));
+        assert!(topic_filter::covers("sensors/+", "sensors/+"));
+        assert!(topic_filter::covers("sensors/#", "sensors/+/temperature"));
+        assert!(topic_filter::covers("sensors/#", "sensors"));
+        assert!(topic_filter::covers("sensors/#", "sensors/#"));
+        assert!(topic_filter::covers("#", "sensors/#"));
+
+        assert!(!topic_filter::covers("sensors/+", "sensors/#"));
+        assert!(!topic_filter::covers("sensors/+", "sensors"));
+        assert!(!topic_filter::covers("sensors/+/temperature", "sensors/+/#"));
+        assert!(!topic_filter::covers("sensors/kitchen", "sensors/+"));
+        assert!(!topic_filter::covers("sensors/+", "sensors/kitchen/temperature"));
+        assert!(!topic_filter::covers("#", "$SYS/#"));
+    }
+
+    #[test]
+    fn test_acl_subscribe_filters() {
+        let config: BrokerConfig = BrokerConfig::parse(
+            "[[acl]]\ntopic = \"sensors/+\"\naccess = \"read\"\n\n[[acl]]\ntopic = \"alarms/#\"\naccess = \"read\"\n\n[[acl]]\ntopic = \"lights/kitchen\"\naccess = \"read\""
+        ).unwrap();
+        let access_control: AccessControl = AccessControl::new(&config.auth, &config.acl);
+
+        assert!(access_control.is_allowed_filter("", "sensors/kitchen", AclAccess::Read));
+        assert!(access_control.is_allowed_filter("", "sensors/+", AclAccess::Read));
+
+        // A `+` rule does not allow the deeper levels of a `#` filter
+        assert!(!access_control.is_allowed_filter("", "sensors/#", AclAccess::Read));
+
+        // A `#` rule allows a narrower `+` filter
+        assert!(access_control.is_allowed_filter("", "alarms/+", AclAccess::Read));
+        assert!(access_control.is_allowed_filter("", "alarms/+/smoke", AclAccess::Read));
+
+        // A `+` in the filter is a wildcard, not the text of a level, so a rule for one level does not allow it
+        assert!(!access_control.is_allowed_filter("", "lights/+", AclAccess::Read));
+        assert!(access_control.is_allowed_filter("", "lights/kitchen", AclAccess::Read));
+    }
+}
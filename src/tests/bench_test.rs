@@ -0,0 +1,116 @@
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::bench::{ self, Report };
+    use crate::bench::options::{ BenchOptions, Pattern };
+    use crate::bench::stats::{ percentile, ProcessStats };
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(|arg: &str| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        assert_eq!(BenchOptions::parse(&[]).unwrap(), BenchOptions::default());
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options: BenchOptions = BenchOptions::parse(
+            &args("--publishers 3 --subscribers 7 --rate 12.5 --payload 128 --qos 0:50,2:50 --pattern fan-out --duration 0.5")
+        ).unwrap();
+
+        assert_eq!(options.publishers, 3);
+        assert_eq!(options.subscribers, 7);
+        assert_eq!(options.rate, 12.5);
+        assert_eq!(options.payload_size, 128);
+        assert_eq!(options.qos_mix, vec![(0, 50), (2, 50)]);
+        assert_eq!(options.pattern, Pattern::FanOut);
+        assert_eq!(options.duration, Duration::from_millis(500));
+        assert_eq!(options.max_qos(), 2);
+    }
+
+    #[test]
+    fn test_parse_pairs_match_subscribers_to_publishers() {
+        let options: BenchOptions = BenchOptions::parse(&args("--publishers 4 --subscribers 9")).unwrap();
+
+        assert_eq!(options.subscribers, 4);
+    }
+
+    #[test]
+    fn test_parse_invalid_options() {
+        let invalid: [&str; 9] = [
+            "--publishers",
+            "--publishers 0",
+            "--rate 0",
+            "--payload 8",
+            "--qos 3:100",
+            "--qos 0:0",
+            "--qos 0-100",
+            "--pattern ring",
+            "--pid 1",
+        ];
+
+        for invalid in invalid {
+            assert!(BenchOptions::parse(&args(invalid)).is_err(), "{} was accepted", invalid);
+        }
+
+        assert_eq!(BenchOptions::parse(&args("--verbose 1")), Err("Unknown option --verbose".to_string()));
+    }
+
+    #[test]
+    fn test_pattern_topics() {
+        assert_eq!(Pattern::Pairs.topic(2), "bench/2");
+        assert_eq!(Pattern::Pairs.topic_filter(2), "bench/2");
+        assert_eq!(Pattern::FanOut.topic(2), "bench/shared");
+        assert_eq!(Pattern::FanOut.topic_filter(5), "bench/shared");
+        assert_eq!(Pattern::FanIn.topic(2), "bench/2");
+        assert_eq!(Pattern::FanIn.topic_filter(5), "bench/+");
+
+        assert_eq!(Pattern::Pairs.deliveries_per_message(10), 1);
+        assert_eq!(Pattern::FanOut.deliveries_per_message(10), 10);
+        assert_eq!(Pattern::FanIn.deliveries_per_message(10), 10);
+    }
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<u64> = (1..=1000).collect();
+
+        assert_eq!(percentile(&samples, 50.0), Some(500));
+        assert_eq!(percentile(&samples, 99.0), Some(990));
+        assert_eq!(percentile(&samples, 99.9), Some(999));
+        assert_eq!(percentile(&samples, 100.0), Some(1000));
+        assert_eq!(percentile(&samples, 0.0), Some(1));
+
+        assert_eq!(percentile(&[7], 99.9), Some(7));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn test_process_stats_parse() {
+        let status: &str = "Name:\tmqtt-broker\nVmPeak:\t  20000 kB\nVmRSS:\t    5120 kB\nThreads:\t12\n";
+
+        assert_eq!(ProcessStats::parse(status), Some(ProcessStats { rss_kib: 5120, threads: 12 }));
+        assert_eq!(ProcessStats::parse("Name:\tmqtt-broker\n"), None);
+
+        let peak: ProcessStats = ProcessStats { rss_kib: 10, threads: 3 }.max(ProcessStats { rss_kib: 5, threads: 8 });
+        assert_eq!(peak, ProcessStats { rss_kib: 10, threads: 8 });
+    }
+
+    #[test]
+    fn test_run_in_process() {
+        let options: BenchOptions = BenchOptions::parse(
+            &args("--publishers 2 --subscribers 3 --rate 50 --qos 0:1,1:1 --pattern fan-out --duration 0.3")
+        ).unwrap();
+
+        let report: Report = bench::run(&options).unwrap();
+
+        assert!(report.published > 0);
+        assert_eq!(report.publish_errors, 0);
+        assert_eq!(report.expected_deliveries, report.published * 3);
+        assert_eq!(report.delivered, report.expected_deliveries);
+        assert!(report.latency(50.0).unwrap() <= report.latency(99.9).unwrap());
+        assert!(report.in_process);
+    }
+}
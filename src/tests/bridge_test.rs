@@ -0,0 +1,250 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::mpsc::Receiver;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{ Duration, Instant };
+
+    use crate::broker::Broker;
+    use crate::common_fn::bit_operations::encode_remaining_length;
+    use crate::models::config::{ BridgeConfig, BridgeDirection, BridgeTopic, BrokerConfig };
+    use crate::models::topic::Topic;
+    use crate::mqtt_client::{ ConnectOptions, Message, MqttClient };
+    use crate::services::bridge::{ self, EchoFilter, Side };
+
+    const CONFIG: &str =
+        r#"
+[[bridge]]
+name = "site1"
+address = "127.0.0.1:1883"
+
+[[bridge.topics]]
+pattern = "sensors/#"
+direction = "out"
+qos = 1
+remote_prefix = "site1/"
+
+[[bridge.topics]]
+pattern = "cmd/#"
+direction = "in"
+local_prefix = "local/"
+remote_prefix = "site1/"
+
+[[bridge.topics]]
+pattern = "chat/+"
+direction = "both"
+"#;
+
+    fn message(topic: &str, payload: &str) -> Message {
+        Message { topic: topic.to_string(), payload: payload.as_bytes().to_vec(), qos: 0, retain: false }
+    }
+
+    fn start_broker() -> Broker {
+        let mut broker: Broker = Broker::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        broker.start().unwrap();
+        broker
+    }
+
+    /// Waits until a client is subscribed to a topic filter on a broker.
+    fn wait_for_subscription(broker: &Broker, client_id: &str, topic_filter: &str) {
+        let deadline: Instant = Instant::now() + Duration::from_secs(10);
+
+        while Instant::now() < deadline {
+            let subscribed: bool = broker.topics
+                .get(topic_filter)
+                .is_some_and(|t: Topic| t.client_ids.iter().any(|(id, _)| id == client_id));
+
+            if subscribed {
+                return;
+            }
+
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        panic!("{} did not subscribe to {}", client_id, topic_filter);
+    }
+
+    fn receive(messages: &Receiver<Message>) -> Option<Message> {
+        messages.recv_timeout(Duration::from_secs(2)).ok()
+    }
+
+    #[test]
+    fn test_bridge_config() {
+        let config: BrokerConfig = BrokerConfig::parse(CONFIG).unwrap();
+
+        assert_eq!(config.bridges.len(), 1);
+
+        let bridge: &BridgeConfig = &config.bridges[0];
+        assert_eq!(bridge.address, "127.0.0.1:1883".parse::<SocketAddr>().unwrap());
+        assert_eq!(bridge.keep_alive, 60);
+        assert_eq!(bridge.reconnect_min_secs, 1);
+        assert_eq!(bridge.reconnect_max_secs, 60);
+        assert_eq!(bridge.topics.len(), 3);
+        assert_eq!(bridge.topics[0].direction, BridgeDirection::Out);
+        assert_eq!(bridge.topics[0].qos, 1);
+        assert_eq!(bridge.topics[2].local_prefix, "");
+
+        assert!(BrokerConfig::parse("").unwrap().bridges.is_empty());
+        assert!(BrokerConfig::parse("[[bridge]]\nname = \"x\"\naddress = \"nowhere\"").is_err());
+    }
+
+    #[test]
+    fn test_map_message() {
+        let topics: Vec<BridgeTopic> = BrokerConfig::parse(CONFIG).unwrap().bridges[0].topics.clone();
+
+        // Out: the remote prefix is added, with the QoS of the bridged topic
+        let forwarded: Message = bridge::map_message(&topics, &message("sensors/kitchen", "21"), Side::Local).unwrap();
+        assert_eq!(forwarded.topic, "site1/sensors/kitchen");
+        assert_eq!(forwarded.payload, b"21");
+        assert_eq!(forwarded.qos, 1);
+
+        // In: the prefixes are swapped
+        let forwarded: Message = bridge::map_message(&topics, &message("site1/cmd/reboot", ""), Side::Remote).unwrap();
+        assert_eq!(forwarded.topic, "local/cmd/reboot");
+
+        // Both ways without prefixes
+        assert_eq!(bridge::map_message(&topics, &message("chat/a", ""), Side::Local).unwrap().topic, "chat/a");
+        assert_eq!(bridge::map_message(&topics, &message("chat/a", ""), Side::Remote).unwrap().topic, "chat/a");
+
+        // Wrong direction, or not bridged at all
+        assert!(bridge::map_message(&topics, &message("site1/sensors/kitchen", ""), Side::Remote).is_none());
+        assert!(bridge::map_message(&topics, &message("local/cmd/reboot", ""), Side::Local).is_none());
+        assert!(bridge::map_message(&topics, &message("chat/a/b", ""), Side::Local).is_none());
+    }
+
+    #[test]
+    fn test_echo_filter() {
+        let mut echo_filter: EchoFilter = EchoFilter::default();
+
+        echo_filter.remember(Side::Remote, &message("chat/a", "hello"));
+
+        // Only the side it was forwarded to, with the same topic and payload, is an echo
+        assert!(!echo_filter.take(Side::Local, &message("chat/a", "hello")));
+        assert!(!echo_filter.take(Side::Remote, &message("chat/a", "bye")));
+        assert!(echo_filter.take(Side::Remote, &message("chat/a", "hello")));
+
+        // An echo is dropped only once
+        assert!(!echo_filter.take(Side::Remote, &message("chat/a", "hello")));
+    }
+
+    #[test]
+    fn test_encode_remaining_length() {
+        // The smallest and largest length of each number of bytes
+        let cases: [(usize, &[u8]); 8] = [
+            (0, &[0x00]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xff, 0x7f]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (2_097_151, &[0xff, 0xff, 0x7f]),
+            (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+            (268_435_455, &[0xff, 0xff, 0xff, 0x7f]),
+        ];
+
+        for (length, encoded) in cases {
+            assert_eq!(encode_remaining_length(length).unwrap(), encoded);
+        }
+
+        assert_eq!(encode_remaining_length(321).unwrap(), vec![0xc1, 0x02]);
+        assert!(encode_remaining_length(268_435_456).is_err());
+    }
+
+    #[test]
+    fn test_bridge_forwards_between_brokers() {
+        let site: Broker = start_broker();
+        let central: Broker = start_broker();
+
+        let mut config: BridgeConfig = BrokerConfig::parse(CONFIG).unwrap().bridges.remove(0);
+        config.address = central.local_addr;
+        config.name = "forwarding".to_string();
+
+        bridge::start(&[config], site.local_addr);
+
+        wait_for_subscription(&site, "bridge-forwarding", "sensors/#");
+        wait_for_subscription(&site, "bridge-forwarding", "chat/+");
+        wait_for_subscription(&central, "bridge-forwarding", "site1/cmd/#");
+        wait_for_subscription(&central, "bridge-forwarding", "chat/+");
+
+        let (site_client, site_messages) = MqttClient::connect(
+            site.local_addr,
+            &ConnectOptions::new("site-client")
+        ).unwrap();
+        let (central_client, central_messages) = MqttClient::connect(
+            central.local_addr,
+            &ConnectOptions::new("central-client")
+        ).unwrap();
+
+        site_client.subscribe(&[("local/cmd/#".to_string(), 0), ("chat/+".to_string(), 0)]).unwrap();
+        central_client.subscribe(&[("site1/sensors/#".to_string(), 0), ("chat/+".to_string(), 0)]).unwrap();
+
+        // Out, with the remote prefix added
+        site_client.publish(&message("sensors/kitchen", "21.5")).unwrap();
+        let received: Message = receive(&central_messages).unwrap();
+        assert_eq!(received.topic, "site1/sensors/kitchen");
+        assert_eq!(received.payload, b"21.5");
+
+        // In, with the prefixes swapped
+        central_client.publish(&message("site1/cmd/reboot", "now")).unwrap();
+        let received: Message = receive(&site_messages).unwrap();
+        assert_eq!(received.topic, "local/cmd/reboot");
+        assert_eq!(received.payload, b"now");
+
+        // Both ways, each message arrives once on each broker and is not sent back
+        site_client.publish(&message("chat/general", "from site")).unwrap();
+        assert_eq!(receive(&site_messages).unwrap().payload, b"from site");
+        assert_eq!(receive(&central_messages).unwrap().payload, b"from site");
+
+        central_client.publish(&message("chat/general", "from central")).unwrap();
+        assert_eq!(receive(&central_messages).unwrap().payload, b"from central");
+        assert_eq!(receive(&site_messages).unwrap().payload, b"from central");
+
+        assert!(receive(&site_messages).is_none());
+        assert!(receive(&central_messages).is_none());
+
+        site_client.disconnect();
+        central_client.disconnect();
+    }
+
+    #[test]
+    fn test_bridge_reconnects() {
+        let site: Broker = start_broker();
+
+        // Reserve a port for the remote broker, which only starts after the bridge
+        let remote_addr: SocketAddr = {
+            let remote: Broker = Broker::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            remote.local_addr
+        };
+
+        let mut config: BridgeConfig = BrokerConfig::parse(CONFIG).unwrap().bridges.remove(0);
+        config.address = remote_addr;
+        config.name = "reconnecting".to_string();
+
+        bridge::start(&[config], site.local_addr);
+
+        // The first attempts fail, the bridge keeps retrying with a delay
+        thread::sleep(Duration::from_millis(1500));
+
+        let mut remote: Broker = Broker::bind(remote_addr).unwrap();
+        remote.start().unwrap();
+
+        wait_for_subscription(&remote, "bridge-reconnecting", "site1/cmd/#");
+
+        let (site_client, site_messages) = MqttClient::connect(
+            site.local_addr,
+            &ConnectOptions::new("reconnect-site-client")
+        ).unwrap();
+        site_client.subscribe(&[("local/cmd/#".to_string(), 0)]).unwrap();
+
+        let remote_client: Arc<MqttClient> = MqttClient::connect(
+            remote.local_addr,
+            &ConnectOptions::new("reconnect-remote-client")
+        ).unwrap().0;
+        remote_client.publish(&message("site1/cmd/ping", "1")).unwrap();
+
+        assert_eq!(receive(&site_messages).unwrap().topic, "local/cmd/ping");
+
+        site_client.disconnect();
+        remote_client.disconnect();
+    }
+}
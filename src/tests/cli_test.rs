@@ -0,0 +1,243 @@
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::thread::{ self, JoinHandle };
+    use std::time::Duration;
+
+    use crate::broker::Broker;
+    use crate::cli::{ self, format::OutputFormat, options::{ Input, PubOptions, SubOptions, TlsOptions } };
+    use crate::cli::session::Session;
+    use crate::common_fn::topic_filter;
+    use crate::mqtt_client::Message;
+    use crate::packet::Publish;
+    use crate::tests::scripted_client::{ start_broker, ScriptedClient };
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg: &&str| arg.to_string()).collect()
+    }
+
+    /// Connection arguments for the broker of a test.
+    fn broker_args(broker: &Broker, client_id: &str) -> Vec<String> {
+        args(&["--host", "127.0.0.1", "--port", &broker.local_addr.port().to_string(), "--id", client_id])
+    }
+
+    fn message(topic: &str, payload: &[u8], qos: u8, retain: bool) -> Message {
+        Message { topic: topic.to_string(), payload: payload.to_vec(), qos, retain }
+    }
+
+    #[test]
+    fn test_parse_pub_options() {
+        let options: PubOptions = PubOptions::parse(
+            &args(&[
+                "--topic", "lights/kitchen", "--message", "on", "--qos", "2", "--retain",
+                "--host", "broker.local", "--username", "user", "--password", "secret", "--keep-alive", "10",
+                "--will-topic", "lights/status", "--will-payload", "offline", "--will-qos", "1", "--will-retain",
+            ])
+        ).unwrap();
+
+        assert_eq!(options.topic, "lights/kitchen");
+        assert_eq!(options.input, Input::Message(b"on".to_vec()));
+        assert_eq!(options.qos, 2);
+        assert!(options.retain);
+
+        assert_eq!(options.connection.host, "broker.local");
+        assert_eq!(options.connection.port, 1883);
+        assert_eq!(options.connection.tls, None);
+        assert_eq!(options.connection.connect.username.as_deref(), Some("user"));
+        assert_eq!(options.connection.connect.password.as_deref(), Some("secret"));
+        assert_eq!(options.connection.connect.keep_alive, 10);
+        assert!(options.connection.connect.client_id.starts_with("mqtt-pub-"));
+        assert_eq!(options.connection.connect.will, Some(message("lights/status", b"offline", 1, true)));
+    }
+
+    #[test]
+    fn test_parse_tls_options() {
+        let options: SubOptions = SubOptions::parse(&args(&["--topic", "a/#", "--tls"])).unwrap();
+        assert_eq!(options.connection.tls, Some(TlsOptions { ca_file: None }));
+        assert_eq!(options.connection.port, 8883);
+
+        let options: SubOptions = SubOptions::parse(&args(&["--topic", "a/#", "--cafile", "ca.pem", "--port", "9000"])).unwrap();
+        assert_eq!(options.connection.tls, Some(TlsOptions { ca_file: Some(PathBuf::from("ca.pem")) }));
+        assert_eq!(options.connection.port, 9000);
+    }
+
+    #[test]
+    fn test_parse_sub_options() {
+        let options: SubOptions = SubOptions::parse(
+            &args(&["--topic", "sensors/+/temperature", "--topic", "buttons/#", "--qos", "1", "--format", "json", "--count", "5"])
+        ).unwrap();
+
+        assert_eq!(options.topic_filters, vec!["sensors/+/temperature".to_string(), "buttons/#".to_string()]);
+        assert_eq!(options.qos, 1);
+        assert_eq!(options.format, OutputFormat::Json);
+        assert_eq!(options.count, Some(5));
+        assert!(options.connection.connect.client_id.starts_with("mqtt-sub-"));
+    }
+
+    #[test]
+    fn test_parse_invalid_options() {
+        let invalid_pub: [&[&str]; 9] = [
+            &["--message", "on"],
+            &["--topic", "a/+", "--message", "on"],
+            &["--topic", "a", "--message", "on", "--stdin"],
+            &["--topic", "a"],
+            &["--topic", "a", "--message", "on", "--qos", "3"],
+            &["--topic", "a", "--message", "on", "--password", "secret"],
+            &["--topic", "a", "--message", "on", "--will-payload", "offline"],
+            &["--topic", "a", "--message", "on", "--will-topic", "a/#"],
+            &["--topic", "a", "--message"],
+        ];
+
+        for invalid in invalid_pub {
+            assert!(PubOptions::parse(&args(invalid)).is_err(), "{:?} was accepted", invalid);
+        }
+
+        let invalid_sub: [&[&str]; 5] = [
+            &[],
+            &["--topic", "a/#/b"],
+            &["--topic", "a", "--format", "xml"],
+            &["--topic", "a", "--count", "many"],
+            &["--topic", "a", "--retain"],
+        ];
+
+        for invalid in invalid_sub {
+            assert!(SubOptions::parse(&args(invalid)).is_err(), "{:?} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn test_topic_filter_is_valid() {
+        assert!(topic_filter::is_valid("sensors/kitchen"));
+        assert!(topic_filter::is_valid("sensors/+/temperature"));
+        assert!(topic_filter::is_valid("sensors/#"));
+        assert!(topic_filter::is_valid("#"));
+        assert!(topic_filter::is_valid("+/+"));
+
+        assert!(!topic_filter::is_valid(""));
+        assert!(!topic_filter::is_valid("sensors/#/temperature"));
+        assert!(!topic_filter::is_valid("sensors#"));
+        assert!(!topic_filter::is_valid("sensors/kitchen+"));
+    }
+
+    #[test]
+    fn test_output_formats() {
+        let text: Message = message("a/b", b"on", 1, false);
+
+        assert_eq!(OutputFormat::Text.format(&text), "a/b on");
+        assert_eq!(OutputFormat::Hex.format(&text), "a/b 6f6e");
+        assert_eq!(OutputFormat::Json.format(&text), r#"{"payload":"on","qos":1,"retain":false,"topic":"a/b"}"#);
+
+        // A payload that is not UTF-8 is printed in hex in JSON
+        let binary: Message = message("a/b", &[0xff, 0x00], 0, true);
+
+        assert_eq!(OutputFormat::Text.format(&binary), "a/b \u{fffd}\u{0}");
+        assert_eq!(OutputFormat::Json.format(&binary), r#"{"payload_hex":"ff00","qos":0,"retain":true,"topic":"a/b"}"#);
+    }
+
+    #[test]
+    fn test_pub_lines_to_sub() {
+        let broker: Broker = start_broker();
+
+        let mut sub_args: Vec<String> = broker_args(&broker, "cli-sub");
+        sub_args.extend(args(&["--topic", "cli/lines/+", "--qos", "2", "--count", "3"]));
+        let sub_options: SubOptions = SubOptions::parse(&sub_args).unwrap();
+
+        let subscriber: JoinHandle<(Result<usize, String>, Vec<u8>)> = thread::spawn(move || {
+            let mut output: Vec<u8> = Vec::new();
+            let result: Result<usize, String> = cli::run_sub(&sub_options, &mut output);
+
+            (result, output)
+        });
+
+        // Let the subscription arrive first
+        thread::sleep(Duration::from_millis(300));
+
+        let mut pub_args: Vec<String> = broker_args(&broker, "cli-pub");
+        pub_args.extend(args(&["--topic", "cli/lines/a", "--stdin", "--qos", "1"]));
+        let pub_options: PubOptions = PubOptions::parse(&pub_args).unwrap();
+
+        // Empty lines are skipped, \r\n line breaks are stripped
+        let stdin: Cursor<Vec<u8>> = Cursor::new(b"first\n\nsecond\r\nthird".to_vec());
+        assert_eq!(cli::run_pub(&pub_options, stdin), Ok(3));
+
+        let (result, output) = subscriber.join().unwrap();
+        assert_eq!(result, Ok(3));
+        assert_eq!(String::from_utf8(output).unwrap(), "cli/lines/a first\ncli/lines/a second\ncli/lines/a third\n");
+    }
+
+    #[test]
+    fn test_pub_file_and_retain() {
+        let broker: Broker = start_broker();
+
+        let path: PathBuf = std::env::temp_dir().join(format!("mqtt-pub-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "21.5\n22.0\n").unwrap();
+
+        let mut pub_args: Vec<String> = broker_args(&broker, "cli-file");
+        pub_args.extend(args(&["--topic", "cli/file", "--file", path.to_str().unwrap(), "--qos", "2", "--retain"]));
+        let pub_options: PubOptions = PubOptions::parse(&pub_args).unwrap();
+
+        assert_eq!(cli::run_pub(&pub_options, Cursor::new(Vec::new())), Ok(2));
+        std::fs::remove_file(&path).unwrap();
+
+        // The last line is retained
+        let mut subscriber: ScriptedClient = ScriptedClient::connected(&broker, "cli-file-sub");
+        subscriber.subscribe("cli/file", 0);
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.payload, b"22.0");
+        assert!(publish.retain);
+
+        // A missing file fails before connecting
+        let missing: PubOptions = PubOptions::parse(&args(&["--topic", "a", "--file", "/nonexistent/input.txt", "--port", "1"])).unwrap();
+        assert!(cli::run_pub(&missing, Cursor::new(Vec::new())).unwrap_err().starts_with("Could not open"));
+    }
+
+    #[test]
+    fn test_will() {
+        let broker: Broker = start_broker();
+
+        let mut watcher: ScriptedClient = ScriptedClient::connected(&broker, "cli-will-watcher");
+        watcher.subscribe("cli/will", 0);
+
+        let mut sub_args: Vec<String> = broker_args(&broker, "cli-will");
+        sub_args.extend(args(&["--topic", "cli/other", "--will-topic", "cli/will", "--will-payload", "gone"]));
+        let sub_options: SubOptions = SubOptions::parse(&sub_args).unwrap();
+
+        // Dropping the session without a DISCONNECT loses the connection, the broker publishes the will
+        let session: Session = Session::open(&sub_options.connection).unwrap();
+        drop(session);
+
+        assert_eq!(watcher.expect_publish().payload, b"gone");
+    }
+
+    #[test]
+    fn test_keep_alive_while_idle() {
+        let broker: Broker = start_broker();
+
+        let mut sub_args: Vec<String> = broker_args(&broker, "cli-idle");
+        sub_args.extend(args(&["--topic", "cli/idle", "--keep-alive", "1"]));
+        let sub_options: SubOptions = SubOptions::parse(&sub_args).unwrap();
+
+        let mut session: Session = Session::open(&sub_options.connection).unwrap();
+        assert_eq!(session.subscribe(&[("cli/idle".to_string(), 1)]), Ok(vec![1]));
+
+        // Stay idle for longer than the broker allows without pings, then receive a message
+        let publisher: JoinHandle<()> = thread::spawn({
+            let port: u16 = broker.local_addr.port();
+
+            move || {
+                thread::sleep(Duration::from_millis(2500));
+
+                let mut pub_args: Vec<String> = args(&["--host", "127.0.0.1", "--port", &port.to_string(), "--id", "cli-idle-pub"]);
+                pub_args.extend(args(&["--topic", "cli/idle", "--message", "still here"]));
+
+                cli::run_pub(&PubOptions::parse(&pub_args).unwrap(), Cursor::new(Vec::new())).unwrap();
+            }
+        });
+
+        assert_eq!(session.next_message().unwrap().payload, b"still here");
+        publisher.join().unwrap();
+        session.disconnect();
+    }
+}
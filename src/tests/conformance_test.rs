@@ -0,0 +1,1037 @@
+#[cfg(test)]
+mod tests {
+    use std::panic::{ self, AssertUnwindSafe };
+    use std::time::{ Duration, Instant };
+
+    use crate::broker::Broker;
+    use crate::packet::{
+        Connack,
+        Connect,
+        LastWill,
+        Packet,
+        Puback,
+        Pubcomp,
+        Publish,
+        Pubrec,
+        Pubrel,
+        Suback,
+        Subscribe,
+        Unsuback,
+        Unsubscribe,
+    };
+    use crate::tests::scripted_client::{ connect_packet, publish_packet, start_broker, ScriptedClient };
+
+    /// How long a check waits to be sure that a packet is not sent.
+    const QUIET: Duration = Duration::from_millis(200);
+
+    /// How a normative statement of the MQTT 3.1.1 specification is covered.
+    enum Coverage {
+        /// Asserted against a running broker, the check panics when the broker does not conform.
+        Checked(fn(&Broker)),
+        /// The broker knowingly behaves differently, with the reason.
+        Deviation(&'static str),
+        /// Not checked, with the reason.
+        NotChecked(&'static str),
+    }
+
+    /// A normative statement that applies to a server, or to the packets a server accepts.
+    struct Statement {
+        id: &'static str,
+        summary: &'static str,
+        coverage: Coverage,
+    }
+
+    const STATEMENTS: &[Statement] = &[
+        Statement {
+            id: "MQTT-1.5.3-1",
+            summary: "Ill-formed UTF-8 in a string closes the connection",
+            coverage: Coverage::Checked(check_1_5_3_1),
+        },
+        Statement {
+            id: "MQTT-1.5.3-2",
+            summary: "A null character in a string closes the connection",
+            coverage: Coverage::Checked(check_1_5_3_2),
+        },
+        Statement {
+            id: "MQTT-2.2.2-2",
+            summary: "Invalid fixed header flags close the connection",
+            coverage: Coverage::Checked(check_2_2_2_2),
+        },
+        Statement {
+            id: "MQTT-2.3.1-1",
+            summary: "Packets that need a packet identifier have a non-zero one",
+            coverage: Coverage::Checked(check_2_3_1_1),
+        },
+        Statement {
+            id: "MQTT-2.3.1-6",
+            summary: "PUBACK, PUBREC and PUBREL have the packet identifier of the PUBLISH",
+            coverage: Coverage::Checked(check_2_3_1_6),
+        },
+        Statement {
+            id: "MQTT-2.3.1-7",
+            summary: "SUBACK and UNSUBACK have the packet identifier of the request",
+            coverage: Coverage::Checked(check_2_3_1_7),
+        },
+        Statement {
+            id: "MQTT-3.1.0-1",
+            summary: "The first packet from the client is a CONNECT",
+            coverage: Coverage::Checked(check_3_1_0_1),
+        },
+        Statement {
+            id: "MQTT-3.1.0-2",
+            summary: "A second CONNECT is a protocol violation",
+            coverage: Coverage::Checked(check_3_1_0_2),
+        },
+        Statement {
+            id: "MQTT-3.1.2-1",
+            summary: "An incorrect protocol name may close the connection",
+            coverage: Coverage::Checked(check_3_1_2_1),
+        },
+        Statement {
+            id: "MQTT-3.1.2-2",
+            summary: "An unsupported protocol level gets CONNACK 0x01 and a disconnect",
+            coverage: Coverage::Checked(check_3_1_2_2),
+        },
+        Statement {
+            id: "MQTT-3.1.2-3",
+            summary: "A set reserved connect flag closes the connection",
+            coverage: Coverage::Checked(check_3_1_2_3),
+        },
+        Statement {
+            id: "MQTT-3.1.2-4",
+            summary: "Clean Session 0 resumes the session of the client identifier",
+            coverage: Coverage::Checked(check_3_1_2_4),
+        },
+        Statement {
+            id: "MQTT-3.1.2-6",
+            summary: "Clean Session 1 discards the previous session",
+            coverage: Coverage::Checked(check_3_1_2_6),
+        },
+        Statement {
+            id: "MQTT-3.1.2-8",
+            summary: "The will is published when the connection closes without a DISCONNECT",
+            coverage: Coverage::Checked(check_3_1_2_8),
+        },
+        Statement {
+            id: "MQTT-3.1.2-10",
+            summary: "The will is removed when a DISCONNECT is received",
+            coverage: Coverage::Checked(check_3_1_2_10),
+        },
+        Statement {
+            id: "MQTT-3.1.2-13",
+            summary: "Without a will, Will QoS and Will Retain are 0",
+            coverage: Coverage::Checked(check_3_1_2_13),
+        },
+        Statement {
+            id: "MQTT-3.1.2-14",
+            summary: "A Will QoS of 3 is not allowed",
+            coverage: Coverage::Checked(check_3_1_2_14),
+        },
+        Statement {
+            id: "MQTT-3.1.2-17",
+            summary: "A will with Will Retain 1 is published as a retained message",
+            coverage: Coverage::Checked(check_3_1_2_17),
+        },
+        Statement {
+            id: "MQTT-3.1.2-22",
+            summary: "A password without a user name is not allowed",
+            coverage: Coverage::Checked(check_3_1_2_22),
+        },
+        Statement {
+            id: "MQTT-3.1.2-24",
+            summary: "No packet within one and a half times the keep alive closes the connection",
+            coverage: Coverage::Checked(check_3_1_2_24),
+        },
+        Statement {
+            id: "MQTT-3.1.3-6",
+            summary: "An empty client identifier with Clean Session 1 is assigned a unique one",
+            coverage: Coverage::Checked(check_3_1_3_6),
+        },
+        Statement {
+            id: "MQTT-3.1.3-8",
+            summary: "An empty client identifier with Clean Session 0 gets CONNACK 0x02",
+            coverage: Coverage::Checked(check_3_1_3_8),
+        },
+        Statement {
+            id: "MQTT-3.1.4-1",
+            summary: "A CONNECT that does not conform closes the connection without a CONNACK",
+            coverage: Coverage::Checked(check_3_1_4_1),
+        },
+        Statement {
+            id: "MQTT-3.1.4-2",
+            summary: "A CONNECT with the identifier of a connected client disconnects the existing client",
+            coverage: Coverage::Checked(check_3_1_4_2),
+        },
+        Statement {
+            id: "MQTT-3.1.4-4",
+            summary: "A valid CONNECT is acknowledged with CONNACK 0x00",
+            coverage: Coverage::Checked(check_3_1_4_4),
+        },
+        Statement {
+            id: "MQTT-3.1.4-5",
+            summary: "Nothing sent after a refused CONNECT is processed",
+            coverage: Coverage::Checked(check_3_1_4_5),
+        },
+        Statement {
+            id: "MQTT-3.2.0-1",
+            summary: "The first packet sent to the client is a CONNACK",
+            coverage: Coverage::Checked(check_3_2_0_1),
+        },
+        Statement {
+            id: "MQTT-3.2.2-1",
+            summary: "Clean Session 1 gets Session Present 0",
+            coverage: Coverage::Checked(check_3_2_2_1),
+        },
+        Statement {
+            id: "MQTT-3.2.2-2",
+            summary: "A resumed session gets Session Present 1",
+            coverage: Coverage::Checked(check_3_2_2_2),
+        },
+        Statement {
+            id: "MQTT-3.2.2-3",
+            summary: "Clean Session 0 without a stored session gets Session Present 0",
+            coverage: Coverage::Checked(check_3_2_2_3),
+        },
+        Statement {
+            id: "MQTT-3.2.2-4",
+            summary: "A non-zero return code has Session Present 0",
+            coverage: Coverage::Checked(check_3_2_2_4),
+        },
+        Statement {
+            id: "MQTT-3.2.2-5",
+            summary: "A non-zero return code closes the connection",
+            coverage: Coverage::Checked(check_3_2_2_5),
+        },
+        Statement {
+            id: "MQTT-3.3.1-1",
+            summary: "A PUBLISH sent again has the DUP flag set",
+            coverage: Coverage::NotChecked("Resending only happens after 222 seconds, or on session resume with storage"),
+        },
+        Statement {
+            id: "MQTT-3.3.1-4",
+            summary: "A PUBLISH with QoS 3 closes the connection",
+            coverage: Coverage::Checked(check_3_3_1_4),
+        },
+        Statement {
+            id: "MQTT-3.3.1-5",
+            summary: "A retained PUBLISH is stored for future subscribers",
+            coverage: Coverage::Checked(check_3_3_1_5),
+        },
+        Statement {
+            id: "MQTT-3.3.1-6",
+            summary: "A new subscription gets the retained messages of every topic its filter matches",
+            coverage: Coverage::Checked(check_3_3_1_6),
+        },
+        Statement {
+            id: "MQTT-3.3.1-8",
+            summary: "A retained message sent for a new subscription has RETAIN 1",
+            coverage: Coverage::Checked(check_3_3_1_5),
+        },
+        Statement {
+            id: "MQTT-3.3.1-9",
+            summary: "A message forwarded to an existing subscription has RETAIN 0",
+            coverage: Coverage::Checked(check_3_3_1_9),
+        },
+        Statement {
+            id: "MQTT-3.3.1-10",
+            summary: "A retained PUBLISH with an empty payload removes the retained message",
+            coverage: Coverage::Checked(check_3_3_1_10),
+        },
+        Statement {
+            id: "MQTT-3.3.1-12",
+            summary: "A PUBLISH with RETAIN 0 does not replace the retained message",
+            coverage: Coverage::Checked(check_3_3_1_12),
+        },
+        Statement {
+            id: "MQTT-3.3.2-2",
+            summary: "A topic name with wildcards closes the connection",
+            coverage: Coverage::Checked(check_3_3_2_2),
+        },
+        Statement {
+            id: "MQTT-3.3.2-3",
+            summary: "The topic name sent to a subscriber is the one of the PUBLISH",
+            coverage: Coverage::Checked(check_3_3_2_3),
+        },
+        Statement {
+            id: "MQTT-3.3.5-1",
+            summary: "A message matching several subscriptions of a client is delivered once, at the highest granted QoS",
+            coverage: Coverage::Checked(check_3_3_5_1),
+        },
+        Statement {
+            id: "MQTT-3.3.5-2",
+            summary: "A PUBLISH the client is not authorized for is acknowledged or closes the connection",
+            coverage: Coverage::NotChecked("Needs an ACL, the broker wide access control is not configured in tests"),
+        },
+        Statement {
+            id: "MQTT-3.6.1-1",
+            summary: "A PUBREL with flags other than 0b0010 closes the connection",
+            coverage: Coverage::Checked(check_3_6_1_1),
+        },
+        Statement {
+            id: "MQTT-3.8.1-1",
+            summary: "A SUBSCRIBE with flags other than 0b0010 closes the connection",
+            coverage: Coverage::Checked(check_3_8_1_1),
+        },
+        Statement {
+            id: "MQTT-3.8.3-3",
+            summary: "A SUBSCRIBE without topic filters closes the connection",
+            coverage: Coverage::Checked(check_3_8_3_3),
+        },
+        Statement {
+            id: "MQTT-3.8.3-4",
+            summary: "A requested QoS above 2 closes the connection",
+            coverage: Coverage::Deviation("The topic filter is refused with return code 0x80 in the SUBACK instead"),
+        },
+        Statement {
+            id: "MQTT-3.8.4-1",
+            summary: "A SUBSCRIBE is answered with a SUBACK",
+            coverage: Coverage::Checked(check_2_3_1_7),
+        },
+        Statement {
+            id: "MQTT-3.8.4-3",
+            summary: "Subscribing to an existing topic filter replaces the subscription",
+            coverage: Coverage::Checked(check_3_8_4_3),
+        },
+        Statement {
+            id: "MQTT-3.8.4-5",
+            summary: "The SUBACK has one return code per topic filter, in order",
+            coverage: Coverage::Checked(check_3_8_4_5),
+        },
+        Statement {
+            id: "MQTT-3.8.4-6",
+            summary: "A message is delivered at the lower of its QoS and the granted QoS",
+            coverage: Coverage::Checked(check_3_8_4_6),
+        },
+        Statement {
+            id: "MQTT-3.10.1-1",
+            summary: "An UNSUBSCRIBE with flags other than 0b0010 closes the connection",
+            coverage: Coverage::Checked(check_3_10_1_1),
+        },
+        Statement {
+            id: "MQTT-3.10.3-2",
+            summary: "An UNSUBSCRIBE without topic filters closes the connection",
+            coverage: Coverage::Checked(check_3_10_3_2),
+        },
+        Statement {
+            id: "MQTT-3.10.4-2",
+            summary: "No messages are delivered to a removed subscription",
+            coverage: Coverage::Checked(check_3_10_4_2),
+        },
+        Statement {
+            id: "MQTT-3.10.4-5",
+            summary: "An UNSUBSCRIBE is answered with an UNSUBACK, also when nothing matched",
+            coverage: Coverage::Checked(check_3_10_4_5),
+        },
+        Statement {
+            id: "MQTT-3.12.4-1",
+            summary: "A PINGREQ is answered with a PINGRESP",
+            coverage: Coverage::Checked(check_3_12_4_1),
+        },
+        Statement {
+            id: "MQTT-3.14.1-1",
+            summary: "A DISCONNECT with reserved flags set closes the connection",
+            coverage: Coverage::Checked(check_3_14_1_1),
+        },
+        Statement {
+            id: "MQTT-4.3.2-2",
+            summary: "A QoS 1 PUBLISH is acknowledged with a PUBACK",
+            coverage: Coverage::Checked(check_2_3_1_6),
+        },
+        Statement {
+            id: "MQTT-4.3.3-2",
+            summary: "A QoS 2 message is delivered once, also when the PUBLISH is sent again",
+            coverage: Coverage::Checked(check_4_3_3_2),
+        },
+        Statement {
+            id: "MQTT-4.4.0-1",
+            summary: "Unacknowledged messages are sent again when a session resumes",
+            coverage: Coverage::NotChecked("Needs storage, the broker wide storage is not configured in tests"),
+        },
+        Statement {
+            id: "MQTT-4.7.1-2",
+            summary: "A multi-level wildcard that is not the last character of a filter is refused",
+            coverage: Coverage::Checked(check_4_7_1_2),
+        },
+        Statement {
+            id: "MQTT-4.7.2-1",
+            summary: "Filters starting with a wildcard do not match topics starting with $",
+            coverage: Coverage::Checked(check_4_7_2_1),
+        },
+        Statement {
+            id: "MQTT-4.7.3-1",
+            summary: "Topic names and topic filters are at least one character long",
+            coverage: Coverage::Checked(check_4_7_3_1),
+        },
+    ];
+
+    #[test]
+    fn test_conformance() {
+        let broker: Broker = start_broker();
+
+        let mut report: Vec<String> = Vec::new();
+        let mut failed: Vec<&'static str> = Vec::new();
+        let (mut checked, mut deviations, mut not_checked): (usize, usize, usize) = (0, 0, 0);
+
+        for statement in STATEMENTS {
+            let (status, reason): (&str, String) = match &statement.coverage {
+                Coverage::Checked(check) => {
+                    checked += 1;
+
+                    match panic::catch_unwind(AssertUnwindSafe(|| check(&broker))) {
+                        Ok(()) => ("pass", String::new()),
+                        Err(err) => {
+                            failed.push(statement.id);
+
+                            let message: String = err
+                                .downcast_ref::<String>()
+                                .cloned()
+                                .or_else(|| err.downcast_ref::<&str>().map(|s: &&str| s.to_string()))
+                                .unwrap_or_default();
+
+                            ("FAIL", message)
+                        }
+                    }
+                }
+                Coverage::Deviation(reason) => {
+                    deviations += 1;
+                    ("deviation", reason.to_string())
+                }
+                Coverage::NotChecked(reason) => {
+                    not_checked += 1;
+                    ("not checked", reason.to_string())
+                }
+            };
+
+            report.push(format!("{:<14} {:<12} {}", statement.id, status, statement.summary));
+
+            if !reason.is_empty() {
+                report.push(format!("{:<27} {}", "", reason));
+            }
+        }
+
+        // Shown with `cargo test conformance -- --nocapture`, or when the test fails
+        println!("MQTT 3.1.1 conformance, server statements:");
+        println!("{}", report.join("\n"));
+        println!(
+            "{} statements: {} checked ({} failed), {} deviations, {} not checked",
+            STATEMENTS.len(),
+            checked,
+            failed.len(),
+            deviations,
+            not_checked
+        );
+
+        assert!(failed.is_empty(), "Non-conforming behavior: {}", failed.join(", "));
+    }
+
+    #[test]
+    fn test_statements_are_unique() {
+        for (index, statement) in STATEMENTS.iter().enumerate() {
+            assert!(
+                STATEMENTS[..index].iter().all(|s: &Statement| s.id != statement.id),
+                "{} is listed twice",
+                statement.id
+            );
+        }
+    }
+
+    /// Connects with a clean session, and asserts that the connection is accepted.
+    fn connected(broker: &Broker, client_id: &str) -> ScriptedClient {
+        ScriptedClient::connected(broker, client_id)
+    }
+
+    /// Connects, sends the bytes of a packet and asserts that the broker closes the connection.
+    fn assert_closes(broker: &Broker, client_id: &str, bytes: &[u8]) {
+        let mut client: ScriptedClient = connected(broker, client_id);
+        client.send_bytes(bytes);
+        client.expect_closed();
+    }
+
+    /// Opens a connection, sends the bytes of a CONNECT and asserts that the broker closes the
+    /// connection without a CONNACK.
+    fn assert_connect_closes(broker: &Broker, bytes: &[u8]) {
+        let mut client: ScriptedClient = ScriptedClient::open(broker);
+        client.send_bytes(bytes);
+        client.expect_closed();
+    }
+
+    /// The bytes of a CONNECT, with the connect flags and the fields after the client identifier.
+    fn connect_bytes(protocol_name: &[u8], connect_flags: u8, fields: &[u8]) -> Vec<u8> {
+        let mut body: Vec<u8> = vec![0, protocol_name.len() as u8];
+        body.extend_from_slice(protocol_name);
+        body.extend_from_slice(&[4, connect_flags, 0, 60, 0, 1, b'c']);
+        body.extend_from_slice(fields);
+
+        let mut bytes: Vec<u8> = vec![0x10, body.len() as u8];
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    fn connect_with_will(client_id: &str, topic: &str) -> Connect {
+        let mut connect: Connect = connect_packet(client_id, true);
+        connect.will = Some(LastWill { topic: topic.to_string(), message: b"gone".to_vec(), qos: 0, retain: false });
+        connect
+    }
+
+    /// Publishes a QoS 1 message and waits for the PUBACK.
+    fn publish_qos_1(publisher: &mut ScriptedClient, topic_name: &str, payload: &str, retain: bool) {
+        publisher.send(publish_packet(topic_name, payload, 1, retain, 1));
+        assert_eq!(publisher.expect(), Packet::Puback(Puback { packet_id: 1 }));
+    }
+
+    fn check_1_5_3_1(broker: &Broker) {
+        // PUBLISH to a topic name with the byte 0xff
+        assert_closes(broker, "1.5.3-1", &[0x30, 4, 0, 2, b'a', 0xff]);
+    }
+
+    fn check_1_5_3_2(broker: &Broker) {
+        assert_closes(broker, "1.5.3-2", &[0x30, 4, 0, 2, b'a', 0x00]);
+    }
+
+    fn check_2_2_2_2(broker: &Broker) {
+        // PINGREQ with flags 0b0001
+        assert_closes(broker, "2.2.2-2", &[0xc1, 0]);
+    }
+
+    fn check_2_3_1_1(broker: &Broker) {
+        // QoS 1 PUBLISH with packet identifier 0
+        assert_closes(broker, "2.3.1-1", &[0x32, 5, 0, 1, b'a', 0, 0]);
+    }
+
+    fn check_2_3_1_6(broker: &Broker) {
+        let mut client: ScriptedClient = connected(broker, "2.3.1-6");
+
+        client.send(publish_packet("conformance/2.3.1-6", "qos 1", 1, false, 0x1234));
+        assert_eq!(client.expect(), Packet::Puback(Puback { packet_id: 0x1234 }));
+
+        client.send(publish_packet("conformance/2.3.1-6", "qos 2", 2, false, 0x4321));
+        assert_eq!(client.expect(), Packet::Pubrec(Pubrec { packet_id: 0x4321 }));
+        client.send(Packet::Pubrel(Pubrel { packet_id: 0x4321 }));
+        assert_eq!(client.expect(), Packet::Pubcomp(Pubcomp { packet_id: 0x4321 }));
+    }
+
+    fn check_2_3_1_7(broker: &Broker) {
+        let mut client: ScriptedClient = connected(broker, "2.3.1-7");
+
+        client.send(Packet::Subscribe(Subscribe { packet_id: 77, topic_filters: vec![("a".to_string(), 0)] }));
+        assert_eq!(client.expect(), Packet::Suback(Suback { packet_id: 77, return_codes: vec![0] }));
+
+        client.send(Packet::Unsubscribe(Unsubscribe { packet_id: 78, topic_filters: vec!["a".to_string()] }));
+        assert_eq!(client.expect(), Packet::Unsuback(Unsuback { packet_id: 78 }));
+    }
+
+    fn check_3_1_0_1(broker: &Broker) {
+        let mut client: ScriptedClient = ScriptedClient::open(broker);
+        client.send(Packet::Pingreq);
+        client.expect_closed();
+    }
+
+    fn check_3_1_0_2(broker: &Broker) {
+        let mut client: ScriptedClient = connected(broker, "3.1.0-2");
+        client.send(Packet::Connect(connect_packet("3.1.0-2", true)));
+        client.expect_closed();
+    }
+
+    fn check_3_1_2_1(broker: &Broker) {
+        let mut connect: Connect = connect_packet("3.1.2-1", true);
+        connect.protocol_name = "MQIsdp".to_string();
+
+        assert_connect_closes(broker, &Packet::Connect(connect).to_vec().unwrap());
+    }
+
+    fn check_3_1_2_2(broker: &Broker) {
+        let mut connect: Connect = connect_packet("3.1.2-2", true);
+        connect.protocol_level = 5;
+
+        let (mut client, connack) = ScriptedClient::connect(broker, connect);
+        assert_eq!(connack.return_code, 1);
+        client.expect_closed();
+    }
+
+    fn check_3_1_2_3(broker: &Broker) {
+        assert_connect_closes(broker, &connect_bytes(b"MQTT", 0b0000_0011, &[]));
+    }
+
+    fn check_3_1_2_4(broker: &Broker) {
+        let (mut client, _) = ScriptedClient::connect(broker, connect_packet("3.1.2-4", false));
+        client.subscribe("conformance/3.1.2-4", 0);
+        client.send(Packet::Disconnect);
+        client.expect_closed();
+
+        // The subscription is part of the session
+        let (mut client, connack) = ScriptedClient::connect(broker, connect_packet("3.1.2-4", false));
+        assert!(connack.session_present);
+
+        let mut publisher: ScriptedClient = connected(broker, "3.1.2-4-pub");
+        publisher.send(publish_packet("conformance/3.1.2-4", "resumed", 0, false, 0));
+        assert_eq!(client.expect_publish().payload, b"resumed");
+    }
+
+    fn check_3_1_2_6(broker: &Broker) {
+        let (mut client, _) = ScriptedClient::connect(broker, connect_packet("3.1.2-6", false));
+        client.subscribe("conformance/3.1.2-6", 1);
+        client.send(Packet::Disconnect);
+        client.expect_closed();
+
+        let (mut client, connack) = ScriptedClient::connect(broker, connect_packet("3.1.2-6", true));
+        assert!(!connack.session_present);
+
+        // The subscription went with the discarded session
+        let mut publisher: ScriptedClient = connected(broker, "3.1.2-6-pub");
+        publish_qos_1(&mut publisher, "conformance/3.1.2-6", "discarded", false);
+
+        client.expect_nothing(QUIET);
+    }
+
+    fn check_3_1_2_8(broker: &Broker) {
+        let mut watcher: ScriptedClient = connected(broker, "3.1.2-8-watcher");
+        watcher.subscribe("conformance/3.1.2-8", 0);
+
+        let (client, _) = ScriptedClient::connect(broker, connect_with_will("3.1.2-8", "conformance/3.1.2-8"));
+        client.drop_connection();
+
+        assert_eq!(watcher.expect_publish().payload, b"gone");
+    }
+
+    fn check_3_1_2_10(broker: &Broker) {
+        let mut watcher: ScriptedClient = connected(broker, "3.1.2-10-watcher");
+        watcher.subscribe("conformance/3.1.2-10", 0);
+
+        let (mut client, _) = ScriptedClient::connect(broker, connect_with_will("3.1.2-10", "conformance/3.1.2-10"));
+        client.send(Packet::Disconnect);
+        client.expect_closed();
+
+        watcher.expect_nothing(QUIET);
+    }
+
+    fn check_3_1_2_13(broker: &Broker) {
+        // Will Retain without the Will Flag
+        assert_connect_closes(broker, &connect_bytes(b"MQTT", 0b0010_0010, &[]));
+    }
+
+    fn check_3_1_2_14(broker: &Broker) {
+        // Will Flag with Will QoS 3
+        assert_connect_closes(broker, &connect_bytes(b"MQTT", 0b0001_1110, &[0, 1, b'w', 0, 0]));
+    }
+
+    fn check_3_1_2_17(broker: &Broker) {
+        let mut watcher: ScriptedClient = connected(broker, "3.1.2-17-watcher");
+        watcher.subscribe("conformance/3.1.2-17", 0);
+
+        let mut connect: Connect = connect_with_will("3.1.2-17", "conformance/3.1.2-17");
+        connect.will.as_mut().unwrap().retain = true;
+
+        let (client, _) = ScriptedClient::connect(broker, connect);
+        client.drop_connection();
+
+        // Subscribers get the will as usual, a later subscriber gets it as the retained message
+        assert!(!watcher.expect_publish().retain);
+
+        let mut subscriber: ScriptedClient = connected(broker, "3.1.2-17-sub");
+        subscriber.subscribe("conformance/3.1.2-17", 0);
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.payload, b"gone");
+        assert!(publish.retain);
+    }
+
+    fn check_3_1_2_22(broker: &Broker) {
+        // Password Flag without the User Name Flag
+        assert_connect_closes(broker, &connect_bytes(b"MQTT", 0b0100_0010, &[0, 1, b'p']));
+    }
+
+    fn check_3_1_2_24(broker: &Broker) {
+        let mut connect: Connect = connect_packet("3.1.2-24", true);
+        connect.keep_alive = 1;
+
+        let (mut client, _) = ScriptedClient::connect(broker, connect);
+        let silent_since: Instant = Instant::now();
+        client.expect_closed();
+
+        // One and a half times the keep alive, rounded down to whole seconds
+        assert!(silent_since.elapsed() >= Duration::from_millis(900));
+    }
+
+    fn check_3_1_3_6(broker: &Broker) {
+        let (mut first, connack) = ScriptedClient::connect(broker, connect_packet("", true));
+        assert_eq!(connack, Connack { session_present: false, return_code: 0 });
+        first.subscribe("conformance/3.1.3-6/first", 0);
+
+        // A second client without an identifier gets a session of its own, and does not take over the first
+        let (mut second, connack) = ScriptedClient::connect(broker, connect_packet("", true));
+        assert_eq!(connack, Connack { session_present: false, return_code: 0 });
+        second.subscribe("conformance/3.1.3-6/second", 0);
+
+        let mut publisher: ScriptedClient = connected(broker, "3.1.3-6-pub");
+        publisher.send(publish_packet("conformance/3.1.3-6/first", "first", 0, false, 0));
+
+        assert_eq!(first.expect_publish().payload, b"first");
+        second.expect_nothing(QUIET);
+    }
+
+    fn check_3_1_3_8(broker: &Broker) {
+        let (mut client, connack) = ScriptedClient::connect(broker, connect_packet("", false));
+        assert_eq!(connack.return_code, 2);
+        client.expect_closed();
+    }
+
+    fn check_3_1_4_1(broker: &Broker) {
+        // A CONNECT with a Remaining Length longer than its fields
+        let mut bytes: Vec<u8> = connect_bytes(b"MQTT", 0b0000_0010, &[]);
+        bytes[1] += 1;
+        bytes.push(0);
+
+        assert_connect_closes(broker, &bytes);
+    }
+
+    fn check_3_1_4_2(broker: &Broker) {
+        let mut existing: ScriptedClient = connected(broker, "3.1.4-2");
+
+        // The new connection is accepted, and the existing one is closed
+        let (_client, connack) = ScriptedClient::connect(broker, connect_packet("3.1.4-2", true));
+        assert_eq!(connack, Connack { session_present: false, return_code: 0 });
+        existing.expect_closed();
+    }
+
+    fn check_3_1_4_4(broker: &Broker) {
+        let (_client, connack) = ScriptedClient::connect(broker, connect_packet("3.1.4-4", true));
+        assert_eq!(connack, Connack { session_present: false, return_code: 0 });
+    }
+
+    fn check_3_1_4_5(broker: &Broker) {
+        let mut watcher: ScriptedClient = connected(broker, "3.1.4-5-watcher");
+        watcher.subscribe("conformance/3.1.4-5", 0);
+
+        // A refused CONNECT followed by a PUBLISH in the same write
+        let mut connect: Connect = connect_packet("3.1.4-5", true);
+        connect.protocol_level = 3;
+
+        let mut bytes: Vec<u8> = Packet::Connect(connect).to_vec().unwrap();
+        bytes.extend(publish_packet("conformance/3.1.4-5", "ignored", 0, false, 0).to_vec().unwrap());
+
+        let mut client: ScriptedClient = ScriptedClient::open(broker);
+        client.send_bytes(&bytes);
+
+        assert_eq!(client.expect(), Packet::Connack(Connack { session_present: false, return_code: 1 }));
+        client.expect_closed();
+        watcher.expect_nothing(QUIET);
+    }
+
+    fn check_3_2_0_1(broker: &Broker) {
+        // A retained message is waiting, yet the CONNACK comes first
+        let mut publisher: ScriptedClient = connected(broker, "3.2.0-1-pub");
+        publish_qos_1(&mut publisher, "conformance/3.2.0-1", "retained", true);
+
+        let mut client: ScriptedClient = ScriptedClient::open(broker);
+        client.send(Packet::Connect(connect_packet("3.2.0-1", true)));
+        client.send(Packet::Subscribe(Subscribe { packet_id: 1, topic_filters: vec![("conformance/3.2.0-1".to_string(), 0)] }));
+
+        assert!(matches!(client.expect(), Packet::Connack(_)));
+    }
+
+    fn check_3_2_2_1(broker: &Broker) {
+        let (_client, connack) = ScriptedClient::connect(broker, connect_packet("3.2.2-1", true));
+        assert!(!connack.session_present);
+    }
+
+    fn check_3_2_2_2(broker: &Broker) {
+        let (mut client, _) = ScriptedClient::connect(broker, connect_packet("3.2.2-2", false));
+        client.send(Packet::Disconnect);
+        client.expect_closed();
+
+        let (_client, connack) = ScriptedClient::connect(broker, connect_packet("3.2.2-2", false));
+        assert_eq!(connack, Connack { session_present: true, return_code: 0 });
+    }
+
+    fn check_3_2_2_3(broker: &Broker) {
+        let (_client, connack) = ScriptedClient::connect(broker, connect_packet("3.2.2-3", false));
+        assert_eq!(connack, Connack { session_present: false, return_code: 0 });
+    }
+
+    fn check_3_2_2_4(broker: &Broker) {
+        // A stored session, then a refused connection for the same client identifier
+        let (mut client, _) = ScriptedClient::connect(broker, connect_packet("3.2.2-4", false));
+        client.send(Packet::Disconnect);
+        client.expect_closed();
+
+        let mut connect: Connect = connect_packet("3.2.2-4", false);
+        connect.protocol_level = 3;
+
+        let (_client, connack) = ScriptedClient::connect(broker, connect);
+        assert_eq!(connack, Connack { session_present: false, return_code: 1 });
+    }
+
+    fn check_3_2_2_5(broker: &Broker) {
+        // An empty identifier without a clean session is rejected
+        let (mut client, connack) = ScriptedClient::connect(broker, connect_packet("", false));
+        assert_ne!(connack.return_code, 0);
+        client.expect_closed();
+    }
+
+    fn check_3_3_1_4(broker: &Broker) {
+        assert_closes(broker, "3.3.1-4", &[0x36, 5, 0, 1, b'a', 0, 1]);
+    }
+
+    fn check_3_3_1_5(broker: &Broker) {
+        let mut publisher: ScriptedClient = connected(broker, "3.3.1-5-pub");
+        publish_qos_1(&mut publisher, "conformance/3.3.1-5", "stored", true);
+
+        let mut subscriber: ScriptedClient = connected(broker, "3.3.1-5-sub");
+        subscriber.subscribe("conformance/3.3.1-5", 0);
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.payload, b"stored");
+        assert!(publish.retain);
+    }
+
+    fn check_3_3_1_6(broker: &Broker) {
+        let mut publisher: ScriptedClient = connected(broker, "3.3.1-6-pub");
+        publish_qos_1(&mut publisher, "conformance/3.3.1-6/a", "stored", true);
+
+        // The retained message is sent with the name of its topic, not with the filter
+        let mut subscriber: ScriptedClient = connected(broker, "3.3.1-6-sub");
+        subscriber.subscribe("conformance/3.3.1-6/#", 0);
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.topic_name, "conformance/3.3.1-6/a");
+        assert_eq!(publish.payload, b"stored");
+        assert!(publish.retain);
+    }
+
+    fn check_3_3_1_9(broker: &Broker) {
+        let mut subscriber: ScriptedClient = connected(broker, "3.3.1-9-sub");
+        subscriber.subscribe("conformance/3.3.1-9", 0);
+
+        let mut publisher: ScriptedClient = connected(broker, "3.3.1-9-pub");
+        publish_qos_1(&mut publisher, "conformance/3.3.1-9", "forwarded", true);
+
+        assert!(!subscriber.expect_publish().retain);
+    }
+
+    fn check_3_3_1_10(broker: &Broker) {
+        let mut publisher: ScriptedClient = connected(broker, "3.3.1-10-pub");
+        publish_qos_1(&mut publisher, "conformance/3.3.1-10", "stored", true);
+        publish_qos_1(&mut publisher, "conformance/3.3.1-10", "", true);
+
+        let mut subscriber: ScriptedClient = connected(broker, "3.3.1-10-sub");
+        subscriber.subscribe("conformance/3.3.1-10", 0);
+        subscriber.expect_nothing(QUIET);
+    }
+
+    fn check_3_3_1_12(broker: &Broker) {
+        let mut publisher: ScriptedClient = connected(broker, "3.3.1-12-pub");
+        publish_qos_1(&mut publisher, "conformance/3.3.1-12", "stored", true);
+        publish_qos_1(&mut publisher, "conformance/3.3.1-12", "not retained", false);
+
+        let mut subscriber: ScriptedClient = connected(broker, "3.3.1-12-sub");
+        subscriber.subscribe("conformance/3.3.1-12", 0);
+        assert_eq!(subscriber.expect_publish().payload, b"stored");
+    }
+
+    fn check_3_3_2_2(broker: &Broker) {
+        let mut client: ScriptedClient = connected(broker, "3.3.2-2");
+        client.send(publish_packet("conformance/+", "wildcard", 0, false, 0));
+        client.expect_closed();
+    }
+
+    fn check_3_3_2_3(broker: &Broker) {
+        let mut subscriber: ScriptedClient = connected(broker, "3.3.2-3-sub");
+        subscriber.subscribe("conformance/3.3.2-3/#", 0);
+
+        let mut publisher: ScriptedClient = connected(broker, "3.3.2-3-pub");
+        publisher.send(publish_packet("conformance/3.3.2-3/a/b", "topic", 0, false, 0));
+
+        assert_eq!(subscriber.expect_publish().topic_name, "conformance/3.3.2-3/a/b");
+    }
+
+    fn check_3_3_5_1(broker: &Broker) {
+        let mut subscriber: ScriptedClient = connected(broker, "3.3.5-1-sub");
+        subscriber.subscribe("conformance/3.3.5-1/+", 0);
+        subscriber.subscribe("conformance/3.3.5-1/#", 1);
+
+        let mut publisher: ScriptedClient = connected(broker, "3.3.5-1-pub");
+        publish_qos_1(&mut publisher, "conformance/3.3.5-1/a", "once", false);
+
+        // A single delivery, at the QoS of the "#" subscription
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.payload, b"once");
+        assert_eq!(publish.qos, 1);
+
+        subscriber.send(Packet::Puback(Puback { packet_id: publish.packet_id.unwrap() }));
+        subscriber.expect_nothing(QUIET);
+    }
+
+    fn check_3_6_1_1(broker: &Broker) {
+        assert_closes(broker, "3.6.1-1", &[0x60, 2, 0, 1]);
+    }
+
+    fn check_3_8_1_1(broker: &Broker) {
+        assert_closes(broker, "3.8.1-1", &[0x80, 6, 0, 1, 0, 1, b'a', 0]);
+    }
+
+    fn check_3_8_3_3(broker: &Broker) {
+        assert_closes(broker, "3.8.3-3", &[0x82, 2, 0, 1]);
+    }
+
+    fn check_3_8_4_3(broker: &Broker) {
+        let mut subscriber: ScriptedClient = connected(broker, "3.8.4-3-sub");
+        subscriber.subscribe("conformance/3.8.4-3", 0);
+        subscriber.subscribe("conformance/3.8.4-3", 1);
+
+        let mut publisher: ScriptedClient = connected(broker, "3.8.4-3-pub");
+        publish_qos_1(&mut publisher, "conformance/3.8.4-3", "once", false);
+
+        // One delivery, with the QoS of the newer subscription
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.qos, 1);
+        subscriber.send(Packet::Puback(Puback { packet_id: publish.packet_id.unwrap() }));
+        subscriber.expect_nothing(QUIET);
+    }
+
+    fn check_3_8_4_5(broker: &Broker) {
+        let mut client: ScriptedClient = connected(broker, "3.8.4-5");
+
+        client.send(Packet::Subscribe(Subscribe {
+            packet_id: 5,
+            topic_filters: vec![("a".to_string(), 2), ("b".to_string(), 0), ("c".to_string(), 1)],
+        }));
+        assert_eq!(client.expect(), Packet::Suback(Suback { packet_id: 5, return_codes: vec![2, 0, 1] }));
+    }
+
+    fn check_3_8_4_6(broker: &Broker) {
+        let mut low: ScriptedClient = connected(broker, "3.8.4-6-low");
+        low.subscribe("conformance/3.8.4-6", 0);
+
+        let mut high: ScriptedClient = connected(broker, "3.8.4-6-high");
+        high.subscribe("conformance/3.8.4-6", 2);
+
+        let mut publisher: ScriptedClient = connected(broker, "3.8.4-6-pub");
+
+        // A QoS 1 message is downgraded for the QoS 0 subscription
+        publish_qos_1(&mut publisher, "conformance/3.8.4-6", "downgraded", false);
+        assert_eq!(low.expect_publish().qos, 0);
+
+        let publish: Publish = high.expect_publish();
+        assert_eq!(publish.qos, 1);
+        high.send(Packet::Puback(Puback { packet_id: publish.packet_id.unwrap() }));
+
+        // A QoS 0 message is not upgraded for the QoS 2 subscription
+        publisher.send(publish_packet("conformance/3.8.4-6", "not upgraded", 0, false, 0));
+        assert_eq!(high.expect_publish().qos, 0);
+        assert_eq!(low.expect_publish().qos, 0);
+    }
+
+    fn check_3_10_1_1(broker: &Broker) {
+        assert_closes(broker, "3.10.1-1", &[0xa0, 5, 0, 1, 0, 1, b'a']);
+    }
+
+    fn check_3_10_3_2(broker: &Broker) {
+        assert_closes(broker, "3.10.3-2", &[0xa2, 2, 0, 1]);
+    }
+
+    fn check_3_10_4_2(broker: &Broker) {
+        let mut subscriber: ScriptedClient = connected(broker, "3.10.4-2-sub");
+        subscriber.subscribe("conformance/3.10.4-2", 0);
+
+        subscriber.send(Packet::Unsubscribe(Unsubscribe { packet_id: 2, topic_filters: vec!["conformance/3.10.4-2".to_string()] }));
+        assert_eq!(subscriber.expect(), Packet::Unsuback(Unsuback { packet_id: 2 }));
+
+        let mut publisher: ScriptedClient = connected(broker, "3.10.4-2-pub");
+        publish_qos_1(&mut publisher, "conformance/3.10.4-2", "unsubscribed", false);
+
+        subscriber.expect_nothing(QUIET);
+    }
+
+    fn check_3_10_4_5(broker: &Broker) {
+        let mut client: ScriptedClient = connected(broker, "3.10.4-5");
+
+        client.send(Packet::Unsubscribe(Unsubscribe { packet_id: 9, topic_filters: vec!["never/subscribed".to_string()] }));
+        assert_eq!(client.expect(), Packet::Unsuback(Unsuback { packet_id: 9 }));
+    }
+
+    fn check_3_12_4_1(broker: &Broker) {
+        let mut client: ScriptedClient = connected(broker, "3.12.4-1");
+        client.send(Packet::Pingreq);
+        assert_eq!(client.expect(), Packet::Pingresp);
+    }
+
+    fn check_3_14_1_1(broker: &Broker) {
+        let mut watcher: ScriptedClient = connected(broker, "3.14.1-1-watcher");
+        watcher.subscribe("conformance/3.14.1-1", 0);
+
+        // A malformed DISCONNECT is not a graceful disconnect, the will is published
+        let (mut client, _) = ScriptedClient::connect(broker, connect_with_will("3.14.1-1", "conformance/3.14.1-1"));
+        client.send_bytes(&[0xe1, 0]);
+        client.expect_closed();
+
+        assert_eq!(watcher.expect_publish().payload, b"gone");
+    }
+
+    fn check_4_3_3_2(broker: &Broker) {
+        let mut subscriber: ScriptedClient = connected(broker, "4.3.3-2-sub");
+        subscriber.subscribe("conformance/4.3.3-2", 0);
+
+        // The same QoS 2 PUBLISH twice, the second with the DUP flag, before the PUBREL
+        let mut publisher: ScriptedClient = connected(broker, "4.3.3-2-pub");
+        publisher.send(publish_packet("conformance/4.3.3-2", "once", 2, false, 3));
+        assert_eq!(publisher.expect(), Packet::Pubrec(Pubrec { packet_id: 3 }));
+
+        publisher.send_bytes(&[0x3c, 27, 0, 19]);
+        publisher.send_bytes(b"conformance/4.3.3-2");
+        publisher.send_bytes(&[0, 3, b'o', b'n', b'c', b'e']);
+        assert_eq!(publisher.expect(), Packet::Pubrec(Pubrec { packet_id: 3 }));
+
+        publisher.send(Packet::Pubrel(Pubrel { packet_id: 3 }));
+        assert_eq!(publisher.expect(), Packet::Pubcomp(Pubcomp { packet_id: 3 }));
+
+        assert_eq!(subscriber.expect_publish().payload, b"once");
+        subscriber.expect_nothing(QUIET);
+    }
+
+    fn check_4_7_2_1(broker: &Broker) {
+        let mut publisher: ScriptedClient = connected(broker, "4.7.2-1-pub");
+        publish_qos_1(&mut publisher, "$internal/conformance/retained", "hidden", true);
+
+        // "#" also gets the retained messages the other checks left on the broker, none of them may start with '$'
+        let mut subscriber: ScriptedClient = connected(broker, "4.7.2-1-sub");
+        subscriber.send(
+            Packet::Subscribe(Subscribe {
+                packet_id: 1,
+                topic_filters: vec![("#".to_string(), 0), ("+/conformance".to_string(), 0)],
+            })
+        );
+        publisher.send(publish_packet("$internal/conformance", "hidden", 0, false, 0));
+
+        for packet in subscriber.received(QUIET) {
+            if let Packet::Publish(publish) = packet {
+                assert!(!publish.topic_name.starts_with('$'), "Got {}", publish.topic_name);
+            }
+        }
+    }
+
+    fn check_4_7_1_2(broker: &Broker) {
+        let mut client: ScriptedClient = connected(broker, "4.7.1-2");
+        client.send(
+            Packet::Subscribe(Subscribe {
+                packet_id: 1,
+                topic_filters: vec![
+                    ("conformance/#/4.7.1-2".to_string(), 0),
+                    ("conformance/4.7.1-2#".to_string(), 0),
+                    ("conformance/4.7.1-2+".to_string(), 0),
+                    ("conformance/4.7.1-2/#".to_string(), 0)
+                ],
+            })
+        );
+
+        assert_eq!(client.expect(), Packet::Suback(Suback { packet_id: 1, return_codes: vec![0x80, 0x80, 0x80, 0] }));
+    }
+
+    fn check_4_7_3_1(broker: &Broker) {
+        // A topic filter is refused, a PUBLISH to an empty topic name closes the connection
+        let mut client: ScriptedClient = connected(broker, "4.7.3-1-sub");
+        client.send(Packet::Subscribe(Subscribe { packet_id: 1, topic_filters: vec![(String::new(), 0)] }));
+        assert_eq!(client.expect(), Packet::Suback(Suback { packet_id: 1, return_codes: vec![0x80] }));
+
+        assert_closes(broker, "4.7.3-1-pub", &[0x30, 4, 0, 0, b'h', b'i']);
+    }
+}
@@ -1,13 +1,32 @@
 #[cfg(test)]
 mod tests {
+    use crate::broker::Broker;
     use crate::control_packet;
     use crate::control_packet::connect::handle;
-    use std::sync::mpsc::channel;
+    use crate::control_packet::ProtocolError;
+    use crate::packet::{ decode, Connack, Connect, DecodeError, Packet };
+    use crate::models::client::Clients;
+    use crate::models::config::{ BrokerConfig, SessionConfig, TakeoverWill };
+    use crate::services::session::check_client_id;
+    use crate::mqtt_client::codec;
+    use crate::services::outbound;
+    use std::io::Write;
+    use std::net::{ SocketAddr, TcpStream };
+    use std::time::Duration;
+
+    /// Decodes a CONNECT packet, returning the decode error for malformed packets.
+    fn decode_connect(packet: &[u8]) -> Result<Connect, DecodeError> {
+        match decode(packet)? {
+            (Packet::Connect(connect), length) => {
+                assert_eq!(length, packet.len());
+                Ok(connect)
+            }
+            (packet, _) => panic!("Expected a CONNECT, got {:?}", packet),
+        }
+    }
 
     #[test]
     fn test_handle_valid_packet() {
-        let mut buffer: [u8; 8192] = [0; 8192];
-        // Fill buffer with valid data
 
         let packet = [
             0b0001_0000, // CONNECT
@@ -29,33 +48,36 @@ mod tests {
             b's',
             b't', // Client ID
         ];
-        buffer[..packet.len()].copy_from_slice(&packet);
+        let connect = decode_connect(&packet).unwrap();
+        assert_eq!(connect.client_id, "test");
+        assert_eq!(connect.keep_alive, 60);
+        assert!(connect.clean_session);
 
-        let packet_length = packet.len().clone(); // Set to valid packet length
         let socket_addr = "127.0.0.1:12345".parse().unwrap();
-        let mut clients = Vec::new();
-        let (tx, _rx) = channel();
+        let clients = Clients::new();
+        let (tx, _rx) = outbound::channel();
 
         let result = control_packet::connect::handle(
-            buffer,
-            packet_length,
+            connect,
             socket_addr,
-            &mut clients,
+            &clients,
             tx
         );
 
         assert!(result.is_ok());
-        // Further assertions based on expected Response
-        // ...
+
+        let response = result.unwrap();
+        assert_eq!(response.connack.return_code, 0);
+        assert!(!response.connack.session_present);
+        // Keep alive is 1.5 times the requested value
+        assert_eq!(response.keep_alive, 90);
     }
 
     #[test]
     fn test_handle_invalid_protocol() {
-        let mut buffer: [u8; 8192] = [0; 8192];
-        // Fill buffer with invalid data
         let packet = [
             0b0001_0000, // CONNECT
-            0x0f, // Remaining length
+            0x10, // Remaining length
             0x00,
             0x04, // Protocol name length
             b'M',
@@ -73,24 +95,22 @@ mod tests {
             b's',
             b't', // Client ID
         ];
-        buffer[..packet.len()].copy_from_slice(&packet);
+        // The packet is well formed, the broker answers the unsupported level with return code 1
+        let connect = decode_connect(&packet).unwrap();
+        assert_eq!(connect.protocol_level, 5);
 
-        let packet_length = packet.len(); // Set to invalid packet length
         let socket_addr = "127.0.0.1:12345".parse().unwrap();
-        let mut clients = Vec::new();
-        let (tx, _rx) = channel();
+        let clients = Clients::new();
+        let (tx, _rx) = outbound::channel();
 
-        let result = handle(buffer, packet_length, socket_addr, &mut clients, tx);
+        let result = handle(connect, socket_addr, &clients, tx);
 
-        assert!(result.is_err());
-        // Further assertions based on expected error
-        // ...
+        assert!(matches!(result, Err(ProtocolError::UnsupportedProtocolVersion(5))));
+        assert!(clients.is_empty(), "A refused client is not added to the list");
     }
 
     #[test]
     fn test_handle_invalid_remaining_lenght() {
-        let mut buffer: [u8; 8192] = [0; 8192];
-        // Fill buffer with invalid data
         let packet = [
             0b0001_0000, // CONNECT
             0x05, // Incorrect remaining length
@@ -111,24 +131,14 @@ mod tests {
             b's',
             b't', // Client ID
         ];
-        buffer[..packet.len()].copy_from_slice(&packet);
-
-        let packet_length = packet.len();
-        let socket_addr = "127.0.0.1:12345".parse().unwrap();
-        let mut clients = Vec::new();
-        let (tx, _rx) = channel();
-
-        let result = handle(buffer, packet_length, socket_addr, &mut clients, tx);
+        // The protocol name does not fit in the 5 bytes
+        let result = decode_connect(&packet);
 
-        assert!(result.is_err());
-        // Further assertions based on expected error
-        // ...
+        assert!(matches!(result, Err(DecodeError::MalformedPacket(_))));
     }
 
     #[test]
     fn test_handle_invalid_packet_type() {
-        let mut buffer: [u8; 8192] = [0; 8192];
-        // Fill buffer with invalid data
         let packet = [
             0b0000_0000, // Invalid packet type
             0x0f, // Remaining length
@@ -149,30 +159,16 @@ mod tests {
             b's',
             b't', // Client ID
         ];
-        buffer[..packet.len()].copy_from_slice(&packet);
+        let result = decode(&packet);
 
-        let packet_length = packet.len();
-        let socket_addr = "127.0.0.1:12345".parse().unwrap();
-        let mut clients = Vec::new();
-        let (tx, _rx) = channel();
-
-        let result = handle(buffer, packet_length, socket_addr, &mut clients, tx);
-
-        assert!(result.is_err());
-        // Further assertions based on expected error
-        // ...
+        assert_eq!(result, Err(DecodeError::InvalidPacketType(0)));
     }
 
     #[test]
     fn test_handle_reserved_flag() {
-        let mut buffer: [u8; 8192] = [0; 8192];
-        let socket_addr = "127.0.0.1:12345".parse().unwrap();
-        let mut clients = Vec::new();
-        let (tx, _rx) = channel();
-
         let packet = [
             0b0001_0000, // CONNECT
-            0x0e, // Remaining length
+            0x10, // Remaining length
             0x00,
             0x04, // Protocol name length
             b'M',
@@ -191,11 +187,155 @@ mod tests {
             b't', // Client ID
         ];
 
-        buffer[..packet.len()].copy_from_slice(&packet);
+        let result = decode_connect(&packet);
+
+        assert_eq!(
+            result,
+            Err(DecodeError::MalformedPacket("Reserved connect flag is set")),
+            "Expected error for Reserved flag"
+        );
+    }
+
+    /// Builds a CONNECT packet with protocol level 4.
+    fn connect(client_id: &str, clean_session: bool) -> Connect {
+        Connect {
+            protocol_name: "MQTT".to_string(),
+            protocol_level: 4,
+            clean_session,
+            keep_alive: 60,
+            client_id: client_id.to_string(),
+            will: None,
+            username: None,
+            password: None,
+        }
+    }
+
+    #[test]
+    fn test_handle_refused_connections() {
+        let socket_addr = "127.0.0.1:12345".parse().unwrap();
+        let clients = Clients::new();
+        let (tx, _rx) = outbound::channel();
+
+        // Any protocol name other than "MQTT" is a protocol violation, answered without a CONNACK
+        let mut invalid_name: Connect = connect("test", true);
+        invalid_name.protocol_name = "MQIsdp".to_string();
+
+        let result = handle(invalid_name, socket_addr, &clients, tx.clone());
+        assert!(matches!(result, Err(ProtocolError::ProtocolViolation("Invalid protocol name"))));
+
+        // An empty client identifier is only accepted with a clean session
+        let result = handle(connect("", false), socket_addr, &clients, tx.clone());
+        assert!(matches!(result, Err(ProtocolError::IdentifierRejected(_))));
+
+        assert!(clients.is_empty());
+    }
+
+    #[test]
+    fn test_handle_takeover() {
+        let clients = Clients::new();
+        let old_addr: SocketAddr = "127.0.0.1:12345".parse().unwrap();
+        let new_addr: SocketAddr = "127.0.0.1:12346".parse().unwrap();
+        let (old_tx, old_rx) = outbound::channel();
+        let (new_tx, _new_rx) = outbound::channel();
+
+        let response = handle(connect("test", true), old_addr, &clients, old_tx).unwrap();
+        assert!(response.taken_over.is_none());
+        assert!(!response.session_discarded);
+
+        // A second connection with the identifier of a connected client takes the session over,
+        // with a clean session the previous one is discarded
+        let response = handle(connect("test", true), new_addr, &clients, new_tx).unwrap();
+        assert_eq!(response.connack.return_code, 0);
+        assert!(response.session_discarded);
+        assert_eq!(response.taken_over.unwrap().socket_addr, old_addr);
+
+        // The old connection is told to close, and is no longer found by its address
+        assert_eq!(old_rx.try_recv(), Ok(Err("Session taken over".to_string())));
+        assert_eq!(clients.id_of(&old_addr), None);
+        assert_eq!(clients.id_of(&new_addr).as_deref(), Some("test"));
+        assert_eq!(clients.len(), 1);
+    }
+
+    #[test]
+    fn test_handle_assigned_client_id() {
+        let clients = Clients::new();
+        let (tx, _rx) = outbound::channel();
+
+        // Every client without an identifier gets a unique one
+        let first = handle(connect("", true), "127.0.0.1:12345".parse().unwrap(), &clients, tx.clone()).unwrap();
+        let second = handle(connect("", true), "127.0.0.1:12346".parse().unwrap(), &clients, tx).unwrap();
+
+        assert!(first.client_id.starts_with("auto-"));
+        assert_eq!(first.client_id.len(), "auto-".len() + 16);
+        assert_ne!(first.client_id, second.client_id);
+        assert!(second.taken_over.is_none());
+        assert_eq!(clients.len(), 2);
+        assert!(clients.get("").is_none());
+    }
+
+    #[test]
+    fn test_check_client_id() {
+        let config: SessionConfig = BrokerConfig::parse("[session]\nmax_client_id_length = 8\nclient_id_chars = \"-_\"")
+            .unwrap()
+            .session;
+
+        assert_eq!(check_client_id(&config, "sensor-1"), Ok(()));
+        assert_eq!(check_client_id(&config, "Kitchen_2"), Err("The client identifier is too long"));
+        assert_eq!(check_client_id(&config, "a/b"), Err("The client identifier has a character that is not allowed"));
+        assert_eq!(check_client_id(&config, "é"), Err("The client identifier has a character that is not allowed"));
+
+        // Without a policy any client id is accepted
+        assert_eq!(check_client_id(&SessionConfig::default(), "home/kitchen/sensor é"), Ok(()));
+    }
+
+    #[test]
+    fn test_parse_session_config() {
+        let config: BrokerConfig = BrokerConfig::parse("[session]\ntakeover_will = \"publish\"").unwrap();
+        assert_eq!(config.session.takeover_will, TakeoverWill::Publish);
+        assert_eq!(BrokerConfig::parse("").unwrap().session.takeover_will, TakeoverWill::Suppress);
+        assert_eq!(BrokerConfig::parse("").unwrap().session.assigned_id_prefix, "auto-");
+    }
+
+    #[test]
+    fn test_protocol_error_connack_codes() {
+        assert_eq!(ProtocolError::UnsupportedProtocolVersion(3).connack_code(), Some(1));
+        assert_eq!(ProtocolError::IdentifierRejected("").connack_code(), Some(2));
+        assert_eq!(ProtocolError::BadCredentials.connack_code(), Some(4));
+        assert_eq!(ProtocolError::NotAuthorized.connack_code(), Some(5));
+
+        // Malformed packets and protocol violations close the connection without a CONNACK
+        assert_eq!(ProtocolError::ProtocolViolation("").connack_code(), None);
+        assert_eq!(ProtocolError::from(DecodeError::InvalidPacketType(0)).connack_code(), None);
+        assert_eq!(
+            ProtocolError::from(DecodeError::InvalidPacketType(0)),
+            ProtocolError::MalformedPacket(DecodeError::InvalidPacketType(0))
+        );
+    }
+
+    #[test]
+    fn test_refused_connection_gets_connack() {
+        let mut broker: Broker = Broker::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        broker.start().unwrap();
+
+        let mut stream: TcpStream = TcpStream::connect(broker.local_addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+
+        // Protocol level 3 is answered with return code 1, then the connection is closed
+        let mut unsupported: Connect = connect("refused", true);
+        unsupported.protocol_level = 3;
+        stream.write_all(&Packet::Connect(unsupported).to_vec().unwrap()).unwrap();
+
+        assert_eq!(
+            codec::read_packet(&mut stream).unwrap(),
+            Packet::Connack(Connack { session_present: false, return_code: 1 })
+        );
+        assert!(codec::read_packet(&mut stream).is_err());
 
-        let packet_length = packet.len();
-        let result = handle(buffer, packet_length, socket_addr, &mut clients, tx.clone());
+        // A protocol violation closes the connection without a CONNACK
+        let mut stream: TcpStream = TcpStream::connect(broker.local_addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        stream.write_all(&Packet::Pingreq.to_vec().unwrap()).unwrap();
 
-        assert!(result.is_err(), "Expected error for Reserved flag");
+        assert!(codec::read_packet(&mut stream).is_err());
     }
 }
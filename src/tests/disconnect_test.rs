@@ -1,22 +1,27 @@
 #[cfg(test)]
 mod tests {
-    use crate::control_packet::disconnect::handle;
+    use crate::packet::{ decode, DecodeError, Packet };
 
     #[test]
     fn test_handle_disconnect_packets() {
         // Test with a normal disconnect packet
         let buffer = [0xE0, 0x00];
-        let packet_length = 2;
-        assert_eq!(handle(&buffer, packet_length), Ok("Reserved bits not set"));
+        assert_eq!(decode(&buffer), Ok((Packet::Disconnect, 2)));
 
-        // Test with a disconnect packet with extra data
+        // Test with a disconnect packet followed by the start of another packet
         let buffer = [0xE0, 0x00, 0x01];
-        let packet_length = 3;
-        assert_eq!(handle(&buffer, packet_length), Ok("Reserved bits not set"));
+        assert_eq!(decode(&buffer), Ok((Packet::Disconnect, 2)));
 
-        // Test with a disconnect packet with an incorrect packet length
+        // Test with a disconnect packet with a remaining length, but no payload yet
         let buffer = [0xE0, 0x01];
-        let packet_length = 2;
-        assert_eq!(handle(&buffer, packet_length), Err("Reserved bits are set"));
+        assert_eq!(decode(&buffer), Err(DecodeError::Incomplete));
+
+        // Test with a disconnect packet with a payload
+        let buffer = [0xE0, 0x01, 0x00];
+        assert!(matches!(decode(&buffer), Err(DecodeError::MalformedPacket(_))));
+
+        // Test with the reserved bits set
+        let buffer = [0xE2, 0x00];
+        assert_eq!(decode(&buffer), Err(DecodeError::InvalidFlags(0xE2)));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,310 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{ Duration, Instant };
+
+    use crate::broker::Broker;
+    use crate::control_packet;
+    use crate::models::client::Client;
+    use crate::models::publish_queue_item::{ PublishItemDirection, PublishQueueKey };
+    use crate::packet::{ Connack, Connect, LastWill, Packet, Puback, Pubcomp, Publish, Pubrec, Pubrel };
+    use crate::services::storage::state::StoredMessage;
+    use crate::tests::scripted_client::{ connect_packet, publish_packet, start_broker, wait_for_subscriber, ScriptedClient };
+
+    #[test]
+    fn test_qos_0_delivery() {
+        let broker: Broker = start_broker();
+
+        let mut subscriber: ScriptedClient = ScriptedClient::connected(&broker, "e2e-qos0-sub");
+        subscriber.subscribe("e2e/qos0/+", 0);
+
+        let mut publisher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-qos0-pub");
+        publisher.send(publish_packet("e2e/qos0/a", "hello", 0, false, 0));
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.topic_name, "e2e/qos0/a");
+        assert_eq!(publish.payload, b"hello");
+        assert_eq!(publish.qos, 0);
+        assert_eq!(publish.packet_id, None);
+
+        // QoS 0 is not acknowledged
+        publisher.expect_nothing(Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_qos_1_delivery() {
+        let broker: Broker = start_broker();
+
+        let mut subscriber: ScriptedClient = ScriptedClient::connected(&broker, "e2e-qos1-sub");
+        subscriber.subscribe("e2e/qos1", 1);
+
+        let mut publisher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-qos1-pub");
+        publisher.send(publish_packet("e2e/qos1", "hello", 1, false, 10));
+
+        // The publisher gets a PUBACK with its own packet identifier
+        assert_eq!(publisher.expect(), Packet::Puback(Puback { packet_id: 10 }));
+
+        // The subscriber gets the message with a packet identifier chosen by the broker, and acknowledges it
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.payload, b"hello");
+        assert_eq!(publish.qos, 1);
+
+        subscriber.send(Packet::Puback(Puback { packet_id: publish.packet_id.unwrap() }));
+        subscriber.expect_nothing(Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_qos_2_delivery() {
+        let broker: Broker = start_broker();
+
+        let mut subscriber: ScriptedClient = ScriptedClient::connected(&broker, "e2e-qos2-sub");
+        subscriber.subscribe("e2e/qos2", 2);
+
+        // Publisher side: PUBLISH -> PUBREC -> PUBREL -> PUBCOMP
+        let mut publisher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-qos2-pub");
+        publisher.send(publish_packet("e2e/qos2", "exactly once", 2, false, 20));
+
+        assert_eq!(publisher.expect(), Packet::Pubrec(Pubrec { packet_id: 20 }));
+        publisher.send(Packet::Pubrel(Pubrel { packet_id: 20 }));
+        assert_eq!(publisher.expect(), Packet::Pubcomp(Pubcomp { packet_id: 20 }));
+
+        // Subscriber side: PUBLISH -> PUBREC -> PUBREL -> PUBCOMP
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.payload, b"exactly once");
+        assert_eq!(publish.qos, 2);
+
+        let packet_id: u16 = publish.packet_id.unwrap();
+        subscriber.send(Packet::Pubrec(Pubrec { packet_id }));
+        assert_eq!(subscriber.expect(), Packet::Pubrel(Pubrel { packet_id }));
+        subscriber.send(Packet::Pubcomp(Pubcomp { packet_id }));
+
+        // The message is delivered once
+        subscriber.expect_nothing(Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_retained_message() {
+        let broker: Broker = start_broker();
+
+        let mut publisher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-retain-pub");
+        publisher.send(publish_packet("e2e/retained", "last value", 1, true, 1));
+        assert_eq!(publisher.expect(), Packet::Puback(Puback { packet_id: 1 }));
+
+        // A client subscribing afterwards gets the retained message, with the RETAIN flag set
+        let mut subscriber: ScriptedClient = ScriptedClient::connected(&broker, "e2e-retain-sub");
+        subscriber.subscribe("e2e/retained", 1);
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.topic_name, "e2e/retained");
+        assert_eq!(publish.payload, b"last value");
+        assert!(publish.retain);
+
+        subscriber.send(Packet::Puback(Puback { packet_id: publish.packet_id.unwrap() }));
+
+        // A newer retained message replaces it
+        publisher.send(publish_packet("e2e/retained", "newer value", 1, true, 2));
+        assert_eq!(publisher.expect(), Packet::Puback(Puback { packet_id: 2 }));
+
+        // The current subscriber gets the newer message without the RETAIN flag
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.payload, b"newer value");
+        assert!(!publish.retain);
+
+        let mut late_subscriber: ScriptedClient = ScriptedClient::connected(&broker, "e2e-retain-late");
+        late_subscriber.subscribe("e2e/retained", 1);
+        assert_eq!(late_subscriber.expect_publish().payload, b"newer value");
+    }
+
+    #[test]
+    fn test_will() {
+        let broker: Broker = start_broker();
+
+        let mut watcher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-will-watcher");
+        watcher.subscribe("e2e/will/+", 0);
+
+        let connect_with_will = |client_id: &str| {
+            let mut connect: Connect = connect_packet(client_id, true);
+            connect.will = Some(LastWill {
+                topic: format!("e2e/will/{}", client_id),
+                message: b"offline".to_vec(),
+                qos: 0,
+                retain: false,
+            });
+            connect
+        };
+
+        // A client that loses its connection has its will published
+        let (client, connack) = ScriptedClient::connect(&broker, connect_with_will("lost"));
+        assert_eq!(connack.return_code, 0);
+        client.drop_connection();
+
+        let publish: Publish = watcher.expect_publish();
+        assert_eq!(publish.topic_name, "e2e/will/lost");
+        assert_eq!(publish.payload, b"offline");
+
+        // A client that sends DISCONNECT does not
+        let (mut client, connack) = ScriptedClient::connect(&broker, connect_with_will("graceful"));
+        assert_eq!(connack.return_code, 0);
+        client.send(Packet::Disconnect);
+        client.expect_closed();
+
+        watcher.expect_nothing(Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_session_takeover() {
+        let broker: Broker = start_broker();
+
+        let mut watcher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-takeover-watcher");
+        watcher.subscribe("e2e/takeover/will", 0);
+
+        let mut connect: Connect = connect_packet("e2e-takeover", false);
+        connect.will = Some(LastWill {
+            topic: "e2e/takeover/will".to_string(),
+            message: b"offline".to_vec(),
+            qos: 0,
+            retain: false,
+        });
+
+        let (mut old, connack) = ScriptedClient::connect(&broker, connect.clone());
+        assert_eq!(connack.return_code, 0);
+        old.subscribe("e2e/takeover/data", 0);
+
+        // The device reconnects from a new address before the old socket timed out
+        let (mut new, connack) = ScriptedClient::connect(&broker, connect);
+        assert_eq!(connack, Connack { session_present: true, return_code: 0 });
+        old.expect_closed();
+
+        // The will of the old connection is suppressed by default, the client is still online
+        watcher.expect_nothing(Duration::from_millis(300));
+
+        // The new connection has the session, with its subscription
+        let mut publisher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-takeover-pub");
+        publisher.send(publish_packet("e2e/takeover/data", "after", 0, false, 0));
+        assert_eq!(new.expect_publish().payload, b"after");
+
+        // Losing the new connection publishes the will as usual
+        new.drop_connection();
+        assert_eq!(watcher.expect_publish().payload, b"offline");
+    }
+
+    #[test]
+    fn test_session_resume() {
+        let broker: Broker = start_broker();
+
+        // A new persistent session
+        let (mut client, connack) = ScriptedClient::connect(&broker, connect_packet("e2e-session", false));
+        assert_eq!(connack, Connack { session_present: false, return_code: 0 });
+
+        client.subscribe("e2e/session", 1);
+        client.send(Packet::Disconnect);
+        client.expect_closed();
+
+        // Connecting again resumes the session, with its subscriptions
+        let (mut client, connack) = ScriptedClient::connect(&broker, connect_packet("e2e-session", false));
+        assert_eq!(connack, Connack { session_present: true, return_code: 0 });
+
+        let mut publisher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-session-pub");
+        publisher.send(publish_packet("e2e/session", "still subscribed", 1, false, 1));
+        assert_eq!(publisher.expect(), Packet::Puback(Puback { packet_id: 1 }));
+
+        let publish: Publish = client.expect_publish();
+        assert_eq!(publish.payload, b"still subscribed");
+        client.send(Packet::Puback(Puback { packet_id: publish.packet_id.unwrap() }));
+        client.send(Packet::Disconnect);
+        client.expect_closed();
+
+        // A clean session discards the stored one
+        let (_client, connack) = ScriptedClient::connect(&broker, connect_packet("e2e-session", true));
+        assert_eq!(connack, Connack { session_present: false, return_code: 0 });
+    }
+
+    #[test]
+    fn test_keep_alive_expiry() {
+        let broker: Broker = start_broker();
+
+        let mut watcher: ScriptedClient = ScriptedClient::connected(&broker, "e2e-keep-alive-watcher");
+        watcher.subscribe("e2e/keep-alive", 0);
+
+        // Keep alive of one second, the broker allows one and a half times that before closing
+        let mut connect: Connect = connect_packet("e2e-keep-alive", true);
+        connect.keep_alive = 1;
+        connect.will = Some(LastWill {
+            topic: "e2e/keep-alive".to_string(),
+            message: b"timed out".to_vec(),
+            qos: 0,
+            retain: false,
+        });
+
+        let (mut client, connack) = ScriptedClient::connect(&broker, connect);
+        assert_eq!(connack.return_code, 0);
+
+        // A PINGREQ is answered, while the client is within its keep alive
+        client.send(Packet::Pingreq);
+        assert_eq!(client.expect(), Packet::Pingresp);
+
+        // Then the client goes silent
+        let silent_since: Instant = Instant::now();
+        client.expect_closed();
+        assert!(silent_since.elapsed() >= Duration::from_millis(900));
+
+        // The connection was not closed gracefully, so the will is published
+        assert_eq!(watcher.expect_publish().payload, b"timed out");
+    }
+
+    #[test]
+    fn test_session_resume_completes_qos_2() {
+        let broker: Broker = start_broker();
+
+        let (mut client, connack) = ScriptedClient::connect(&broker, connect_packet("e2e-resume-qos2", false));
+        assert_eq!(connack, Connack { session_present: false, return_code: 0 });
+        client.subscribe("e2e/resume", 2);
+        wait_for_subscriber(&broker, "e2e/resume", "e2e-resume-qos2");
+
+        control_packet::publish::publish(
+            &broker.topics,
+            &broker.clients,
+            Arc::clone(&broker.publish_queue),
+            "e2e/resume",
+            "queued",
+            &false,
+            &2,
+            &false
+        );
+
+        // The client loses its connection before it acknowledged the PUBLISH
+        let publish: Publish = client.expect_publish();
+        let packet_id: u16 = publish.packet_id.unwrap();
+        let key: PublishQueueKey = PublishQueueKey::new("e2e-resume-qos2", PublishItemDirection::ToSubscriber, packet_id as usize);
+        let packet: Vec<u8> = broker.publish_queue.with(&key, |item| item.publish_packet.clone()).unwrap();
+        client.drop_connection();
+
+        let (mut client, connack) = ScriptedClient::connect(&broker, connect_packet("e2e-resume-qos2", false));
+        assert_eq!(connack, Connack { session_present: true, return_code: 0 });
+
+        // What the broker does with the queued messages of the storage when the session is resumed,
+        // the storage is not opened in tests
+        let resumed: Client = broker.clients.get("e2e-resume-qos2").unwrap();
+        control_packet::publish::resend_to_client(
+            &resumed,
+            Arc::clone(&broker.publish_queue),
+            StoredMessage { packet_id: packet_id as usize, qos: 2, packet }
+        );
+
+        // The PUBLISH is sent again with its packet id and the DUP flag, and its flow completes the handshake
+        let publish: Publish = client.expect_publish();
+        assert!(publish.dup);
+        assert_eq!(publish.packet_id, Some(packet_id));
+        assert_eq!(publish.payload, b"queued");
+
+        client.send(Packet::Pubrec(Pubrec { packet_id }));
+        assert_eq!(client.expect(), Packet::Pubrel(Pubrel { packet_id }));
+        client.send(Packet::Pubcomp(Pubcomp { packet_id }));
+
+        let deadline: Instant = Instant::now() + Duration::from_secs(3);
+        while broker.publish_queue.contains(&key) {
+            assert!(Instant::now() < deadline, "The QoS 2 flow was not completed");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+}
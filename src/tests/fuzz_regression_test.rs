@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::SocketAddr;
+    use std::path::{ Path, PathBuf };
+
+    use crate::control_packet::{ connect, publish, subcribe, unsubcribe, ProtocolError };
+    use crate::models::client::Clients;
+    use crate::packet::{ decode, DecodeError, Packet };
+    use crate::services::outbound;
+
+    /// Reads the inputs kept for a fuzz target, in `fuzz/regressions/<target>`.
+    fn regressions(target: &str) -> Vec<(PathBuf, Vec<u8>)> {
+        let directory: PathBuf = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions").join(target);
+
+        let mut inputs: Vec<(PathBuf, Vec<u8>)> = fs::read_dir(&directory)
+            .unwrap_or_else(|err| panic!("Could not read {}: {}", directory.display(), err))
+            .map(|entry| {
+                let path: PathBuf = entry.unwrap().path();
+                let bytes: Vec<u8> = fs::read(&path).unwrap();
+                (path, bytes)
+            })
+            .collect();
+
+        inputs.sort();
+        assert!(!inputs.is_empty(), "No regression inputs in {}", directory.display());
+
+        inputs
+    }
+
+    /// Replays an input the way the fuzz targets do: decode, encode again and decode the encoded bytes.
+    fn replay(bytes: &[u8]) -> Result<Packet, DecodeError> {
+        let (packet, length) = decode(bytes)?;
+        assert!(length <= bytes.len());
+
+        let encoded: Vec<u8> = packet.to_vec().unwrap();
+        assert_eq!(decode(&encoded), Ok((packet.clone(), encoded.len())));
+
+        Ok(packet)
+    }
+
+    #[test]
+    fn test_decode_regressions() {
+        for (path, bytes) in regressions("decode") {
+            // Each of these inputs panicked a parser before the shared decoder, they are all refused now
+            assert!(replay(&bytes).is_err(), "{} decoded", path.display());
+        }
+    }
+
+    #[test]
+    fn test_connect_regressions() {
+        let socket_addr: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 1883));
+
+        for (path, bytes) in regressions("connect") {
+            if let Ok(Packet::Connect(connect)) = replay(&bytes) {
+                let clients: Clients = Clients::new();
+                let (tx, _rx) = outbound::channel();
+
+                match connect::handle(connect, socket_addr, &clients, tx) {
+                    Ok(_) => assert_eq!(clients.len(), 1, "{}", path.display()),
+                    Err(err) => assert!(clients.is_empty(), "{}: {}", path.display(), err),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_publish_regressions() {
+        for (path, bytes) in regressions("publish") {
+            if let Ok(Packet::Publish(publish)) = replay(&bytes) {
+                let result = publish::handle_publish(publish);
+                assert!(matches!(result, Err(ProtocolError::ProtocolViolation(_))), "{}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn test_subscribe_regressions() {
+        for (path, bytes) in regressions("subscribe") {
+            if let Ok(Packet::Subscribe(subscribe)) = replay(&bytes) {
+                let topic_filters: usize = subscribe.topic_filters.len();
+                let sub_info = subcribe::handle(subscribe, 2);
+
+                assert_eq!(sub_info.return_codes.len(), topic_filters, "{}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsubscribe_regressions() {
+        for (path, bytes) in regressions("unsubscribe") {
+            if let Ok(Packet::Unsubscribe(unsubscribe)) = replay(&bytes) {
+                let topic_filters: usize = unsubscribe.topic_filters.len();
+                let sub_info = unsubcribe::handle(unsubscribe);
+
+                assert_eq!(sub_info.topic_qos_pair.len(), topic_filters, "{}", path.display());
+            }
+        }
+    }
+}
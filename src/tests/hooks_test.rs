@@ -0,0 +1,236 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::{ Arc, Mutex };
+    use std::time::{ Duration, Instant };
+
+    use crate::broker::Broker;
+    use crate::packet::{ Packet, Puback, Publish, Suback, Subscribe };
+    use crate::services::hooks::{ self, AuthDecision, BrokerHooks, ConnectInfo, HookChain, Message, PublishAction };
+    use crate::tests::scripted_client::{ connect_packet, publish_packet, start_broker, ScriptedClient };
+
+    /// Records the callbacks it gets, and decides by fixed rules.
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BrokerHooks for Recorder {
+        fn on_connect(&self, connect: &ConnectInfo) -> AuthDecision {
+            self.calls.lock().unwrap().push(format!("{} connect {}", self.name, connect.client_id));
+
+            match (self.name, connect.client_id) {
+                ("first", "denied") => AuthDecision::NotAuthorized,
+                ("second", "denied" | "allowed") => AuthDecision::Allow,
+                _ => AuthDecision::Continue,
+            }
+        }
+
+        fn on_subscribe(&self, _client_id: &str, topic_filter: &str, qos: u8) -> Option<u8> {
+            match (self.name, topic_filter) {
+                ("first", "lowered") => Some(1),
+                ("second", "lowered") => Some(2),
+                ("second", "refused") => None,
+                _ => Some(qos),
+            }
+        }
+
+        fn on_publish(&self, _client_id: &str, message: &mut Message) -> PublishAction {
+            message.payload.push_str(self.name);
+            message.qos = 0;
+
+            if message.topic == "drop" && self.name == "first" { PublishAction::Drop } else { PublishAction::Continue }
+        }
+
+        fn on_deliver(&self, client_id: &str, _message: &Message) -> bool {
+            !(self.name == "second" && client_id == "muted")
+        }
+
+        fn on_disconnect(&self, client_id: &str, reason: &str) {
+            self.calls.lock().unwrap().push(format!("{} disconnect {} {}", self.name, client_id, reason));
+        }
+    }
+
+    fn connect_info(client_id: &str) -> ConnectInfo<'_> {
+        ConnectInfo {
+            client_id,
+            username: None,
+            password: None,
+            address: SocketAddr::from(([127, 0, 0, 1], 50000)),
+            clean_session: true,
+        }
+    }
+
+    #[test]
+    fn test_hook_chain() {
+        let calls: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let chain: HookChain = HookChain::new()
+            .with(Arc::new(Recorder { name: "first", calls: Arc::clone(&calls) }))
+            .with(Arc::new(Recorder { name: "second", calls: Arc::clone(&calls) }));
+
+        // The first decision wins, the hooks after it are not asked
+        assert_eq!(chain.on_connect(&connect_info("denied")), AuthDecision::NotAuthorized);
+        assert_eq!(chain.on_connect(&connect_info("allowed")), AuthDecision::Allow);
+        assert_eq!(chain.on_connect(&connect_info("other")), AuthDecision::Continue);
+        assert_eq!(*calls.lock().unwrap(), vec![
+            "first connect denied",
+            "first connect allowed",
+            "second connect allowed",
+            "first connect other",
+            "second connect other",
+        ]);
+
+        // A hook can lower the QoS granted by the hooks before it, but not raise it
+        assert_eq!(chain.on_subscribe("sensor/1", "lowered", 2), Some(1));
+        assert_eq!(chain.on_subscribe("sensor/1", "refused", 2), None);
+        assert_eq!(chain.on_subscribe("sensor/1", "other", 2), Some(2));
+
+        // Each hook sees the message as the hook before it left it, the QoS is kept
+        let mut message: Message = Message { topic: "sensors".to_string(), payload: "21.5 ".to_string(), qos: 1, retain: false };
+        assert_eq!(chain.on_publish("sensor/1", &mut message), PublishAction::Continue);
+        assert_eq!(message.payload, "21.5 firstsecond");
+        assert_eq!(message.qos, 1);
+
+        let mut message: Message = Message { topic: "drop".to_string(), payload: String::new(), qos: 1, retain: false };
+        assert_eq!(chain.on_publish("sensor/1", &mut message), PublishAction::Drop);
+        assert_eq!(message.payload, "first");
+
+        assert!(!chain.on_deliver("muted", &message));
+        assert!(chain.on_deliver("display", &message));
+
+        // Every hook hears about a disconnect
+        calls.lock().unwrap().clear();
+        chain.on_disconnect("sensor/1", "Keep alive timeout");
+        assert_eq!(*calls.lock().unwrap(), vec![
+            "first disconnect sensor/1 Keep alive timeout",
+            "second disconnect sensor/1 Keep alive timeout",
+        ]);
+    }
+
+    /// A registered hook, it only acts on clients with ids starting with `hooks-`
+    /// so the other tests sharing the process are left alone.
+    struct Scoped {
+        disconnects: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl BrokerHooks for Scoped {
+        fn on_connect(&self, connect: &ConnectInfo) -> AuthDecision {
+            match connect.client_id {
+                "hooks-denied" => AuthDecision::BadCredentials,
+                _ => AuthDecision::Continue,
+            }
+        }
+
+        fn on_subscribe(&self, client_id: &str, topic_filter: &str, qos: u8) -> Option<u8> {
+            if !client_id.starts_with("hooks-") {
+                return Some(qos);
+            }
+
+            match topic_filter {
+                "hooks/secret/#" => None,
+                "hooks/limited" => Some(0),
+                _ => Some(qos),
+            }
+        }
+
+        fn on_publish(&self, client_id: &str, message: &mut Message) -> PublishAction {
+            if !client_id.starts_with("hooks-") {
+                return PublishAction::Continue;
+            }
+
+            match message.topic.as_str() {
+                "hooks/drop" => PublishAction::Drop,
+                "hooks/raw" => {
+                    message.topic = "hooks/enriched".to_string();
+                    message.payload = format!("{{\"from\":\"{}\",\"value\":{}}}", client_id, message.payload);
+                    PublishAction::Continue
+                }
+                _ => PublishAction::Continue,
+            }
+        }
+
+        fn on_deliver(&self, client_id: &str, _message: &Message) -> bool {
+            client_id != "hooks-muted"
+        }
+
+        fn on_disconnect(&self, client_id: &str, reason: &str) {
+            if client_id.starts_with("hooks-") {
+                self.disconnects.lock().unwrap().push(format!("{} {}", client_id, reason));
+            }
+        }
+    }
+
+    #[test]
+    fn test_registered_hooks() {
+        let disconnects: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        hooks::register(Arc::new(Scoped { disconnects: Arc::clone(&disconnects) }));
+
+        let broker: Broker = start_broker();
+
+        // on_connect refuses the client with the return code of its decision
+        let (_, connack) = ScriptedClient::connect(&broker, connect_packet("hooks-denied", true));
+        assert_eq!(connack.return_code, 4);
+
+        // on_subscribe refuses or lowers topic filters
+        let mut subscriber: ScriptedClient = ScriptedClient::connected(&broker, "hooks-sub");
+        subscriber.send(
+            Packet::Subscribe(Subscribe {
+                packet_id: 1,
+                topic_filters: vec![
+                    ("hooks/secret/#".to_string(), 1),
+                    ("hooks/limited".to_string(), 2),
+                    ("hooks/enriched".to_string(), 1),
+                    ("hooks/drop".to_string(), 1),
+                ],
+            })
+        );
+        assert_eq!(subscriber.expect(), Packet::Suback(Suback { packet_id: 1, return_codes: vec![0x80, 0, 1, 1] }));
+
+        let mut muted: ScriptedClient = ScriptedClient::connected(&broker, "hooks-muted");
+        muted.subscribe("hooks/enriched", 0);
+
+        let mut publisher: ScriptedClient = ScriptedClient::connected(&broker, "hooks-pub");
+
+        // on_publish drops the message, the publisher still gets its PUBACK
+        publisher.send(publish_packet("hooks/drop", "lost", 1, false, 7));
+        assert_eq!(publisher.expect(), Packet::Puback(Puback { packet_id: 7 }));
+
+        // on_publish redirects and enriches the message, on_deliver skips the muted client
+        publisher.send(publish_packet("hooks/raw", "21.5", 1, false, 8));
+        assert_eq!(publisher.expect(), Packet::Puback(Puback { packet_id: 8 }));
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.topic_name, "hooks/enriched");
+        assert_eq!(publish.payload, b"{\"from\":\"hooks-pub\",\"value\":21.5}");
+        assert_eq!(publish.qos, 1);
+        muted.expect_nothing(Duration::from_millis(300));
+
+        // on_disconnect hears why the connection was closed
+        publisher.send(Packet::Disconnect);
+        muted.drop_connection();
+
+        let deadline: Instant = Instant::now() + Duration::from_secs(3);
+        while disconnects.lock().unwrap().len() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut closed: Vec<String> = disconnects.lock().unwrap().clone();
+        closed.sort();
+        assert_eq!(closed, vec![
+            "hooks-muted Connection closed by the client".to_string(),
+            "hooks-pub Client disconnected".to_string(),
+        ]);
+
+        // A takeover reports the old connection once, before the new connection gets its CONNACK
+        disconnects.lock().unwrap().clear();
+
+        let (mut old, _) = ScriptedClient::connect(&broker, connect_packet("hooks-takeover", false));
+        let (_new, connack) = ScriptedClient::connect(&broker, connect_packet("hooks-takeover", false));
+        assert!(connack.session_present);
+        assert_eq!(*disconnects.lock().unwrap(), vec!["hooks-takeover Session taken over".to_string()]);
+
+        old.expect_closed();
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(*disconnects.lock().unwrap(), vec!["hooks-takeover Session taken over".to_string()]);
+    }
+}
@@ -0,0 +1,43 @@
+#[cfg(test)]
+mod tests {
+    use crate::common_fn::bit_operations::to_hex_string;
+    use crate::models::config::{ BrokerConfig, LogFormat, LoggingConfig };
+    use crate::services::logging;
+
+    #[test]
+    fn test_logging_config() {
+        let config: BrokerConfig = BrokerConfig::parse(
+            "[logging]\nlevel = \"warn,mqtt_broker::control_packet=trace\"\nformat = \"json\"\n"
+        ).unwrap();
+
+        assert_eq!(config.logging.level, "warn,mqtt_broker::control_packet=trace");
+        assert_eq!(config.logging.format, LogFormat::Json);
+
+        // Defaults to info level text output
+        let config: BrokerConfig = BrokerConfig::parse("").unwrap();
+        assert_eq!(config.logging.level, "info");
+        assert_eq!(config.logging.format, LogFormat::Text);
+
+        // Unknown formats are rejected
+        assert!(BrokerConfig::parse("[logging]\nformat = \"xml\"\n").is_err());
+    }
+
+    #[test]
+    fn test_invalid_level_is_rejected() {
+        let config: LoggingConfig = LoggingConfig {
+            level: "info,=[".to_string(),
+            format: LogFormat::Text,
+        };
+
+        if std::env::var("RUST_LOG").is_err() {
+            assert!(logging::init(&config).is_err());
+        }
+    }
+
+    #[test]
+    fn test_to_hex_string() {
+        assert_eq!(to_hex_string(&[0xc0, 0x00]), "c0 00");
+        assert_eq!(to_hex_string(&[0x10, 0x0f, 0xff]), "10 0f ff");
+        assert_eq!(to_hex_string(&[]), "");
+    }
+}
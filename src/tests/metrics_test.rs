@@ -0,0 +1,110 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{ Read, Write };
+    use std::net::TcpStream;
+    use std::sync::Arc;
+
+    use crate::models::client::Clients;
+    use crate::models::config::BrokerConfig;
+    use crate::models::publish_queue_item::PublishQueue;
+    use crate::models::topic::Topics;
+    use crate::services::metrics::{ self, Gauges, Metrics };
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let metrics: Metrics = Metrics::new();
+
+        metrics.qos_1_retries.observe(0.0);
+        metrics.qos_1_retries.observe(1.0);
+        metrics.qos_1_retries.observe(20.0);
+
+        let output: String = metrics.render(
+            &(Gauges { clients: 0, clients_connected: 0, topics: 0, publish_queue: 0, outbound_messages: 0, outbound_bytes: 0 })
+        );
+
+        assert_eq!(metrics.qos_1_retries.count(), 3);
+        assert!(output.contains("mqtt_qos_retries_bucket{qos=\"1\",le=\"0\"} 1"));
+        assert!(output.contains("mqtt_qos_retries_bucket{qos=\"1\",le=\"1\"} 2"));
+        assert!(output.contains("mqtt_qos_retries_bucket{qos=\"1\",le=\"10\"} 2"));
+        assert!(output.contains("mqtt_qos_retries_bucket{qos=\"1\",le=\"+Inf\"} 3"));
+        assert!(output.contains("mqtt_qos_retries_sum{qos=\"1\"} 21"));
+        assert!(output.contains("mqtt_qos_retries_count{qos=\"2\"} 0"));
+    }
+
+    #[test]
+    fn test_render_counters_and_gauges() {
+        let metrics: Metrics = Metrics::new();
+
+        metrics.packet_received(3);
+        metrics.packet_received(3);
+        metrics.packet_received(12);
+        metrics.connack(0);
+        metrics.connack(2);
+        metrics.connack(2);
+
+        let output: String = metrics.render(
+            &(Gauges {
+                clients: 4,
+                clients_connected: 3,
+                topics: 2,
+                publish_queue: 1,
+                outbound_messages: 5,
+                outbound_bytes: 640,
+            })
+        );
+
+        assert!(output.contains("mqtt_packets_received_total{type=\"PUBLISH\"} 2"));
+        assert!(output.contains("mqtt_packets_received_total{type=\"PINGREQ\"} 1"));
+        assert!(output.contains("mqtt_connections_accepted_total 1"));
+        assert!(output.contains("mqtt_connections_rejected_total{return_code=\"2\"} 2"));
+        assert!(output.contains("mqtt_clients 4"));
+        assert!(output.contains("mqtt_clients_connected 3"));
+        assert!(output.contains("mqtt_topics 2"));
+        assert!(output.contains("mqtt_publish_queue 1"));
+        assert!(output.contains("mqtt_outbound_queue_messages 5"));
+        assert!(output.contains("mqtt_outbound_queue_bytes 640"));
+    }
+
+    #[test]
+    fn test_metrics_endpoint() {
+        let bound_addr = metrics
+            ::start(
+                "127.0.0.1:0".parse().unwrap(),
+                Arc::new(Clients::new()),
+                Arc::new(Topics::new()),
+                Arc::new(PublishQueue::new())
+            )
+            .unwrap();
+
+        let mut stream: TcpStream = TcpStream::connect(bound_addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        let mut response: String = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE mqtt_publish_deliver_latency_seconds histogram"));
+
+        // Unknown paths are not served
+        let mut stream: TcpStream = TcpStream::connect(bound_addr).unwrap();
+        stream.write_all(b"GET /other HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut response: String = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_config_metrics_section() {
+        let config: BrokerConfig = BrokerConfig::parse(
+            "[metrics]\naddress = \"127.0.0.1:9100\"\n"
+        ).unwrap();
+
+        assert_eq!(config.metrics.address, Some("127.0.0.1:9100".parse().unwrap()));
+        assert_eq!(config.listener.port, 1883);
+
+        // An empty config disables the endpoint
+        assert!(BrokerConfig::parse("").unwrap().metrics.address.is_none());
+    }
+}
@@ -0,0 +1,228 @@
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::{ SocketAddr, TcpListener, TcpStream };
+    use std::sync::mpsc::{ channel, Receiver };
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{ Duration, Instant };
+
+    use crate::broker::Broker;
+    use crate::models::client::Client;
+    use crate::mqtt_client::codec;
+    use crate::mqtt_client::{ ConnectOptions, Message, MqttClient, Reconnect };
+    use crate::packet::{ Connack, Packet, Pubcomp, Pubrec, Pubrel };
+    use crate::services::admin_api;
+
+    fn message(topic: &str, payload: &str, qos: u8) -> Message {
+        Message { topic: topic.to_string(), payload: payload.as_bytes().to_vec(), qos, retain: false }
+    }
+
+    fn start_broker() -> Broker {
+        let mut broker: Broker = Broker::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        broker.start().unwrap();
+        broker
+    }
+
+    fn receive(messages: &Receiver<Message>) -> Option<Message> {
+        messages.recv_timeout(Duration::from_secs(2)).ok()
+    }
+
+    #[test]
+    fn test_codec() {
+        let mut options: ConnectOptions = ConnectOptions::new("c");
+        options.keep_alive = 10;
+        options.username = Some("u".to_string());
+        options.password = Some("p".to_string());
+
+        assert_eq!(
+            codec::connect_packet(&options).to_vec().unwrap(),
+            vec![0x10, 19, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b1100_0010, 0, 10, 0, 1, b'c', 0, 1, b'u', 0, 1, b'p']
+        );
+
+        // The packet identifier is only written for QoS 1 and QoS 2
+        assert_eq!(
+            codec::publish_packet(&message("a", "on", 0), 7).to_vec().unwrap(),
+            vec![0x30, 5, 0, 1, b'a', b'o', b'n']
+        );
+        assert_eq!(
+            codec::publish_packet(&message("a", "on", 1), 7).to_vec().unwrap(),
+            vec![0x32, 7, 0, 1, b'a', 0, 7, b'o', b'n']
+        );
+        assert!(codec::publish_packet(&message("a", "on", 3), 7).to_vec().is_err());
+
+        // A packet is read from a stream, and its message taken out again
+        let sent: Message = message("a/b", &"x".repeat(200), 2);
+        let bytes: Vec<u8> = codec::publish_packet(&sent, 300).to_vec().unwrap();
+
+        match codec::read_packet(&mut bytes.as_slice()).unwrap() {
+            Packet::Publish(publish) => {
+                assert_eq!(publish.packet_id, Some(300));
+                assert_eq!(codec::message_from(publish), sent);
+            }
+            packet => panic!("Expected a PUBLISH, got {:?}", packet),
+        }
+
+        // A stream that ends in the middle of a packet
+        assert!(codec::read_packet(&mut &bytes[..100]).is_err());
+    }
+
+    #[test]
+    fn test_publish_at_each_qos() {
+        let broker: Broker = start_broker();
+
+        let (subscriber, messages) = MqttClient::connect(broker.local_addr, &ConnectOptions::new("qos-subscriber")).unwrap();
+        assert_eq!(subscriber.subscribe(&[("qos/test".to_string(), 2)]).unwrap(), vec![2]);
+
+        let (publisher, _) = MqttClient::connect(broker.local_addr, &ConnectOptions::new("qos-publisher")).unwrap();
+
+        for qos in 0..=2 {
+            // Returns once the PUBLISH is written (QoS 0), acknowledged (QoS 1) or completed (QoS 2)
+            publisher.publish(&message("qos/test", &format!("qos {}", qos), qos)).unwrap();
+
+            let received: Message = receive(&messages).unwrap_or_else(|| panic!("QoS {} message not received", qos));
+            assert_eq!(received.topic, "qos/test");
+            assert_eq!(received.payload, format!("qos {}", qos).as_bytes());
+        }
+
+        publisher.disconnect();
+        subscriber.disconnect();
+    }
+
+    #[test]
+    fn test_subscribe_with_callback() {
+        let broker: Broker = start_broker();
+
+        let (client, messages) = MqttClient::connect(broker.local_addr, &ConnectOptions::new("callback-client")).unwrap();
+
+        let (tx, rx) = channel::<Message>();
+        let tx: Mutex<_> = Mutex::new(tx);
+
+        let return_code: u8 = client
+            .subscribe_with("buttons/+", 0, move |message: &Message| {
+                _ = tx.lock().unwrap().send(message.clone());
+            })
+            .unwrap();
+        assert_eq!(return_code, 0);
+
+        client.subscribe(&[("other".to_string(), 0)]).unwrap();
+
+        // Matching messages go to the callback, the rest to the channel
+        client.publish(&message("buttons/1", "pressed", 0)).unwrap();
+        assert_eq!(receive(&rx).unwrap().topic, "buttons/1");
+
+        thread::sleep(Duration::from_millis(100));
+
+        client.publish(&message("other", "x", 0)).unwrap();
+        assert_eq!(receive(&messages).unwrap().topic, "other");
+        assert!(rx.try_recv().is_err());
+
+        // After unsubscribing, nothing is delivered anymore
+        client.unsubscribe(&["buttons/+".to_string()]).unwrap();
+
+        client.publish(&message("buttons/1", "pressed", 0)).unwrap();
+        assert!(receive(&rx).is_none());
+        assert!(messages.try_recv().is_err());
+
+        client.disconnect();
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let broker: Broker = start_broker();
+
+        let mut options: ConnectOptions = ConnectOptions::new("keep-alive-client");
+        options.keep_alive = 1;
+
+        let (client, _messages) = MqttClient::connect(broker.local_addr, &options).unwrap();
+
+        // The broker closes a connection after 1.5 times the keep alive without packets
+        thread::sleep(Duration::from_secs(3));
+
+        assert!(client.is_connected());
+        assert!(broker.clients.with("keep-alive-client", |c: &Client| c.is_connected).unwrap_or(false));
+
+        client.disconnect();
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let broker: Broker = start_broker();
+
+        let mut options: ConnectOptions = ConnectOptions::new("reconnect-client");
+        options.reconnect = Some(Reconnect { min_delay: Duration::from_millis(100), max_delay: Duration::from_secs(1) });
+
+        let (client, messages) = MqttClient::connect(broker.local_addr, &options).unwrap();
+        client.subscribe(&[("reconnect/test".to_string(), 0)]).unwrap();
+
+        // Remove the subscription, so only the one made after reconnecting delivers
+        broker.topics.remove_client("reconnect-client");
+
+        assert!(admin_api::disconnect_client(&broker.clients, "reconnect-client", true));
+
+        // Wait until the client is connected and subscribed again
+        let deadline: Instant = Instant::now() + Duration::from_secs(10);
+        while
+            !client.is_connected() ||
+            broker.topics.subscriptions("reconnect-client").is_empty()
+        {
+            assert!(Instant::now() < deadline, "The client did not subscribe again");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let (publisher, _) = MqttClient::connect(broker.local_addr, &ConnectOptions::new("reconnect-publisher")).unwrap();
+        publisher.publish(&message("reconnect/test", "again", 0)).unwrap();
+
+        assert_eq!(receive(&messages).unwrap().payload, b"again");
+
+        // A closed client does not reconnect, and its channel closes
+        client.disconnect();
+        assert!(messages.recv_timeout(Duration::from_secs(2)).is_err());
+        assert!(!client.is_connected());
+
+        publisher.disconnect();
+    }
+
+    #[test]
+    fn test_qos_2_duplicates_are_delivered_once() {
+        // A broker scripted by the test, which sends a QoS 2 PUBLISH again before it sends the PUBREL
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+
+        let accept = thread::spawn(move || -> TcpStream {
+            let (mut stream, _): (TcpStream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+
+            assert!(matches!(codec::read_packet(&mut stream).unwrap(), Packet::Connect(_)));
+            stream.write_all(&Packet::Connack(Connack { session_present: false, return_code: 0 }).to_vec().unwrap()).unwrap();
+
+            stream
+        });
+
+        let (client, messages) = MqttClient::connect(address, &ConnectOptions::new("qos-2-client")).unwrap();
+        let mut broker: TcpStream = accept.join().unwrap();
+
+        let publish: Vec<u8> = codec::publish_packet(&message("a/b", "once", 2), 9).to_vec().unwrap();
+        let mut resent: Vec<u8> = publish.clone();
+        resent[0] |= 1 << 3;
+
+        // Both are answered with a PUBREC, as the first PUBREC may have been lost
+        broker.write_all(&publish).unwrap();
+        assert_eq!(codec::read_packet(&mut broker).unwrap(), Packet::Pubrec(Pubrec { packet_id: 9 }));
+        broker.write_all(&resent).unwrap();
+        assert_eq!(codec::read_packet(&mut broker).unwrap(), Packet::Pubrec(Pubrec { packet_id: 9 }));
+
+        assert_eq!(receive(&messages), Some(message("a/b", "once", 2)));
+        assert!(messages.recv_timeout(Duration::from_millis(300)).is_err());
+
+        // After the PUBREL the packet identifier is free again, for a new message
+        broker.write_all(&Packet::Pubrel(Pubrel { packet_id: 9 }).to_vec().unwrap()).unwrap();
+        assert_eq!(codec::read_packet(&mut broker).unwrap(), Packet::Pubcomp(Pubcomp { packet_id: 9 }));
+
+        broker.write_all(&codec::publish_packet(&message("a/b", "next", 2), 9).to_vec().unwrap()).unwrap();
+        assert_eq!(codec::read_packet(&mut broker).unwrap(), Packet::Pubrec(Pubrec { packet_id: 9 }));
+        assert_eq!(receive(&messages), Some(message("a/b", "next", 2)));
+
+        client.disconnect();
+    }
+}
@@ -0,0 +1,147 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{ RecvError, RecvTimeoutError, TryRecvError };
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::broker::Broker;
+    use crate::models::config::{ BrokerConfig, OutboundConfig, OverflowPolicy };
+    use crate::packet::{ Packet, Puback, Publish };
+    use crate::services::outbound::{ self, OutboundReceiver, OutboundSender, QueueDepth, SendError };
+    use crate::tests::scripted_client::{ publish_packet, start_broker, ScriptedClient };
+
+    fn publish(payload: &str, qos: u8) -> Vec<u8> {
+        let packet_id: Option<u16> = if qos > 0 { Some(1) } else { None };
+
+        Packet::Publish(Publish {
+            dup: false,
+            qos,
+            retain: false,
+            topic_name: "outbound".to_string(),
+            packet_id,
+            payload: payload.as_bytes().to_vec(),
+        })
+            .to_vec()
+            .unwrap()
+    }
+
+    fn payload_of(packet: Vec<u8>) -> String {
+        match crate::packet::decode(&packet).unwrap().0 {
+            Packet::Publish(publish) => String::from_utf8(publish.payload).unwrap(),
+            other => panic!("Expected a PUBLISH, got {:?}", other),
+        }
+    }
+
+    fn queue(max_messages: Option<usize>, max_bytes: Option<usize>, overflow: OverflowPolicy) -> (OutboundSender, OutboundReceiver) {
+        outbound::bounded(&OutboundConfig { max_messages, max_bytes, overflow })
+    }
+
+    #[test]
+    fn test_parse_outbound_config() {
+        let config: BrokerConfig = BrokerConfig::parse("[outbound]\nmax_messages = 500\noverflow = \"drop_new\"").unwrap();
+        assert_eq!(config.outbound.max_messages, Some(500));
+        assert_eq!(config.outbound.max_bytes, None);
+        assert_eq!(config.outbound.overflow, OverflowPolicy::DropNew);
+
+        // Unbounded by default
+        let config: BrokerConfig = BrokerConfig::parse("").unwrap();
+        assert_eq!(config.outbound.max_messages, None);
+        assert_eq!(config.outbound.overflow, OverflowPolicy::DropOldest);
+        assert!(BrokerConfig::parse("[outbound]\noverflow = \"block\"").is_err());
+    }
+
+    #[test]
+    fn test_drop_oldest_qos_0() {
+        let (tx, rx) = queue(Some(2), None, OverflowPolicy::DropOldest);
+        let puback: Vec<u8> = Packet::Puback(Puback { packet_id: 7 }).to_vec().unwrap();
+
+        assert_eq!(tx.send(Ok(puback.clone())), Ok(()));
+        assert_eq!(tx.send(Ok(publish("a", 0))), Ok(()));
+        assert_eq!(tx.send(Ok(publish("b", 1))), Ok(()));
+
+        // The oldest QoS 0 PUBLISH makes room, the PUBACK does not count against the limit
+        assert_eq!(tx.send(Ok(publish("c", 0))), Ok(()));
+        assert_eq!(tx.depth().messages, 2);
+
+        assert_eq!(tx.send(Ok(publish("d", 2))), Ok(()));
+
+        // Nothing left to drop, so the new PUBLISH is dropped
+        assert_eq!(tx.send(Ok(publish("e", 1))), Err(SendError::Dropped));
+
+        assert_eq!(rx.try_recv(), Ok(Ok(puback)));
+        assert_eq!(payload_of(rx.try_recv().unwrap().unwrap()), "b");
+        assert_eq!(payload_of(rx.try_recv().unwrap().unwrap()), "d");
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(tx.depth(), QueueDepth::default());
+    }
+
+    #[test]
+    fn test_drop_new_by_bytes() {
+        let first: Vec<u8> = publish("0123456789", 0);
+        let (tx, rx) = queue(None, Some(first.len() + 5), OverflowPolicy::DropNew);
+
+        assert_eq!(tx.send(Ok(first.clone())), Ok(()));
+        assert_eq!(tx.depth(), QueueDepth { messages: 1, bytes: first.len() });
+        assert_eq!(tx.send(Ok(publish("0123456789", 0))), Err(SendError::Dropped));
+
+        // Once written, there is room again
+        assert_eq!(rx.recv(), Ok(Ok(first)));
+        assert_eq!(tx.send(Ok(publish("later", 0))), Ok(()));
+        assert_eq!(payload_of(rx.recv().unwrap().unwrap()), "later");
+    }
+
+    #[test]
+    fn test_disconnect_on_overflow() {
+        let (tx, rx) = queue(Some(1), None, OverflowPolicy::Disconnect);
+
+        assert_eq!(tx.send(Ok(publish("a", 0))), Ok(()));
+        assert_eq!(tx.send(Ok(publish("b", 0))), Err(SendError::Overflow));
+
+        // The queued PUBLISH is not written, the write thread only closes the stream
+        assert_eq!(rx.recv(), Ok(Err("Outbound queue full".to_string())));
+        assert_eq!(tx.send(Ok(publish("c", 0))), Err(SendError::Closed));
+        assert_eq!(tx.depth(), QueueDepth::default());
+    }
+
+    #[test]
+    fn test_senders_and_receiver_dropped() {
+        let (tx, rx) = outbound::bounded(&OutboundConfig::default());
+        let other: OutboundSender = tx.clone();
+
+        // A receiver waiting for a packet is woken by another thread
+        let writer: thread::JoinHandle<()> = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            _ = other.send(Ok(vec![0xd0, 0x00]));
+        });
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(Ok(vec![0xd0, 0x00])));
+        writer.join().unwrap();
+
+        assert_eq!(rx.recv_timeout(Duration::from_millis(10)), Err(RecvTimeoutError::Timeout));
+
+        // Without senders, the receiver sees the queue is disconnected
+        drop(tx);
+        assert_eq!(rx.recv(), Err(RecvError));
+
+        let (tx, rx) = outbound::bounded(&OutboundConfig::default());
+        drop(rx);
+        assert_eq!(tx.send(Ok(vec![0xd0, 0x00])), Err(SendError::Closed));
+    }
+
+    #[test]
+    fn test_large_publish_is_written_whole() {
+        let broker: Broker = start_broker();
+
+        let mut subscriber: ScriptedClient = ScriptedClient::connected(&broker, "outbound-large-sub");
+        subscriber.subscribe("outbound/large", 0);
+
+        // Much more than a socket send buffer, so the write thread has to handle partial writes
+        let payload: String = "x".repeat(4 * 1024 * 1024);
+
+        let mut publisher: ScriptedClient = ScriptedClient::connected(&broker, "outbound-large-pub");
+        publisher.send(publish_packet("outbound/large", &payload, 0, false, 0));
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.payload.len(), payload.len());
+    }
+}
@@ -0,0 +1,292 @@
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+
+    use crate::packet::{
+        decode,
+        encode,
+        Connack,
+        Connect,
+        DecodeError,
+        LastWill,
+        Packet,
+        Puback,
+        Pubcomp,
+        Publish,
+        Pubrec,
+        Pubrel,
+        Suback,
+        Subscribe,
+        Unsuback,
+        Unsubscribe,
+    };
+
+    /// UTF-8 strings without null characters, the only strings a packet can carry.
+    fn string() -> impl Strategy<Value = String> {
+        "[^\u{0}]{0,20}"
+    }
+
+    fn packet_id() -> impl Strategy<Value = u16> {
+        1..=u16::MAX
+    }
+
+    fn connect() -> impl Strategy<Value = Packet> {
+        let will = (string(), vec(any::<u8>(), 0..20), 0..=2u8, any::<bool>()).prop_map(
+            |(topic, message, qos, retain)| LastWill { topic, message, qos, retain }
+        );
+
+        // A password is only allowed with a user name
+        let credentials = option::of((string(), option::of(vec(any::<u8>(), 0..20))));
+
+        (string(), any::<u8>(), any::<bool>(), any::<u16>(), string(), option::of(will), credentials).prop_map(
+            |(protocol_name, protocol_level, clean_session, keep_alive, client_id, will, credentials)| {
+                let (username, password) = match credentials {
+                    Some((username, password)) => (Some(username), password),
+                    None => (None, None),
+                };
+
+                Packet::Connect(Connect {
+                    protocol_name,
+                    protocol_level,
+                    clean_session,
+                    keep_alive,
+                    client_id,
+                    will,
+                    username,
+                    password,
+                })
+            }
+        )
+    }
+
+    fn publish() -> impl Strategy<Value = Packet> {
+        (0..=2u8, any::<bool>(), any::<bool>(), string(), packet_id(), vec(any::<u8>(), 0..300)).prop_map(
+            |(qos, dup, retain, topic_name, packet_id, payload)| {
+                // The DUP flag and the packet identifier are only used for QoS 1 and QoS 2
+                Packet::Publish(Publish {
+                    dup: dup && qos > 0,
+                    qos,
+                    retain,
+                    topic_name,
+                    packet_id: if qos > 0 { Some(packet_id) } else { None },
+                    payload,
+                })
+            }
+        )
+    }
+
+    fn any_packet() -> impl Strategy<Value = Packet> {
+        prop_oneof![
+            connect(),
+            (any::<bool>(), any::<u8>()).prop_map(|(session_present, return_code)|
+                Packet::Connack(Connack { session_present, return_code })
+            ),
+            publish(),
+            packet_id().prop_map(|packet_id| Packet::Puback(Puback { packet_id })),
+            packet_id().prop_map(|packet_id| Packet::Pubrec(Pubrec { packet_id })),
+            packet_id().prop_map(|packet_id| Packet::Pubrel(Pubrel { packet_id })),
+            packet_id().prop_map(|packet_id| Packet::Pubcomp(Pubcomp { packet_id })),
+            (packet_id(), vec((string(), any::<u8>()), 1..5)).prop_map(|(packet_id, topic_filters)|
+                Packet::Subscribe(Subscribe { packet_id, topic_filters })
+            ),
+            (packet_id(), vec(any::<u8>(), 0..5)).prop_map(|(packet_id, return_codes)|
+                Packet::Suback(Suback { packet_id, return_codes })
+            ),
+            (packet_id(), vec(string(), 1..5)).prop_map(|(packet_id, topic_filters)|
+                Packet::Unsubscribe(Unsubscribe { packet_id, topic_filters })
+            ),
+            packet_id().prop_map(|packet_id| Packet::Unsuback(Unsuback { packet_id })),
+            Just(Packet::Pingreq),
+            Just(Packet::Pingresp),
+            Just(Packet::Disconnect),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_round_trip(packet in any_packet()) {
+            let mut buffer: BytesMut = BytesMut::new();
+            encode(&packet, &mut buffer).unwrap();
+
+            prop_assert_eq!(buffer[0] >> 4, packet.packet_type());
+            prop_assert_eq!(decode(&buffer), Ok((packet, buffer.len())));
+        }
+
+        #[test]
+        fn test_round_trip_of_consecutive_packets(packets in vec(any_packet(), 1..5)) {
+            let mut buffer: BytesMut = BytesMut::new();
+
+            for packet in &packets {
+                encode(packet, &mut buffer).unwrap();
+            }
+
+            // Decode the packets one by one, as the broker does with the bytes of a connection
+            let mut decoded: Vec<Packet> = Vec::new();
+
+            while !buffer.is_empty() {
+                let (packet, length) = decode(&buffer).unwrap();
+                decoded.push(packet);
+                _ = buffer.split_to(length);
+            }
+
+            prop_assert_eq!(decoded, packets);
+        }
+
+        #[test]
+        fn test_every_prefix_is_incomplete(packet in any_packet()) {
+            let bytes: Vec<u8> = packet.to_vec().unwrap();
+
+            for length in 0..bytes.len() {
+                prop_assert_eq!(decode(&bytes[..length]), Err(DecodeError::Incomplete));
+            }
+        }
+
+        #[test]
+        fn test_decode_arbitrary_bytes(bytes in vec(any::<u8>(), 0..64)) {
+            // Never panics, and a decoded packet is never longer than the input
+            if let Ok((packet, length)) = decode(&bytes) {
+                prop_assert!(length <= bytes.len());
+
+                // A decoded packet can be encoded again, the Remaining Length may then take fewer bytes
+                let encoded: Vec<u8> = packet.to_vec().unwrap();
+                prop_assert_eq!(decode(&encoded), Ok((packet, encoded.len())));
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoded_bytes() {
+        // CONNECT with a user name and a clean session
+        let connect: Packet = Packet::Connect(Connect {
+            protocol_name: "MQTT".to_string(),
+            protocol_level: 4,
+            clean_session: true,
+            keep_alive: 10,
+            client_id: "c".to_string(),
+            will: None,
+            username: Some("u".to_string()),
+            password: None,
+        });
+        assert_eq!(
+            connect.to_vec().unwrap(),
+            vec![0x10, 16, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b1000_0010, 0, 10, 0, 1, b'c', 0, 1, b'u']
+        );
+
+        assert_eq!(
+            Packet::Subscribe(Subscribe { packet_id: 1, topic_filters: vec![("a/#".to_string(), 1)] }).to_vec().unwrap(),
+            vec![0x82, 8, 0, 1, 0, 3, b'a', b'/', b'#', 1]
+        );
+        assert_eq!(
+            Packet::Unsubscribe(Unsubscribe { packet_id: 2, topic_filters: vec!["a".to_string()] }).to_vec().unwrap(),
+            vec![0xa2, 5, 0, 2, 0, 1, b'a']
+        );
+        assert_eq!(Packet::Pubrel(Pubrel { packet_id: 258 }).to_vec().unwrap(), vec![0x62, 2, 1, 2]);
+        assert_eq!(Packet::Suback(Suback { packet_id: 1, return_codes: vec![0, 0x80] }).to_vec().unwrap(), vec![0x90, 4, 0, 1, 0, 0x80]);
+        assert_eq!(Packet::Connack(Connack { session_present: true, return_code: 0 }).to_vec().unwrap(), vec![0x20, 2, 1, 0]);
+        assert_eq!(Packet::Disconnect.to_vec().unwrap(), vec![0xe0, 0]);
+    }
+
+    #[test]
+    fn test_remaining_length() {
+        // A payload over 127 bytes needs a two byte Remaining Length
+        let publish: Packet = Packet::Publish(Publish {
+            dup: false,
+            qos: 0,
+            retain: false,
+            topic_name: "a".to_string(),
+            packet_id: None,
+            payload: vec![b'x'; 200],
+        });
+        let bytes: Vec<u8> = publish.to_vec().unwrap();
+        assert_eq!(&bytes[..3], &[0x30, 0xcb, 0x01]);
+        assert_eq!(decode(&bytes), Ok((publish, bytes.len())));
+
+        // The Remaining Length is only known once its last byte has arrived
+        assert_eq!(decode(&[0x30]), Err(DecodeError::Incomplete));
+        assert_eq!(decode(&[0x30, 0xff, 0xff, 0xff]), Err(DecodeError::Incomplete));
+
+        // The largest Remaining Length, the packet is incomplete but the header is valid
+        assert_eq!(decode(&[0x30, 0xff, 0xff, 0xff, 0x7f]), Err(DecodeError::Incomplete));
+
+        // A fifth byte is malformed
+        assert_eq!(decode(&[0x30, 0x80, 0x80, 0x80, 0x80, 0x01]), Err(DecodeError::MalformedRemainingLength));
+    }
+
+    #[test]
+    fn test_invalid_fixed_header() {
+        assert_eq!(decode(&[0x00, 0]), Err(DecodeError::InvalidPacketType(0)));
+        assert_eq!(decode(&[0xf0, 0]), Err(DecodeError::InvalidPacketType(15)));
+
+        // PUBREL, SUBSCRIBE and UNSUBSCRIBE MUST have the flags 0b0010
+        assert_eq!(decode(&[0x60, 2, 0, 1]), Err(DecodeError::InvalidFlags(0x60)));
+        assert_eq!(decode(&[0x80, 6, 0, 1, 0, 1, b'a', 0]), Err(DecodeError::InvalidFlags(0x80)));
+        assert_eq!(decode(&[0x41, 2, 0, 1]), Err(DecodeError::InvalidFlags(0x41)));
+
+        // PUBLISH with QoS 3, or with the DUP flag at QoS 0
+        assert_eq!(decode(&[0x36, 3, 0, 1, b'a']), Err(DecodeError::InvalidFlags(0x36)));
+        assert_eq!(decode(&[0x38, 3, 0, 1, b'a']), Err(DecodeError::InvalidFlags(0x38)));
+    }
+
+    #[test]
+    fn test_malformed_packets() {
+        let malformed = |bytes: &[u8]| matches!(decode(bytes), Err(DecodeError::MalformedPacket(_)));
+
+        // A packet identifier MUST be non-zero
+        assert!(malformed(&[0x40, 2, 0, 0]));
+        assert!(malformed(&[0x32, 5, 0, 1, b'a', 0, 0]));
+
+        // Acknowledgements have exactly two bytes
+        assert!(malformed(&[0x40, 3, 0, 1, 0]));
+        assert!(malformed(&[0x40, 1, 0]));
+
+        // SUBSCRIBE and UNSUBSCRIBE need at least one topic filter
+        assert!(malformed(&[0x82, 2, 0, 1]));
+        assert!(malformed(&[0xa2, 2, 0, 1]));
+
+        // A topic filter without its QoS
+        assert!(malformed(&[0x82, 5, 0, 1, 0, 1, b'a']));
+
+        // A string longer than the packet
+        assert!(malformed(&[0x30, 3, 0, 5, b'a']));
+
+        // Invalid UTF-8, and a null character
+        assert!(malformed(&[0x30, 3, 0, 1, 0xff]));
+        assert!(malformed(&[0x30, 3, 0, 1, 0x00]));
+
+        // CONNECT with a password but no user name
+        assert!(malformed(&[0x10, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b0100_0010, 0, 10, 0, 1, b'c']));
+
+        // CONNECT with a will QoS but no will
+        assert!(malformed(&[0x10, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b0000_1010, 0, 10, 0, 1, b'c']));
+    }
+
+    #[test]
+    fn test_encode_errors() {
+        let mut buffer: BytesMut = BytesMut::new();
+
+        // A string longer than its two byte length prefix
+        let long_topic: Packet = Packet::Unsubscribe(Unsubscribe { packet_id: 1, topic_filters: vec!["a".repeat(0x10000)] });
+        assert!(encode(&long_topic, &mut buffer).is_err());
+
+        // A PUBLISH with QoS 3, or without a packet identifier at QoS 1
+        let mut publish: Publish = Publish {
+            dup: false,
+            qos: 3,
+            retain: false,
+            topic_name: "a".to_string(),
+            packet_id: Some(1),
+            payload: Vec::new(),
+        };
+        assert!(encode(&Packet::Publish(publish.clone()), &mut buffer).is_err());
+
+        publish.qos = 1;
+        publish.packet_id = None;
+        assert!(encode(&Packet::Publish(publish), &mut buffer).is_err());
+
+        // The buffer is left unchanged
+        assert!(buffer.is_empty());
+    }
+}
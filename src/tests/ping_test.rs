@@ -1,112 +1,83 @@
 #[cfg(test)]
-mod ping_test{
-    use crate::control_packet;
-
-    //use super::*;
+mod tests {
+    use crate::packet::{ self, DecodeError, Packet };
 
     #[test]
     fn handle_validping_returnsbytearray()
     {
         //arrange
-        let mut package: [u8; 8192] = [0; 8192];
-        package[0] = 192;
-        package[1] = 0;
-        let length:usize = 2;
+        let package: [u8; 2] = [192, 0];
         //act
-        let result: Result<[u8; 2], &str> = control_packet::ping::handle(package, length);
+        let result = packet::decode(&package);
         //assert
-        assert_eq!(result, Ok([208, 0]));
-        
+        assert_eq!(result, Ok((Packet::Pingreq, 2)));
+        assert_eq!(Packet::Pingresp.to_vec(), Ok(vec![208, 0]));
     }
 
     #[test]
     fn handle_reservedbit3set_returnserr()
     {
         //arrange
-        let mut package: [u8; 8192] = [0; 8192];
-        package[0] = 200;
-        package[1] = 0;
-        let length:usize = 2;
+        let package: [u8; 2] = [200, 0];
         //act
-        let result: Result<[u8; 2], &str> = control_packet::ping::handle(package, length);
+        let result = packet::decode(&package);
         //assert
-        assert_eq!(result, Err("Reserved bits MUST not be set"));
-        
+        assert_eq!(result, Err(DecodeError::InvalidFlags(200)));
     }
 
     #[test]
     fn handle_reservedbit2set_returnserr()
     {
         //arrange
-        let mut package: [u8; 8192] = [0; 8192];
-        package[0] = 196;
-        package[1] = 0;
-        let length:usize = 2;
+        let package: [u8; 2] = [196, 0];
         //act
-        let result: Result<[u8; 2], &str> = control_packet::ping::handle(package, length);
+        let result = packet::decode(&package);
         //assert
-        assert_eq!(result, Err("Reserved bits MUST not be set"));
-        
+        assert_eq!(result, Err(DecodeError::InvalidFlags(196)));
     }
 
     #[test]
     fn handle_reservedbit1set_returnserr()
     {
         //arrange
-        let mut package: [u8; 8192] = [0; 8192];
-        package[0] = 194;
-        package[1] = 0;
-        let length:usize = 2;
+        let package: [u8; 2] = [194, 0];
         //act
-        let result: Result<[u8; 2], &str> = control_packet::ping::handle(package, length);
+        let result = packet::decode(&package);
         //assert
-        assert_eq!(result, Err("Reserved bits MUST not be set"));
-        
+        assert_eq!(result, Err(DecodeError::InvalidFlags(194)));
     }
 
     #[test]
     fn handle_reservedbit0set_returnserr()
     {
         //arrange
-        let mut package: [u8; 8192] = [0; 8192];
-        package[0] = 193;
-        package[1] = 0;
-        let length:usize = 2;
+        let package: [u8; 2] = [193, 0];
         //act
-        let result = control_packet::ping::handle(package, length);
+        let result = packet::decode(&package);
         //assert
-        assert_eq!(result, Err("Reserved bits MUST not be set"));
-        
+        assert_eq!(result, Err(DecodeError::InvalidFlags(193)));
     }
 
     #[test]
     fn handle_bytearray3long_returnserr()
     {
         //arrange
-        let mut package: [u8; 8192] = [0; 8192];
-        package[0] = 192;
-        package[1] = 1;
-        package[2] = 1;
-        let length:usize = 2;
+        let package: [u8; 3] = [192, 1, 1];
         //act
-        let result = control_packet::ping::handle(package, length);
+        let result = packet::decode(&package);
         //assert
-        assert_eq!(result, Err("No payload expected here"));
-        
+        assert!(matches!(result, Err(DecodeError::MalformedPacket(_))));
     }
 
     #[test]
-    fn handle_lengthis3_returnserr()
+    fn handle_lengthis3_decodesfirstpacket()
     {
         //arrange
-        let mut package: [u8; 8192] = [0; 8192];
-        package[0] = 192;
-        package[1] = 0;
-        let length:usize = 3;
+        // The third byte is the start of the next packet
+        let package: [u8; 3] = [192, 0, 192];
         //act
-        let result = control_packet::ping::handle(package, length);
+        let result = packet::decode(&package);
         //assert
-        assert_eq!(result, Err("No payload expected here"));
-        
+        assert_eq!(result, Ok((Packet::Pingreq, 2)));
     }
-}
\ No newline at end of file
+}
@@ -1,20 +1,22 @@
 #[cfg(test)]
 mod tests {
-    use crate::control_packet::publish::Response;
-    use crate::models::client::Client;
+    use crate::control_packet::publish::{ handle_publish, Response };
+    use crate::control_packet::ProtocolError;
+    use crate::packet::Publish;
+    use crate::models::client::{ Client, Clients };
     use crate::models::flags::ConnectFlags;
-    use crate::models::publish_queue_item::PublishItemState;
-    use crate::models::topic::Topic;
+    use crate::models::publish_queue_item::{ PublishItemDirection, PublishItemState, PublishQueue, PublishQueueKey };
+    use crate::models::topic::{ Topic, Topics };
+    use crate::services::outbound;
     use crate::{handle_qos_1_session, handle_qos_2_session};
     use std::net::SocketAddr;
-    use std::sync::mpsc::{channel, Receiver, Sender};
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
     use std::thread::sleep;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_handle_qos_1_session() {
-        let clients = Arc::new(Mutex::new(Vec::new()));
+        let clients = Arc::new(Clients::new());
 
         let connect_flags = ConnectFlags {
             username_flag: true,
@@ -26,10 +28,7 @@ mod tests {
         };
 
         let socket_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-        let (tx, rx): (
-            Sender<Result<Vec<u8>, String>>,
-            Receiver<Result<Vec<u8>, String>>,
-        ) = channel();
+        let (tx, rx) = outbound::channel();
         let client = Client::new(
             "client_id".to_string(),
             "will_topic".to_string(),
@@ -42,16 +41,14 @@ mod tests {
             connect_flags,
         );
 
-        clients.lock().unwrap().push(client);
-        let topics = Arc::new(Mutex::new(vec![
-            Topic {
-                topic_name: "topic1".to_string(),
-                retained_msg: (String::new(), 0),
-                client_ids: vec![("client1".to_string(), 0)],
-            },
-            // Add more topics if needed...
-        ]));
-        let publish_queue = Arc::new(Mutex::new(Vec::new()));
+        clients.insert(client);
+        let topics = Arc::new(Topics::new());
+        topics.insert(Topic {
+            topic_name: "topic1".to_string(),
+            retained_msg: (String::new(), 0),
+            client_ids: vec![("client1".to_string(), 0)],
+        });
+        let publish_queue = Arc::new(PublishQueue::new());
         let response = Response {
             dup_flag: false,
             qos_level: 1,
@@ -59,6 +56,7 @@ mod tests {
             packet_id: 10,
             topic_name: "test".to_string(),
             payload_message: "test".to_string(),
+            received_at: Instant::now(),
             // Fill in the fields of the Response struct
             // ...
         };
@@ -96,12 +94,14 @@ mod tests {
             packet_id: 10,
             topic_name: "test".to_string(),
             payload_message: "test".to_string(),
+            received_at: Instant::now(),
             // Fill in the fields of the Response struct
             // ...
         };
-        let clients = Arc::new(Mutex::new(Vec::new()));
-        let topics = Arc::new(Mutex::new(Vec::new()));
-        let publish_queue = Arc::new(Mutex::new(Vec::new()));
+        let clients = Arc::new(Clients::new());
+        let topics = Arc::new(Topics::new());
+        let publish_queue = Arc::new(PublishQueue::new());
+        let key = PublishQueueKey::new("client_id", PublishItemDirection::FromClient, 10);
 
         let connect_flags = ConnectFlags {
             username_flag: true,
@@ -113,10 +113,7 @@ mod tests {
         };
 
         let socket_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-        let (tx, rx): (
-            Sender<Result<Vec<u8>, String>>,
-            Receiver<Result<Vec<u8>, String>>,
-        ) = channel();
+        let (tx, rx) = outbound::channel();
         let client = Client::new(
             "client_id".to_string(),
             "will_topic".to_string(),
@@ -129,13 +126,20 @@ mod tests {
             connect_flags,
         );
 
-        clients.lock().unwrap().push(client);
+        clients.insert(client);
 
-        handle_qos_2_session(tx.clone(), response, clients, topics, publish_queue.clone());
+        handle_qos_2_session(
+            tx.clone(),
+            "client_id".to_string(),
+            response,
+            clients,
+            topics,
+            publish_queue.clone()
+        );
 
         sleep(Duration::from_millis(100));
         // Check that the publish queue is not empty (since QoS is 2)
-        assert!(!publish_queue.lock().unwrap().is_empty());
+        assert!(publish_queue.contains(&key));
 
         let received_packet = rx.try_recv();
         assert!(received_packet.is_ok());
@@ -144,18 +148,42 @@ mod tests {
 
         assert_eq!(received_packet.unwrap(), Ok(expected_packet));
         assert_eq!(
-            publish_queue.lock().unwrap()[0].state,
-            PublishItemState::AwaitingPubrel
+            publish_queue.with(&key, |item| item.state == PublishItemState::AwaitingPubrel),
+            Some(true)
         );
 
-        _ = publish_queue.lock().unwrap()[0]
-            .tx
-            .send(PublishItemState::PubrelRecieved);
+        // The same packet id from another client is another flow
+        assert!(!publish_queue.notify(
+            &PublishQueueKey::new("other_client", PublishItemDirection::FromClient, 10),
+            PublishItemState::PubrelRecieved
+        ));
+
+        assert!(publish_queue.notify(&key, PublishItemState::PubrelRecieved));
 
         sleep(Duration::from_millis(1200));
         // Check that the publish queue is not empty after "broker" has received Pubrec
 
         // Check that the publish queue is empty after "broker" has received Pubcomp
-        assert!(publish_queue.lock().unwrap().is_empty());
+        assert!(publish_queue.is_empty());
+    }
+
+    #[test]
+    fn test_handle_publish_wildcard_topic() {
+        let publish = |topic_name: &str| Publish {
+            dup: false,
+            qos: 1,
+            retain: false,
+            topic_name: topic_name.to_string(),
+            packet_id: Some(1),
+            payload: b"on".to_vec(),
+        };
+
+        let response = handle_publish(publish("a/b")).ok().unwrap();
+        assert_eq!(response.topic_name, "a/b");
+        assert_eq!(response.packet_id, 1);
+
+        // The topic name of a PUBLISH MUST NOT contain wildcards, the connection is closed
+        assert!(matches!(handle_publish(publish("a/+")), Err(ProtocolError::ProtocolViolation(_))));
+        assert!(matches!(handle_publish(publish("a/#")), Err(ProtocolError::ProtocolViolation(_))));
     }
 }
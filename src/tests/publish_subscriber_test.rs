@@ -2,17 +2,23 @@
 mod tests {
 
     use std::net::SocketAddr;
-    use std::sync::mpsc::{channel, Receiver, Sender};
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
 
     use std::thread::sleep;
-    use std::time::Duration;
-    use crate::common_fn;
+    use std::time::{ Duration, Instant };
     use crate::control_packet::publish::publish_to_client;
     use crate::models::client::Client;
     use crate::models::flags::ConnectFlags;
-    use crate::models::publish_queue_item::PublishItemState;
+    use crate::models::publish_queue_item::{
+        PublishItemDirection,
+        PublishItemState,
+        PublishQueue,
+        PublishQueueItem,
+        PublishQueueKey,
+    };
     use crate::models::topic::Topic;
+    use crate::services::outbound;
 
     #[test]
     fn test_publish_to_client() {
@@ -27,10 +33,7 @@ mod tests {
 
         let socket_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
         // Create a mock client with a receiver so we can check the messages sent to it
-        let (tx, rx): (
-            Sender<Result<Vec<u8>, String>>,
-            Receiver<Result<Vec<u8>, String>>,
-        ) = channel();
+        let (tx, rx) = outbound::channel();
         let client = Client::new(
             "client_id".to_string(),
             "will_topic".to_string(),
@@ -44,7 +47,7 @@ mod tests {
         );
 
         // Create a mock publish queue
-        let publish_queue = Arc::new(Mutex::new(Vec::new()));
+        let publish_queue = Arc::new(PublishQueue::new());
 
         // Create a new Topic
         let topic = Topic {
@@ -57,14 +60,14 @@ mod tests {
         publish_to_client(
             &client,
             publish_queue.clone(),
-            &topic,
+            &topic.topic_name,
             "test",
             &0,
             &false,
         );
 
         // Check that the publish queue is still empty (since QoS is 0)
-        assert!(publish_queue.lock().unwrap().is_empty());
+        assert!(publish_queue.is_empty());
 
         // Check that the client received the correct packet
         // This will depend on how your publish_to_client function constructs the packet
@@ -97,10 +100,7 @@ fn test_publish_to_client_qos_1() {
     };
 
     let socket_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-    let (tx, rx): (
-        Sender<Result<Vec<u8>, String>>,
-        Receiver<Result<Vec<u8>, String>>,
-    ) = channel();
+    let (tx, rx) = outbound::channel();
     let client = Client::new(
         "client_id".to_string(),
         "will_topic".to_string(),
@@ -113,7 +113,7 @@ fn test_publish_to_client_qos_1() {
         connect_flags,
     );
 
-    let publish_queue = Arc::new(Mutex::new(Vec::new()));
+    let publish_queue = Arc::new(PublishQueue::new());
 
     let topic = Topic {
         topic_name: "test".to_string(),
@@ -124,7 +124,7 @@ fn test_publish_to_client_qos_1() {
     publish_to_client(
         &client,
         publish_queue.clone(),
-        &topic,
+        &topic.topic_name,
         "test",
         &1,
         &false,
@@ -133,12 +133,13 @@ fn test_publish_to_client_qos_1() {
 
     sleep(Duration::from_millis(100));
     // Check that the publish queue is not empty (since QoS is 1)
-    assert!(!publish_queue.lock().unwrap().is_empty());
+    assert!(!publish_queue.is_empty());
+    let key: PublishQueueKey = publish_queue.keys()[0].clone();
 
     let received_packet = rx.try_recv();
     assert!(received_packet.is_ok());
 
-    let packet_id = common_fn::msb_lsb_creater::split_into_msb_lsb(publish_queue.lock().unwrap()[0].packet_id);
+    let packet_id = (key.packet_id as u16).to_be_bytes();
 
     let mut expected_packet = vec![
         0b00110010, // Publish packet, QoS level 1, no retain
@@ -157,12 +158,12 @@ fn test_publish_to_client_qos_1() {
 
     assert_eq!(received_packet.unwrap(), Ok(expected_packet));
 
-    _ = publish_queue.lock().unwrap()[0].tx.send(PublishItemState::PubackRecieved);
+    assert!(publish_queue.notify(&key, PublishItemState::PubackRecieved));
 
 
     sleep(Duration::from_millis(1000));
     // Check that the publish queue is empty after "broker" has recieved Puback
-    assert!(publish_queue.lock().unwrap().is_empty());
+    assert!(publish_queue.is_empty());
 
 }
 
@@ -178,10 +179,7 @@ fn test_publish_to_client_qos_2() {
     };
 
     let socket_addr = SocketAddr::from(([127, 0, 0, 1], 8080));
-    let (tx, rx): (
-        Sender<Result<Vec<u8>, String>>,
-        Receiver<Result<Vec<u8>, String>>,
-    ) = channel();
+    let (tx, rx) = outbound::channel();
     let client = Client::new(
         "client_id".to_string(),
         "will_topic".to_string(),
@@ -194,7 +192,7 @@ fn test_publish_to_client_qos_2() {
         connect_flags,
     );
 
-    let publish_queue = Arc::new(Mutex::new(Vec::new()));
+    let publish_queue = Arc::new(PublishQueue::new());
 
     let topic = Topic {
         topic_name: "test".to_string(),
@@ -205,7 +203,7 @@ fn test_publish_to_client_qos_2() {
     publish_to_client(
         &client,
         publish_queue.clone(),
-        &topic,
+        &topic.topic_name,
         "test",
         &2,
         &false,
@@ -213,12 +211,13 @@ fn test_publish_to_client_qos_2() {
 
     sleep(Duration::from_millis(100));
     // Check that the publish queue is not empty (since QoS is 2)
-    assert!(!publish_queue.lock().unwrap().is_empty());
+    assert!(!publish_queue.is_empty());
+    let key: PublishQueueKey = publish_queue.keys()[0].clone();
 
     let received_packet = rx.try_recv();
     assert!(received_packet.is_ok());
 
-    let packet_id = common_fn::msb_lsb_creater::split_into_msb_lsb(publish_queue.lock().unwrap()[0].packet_id);
+    let packet_id = (key.packet_id as u16).to_be_bytes();
 
     let mut expected_packet = vec![
         0b00110100, // Publish packet, QoS level 2, no retain
@@ -236,16 +235,49 @@ fn test_publish_to_client_qos_2() {
 
     assert_eq!(received_packet.unwrap(), Ok(expected_packet));
 
-    _ = publish_queue.lock().unwrap()[0].tx.send(PublishItemState::PubrecRecieved);
+    assert!(publish_queue.notify(&key, PublishItemState::PubrecRecieved));
 
     sleep(Duration::from_millis(1200));
     // Check that the publish queue is not empty after "broker" has received Pubrec
-    assert_eq!(publish_queue.lock().unwrap()[0].state, PublishItemState::PubrecRecieved);
+    assert_eq!(publish_queue.with(&key, |item| item.state == PublishItemState::PubrecRecieved), Some(true));
 
-    _ = publish_queue.lock().unwrap()[0].tx.send(PublishItemState::PubcompRecieved);
+    assert!(publish_queue.notify(&key, PublishItemState::PubcompRecieved));
 
     sleep(Duration::from_millis(1000));
     // Check that the publish queue is empty after "broker" has received Pubcomp
-    assert!(publish_queue.lock().unwrap().is_empty());
+    assert!(publish_queue.is_empty());
+}
+
+#[test]
+fn test_next_packet_id() {
+    let publish_queue: PublishQueue = PublishQueue::new();
+
+    let push = |client_id: &str, flow_direction: PublishItemDirection, packet_id: usize| {
+        let (tx, _rx) = channel::<PublishItemState>();
+        assert!(publish_queue.push(PublishQueueItem {
+            client_id: client_id.to_string(),
+            packet_id,
+            timestamp_sent: Instant::now(),
+            publish_packet: vec![],
+            state: PublishItemState::AwaitingPuback,
+            qos_level: 1,
+            flow_direction,
+            tx,
+        }));
+    };
+
+    // Each client has its own packet ids, handed out in turn
+    assert_eq!(publish_queue.next_packet_id("a"), Some(1));
+    assert_eq!(publish_queue.next_packet_id("a"), Some(2));
+    assert_eq!(publish_queue.next_packet_id("b"), Some(1));
+
+    // An id in flight to the client is skipped, an id the client publishes with is not
+    push("a", PublishItemDirection::ToSubscriber, 3);
+    push("a", PublishItemDirection::FromClient, 4);
+    assert_eq!(publish_queue.next_packet_id("a"), Some(4));
+
+    // The ids start over when the session is removed
+    publish_queue.remove_client("a");
+    assert_eq!(publish_queue.next_packet_id("a"), Some(1));
 }
 }
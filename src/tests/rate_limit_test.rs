@@ -0,0 +1,200 @@
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{ Duration, Instant };
+
+    use crate::models::config::{ BrokerConfig, LimitAction, LimitsConfig };
+    use crate::services::metrics::{ Gauges, Metrics };
+    use crate::services::rate_limit::{ ClientLimiter, ConnectionLimiter, Decision, Limit, TokenBucket };
+
+    const CONFIG: &str = r#"
+[limits]
+connections_per_second = 2
+connection_burst = 3
+publishes_per_second = 10
+bytes_per_second = 1000
+bytes_burst = 2000
+max_subscriptions = 5
+action = "drop"
+"#;
+
+    fn limits(publishes_per_second: Option<f64>, bytes_per_second: Option<f64>, action: LimitAction) -> LimitsConfig {
+        LimitsConfig { publishes_per_second, bytes_per_second, action, ..LimitsConfig::default() }
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let config: BrokerConfig = BrokerConfig::parse(CONFIG).unwrap();
+
+        assert_eq!(config.limits.connections_per_second, Some(2.0));
+        assert_eq!(config.limits.connection_burst, Some(3.0));
+        assert_eq!(config.limits.publishes_per_second, Some(10.0));
+        assert_eq!(config.limits.publish_burst, None);
+        assert_eq!(config.limits.bytes_burst, Some(2000.0));
+        assert_eq!(config.limits.max_subscriptions, Some(5));
+        assert_eq!(config.limits.action, LimitAction::Drop);
+
+        // Without the section nothing is limited
+        let config: BrokerConfig = BrokerConfig::parse("").unwrap();
+
+        assert_eq!(config.limits.publishes_per_second, None);
+        assert_eq!(config.limits.max_subscriptions, None);
+        assert_eq!(config.limits.action, LimitAction::Throttle);
+
+        assert!(BrokerConfig::parse("[limits]\naction = \"ignore\"").is_err());
+
+        // A rate or burst that is not positive is refused
+        assert!(BrokerConfig::parse("[limits]\npublishes_per_second = 0").is_err());
+        assert!(BrokerConfig::parse("[limits]\nbytes_per_second = -100").is_err());
+        assert!(BrokerConfig::parse("[limits]\nconnection_burst = 0").is_err());
+        assert!(BrokerConfig::parse("[limits]\nconnections_per_second = nan").is_err());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let start: Instant = Instant::now();
+        let mut bucket: TokenBucket = TokenBucket::new(2.0, Some(4.0), start);
+
+        // A full bucket allows the burst, then nothing until it refills
+        for _ in 0..4 {
+            assert!(bucket.try_take(1.0, start));
+        }
+        assert!(!bucket.try_take(1.0, start));
+        assert!(!bucket.is_full(start));
+
+        // Two tokens per second
+        assert!(!bucket.try_take(1.0, start + Duration::from_millis(400)));
+        assert!(bucket.try_take(1.0, start + Duration::from_millis(500)));
+
+        // It never holds more than the burst
+        assert!(bucket.is_full(start + Duration::from_secs(60)));
+        for _ in 0..4 {
+            assert!(bucket.try_take(1.0, start + Duration::from_secs(60)));
+        }
+        assert!(!bucket.try_take(1.0, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_token_bucket_larger_than_burst() {
+        let start: Instant = Instant::now();
+        let mut bucket: TokenBucket = TokenBucket::new(100.0, None, start);
+
+        // An amount larger than the burst is taken from a full bucket, leaving it in debt
+        assert!(bucket.try_take(250.0, start));
+        assert!(!bucket.try_take(1.0, start + Duration::from_secs(1)));
+        assert!(bucket.try_take(1.0, start + Duration::from_millis(1510)));
+    }
+
+    #[test]
+    fn test_token_bucket_take() {
+        let start: Instant = Instant::now();
+        let mut bucket: TokenBucket = TokenBucket::new(10.0, Some(1.0), start);
+
+        assert_eq!(bucket.take(1.0, start), Duration::ZERO);
+
+        // Every token taken beyond the bucket adds a tenth of a second to the wait
+        let wait: Duration = bucket.take(1.0, start);
+        assert!(wait > Duration::from_millis(99) && wait <= Duration::from_millis(100));
+
+        let wait: Duration = bucket.take(1.0, start);
+        assert!(wait > Duration::from_millis(199) && wait <= Duration::from_millis(200));
+
+        // A bucket that is never refilled waits forever instead of panicking
+        let mut bucket: TokenBucket = TokenBucket::new(0.0, Some(1.0), start);
+        assert_eq!(bucket.take(1.0, start), Duration::ZERO);
+        assert_eq!(bucket.take(1.0, start), Duration::MAX);
+
+        let mut bucket: TokenBucket = TokenBucket::new(-1.0, Some(1.0), start);
+        assert_eq!(bucket.take(2.0, start), Duration::MAX);
+    }
+
+    #[test]
+    fn test_connection_limiter() {
+        let start: Instant = Instant::now();
+        let mut limiter: ConnectionLimiter = ConnectionLimiter::new(1.0, Some(2.0));
+        let device: IpAddr = "192.168.1.20".parse().unwrap();
+        let other: IpAddr = "192.168.1.21".parse().unwrap();
+
+        assert!(limiter.allow(device, start));
+        assert!(limiter.allow(device, start));
+        assert!(!limiter.allow(device, start));
+
+        // Each address has its own bucket
+        assert!(limiter.allow(other, start));
+
+        assert!(limiter.allow(device, start + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_client_limiter_unlimited() {
+        let start: Instant = Instant::now();
+        let mut limiter: ClientLimiter = ClientLimiter::new(&LimitsConfig::default(), start);
+
+        for _ in 0..10_000 {
+            assert_eq!(limiter.check_publish(1_000_000, start), Decision::Allow);
+        }
+    }
+
+    #[test]
+    fn test_client_limiter_throttle() {
+        let start: Instant = Instant::now();
+        let mut limiter: ClientLimiter = ClientLimiter::new(&limits(Some(10.0), None, LimitAction::Throttle), start);
+
+        for _ in 0..10 {
+            assert_eq!(limiter.check_publish(100, start), Decision::Allow);
+        }
+
+        // Over the limit the client waits, longer for every PUBLISH it sends meanwhile
+        let Decision::Wait(first) = limiter.check_publish(100, start) else {
+            panic!("Expected a wait");
+        };
+        let Decision::Wait(second) = limiter.check_publish(100, start) else {
+            panic!("Expected a wait");
+        };
+        assert!(second > first);
+
+        // The bytes limit throttles as well, the wait is the longer of the two
+        let mut limiter: ClientLimiter = ClientLimiter::new(&limits(Some(1000.0), Some(1000.0), LimitAction::Throttle), start);
+
+        assert_eq!(limiter.check_publish(1000, start), Decision::Allow);
+        assert_eq!(limiter.check_publish(500, start), Decision::Wait(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_client_limiter_drop() {
+        let start: Instant = Instant::now();
+        let mut limiter: ClientLimiter = ClientLimiter::new(&limits(Some(2.0), Some(1000.0), LimitAction::Drop), start);
+
+        assert_eq!(limiter.check_publish(100, start), Decision::Allow);
+        assert_eq!(limiter.check_publish(100, start), Decision::Allow);
+        assert_eq!(limiter.check_publish(100, start), Decision::Drop);
+
+        // A dropped PUBLISH is not counted, so the bytes limit is untouched by it
+        let later: Instant = start + Duration::from_secs(1);
+        assert_eq!(limiter.check_publish(800, later), Decision::Allow);
+        assert_eq!(limiter.check_publish(800, later), Decision::Drop);
+    }
+
+    #[test]
+    fn test_client_limiter_disconnect() {
+        let start: Instant = Instant::now();
+        let mut limiter: ClientLimiter = ClientLimiter::new(&limits(None, Some(100.0), LimitAction::Disconnect), start);
+
+        assert_eq!(limiter.check_publish(100, start), Decision::Allow);
+        assert_eq!(limiter.check_publish(1, start), Decision::Disconnect);
+    }
+
+    #[test]
+    fn test_rate_limited_metric() {
+        let metrics: Metrics = Metrics::new();
+        metrics.rate_limited(Limit::Publishes);
+        metrics.rate_limited(Limit::Publishes);
+        metrics.rate_limited(Limit::Subscriptions);
+
+        let output: String = metrics.render(&Gauges { clients: 0, clients_connected: 0, topics: 0, publish_queue: 0, outbound_messages: 0, outbound_bytes: 0 });
+
+        assert!(output.contains("mqtt_rate_limited_total{limit=\"connections\"} 0\n"));
+        assert!(output.contains("mqtt_rate_limited_total{limit=\"publishes\"} 2\n"));
+        assert!(output.contains("mqtt_rate_limited_total{limit=\"subscriptions\"} 1\n"));
+    }
+}
@@ -0,0 +1,193 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{ TcpListener, TcpStream };
+    use std::time::Duration;
+
+    use serde_json::{ json, Value };
+
+    use crate::broker::Broker;
+    use crate::common_fn::{ http, topic_filter };
+    use crate::models::config::{ BrokerConfig, RuleAction };
+    use crate::packet::Publish;
+    use crate::services::hooks::Message;
+    use crate::services::rules::RuleSet;
+    use crate::tests::scripted_client::{ start_broker, wait_for_subscriber, ScriptedClient };
+
+    const CONFIG: &str = r#"
+        [[rule]]
+        name = "fahrenheit"
+        topic = "raw/+/temp"
+        conditions = [{ field = "unit", equals = "F" }, { field = "value", greater_than = -100 }]
+        action = "republish"
+        target = "normalized/{1}/temperature"
+        convert = [{ field = "value", multiply = 0.5555556, add = -17.777778, decimals = 1 }]
+        set = { unit = "C", "meta.device" = "{1}", "meta.source" = "{topic}" }
+
+        [[rule]]
+        name = "noise"
+        topic = "raw/#"
+        conditions = [{ field = "debug", exists = true }]
+        action = "drop"
+
+        [[rule]]
+        name = "copy"
+        topic = "raw/+/status"
+        action = "republish"
+        target = "status/{client_id}/{1}"
+        qos = 0
+        retain = true
+    "#;
+
+    fn rules() -> RuleSet {
+        RuleSet::new(&BrokerConfig::parse(CONFIG).unwrap().rules).unwrap()
+    }
+
+    fn message(topic: &str, payload: &str) -> Message {
+        Message { topic: topic.to_string(), payload: payload.to_string(), qos: 1, retain: false }
+    }
+
+    #[test]
+    fn test_parse_rule_config() {
+        let config: BrokerConfig = BrokerConfig::parse(CONFIG).unwrap();
+
+        assert_eq!(config.rules.len(), 3);
+        assert_eq!(config.rules[0].action, RuleAction::Republish);
+        assert_eq!(config.rules[0].conditions[0].equals, Some(json!("F")));
+        assert_eq!(config.rules[0].convert[0].decimals, Some(1));
+        assert_eq!(config.rules[1].action, RuleAction::Drop);
+        assert_eq!(config.rules[1].convert.len(), 0);
+        assert_eq!(config.rules[2].qos, Some(0));
+
+        assert!(BrokerConfig::parse("[[rule]]\nname = \"a\"\ntopic = \"a\"\naction = \"forward\"").is_err());
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let invalid = |rule: &str| -> String { RuleSet::new(&BrokerConfig::parse(rule).unwrap().rules).unwrap_err() };
+
+        assert_eq!(
+            invalid("[[rule]]\nname = \"a\"\ntopic = \"raw/#/temp\"\naction = \"drop\""),
+            "Invalid rule a: the topic filter is not valid"
+        );
+        assert_eq!(
+            invalid("[[rule]]\nname = \"b\"\ntopic = \"raw/#\"\naction = \"republish\""),
+            "Invalid rule b: a republish rule needs a target"
+        );
+        assert_eq!(invalid("[[rule]]\nname = \"c\"\ntopic = \"raw/#\"\naction = \"sink\""), "Invalid rule c: a sink rule needs a sink");
+        assert_eq!(
+            invalid("[[rule]]\nname = \"d\"\ntopic = \"raw/#\"\naction = \"republish\"\ntarget = \"x\"\nqos = 3"),
+            "Invalid rule d: the QoS must be 0, 1 or 2"
+        );
+    }
+
+    #[test]
+    fn test_topic_filter_captures() {
+        assert_eq!(topic_filter::captures("raw/+/temp", "raw/kitchen/temp"), Some(vec!["kitchen".to_string()]));
+        assert_eq!(
+            topic_filter::captures("raw/+/#", "raw/kitchen/temp/celsius"),
+            Some(vec!["kitchen".to_string(), "temp/celsius".to_string()])
+        );
+        assert_eq!(topic_filter::captures("raw/#", "raw"), Some(vec![String::new()]));
+        assert_eq!(topic_filter::captures("raw/kitchen", "raw/kitchen"), Some(vec![]));
+        assert_eq!(topic_filter::captures("raw/+/temp", "raw/kitchen/humidity"), None);
+    }
+
+    #[test]
+    fn test_evaluate_rules() {
+        let rules: RuleSet = rules();
+
+        let results: Vec<(RuleAction, Message)> = rules.evaluate("sensor/1", &message("raw/kitchen/temp", r#"{"value":72.5,"unit":"F"}"#));
+        assert_eq!(results.len(), 1);
+
+        let (action, converted) = &results[0];
+        assert_eq!(*action, RuleAction::Republish);
+        assert_eq!(converted.topic, "normalized/kitchen/temperature");
+        assert_eq!(converted.qos, 1);
+        assert!(!converted.retain);
+        assert_eq!(
+            serde_json::from_str::<Value>(&converted.payload).unwrap(),
+            json!({ "value": 22.5, "unit": "C", "meta": { "device": "kitchen", "source": "raw/kitchen/temp" } })
+        );
+
+        // A condition that does not hold, or a payload that is not a JSON object, skips the rule
+        assert!(rules.evaluate("sensor/1", &message("raw/kitchen/temp", r#"{"value":22.5,"unit":"C"}"#)).is_empty());
+        assert!(rules.evaluate("sensor/1", &message("raw/kitchen/temp", "72.5")).is_empty());
+
+        // Both matching rules apply
+        let results: Vec<(RuleAction, Message)> = rules.evaluate(
+            "sensor/1",
+            &message("raw/kitchen/temp", r#"{"value":72.5,"unit":"F","debug":true}"#)
+        );
+        assert_eq!(results.iter().map(|(action, _)| *action).collect::<Vec<RuleAction>>(), vec![RuleAction::Republish, RuleAction::Drop]);
+
+        // A rule without conditions or conversions takes any payload, and keeps it as it is
+        let results: Vec<(RuleAction, Message)> = rules.evaluate("sensor/1", &message("raw/kitchen/status", "online"));
+        assert_eq!(results[0].1, Message { topic: "status/sensor/1/kitchen".to_string(), payload: "online".to_string(), qos: 0, retain: true });
+    }
+
+    #[test]
+    fn test_run_rules() {
+        let broker: Broker = start_broker();
+        let rules: RuleSet = rules();
+
+        let mut subscriber: ScriptedClient = ScriptedClient::connected(&broker, "rules-sub");
+        subscriber.subscribe("normalized/#", 1);
+        wait_for_subscriber(&broker, "normalized/kitchen/temperature", "rules-sub");
+
+        // The converted message is republished, the original is not dropped
+        let dropped: bool = rules.run(
+            &broker.topics,
+            &broker.clients,
+            &broker.publish_queue,
+            "sensor/1",
+            &message("raw/kitchen/temp", r#"{"value":212,"unit":"F"}"#)
+        );
+        assert!(!dropped);
+
+        let publish: Publish = subscriber.expect_publish();
+        assert_eq!(publish.topic_name, "normalized/kitchen/temperature");
+        assert_eq!(serde_json::from_slice::<Value>(&publish.payload).unwrap()["value"], json!(100.0));
+
+        assert!(
+            rules.run(
+                &broker.topics,
+                &broker.clients,
+                &broker.publish_queue,
+                "sensor/1",
+                &message("raw/garage/door", r#"{"debug":"open"}"#)
+            )
+        );
+
+        // A retained republish is kept for later subscribers
+        rules.run(&broker.topics, &broker.clients, &broker.publish_queue, "sensor/1", &message("raw/garage/status", "online"));
+        assert_eq!(broker.topics.retained("status/sensor/1/garage"), Some(("online".to_string(), 0)));
+    }
+
+    #[test]
+    fn test_sink_rule() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let config: BrokerConfig = BrokerConfig::parse(
+            &format!(
+                "[[rule]]\nname = \"alarms\"\ntopic = \"alarms/#\"\naction = \"sink\"\n[rule.sink]\nurl = \"http://{}/alarms\"\nbatch_interval_ms = 10",
+                listener.local_addr().unwrap()
+            )
+        ).unwrap();
+        let rules: RuleSet = RuleSet::new(&config.rules).unwrap();
+        let broker: Broker = start_broker();
+
+        rules.run(&broker.topics, &broker.clients, &broker.publish_queue, "sensor/1", &message("alarms/smoke", "kitchen"));
+
+        let (mut stream, _): (TcpStream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+
+        let request: http::Request = http::read_request(&mut stream).unwrap();
+        http::write_response(&mut stream, 204, "text/plain", b"");
+
+        let events: Vec<Value> = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(request.path, "/alarms");
+        assert_eq!(events[0]["event"], "message_published");
+        assert_eq!(events[0]["topic"], "alarms/smoke");
+        assert_eq!(events[0]["payload"], "kitchen");
+    }
+}
@@ -0,0 +1,154 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use serde_json::{ json, Value };
+
+    use crate::broker::Broker;
+    use crate::models::config::{ BrokerConfig, InvalidAction };
+    use crate::packet::Publish;
+    use crate::services::hooks::Message;
+    use crate::services::schemas::{ self, SchemaSet, Violation };
+    use crate::tests::scripted_client::{ start_broker, wait_for_subscriber, ScriptedClient };
+
+    const CONFIG: &str = r#"
+        [[schema]]
+        topic = "sensors/+/telemetry"
+
+        [schema.schema]
+        type = "object"
+        required = ["value", "unit"]
+        properties = { value = { type = "number" }, unit = { enum = ["C", "F"] } }
+
+        [[schema]]
+        topic = "sensors/#"
+        on_invalid = "disconnect"
+        dead_letter = false
+        schema = { type = "object", properties = { battery = { type = "integer", minimum = 0, maximum = 100 } } }
+    "#;
+
+    fn message(topic: &str, payload: &str) -> Message {
+        Message { topic: topic.to_string(), payload: payload.to_string(), qos: 1, retain: false }
+    }
+
+    fn error_of(schemas: &SchemaSet, topic: &str, payload: &str) -> String {
+        schemas.validate(&message(topic, payload)).unwrap_err().error
+    }
+
+    #[test]
+    fn test_parse_schema_config() {
+        let config: BrokerConfig = BrokerConfig::parse(CONFIG).unwrap();
+
+        assert_eq!(config.schemas.len(), 2);
+        assert_eq!(config.schemas[0].on_invalid, InvalidAction::Drop);
+        assert!(config.schemas[0].dead_letter);
+        assert_eq!(config.schemas[0].schema.as_ref().unwrap()["required"], json!(["value", "unit"]));
+        assert_eq!(config.schemas[1].on_invalid, InvalidAction::Disconnect);
+        assert!(!config.schemas[1].dead_letter);
+
+        assert!(BrokerConfig::parse("[[schema]]\ntopic = \"a\"\nfile = \"a.json\"\non_invalid = \"ignore\"").is_err());
+    }
+
+    #[test]
+    fn test_invalid_schemas() {
+        let invalid = |schema: &str| -> String { SchemaSet::new(&BrokerConfig::parse(schema).unwrap().schemas).err().unwrap() };
+
+        assert_eq!(
+            invalid("[[schema]]\ntopic = \"sensors/#/x\"\nschema = { type = \"object\" }"),
+            "Invalid schema for sensors/#/x: the topic filter is not valid"
+        );
+        assert_eq!(invalid("[[schema]]\ntopic = \"sensors\""), "Invalid schema for sensors: one of schema and file must be set");
+        assert_eq!(
+            invalid("[[schema]]\ntopic = \"sensors\"\nfile = \"a.json\"\nschema = { type = \"object\" }"),
+            "Invalid schema for sensors: one of schema and file must be set"
+        );
+        assert!(invalid("[[schema]]\ntopic = \"sensors\"\nfile = \"/nonexistent/schema.json\"").starts_with("Invalid schema for sensors: could not read"));
+        assert!(invalid("[[schema]]\ntopic = \"sensors\"\nschema = { type = 5 }").starts_with("Invalid schema for sensors: "));
+    }
+
+    #[test]
+    fn test_validate_payloads() {
+        let schemas: SchemaSet = SchemaSet::new(&BrokerConfig::parse(CONFIG).unwrap().schemas).unwrap();
+
+        assert_eq!(schemas.validate(&message("sensors/1/telemetry", r#"{"value":21.5,"unit":"C"}"#)), Ok(()));
+        assert_eq!(schemas.validate(&message("sensors/1/status", r#"{"battery":80}"#)), Ok(()));
+
+        // Topics without a schema are not checked
+        assert_eq!(schemas.validate(&message("lights/kitchen", "on")), Ok(()));
+
+        // The error names the invalid field
+        assert_eq!(
+            schemas.validate(&message("sensors/1/telemetry", r#"{"value":"hot","unit":"C"}"#)),
+            Err(Violation {
+                action: InvalidAction::Drop,
+                dead_letter: true,
+                error: "/value: \"hot\" is not of type \"number\"".to_string(),
+            })
+        );
+        assert_eq!(error_of(&schemas, "sensors/1/telemetry", r#"{"value":21.5}"#), "\"unit\" is a required property");
+        assert!(error_of(&schemas, "sensors/1/telemetry", "21.5 C").starts_with("The payload is not valid JSON: "));
+
+        // A message has to follow every schema of its topic, the action is the one of the schema it failed
+        let violation: Violation = schemas
+            .validate(&message("sensors/1/telemetry", r#"{"value":21.5,"unit":"C","battery":120}"#))
+            .unwrap_err();
+        assert_eq!(violation.action, InvalidAction::Disconnect);
+        assert!(!violation.dead_letter);
+        assert!(violation.error.starts_with("/battery: 120 is greater than the maximum of 100"));
+    }
+
+    #[test]
+    fn test_schema_file() {
+        let path: PathBuf = std::env::temp_dir().join(format!("mqtt-schema-test-{}.json", std::process::id()));
+        fs::write(&path, r#"{"type":"array","items":{"type":"number"}}"#).unwrap();
+
+        let config: BrokerConfig = BrokerConfig::parse(&format!("[[schema]]\ntopic = \"readings\"\nfile = {:?}", path)).unwrap();
+        let schemas: Result<SchemaSet, String> = SchemaSet::new(&config.schemas);
+        _ = fs::remove_file(&path);
+
+        let schemas: SchemaSet = schemas.unwrap();
+        assert_eq!(schemas.validate(&message("readings", "[1, 2.5]")), Ok(()));
+        assert_eq!(error_of(&schemas, "readings", "[1, \"2\"]"), "/1: \"2\" is not of type \"number\"");
+    }
+
+    #[test]
+    fn test_dead_letter() {
+        let broker: Broker = start_broker();
+
+        let mut dead_letters: ScriptedClient = ScriptedClient::connected(&broker, "schema-dlq");
+        dead_letters.subscribe("$dlq/#", 1);
+
+        let mut everything: ScriptedClient = ScriptedClient::connected(&broker, "schema-all");
+        everything.subscribe("#", 1);
+
+        wait_for_subscriber(&broker, "$dlq/schema/1/telemetry", "schema-dlq");
+        wait_for_subscriber(&broker, "schema/1/telemetry", "schema-all");
+
+        schemas::dead_letter(
+            &broker.topics,
+            &broker.clients,
+            &broker.publish_queue,
+            "sensor/1",
+            &message("schema/1/telemetry", r#"{"value":"hot"}"#),
+            "/value: \"hot\" is not of type \"number\""
+        );
+
+        let publish: Publish = dead_letters.expect_publish();
+        assert_eq!(publish.topic_name, "$dlq/schema/1/telemetry");
+        assert_eq!(publish.qos, 1);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&publish.payload).unwrap(),
+            json!({
+                "topic": "schema/1/telemetry",
+                "client_id": "sensor/1",
+                "error": "/value: \"hot\" is not of type \"number\"",
+                "payload": "{\"value\":\"hot\"}",
+            })
+        );
+
+        // Dead letters are not matched by wildcard subscriptions
+        everything.expect_nothing(Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,171 @@
+//! A client driven packet by packet over a real socket, shared by the end-to-end and conformance tests.
+#![cfg(test)]
+
+use std::io::{ ErrorKind, Write };
+use std::net::{ Shutdown, TcpStream };
+use std::time::{ Duration, Instant };
+
+use crate::broker::Broker;
+use crate::mqtt_client::codec;
+use crate::packet::{ Connack, Connect, Packet, Publish, Suback, Subscribe };
+
+/// How long a scripted client waits for a packet before the test fails.
+pub const TIMEOUT: Duration = Duration::from_secs(3);
+
+pub fn start_broker() -> Broker {
+    let mut broker: Broker = Broker::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    broker.start().unwrap();
+    broker
+}
+
+pub fn connect_packet(client_id: &str, clean_session: bool) -> Connect {
+    Connect {
+        protocol_name: "MQTT".to_string(),
+        protocol_level: 4,
+        clean_session,
+        keep_alive: 60,
+        client_id: client_id.to_string(),
+        will: None,
+        username: None,
+        password: None,
+    }
+}
+
+pub fn publish_packet(topic_name: &str, payload: &str, qos: u8, retain: bool, packet_id: u16) -> Packet {
+    Packet::Publish(Publish {
+        dup: false,
+        qos,
+        retain,
+        topic_name: topic_name.to_string(),
+        packet_id: if qos > 0 { Some(packet_id) } else { None },
+        payload: payload.as_bytes().to_vec(),
+    })
+}
+
+/// Waits until a client is among the subscribers of a topic.
+///
+/// The SUBACK is sent before the subscription is added to the topics, so a test that publishes
+/// through the broker's state rather than from another client waits for it first.
+pub fn wait_for_subscriber(broker: &Broker, topic_name: &str, client_id: &str) {
+    let deadline: Instant = Instant::now() + TIMEOUT;
+
+    while !broker.topics.subscribers(topic_name).iter().any(|(id, _)| id == client_id) {
+        assert!(Instant::now() < deadline, "{} did not subscribe to {} in time", client_id, topic_name);
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+/// A client driven packet by packet over a real socket, so every step of a flow can be asserted.
+pub struct ScriptedClient {
+    pub stream: TcpStream,
+}
+
+impl ScriptedClient {
+    /// Connects to the broker and sends the CONNECT.
+    ///
+    /// # Returns
+    ///
+    /// The client and the CONNACK the broker answered with.
+    pub fn connect(broker: &Broker, connect: Connect) -> (ScriptedClient, Connack) {
+        let mut client: ScriptedClient = ScriptedClient::open(broker);
+        client.send(Packet::Connect(connect));
+
+        match client.expect() {
+            Packet::Connack(connack) => (client, connack),
+            packet => panic!("Expected a CONNACK, got {:?}", packet),
+        }
+    }
+
+    /// Connects with a clean session, and asserts that the connection is accepted.
+    pub fn connected(broker: &Broker, client_id: &str) -> ScriptedClient {
+        let (client, connack) = ScriptedClient::connect(broker, connect_packet(client_id, true));
+        assert_eq!(connack.return_code, 0);
+
+        client
+    }
+
+    /// Opens a connection without sending anything, for tests of what the first packet may be.
+    pub fn open(broker: &Broker) -> ScriptedClient {
+        let stream: TcpStream = TcpStream::connect(broker.local_addr).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+        ScriptedClient { stream }
+    }
+
+    pub fn send(&mut self, packet: Packet) {
+        self.send_bytes(&packet.to_vec().unwrap());
+    }
+
+    /// Sends raw bytes, for packets the encoder refuses to build.
+    pub fn send_bytes(&mut self, bytes: &[u8]) {
+        // The broker may already have closed the connection, the test then asserts on what it reads
+        _ = self.stream.write_all(bytes);
+    }
+
+    /// Reads the next packet, failing the test if none arrives in time.
+    pub fn expect(&mut self) -> Packet {
+        codec::read_packet(&mut self.stream).unwrap_or_else(|err| panic!("Expected a packet: {}", err))
+    }
+
+    /// Reads the next packet, which must be a PUBLISH.
+    pub fn expect_publish(&mut self) -> Publish {
+        match self.expect() {
+            Packet::Publish(publish) => publish,
+            packet => panic!("Expected a PUBLISH, got {:?}", packet),
+        }
+    }
+
+    /// Asserts that nothing arrives for a while, the connection stays open.
+    pub fn expect_nothing(&mut self, duration: Duration) {
+        self.stream.set_read_timeout(Some(duration)).unwrap();
+
+        match codec::read_packet(&mut self.stream) {
+            Ok(packet) => panic!("Expected nothing, got {:?}", packet),
+            Err(err) => assert!(matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut), "{}", err),
+        }
+
+        self.stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    }
+
+    /// Reads packets until nothing arrives for a while, the connection stays open.
+    ///
+    /// # Returns
+    ///
+    /// The packets that arrived, in order.
+    pub fn received(&mut self, quiet: Duration) -> Vec<Packet> {
+        let mut packets: Vec<Packet> = Vec::new();
+        self.stream.set_read_timeout(Some(quiet)).unwrap();
+
+        loop {
+            match codec::read_packet(&mut self.stream) {
+                Ok(packet) => packets.push(packet),
+                Err(err) => {
+                    assert!(matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut), "{}", err);
+                    break;
+                }
+            }
+        }
+
+        self.stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        packets
+    }
+
+    /// Asserts that the broker closes the connection.
+    pub fn expect_closed(&mut self) {
+        match codec::read_packet(&mut self.stream) {
+            Ok(packet) => panic!("Expected the connection to close, got {:?}", packet),
+            Err(err) => assert!(!matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut), "{}", err),
+        }
+    }
+
+    /// Subscribes to one topic filter and waits for the SUBACK.
+    pub fn subscribe(&mut self, topic_filter: &str, qos: u8) {
+        self.send(Packet::Subscribe(Subscribe { packet_id: 1, topic_filters: vec![(topic_filter.to_string(), qos)] }));
+        assert_eq!(self.expect(), Packet::Suback(Suback { packet_id: 1, return_codes: vec![qos] }));
+    }
+
+    /// Closes the socket without a DISCONNECT, as a client that lost its network would.
+    pub fn drop_connection(self) {
+        _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
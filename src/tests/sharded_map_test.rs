@@ -0,0 +1,135 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::Entry;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::thread::{ self, JoinHandle };
+
+    use crate::common_fn::sharded_map::ShardedMap;
+    use crate::models::client::{ Client, Clients };
+    use crate::models::flags::ConnectFlags;
+    use crate::services::outbound;
+
+    fn client(id: &str, socket_addr: SocketAddr) -> Client {
+        let (tx, _rx) = outbound::channel();
+
+        Client::new(
+            id.to_string(),
+            String::new(),
+            String::new(),
+            60,
+            String::new(),
+            String::new(),
+            socket_addr,
+            tx,
+            ConnectFlags::new(true, false, 0, false, false, false)
+        )
+    }
+
+    #[test]
+    fn test_sharded_map() {
+        let map: ShardedMap<String, u32> = ShardedMap::with_shards(4);
+        assert!(map.is_empty());
+
+        for i in 0..100 {
+            assert_eq!(map.insert(format!("key/{}", i), i), None);
+        }
+
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get("key/42"), Some(42));
+        assert_eq!(map.with("key/7", |value: &u32| value * 2), Some(14));
+        assert_eq!(map.with_mut("key/7", |value: &mut u32| { *value = 70; }), Some(()));
+        assert_eq!(map.get("key/7"), Some(70));
+        assert_eq!(map.with("missing", |value: &u32| *value), None);
+
+        // An entry is checked and updated in a single step
+        let inserted: bool = map.entry("key/7".to_string(), |entry: Entry<'_, String, u32>| matches!(entry, Entry::Vacant(_)));
+        assert!(!inserted);
+
+        // Key 7 holds 70 now, so one more value is even
+        assert_eq!(map.count(|_, value: &u32| value.is_multiple_of(2)), 51);
+
+        map.retain(|_, value: &mut u32| *value < 10);
+        assert_eq!(map.len(), 9);
+        assert_eq!(map.remove("key/1"), Some(1));
+        assert!(!map.contains_key("key/1"));
+    }
+
+    #[test]
+    fn test_sharded_map_from_threads() {
+        let map: Arc<ShardedMap<u32, u32>> = Arc::new(ShardedMap::new());
+
+        let threads: Vec<JoinHandle<()>> = (0..8)
+            .map(|thread: u32| {
+                let map: Arc<ShardedMap<u32, u32>> = Arc::clone(&map);
+
+                thread::spawn(move || {
+                    for i in 0..1000 {
+                        map.entry(i, |entry: Entry<'_, u32, u32>| {
+                            *entry.or_insert(0) += 1;
+                        });
+                        map.insert(10_000 + thread * 1000 + i, i);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // No increment is lost, every thread counted every key
+        assert_eq!(map.count(|key: &u32, value: &u32| *key < 1000 && *value == 8), 1000);
+        assert_eq!(map.len(), 9000);
+    }
+
+    #[test]
+    fn test_clients_by_id_and_address() {
+        let clients: Clients = Clients::new();
+        let first: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 50000));
+        let second: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 50001));
+
+        clients.insert(client("sensor/1", first));
+        clients.insert(client("sensor/2", second));
+
+        assert_eq!(clients.len(), 2);
+        assert_eq!(clients.connected(), 2);
+        assert_eq!(clients.id_of(&first).as_deref(), Some("sensor/1"));
+        assert_eq!(clients.get_by_addr(&second).unwrap().id, "sensor/2");
+
+        // A disconnected session is kept, but is no longer found by its address
+        let disconnected: Client = clients.disconnect(&first).unwrap();
+        assert!(disconnected.is_connected);
+        assert!(!clients.get("sensor/1").unwrap().is_connected);
+        assert_eq!(clients.id_of(&first), None);
+        assert!(clients.disconnect(&first).is_none());
+        assert_eq!(clients.connected(), 1);
+
+        // Taking the session over from another address indexes the new address
+        let third: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 50002));
+        clients.entry("sensor/1".to_string(), |entry: Entry<'_, String, Client>| {
+            if let Entry::Occupied(mut occupied) = entry {
+                occupied.get_mut().socket_addr = third;
+                occupied.get_mut().is_connected = true;
+            }
+        });
+        assert_eq!(clients.id_of(&third).as_deref(), Some("sensor/1"));
+
+        // A connected session taken over by a new connection is left alone when the old one disconnects
+        let fourth: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 50003));
+        clients.entry("sensor/1".to_string(), |entry: Entry<'_, String, Client>| {
+            if let Entry::Occupied(mut occupied) = entry {
+                occupied.get_mut().socket_addr = fourth;
+            }
+        });
+        assert_eq!(clients.id_of(&third), None);
+        assert!(clients.disconnect(&third).is_none());
+        assert_eq!(clients.id_of(&fourth).as_deref(), Some("sensor/1"));
+        assert!(clients.get("sensor/1").unwrap().is_connected);
+
+        // A removed session is removed from the address index as well
+        assert!(clients.remove("sensor/2").is_some());
+        assert_eq!(clients.id_of(&second), None);
+        assert_eq!(clients.len(), 1);
+    }
+}
@@ -0,0 +1,104 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::time::{ Duration, Instant };
+
+    use crate::models::client::{ Client, Clients };
+    use crate::models::config::BrokerConfig;
+    use crate::models::flags::ConnectFlags;
+    use crate::models::publish_queue_item::{ PublishItemDirection, PublishItemState, PublishQueue, PublishQueueItem };
+    use crate::services::outbound::{ self, OutboundSender };
+    use crate::services::shutdown;
+
+    /// A connected client with a will, sending to `tx`.
+    fn client(client_id: &str, socket_addr: SocketAddr, tx: OutboundSender) -> Client {
+        let connect_flags: ConnectFlags = ConnectFlags::new(true, true, 0, false, false, false);
+
+        Client::new(
+            client_id.to_string(),
+            "will_topic".to_string(),
+            "will_message".to_string(),
+            60,
+            String::new(),
+            String::new(),
+            socket_addr,
+            tx,
+            connect_flags
+        )
+    }
+
+    /// Adds a QoS 2 flow from a client, waiting for its PUBREL.
+    fn push_qos_2(publish_queue: &PublishQueue, client_id: &str) {
+        let (tx, _rx) = channel::<PublishItemState>();
+        publish_queue.push(PublishQueueItem {
+            client_id: client_id.to_string(),
+            packet_id: 1,
+            timestamp_sent: Instant::now(),
+            publish_packet: vec![],
+            state: PublishItemState::AwaitingPubrel,
+            qos_level: 2,
+            flow_direction: PublishItemDirection::FromClient,
+            tx,
+        });
+    }
+
+    #[test]
+    fn test_drain_waits_for_qos_2_handshakes() {
+        let clients: Clients = Clients::new();
+        let publish_queue: PublishQueue = PublishQueue::new();
+        let (tx, _rx) = outbound::channel();
+
+        // Nothing in flight
+        assert_eq!(shutdown::drain(&clients, &publish_queue, Duration::from_secs(5)), 0);
+
+        // The flows of an offline persistent session can not complete, so they are not waited for
+        let offline_addr: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 50001));
+        clients.insert(client("offline", offline_addr, tx.clone()));
+        clients.disconnect(&offline_addr);
+        push_qos_2(&publish_queue, "offline");
+
+        let started: Instant = Instant::now();
+        assert_eq!(shutdown::drain(&clients, &publish_queue, Duration::from_secs(5)), 0);
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // The handshake of a connected client never completes, so the drain gives up at the deadline
+        clients.insert(client("publisher", SocketAddr::from(([127, 0, 0, 1], 50002)), tx));
+        push_qos_2(&publish_queue, "publisher");
+
+        let started: Instant = Instant::now();
+        assert_eq!(shutdown::drain(&clients, &publish_queue, Duration::from_millis(200)), 1);
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_close_clients_suppresses_wills() {
+        let (tx, rx) = outbound::channel();
+
+        let clients: Arc<Clients> = Arc::new(Clients::new());
+        clients.insert(client("client_id", SocketAddr::from(([127, 0, 0, 1], 50000)), tx));
+
+        // Acts as the connection thread, marking the client as disconnected once its stream is closed
+        let clients_clone: Arc<Clients> = Arc::clone(&clients);
+        let connection = std::thread::spawn(move || {
+            assert!(rx.recv().unwrap().is_err());
+            clients_clone.disconnect(&SocketAddr::from(([127, 0, 0, 1], 50000)));
+        });
+
+        shutdown::close_clients(&clients);
+        connection.join().unwrap();
+
+        let client: Client = clients.get("client_id").unwrap();
+        assert!(!client.is_connected);
+        assert!(!client.connect_flags.will_flag);
+    }
+
+    #[test]
+    fn test_config_shutdown_section() {
+        assert_eq!(BrokerConfig::parse("").unwrap().shutdown.drain_timeout_secs, 10);
+
+        let config: BrokerConfig = BrokerConfig::parse("[shutdown]\ndrain_timeout_secs = 3\n").unwrap();
+        assert_eq!(config.shutdown.drain_timeout_secs, 3);
+    }
+}
@@ -0,0 +1,45 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::services::{ archive, rules, schemas, signals, webhooks };
+
+    #[test]
+    fn test_reload_applies_nothing_when_a_section_is_invalid() {
+        let path: PathBuf = std::env::temp_dir().join(format!("mqtt-reload-test-{}.toml", std::process::id()));
+
+        // Valid schema, webhook and rule sections, followed by an archive with an invalid topic filter
+        fs::write(
+            &path,
+            r#"
+                [[schema]]
+                topic = "reload/+/telemetry"
+                schema = { type = "object" }
+
+                [[webhook]]
+                url = "http://127.0.0.1:9/reload-events"
+
+                [[rule]]
+                name = "reload"
+                topic = "reload/#"
+                action = "drop"
+
+                [[archive]]
+                directory = "reload-archive"
+                topics = ["reload/#/telemetry"]
+            "#
+        ).unwrap();
+
+        let result: Result<(), String> = signals::reload_config(path.to_str().unwrap());
+        _ = fs::remove_file(&path);
+
+        assert_eq!(result, Err("Invalid archive topic filter: reload/#/telemetry".to_string()));
+
+        // The sections before the invalid one were not installed either
+        assert!(schemas::active().is_none());
+        assert_eq!(webhooks::count(), 0);
+        assert!(rules::active().is_none());
+        assert_eq!(archive::count(), 0);
+    }
+}
@@ -0,0 +1,150 @@
+#[cfg(test)]
+mod tests {
+    use std::fs::{ self, OpenOptions };
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use crate::models::client::{ Client, Clients };
+    use crate::models::config::{ BrokerConfig, FsyncPolicy };
+    use crate::models::topic::{ Topic, Topics };
+    use crate::services::storage::file_store::FileStore;
+    use crate::services::storage::state::{ Record, State, StoredMessage, StoredSession };
+    use crate::services::storage::{ self, Storage };
+
+    fn session(client_id: &str) -> Record {
+        Record::Session(StoredSession {
+            client_id: client_id.to_string(),
+            username: "sensor".to_string(),
+            keep_alive: 60,
+            will_flag: true,
+            will_topic: "status/sensor".to_string(),
+            will_message: "offline".to_string(),
+            will_qos: 1,
+            will_retain: false,
+        })
+    }
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory: PathBuf = std::env::temp_dir().join(
+            format!("mqtt_broker_{}_{}", name, std::process::id())
+        );
+        _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn test_state_ignores_clean_sessions() {
+        let mut state: State = State::default();
+
+        // Subscriptions and messages of clients without a persistent session are not kept
+        assert!(!state.apply(&Record::Subscribed { client_id: "clean".to_string(), topic: "a".to_string(), qos: 1 }));
+
+        assert!(state.apply(&session("persistent")));
+        assert!(!state.apply(&session("persistent")));
+        assert!(state.apply(&Record::Subscribed { client_id: "persistent".to_string(), topic: "a".to_string(), qos: 1 }));
+        assert!(state.apply(&Record::Subscribed { client_id: "persistent".to_string(), topic: "a".to_string(), qos: 2 }));
+        assert_eq!(state.subscriptions["persistent"], vec![("a".to_string(), 2)]);
+
+        // Removing the session removes everything belonging to it
+        assert!(state.apply(&Record::SessionRemoved { client_id: "persistent".to_string() }));
+        assert!(state.subscriptions.is_empty());
+
+        // An empty payload clears a retained message
+        assert!(state.apply(&Record::Retained { topic: "a".to_string(), payload: "1".to_string(), qos: 0 }));
+        assert!(state.apply(&Record::Retained { topic: "a".to_string(), payload: String::new(), qos: 0 }));
+        assert!(state.retained.is_empty());
+    }
+
+    #[test]
+    fn test_file_store_restores_state() {
+        let directory: PathBuf = temporary_directory("restore");
+
+        {
+            let storage: Storage = Storage::open(
+                Box::new(FileStore::open(&directory, FsyncPolicy::Always).unwrap())
+            ).unwrap();
+
+            storage.record(session("sensor/1"));
+            storage.record(Record::Subscribed { client_id: "sensor/1".to_string(), topic: "commands".to_string(), qos: 1 });
+            storage.record(Record::Retained { topic: "status".to_string(), payload: "up".to_string(), qos: 0 });
+            storage.record(Record::Queued {
+                client_id: "sensor/1".to_string(),
+                message: StoredMessage { packet_id: 7, qos: 1, packet: vec![50, 5, 0, 1, 97, 0, 7] },
+            });
+            storage.record(Record::Queued {
+                client_id: "sensor/1".to_string(),
+                message: StoredMessage { packet_id: 8, qos: 1, packet: vec![50, 5, 0, 1, 97, 0, 8] },
+            });
+            storage.record(Record::Acknowledged { client_id: "sensor/1".to_string(), packet_id: 7 });
+        }
+
+        // Simulate a crash in the middle of appending a record
+        let mut log = OpenOptions::new().append(true).open(directory.join("log.jsonl")).unwrap();
+        log.write_all(b"{\"type\":\"retai").unwrap();
+
+        let storage: Storage = Storage::open(
+            Box::new(FileStore::open(&directory, FsyncPolicy::Never).unwrap())
+        ).unwrap();
+        let state: State = storage.state();
+
+        assert_eq!(state.sessions["sensor/1"].will_topic, "status/sensor");
+        assert_eq!(state.subscriptions["sensor/1"], vec![("commands".to_string(), 1)]);
+        assert_eq!(state.retained["status"], ("up".to_string(), 0));
+        assert_eq!(state.queued["sensor/1"].len(), 1);
+        assert_eq!(state.queued["sensor/1"][0].packet_id, 8);
+
+        // Opening compacts everything into the snapshot
+        assert_eq!(fs::read_to_string(directory.join("log.jsonl")).unwrap(), "");
+
+        _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn test_restore_into_broker_lists() {
+        let mut state: State = State::default();
+        state.apply(&session("sensor/1"));
+        state.apply(&Record::Subscribed { client_id: "sensor/1".to_string(), topic: "commands".to_string(), qos: 1 });
+        state.apply(&Record::Retained { topic: "status".to_string(), payload: "up".to_string(), qos: 0 });
+
+        let clients: Clients = Clients::new();
+        let topics: Topics = Topics::new();
+
+        storage::restore(&state, &clients, &topics);
+
+        assert_eq!(clients.len(), 1);
+        let client: Client = clients.get("sensor/1").unwrap();
+        assert!(!client.is_connected);
+        assert!(!client.connect_flags.clean_session_flag);
+        assert_eq!(client.will_message, "offline");
+
+        // A restored session is not connected, so it has no socket address to be found by
+        assert_eq!(clients.connected(), 0);
+        assert_eq!(clients.id_of(&client.socket_addr), None);
+
+        let commands: Topic = topics.get("commands").unwrap();
+        assert_eq!(commands.client_ids, vec![("sensor/1".to_string(), 1)]);
+
+        let status: Topic = topics.get("status").unwrap();
+        assert_eq!(status.retained_msg, ("up".to_string(), 0));
+
+        // The restored client gives back the record it was restored from
+        assert_eq!(storage::session_record(&client), session("sensor/1"));
+    }
+
+    #[test]
+    fn test_config_storage_section() {
+        let config: BrokerConfig = BrokerConfig::parse("").unwrap();
+        assert!(config.storage.path.is_none());
+        assert_eq!(config.storage.fsync, FsyncPolicy::Interval);
+
+        let config: BrokerConfig = BrokerConfig::parse(
+            "[storage]\npath = \"data\"\nfsync = \"always\"\n"
+        ).unwrap();
+        assert_eq!(config.storage.path.as_deref(), Some("data"));
+        assert_eq!(config.storage.fsync, FsyncPolicy::Always);
+
+        // An interval of zero would keep the storage thread busy
+        assert!(BrokerConfig::parse("[storage]\nfsync_interval_ms = 0\n").is_err());
+        assert!(BrokerConfig::parse("[storage]\nsnapshot_interval_secs = 0\n").is_err());
+    }
+}
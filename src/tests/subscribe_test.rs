@@ -1,6 +1,16 @@
 #[cfg(test)]
 mod tests {
     use crate::control_packet::subcribe::handle;
+    use crate::models::sub_info::SubInfo;
+    use crate::packet::{ decode, DecodeError, Packet, Subscribe };
+
+    /// Decodes a SUBSCRIBE packet and passes it to the handler.
+    fn decode_and_handle(buffer: &[u8], packet_length: usize) -> Result<SubInfo, DecodeError> {
+        match decode(&buffer[..packet_length])? {
+            (Packet::Subscribe(subscribe), _) => Ok(handle(subscribe, 2)),
+            (packet, _) => panic!("Expected a SUBSCRIBE, got {:?}", packet),
+        }
+    }
 
     #[test]
     fn test_handle_subscribe_packet() {
@@ -13,7 +23,7 @@ mod tests {
         let packet_length = 8;
 
         // Test the handle function with the subscribe packet
-        let result = handle(&buffer, packet_length);
+        let result = decode_and_handle(&buffer, packet_length);
 
         // Check that the result is Ok
         assert!(result.is_ok());
@@ -22,7 +32,7 @@ mod tests {
         let sub_info = result.unwrap();
         assert_eq!(sub_info.packet_id, 1);
         assert_eq!(sub_info.topic_qos_pair, vec![("a".to_string(), 0)]);
-        // Check the return_packet as needed
+        assert_eq!(sub_info.return_codes, vec![0]);
     }
 
     #[test]
@@ -36,7 +46,7 @@ mod tests {
         let packet_length = 12;
 
         // Test the handle function with the subscribe packet
-        let result = handle(&buffer, packet_length);
+        let result = decode_and_handle(&buffer, packet_length);
 
         // Check that the result is Ok
         assert!(result.is_ok());
@@ -45,6 +55,7 @@ mod tests {
         let sub_info = result.unwrap();
         assert_eq!(sub_info.packet_id, 1);
         assert_eq!(sub_info.topic_qos_pair, vec![("a".to_string(), 0), ("b".to_string(), 1)]);
+        assert_eq!(sub_info.return_codes, vec![0, 1]);
     }
 
     #[test]
@@ -58,9 +69,23 @@ mod tests {
         let packet_length = 8;
 
         // Test the handle function with the subscribe packet
-        let result = handle(&buffer, packet_length);
+        let result = decode_and_handle(&buffer, packet_length);
 
-        // Check that the result is an error
+        // The packet is well formed, the topic filter is refused with the failure return code
         assert!(result.is_ok());
+        assert_eq!(result.unwrap().return_codes, vec![0x80]);
+    }
+
+    #[test]
+    fn test_handle_subscribe_max_qos() {
+        let subscribe: Subscribe = Subscribe {
+            packet_id: 7,
+            topic_filters: vec![("a".to_string(), 0), ("b".to_string(), 2), ("c".to_string(), 3)],
+        };
+
+        // The granted QoS is capped, an invalid QoS is still refused
+        let sub_info: SubInfo = handle(subscribe, 1);
+        assert_eq!(sub_info.topic_qos_pair, vec![("a".to_string(), 0), ("b".to_string(), 1), ("c".to_string(), 3)]);
+        assert_eq!(sub_info.return_codes, vec![0, 1, 0x80]);
     }
-}
\ No newline at end of file
+}
@@ -1,11 +1,20 @@
 #[cfg(test)]
 mod tests {
-    use crate::{add_client_to_topic_list, models::topic::Topic, remove_client_from_topic_list};
+    use crate::models::topic::{ Topic, Topics };
 
+    fn topics(topics: Vec<Topic>) -> Topics {
+        let list: Topics = Topics::new();
+
+        for topic in topics {
+            list.insert(topic);
+        }
+
+        list
+    }
 
     #[test]
     fn test_add_client_to_topic_list() {
-        let mut topics = vec![
+        let topics = topics(vec![
             Topic {
                 topic_name: "topic1".to_string(),
                 retained_msg: (String::new(), 0),
@@ -16,23 +25,27 @@ mod tests {
                 retained_msg: (String::new(), 0),
                 client_ids: vec![("client2".to_string(), 0)],
             },
-        ];
+        ]);
 
         // Test adding a client to an existing topic
-        add_client_to_topic_list(&mut topics, "client3".to_string(), ("topic1".to_string(), 0));
-        assert_eq!(topics[0].client_ids.len(), 2);
-        assert_eq!(topics[0].client_ids[1], ("client3".to_string(), 0));
+        topics.subscribe("client3", ("topic1".to_string(), 0));
+        let topic1 = topics.get("topic1").unwrap();
+        assert_eq!(topic1.client_ids.len(), 2);
+        assert_eq!(topic1.client_ids[1], ("client3".to_string(), 0));
+
+        // Subscribing again replaces the subscription
+        topics.subscribe("client3", ("topic1".to_string(), 1));
+        assert_eq!(topics.get("topic1").unwrap().client_ids, vec![("client1".to_string(), 0), ("client3".to_string(), 1)]);
 
         // Test adding a client to a new topic
-        add_client_to_topic_list(&mut topics, "client4".to_string(), ("topic3".to_string(), 0));
+        topics.subscribe("client4", ("topic3".to_string(), 0));
         assert_eq!(topics.len(), 3);
-        assert_eq!(topics[2].topic_name, "topic3");
-        assert_eq!(topics[2].client_ids[0], ("client4".to_string(), 0));
+        assert_eq!(topics.get("topic3").unwrap().client_ids[0], ("client4".to_string(), 0));
     }
 
     #[test]
     fn test_remove_client_from_topic_list() {
-        let mut topics = vec![
+        let topics = topics(vec![
             Topic {
                 topic_name: "topic1".to_string(),
                 retained_msg: (String::new(), 0),
@@ -43,15 +56,67 @@ mod tests {
                 retained_msg: (String::new(), 0),
                 client_ids: vec![("client3".to_string(), 0)],
             },
-        ];
+        ]);
 
         // Test removing a client from an existing topic
-        remove_client_from_topic_list(&mut topics, "client1".to_string(), ("topic1".to_string(), 0));
-        assert_eq!(topics[0].client_ids.len(), 1);
-        assert_eq!(topics[0].client_ids[0], ("client2".to_string(), 0));
+        assert!(topics.unsubscribe("client1", "topic1"));
+        let topic1 = topics.get("topic1").unwrap();
+        assert_eq!(topic1.client_ids.len(), 1);
+        assert_eq!(topic1.client_ids[0], ("client2".to_string(), 0));
 
         // Test removing a client from a non-existing topic
-        remove_client_from_topic_list(&mut topics, "client4".to_string(), ("topic3".to_string(), 0));
+        assert!(!topics.unsubscribe("client4", "topic3"));
         assert_eq!(topics.len(), 2); // No new topic should be created
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_subscribers_and_subscriptions() {
+        let topics: Topics = Topics::new();
+        topics.subscribe("kitchen", ("sensors/kitchen/+".to_string(), 0));
+        topics.subscribe("kitchen", ("sensors/#".to_string(), 2));
+        topics.subscribe("display", ("sensors/kitchen/temperature".to_string(), 1));
+        topics.subscribe("other", ("lights/#".to_string(), 1));
+
+        // A client subscribed with several matching topic filters is listed once, with the highest QoS
+        let mut subscribers: Vec<(String, u8)> = topics.subscribers("sensors/kitchen/temperature");
+        subscribers.sort();
+        assert_eq!(subscribers, vec![("display".to_string(), 1), ("kitchen".to_string(), 2)]);
+
+        let mut subscriptions: Vec<(String, u8)> = topics.subscriptions("kitchen");
+        subscriptions.sort();
+        assert_eq!(subscriptions, vec![("sensors/#".to_string(), 2), ("sensors/kitchen/+".to_string(), 0)]);
+
+        topics.remove_client("kitchen");
+        assert!(topics.subscriptions("kitchen").is_empty());
+        assert_eq!(topics.subscribers("sensors/kitchen/temperature"), vec![("display".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_retained() {
+        let topics: Topics = Topics::new();
+        assert_eq!(topics.retained("status"), None);
+
+        topics.set_retained("status", ("up".to_string(), 1));
+        assert_eq!(topics.retained("status"), Some(("up".to_string(), 1)));
+
+        assert_eq!(topics.take_retained("status"), Some(("up".to_string(), 1)));
+        assert_eq!(topics.retained("status"), None);
+        assert_eq!(topics.take_retained("status"), None);
+    }
+
+    #[test]
+    fn test_retained_matching() {
+        let topics: Topics = Topics::new();
+        topics.set_retained("sensors/a", ("20".to_string(), 1));
+        topics.set_retained("sensors/b", ("".to_string(), 0));
+        topics.set_retained("status", ("up".to_string(), 0));
+
+        // Only the topics with a retained message that the filter matches are listed, with their own name
+        assert_eq!(
+            topics.retained_matching("sensors/#"),
+            vec![("sensors/a".to_string(), ("20".to_string(), 1))]
+        );
+        assert_eq!(topics.retained_matching("status").len(), 1);
+        assert!(topics.retained_matching("+/c").is_empty());
+    }
+}
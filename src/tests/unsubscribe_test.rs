@@ -1,6 +1,16 @@
 #[cfg(test)]
 mod tests {
     use crate::control_packet::unsubcribe::handle;
+    use crate::models::sub_info::SubInfo;
+    use crate::packet::{ decode, DecodeError, Packet };
+
+    /// Decodes an UNSUBSCRIBE packet and passes it to the handler.
+    fn decode_and_handle(buffer: &[u8], packet_length: usize) -> Result<SubInfo, DecodeError> {
+        match decode(&buffer[..packet_length])? {
+            (Packet::Unsubscribe(unsubscribe), _) => Ok(handle(unsubscribe)),
+            (packet, _) => panic!("Expected an UNSUBSCRIBE, got {:?}", packet),
+        }
+    }
 
     #[test]
     fn test_handle_unsubscribe_packet() {
@@ -13,7 +23,7 @@ mod tests {
         let packet_length = 7;
 
         // Test the handle function with the unsubscribe packet
-        let result = handle(&buffer, packet_length);
+        let result = decode_and_handle(&buffer, packet_length);
 
         // Check that the result is Ok
         assert!(result.is_ok());
@@ -22,7 +32,6 @@ mod tests {
         let sub_info = result.unwrap();
         assert_eq!(sub_info.packet_id, 1);
         assert_eq!(sub_info.topic_qos_pair, vec![("a".to_string(), 0)]);
-        // Check the return_packet as needed
     }
 
     #[test]
@@ -36,7 +45,7 @@ mod tests {
         let packet_length = 10;
 
         // Test the handle function with the unsubscribe packet
-        let result = handle(&buffer, packet_length);
+        let result = decode_and_handle(&buffer, packet_length);
 
         // Check that the result is Ok
         assert!(result.is_ok());
@@ -58,10 +67,10 @@ mod tests {
         let packet_length = 6; // Incorrect packet length
 
         // Test the handle function with the unsubscribe packet
-        let result = handle(&buffer, packet_length);
+        let result = decode_and_handle(&buffer, packet_length);
 
-        // Check that the result is an error
-        assert!(result.is_err());
+        // Check that the result is an error, the rest of the packet has not arrived yet
+        assert!(matches!(result, Err(DecodeError::Incomplete)));
     }
 
 }
\ No newline at end of file
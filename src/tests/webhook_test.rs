@@ -0,0 +1,239 @@
+#[cfg(test)]
+mod tests {
+    use std::net::{ SocketAddr, TcpListener, TcpStream };
+    use std::sync::mpsc::{ channel, Receiver, Sender };
+    use std::thread;
+    use std::time::{ Duration, Instant };
+
+    use serde_json::Value;
+
+    use crate::common_fn::http::{ self, Request, Url };
+    use crate::models::config::{ BrokerConfig, WebhookConfig, WebhookEvent };
+    use crate::services::webhooks::{ Event, Webhook };
+
+    /// Starts an HTTP stub answering the requests with the given statuses, in turn, and 200 after them.
+    /// Every request is passed on to the returned receiver.
+    fn start_stub(statuses: Vec<u16>) -> (SocketAddr, Receiver<Request>) {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address: SocketAddr = listener.local_addr().unwrap();
+        let (tx, rx): (Sender<Request>, Receiver<Request>) = channel();
+
+        thread::spawn(move || {
+            let mut statuses = statuses.into_iter();
+
+            for stream in listener.incoming() {
+                let mut stream: TcpStream = stream.unwrap();
+
+                if let Ok(request) = http::read_request(&mut stream) {
+                    http::write_response(&mut stream, statuses.next().unwrap_or(200), "text/plain", b"");
+
+                    if tx.send(request).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        (address, rx)
+    }
+
+    fn webhook_config(address: SocketAddr) -> WebhookConfig {
+        let mut config: WebhookConfig = BrokerConfig::parse(&format!("[[webhook]]\nurl = \"http://{}/events\"", address))
+            .unwrap()
+            .webhooks.remove(0);
+
+        config.batch_interval_ms = 200;
+        config.retry_backoff_ms = 10;
+        config
+    }
+
+    fn body_of(request: &Request) -> Vec<Value> {
+        serde_json::from_slice::<Vec<Value>>(&request.body).unwrap()
+    }
+
+    #[test]
+    fn test_parse_webhook_config() {
+        let config: BrokerConfig = BrokerConfig::parse(
+            "[[webhook]]\nurl = \"https://hooks.example.com/mqtt\"\nevents = [\"client_connected\", \"message_published\"]\ntopics = [\"sensors/#\"]\nauthorization = \"Bearer secret\"\nbatch_size = 10"
+        ).unwrap();
+
+        assert_eq!(config.webhooks[0].events, vec![WebhookEvent::ClientConnected, WebhookEvent::MessagePublished]);
+        assert_eq!(config.webhooks[0].topics, vec!["sensors/#".to_string()]);
+        assert_eq!(config.webhooks[0].authorization.as_deref(), Some("Bearer secret"));
+        assert_eq!(config.webhooks[0].batch_size, 10);
+
+        // Every event, on every topic, by default
+        let config: BrokerConfig = BrokerConfig::parse("[[webhook]]\nurl = \"http://127.0.0.1:8080\"").unwrap();
+        assert_eq!(config.webhooks[0].events, WebhookEvent::ALL.to_vec());
+        assert_eq!(config.webhooks[0].topics, vec!["#".to_string()]);
+        assert_eq!(config.webhooks[0].max_retries, 5);
+
+        assert!(BrokerConfig::parse("[[webhook]]\nurl = \"http://127.0.0.1\"\nevents = [\"pinged\"]").is_err());
+        assert!(BrokerConfig::parse("").unwrap().webhooks.is_empty());
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            http::parse_url("http://10.0.0.7:8080/mqtt-events?source=broker"),
+            Ok(Url {
+                https: false,
+                host: "10.0.0.7".to_string(),
+                port: 8080,
+                path: "/mqtt-events?source=broker".to_string(),
+            })
+        );
+
+        let url: Url = http::parse_url("https://hooks.example.com").unwrap();
+        assert_eq!((url.https, url.host.as_str(), url.port, url.path.as_str()), (true, "hooks.example.com", 443, "/"));
+
+        let url: Url = http::parse_url("http://[::1]:9000/events").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("::1", 9000));
+
+        assert!(http::parse_url("ftp://example.com").is_err());
+        assert!(http::parse_url("http://:8080/").is_err());
+        assert!(http::parse_url("http://example.com:port/").is_err());
+    }
+
+    #[test]
+    fn test_webhook_posts_batches() {
+        let (address, requests) = start_stub(vec![]);
+
+        let mut config: WebhookConfig = webhook_config(address);
+        config.topics = vec!["sensors/#".to_string()];
+        config.authorization = Some("Bearer secret".to_string());
+        config.batch_size = 3;
+        config.batch_interval_ms = 5000;
+
+        let webhook: Webhook = Webhook::start(&config).unwrap();
+        let client_address: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 50000));
+
+        webhook.emit(&Event::ClientConnected {
+            client_id: "sensor/1",
+            username: "device",
+            address: client_address,
+            clean_session: true,
+        });
+
+        // Not on one of the webhook's topic filters
+        webhook.emit(&Event::MessagePublished {
+            client_id: "sensor/1",
+            topic: "lights/kitchen",
+            qos: 0,
+            retain: false,
+            payload: "on",
+        });
+
+        webhook.emit(&Event::MessagePublished {
+            client_id: "sensor/1",
+            topic: "sensors/kitchen/temperature",
+            qos: 1,
+            retain: true,
+            payload: "21.5",
+        });
+
+        webhook.emit(&Event::ClientDisconnected {
+            client_id: "sensor/1",
+            address: client_address,
+            reason: "Keep alive timeout",
+        });
+
+        // A full batch is posted without waiting for the batch interval
+        let request: Request = requests.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/events");
+        assert_eq!(request.header("Content-Type"), Some("application/json"));
+        assert_eq!(request.header("Authorization"), Some("Bearer secret"));
+
+        let events: Vec<Value> = body_of(&request);
+        assert_eq!(events.len(), 3);
+
+        assert_eq!(events[0]["event"], "client_connected");
+        assert_eq!(events[0]["client_id"], "sensor/1");
+        assert_eq!(events[0]["address"], "127.0.0.1:50000");
+        assert_eq!(events[0]["clean_session"], true);
+        assert!(events[0]["timestamp"].as_u64().unwrap() > 0);
+
+        assert_eq!(events[1]["event"], "message_published");
+        assert_eq!(events[1]["topic"], "sensors/kitchen/temperature");
+        assert_eq!(events[1]["qos"], 1);
+        assert_eq!(events[1]["retain"], true);
+        assert_eq!(events[1]["payload"], "21.5");
+
+        assert_eq!(events[2]["event"], "client_disconnected");
+        assert_eq!(events[2]["reason"], "Keep alive timeout");
+    }
+
+    #[test]
+    fn test_webhook_filters_events_and_flushes_after_interval() {
+        let (address, requests) = start_stub(vec![]);
+
+        let mut config: WebhookConfig = webhook_config(address);
+        config.events = vec![WebhookEvent::Subscribed];
+
+        let webhook: Webhook = Webhook::start(&config).unwrap();
+
+        webhook.emit(&Event::Unsubscribed { client_id: "sensor/1", topic_filter: "sensors/#" });
+        webhook.emit(&Event::Subscribed { client_id: "sensor/1", topic_filter: "sensors/#", qos: 2 });
+
+        // A batch that is not full is posted once the batch interval has passed
+        let events: Vec<Value> = body_of(&requests.recv_timeout(Duration::from_secs(2)).unwrap());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "subscribed");
+        assert_eq!(events[0]["topic_filter"], "sensors/#");
+        assert_eq!(events[0]["qos"], 2);
+
+        // Dropping the webhook stops its thread, nothing more is posted
+        drop(webhook);
+        assert!(requests.recv_timeout(Duration::from_millis(300)).is_err());
+    }
+
+    #[test]
+    fn test_webhook_retries_failed_posts() {
+        let (address, requests) = start_stub(vec![503, 500]);
+
+        let webhook: Webhook = Webhook::start(&webhook_config(address)).unwrap();
+        webhook.emit(&Event::Unsubscribed { client_id: "sensor/1", topic_filter: "sensors/#" });
+
+        // The same batch is posted until the endpoint accepts it
+        let bodies: Vec<Vec<u8>> = (0..3)
+            .map(|_| requests.recv_timeout(Duration::from_secs(2)).unwrap().body)
+            .collect();
+
+        assert_eq!(bodies[0], bodies[1]);
+        assert_eq!(bodies[1], bodies[2]);
+        assert!(requests.recv_timeout(Duration::from_millis(300)).is_err());
+
+        // A 4xx status other than 408 and 429 is not retried
+        let (address, requests) = start_stub(vec![400]);
+
+        let webhook: Webhook = Webhook::start(&webhook_config(address)).unwrap();
+        webhook.emit(&Event::Unsubscribed { client_id: "sensor/1", topic_filter: "sensors/#" });
+
+        assert!(requests.recv_timeout(Duration::from_secs(2)).is_ok());
+        assert!(requests.recv_timeout(Duration::from_millis(300)).is_err());
+    }
+
+    #[test]
+    fn test_slow_endpoint_does_not_block() {
+        // An endpoint that accepts connections but never answers
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let mut config: WebhookConfig = webhook_config(listener.local_addr().unwrap());
+        config.batch_size = 1;
+        config.queue_size = 10;
+        config.timeout_ms = 200;
+        config.max_retries = 0;
+
+        let webhook: Webhook = Webhook::start(&config).unwrap();
+
+        // The queue fills up behind the first POST, the events after that are dropped
+        let started: Instant = Instant::now();
+
+        for _ in 0..1000 {
+            webhook.emit(&Event::Unsubscribed { client_id: "sensor/1", topic_filter: "sensors/#" });
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}